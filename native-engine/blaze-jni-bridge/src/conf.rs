@@ -14,7 +14,7 @@
 
 use datafusion::common::Result;
 
-use crate::{jni_call_static, jni_new_string};
+use crate::{jni_call_static, jni_get_string, jni_new_string};
 
 macro_rules! define_conf {
     ($conftype:ty, $name:ident) => {
@@ -41,6 +41,12 @@ define_conf!(BooleanConf, IGNORE_CORRUPTED_FILES);
 define_conf!(BooleanConf, PARTIAL_AGG_SKIPPING_ENABLE);
 define_conf!(DoubleConf, PARTIAL_AGG_SKIPPING_RATIO);
 define_conf!(IntConf, PARTIAL_AGG_SKIPPING_MIN_ROWS);
+define_conf!(BooleanConf, AGG_SPILL_ENABLE);
+define_conf!(IntConf, COLLECT_SET_MERGE_NUM_THREADS);
+define_conf!(IntConf, COLLECT_SET_MAX_SLOTS);
+define_conf!(StringConf, SPILL_COMPRESSION_CODEC);
+define_conf!(BooleanConf, SPILL_CHECKSUM_ENABLE);
+define_conf!(IntConf, SPILL_COMPRESSION_BLOCK_SIZE);
 
 pub trait BooleanConf {
     fn key(&self) -> &'static str;
@@ -73,3 +79,12 @@ pub trait DoubleConf {
         jni_call_static!(BlazeConf.doubleConf(key.as_obj()) -> f64)
     }
 }
+
+pub trait StringConf {
+    fn key(&self) -> &'static str;
+    fn value(&self) -> Result<String> {
+        let key = jni_new_string!(self.key())?;
+        let value = jni_call_static!(BlazeConf.stringConf(key.as_obj()) -> JObject)?;
+        jni_get_string!(value.as_obj().into())
+    }
+}
@@ -241,6 +241,7 @@ impl TryInto<Arc<dyn ExecutionPlan>> for &protobuf::PhysicalPlanNode {
                     join_type.into(),
                     join_filter,
                     sort_options,
+                    sort_merge_join.null_equals_null,
                 )?))
             }
             PhysicalPlanType::ShuffleWriter(shuffle_writer) => {
@@ -392,6 +393,7 @@ impl TryInto<Arc<dyn ExecutionPlan>> for &protobuf::PhysicalPlanNode {
                     on,
                     join_type.into(),
                     join_filter,
+                    broadcast_join.null_equals_null,
                 )?))
             }
             PhysicalPlanType::BroadcastNestedLoopJoin(bnlj) => {
@@ -648,6 +650,12 @@ impl TryInto<Arc<dyn ExecutionPlan>> for &protobuf::PhysicalPlanNode {
                                 protobuf::AggFunction::BrickhouseCombineUnique => {
                                     WindowFunction::Agg(AggFunction::BrickhouseCombineUnique)
                                 }
+                                other => {
+                                    return Err(PlanSerDeError::General(format!(
+                                        "{:?} is not supported as window function",
+                                        other
+                                    )));
+                                }
                             },
                         };
                         Ok::<_, Self::Error>(WindowExpr::new(window_func, children, field))
@@ -136,6 +136,24 @@ impl From<protobuf::AggFunction> for AggFunction {
             protobuf::AggFunction::CollectSet => AggFunction::CollectSet,
             protobuf::AggFunction::First => AggFunction::First,
             protobuf::AggFunction::FirstIgnoresNull => AggFunction::FirstIgnoresNull,
+            protobuf::AggFunction::BoundedCollectList => AggFunction::BoundedCollectList,
+            protobuf::AggFunction::ArrayAgg => AggFunction::ArrayAgg,
+            protobuf::AggFunction::Last => AggFunction::Last,
+            protobuf::AggFunction::LastIgnoresNull => AggFunction::LastIgnoresNull,
+            protobuf::AggFunction::HistogramNumeric => AggFunction::HistogramNumeric,
+            protobuf::AggFunction::CountMinSketch => AggFunction::CountMinSketch,
+            protobuf::AggFunction::SumDistinct => AggFunction::SumDistinct,
+            protobuf::AggFunction::VarSamp => AggFunction::VarSamp,
+            protobuf::AggFunction::VarPop => AggFunction::VarPop,
+            protobuf::AggFunction::StddevSamp => AggFunction::StddevSamp,
+            protobuf::AggFunction::StddevPop => AggFunction::StddevPop,
+            protobuf::AggFunction::KahanSum => AggFunction::KahanSum,
+            protobuf::AggFunction::ArrayUnion => AggFunction::ArrayUnion,
+            protobuf::AggFunction::ArrayIntersect => AggFunction::ArrayIntersect,
+            protobuf::AggFunction::BoundedCollectSet => AggFunction::BoundedCollectSet,
+            protobuf::AggFunction::ValueCounts => AggFunction::ValueCounts,
+            protobuf::AggFunction::PercentileApprox => AggFunction::PercentileApprox,
+            protobuf::AggFunction::TopDistinct => AggFunction::TopDistinct,
             protobuf::AggFunction::BrickhouseCollect => AggFunction::BrickhouseCollect,
             protobuf::AggFunction::BrickhouseCombineUnique => AggFunction::BrickhouseCombineUnique,
         }
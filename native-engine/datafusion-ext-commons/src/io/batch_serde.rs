@@ -119,6 +119,41 @@ pub fn read_batch<R: Read>(input: &mut R) -> Result<RecordBatch> {
     )?)
 }
 
+/// returns whether arrays of the data type can be serialized with
+/// `write_array()` and `read_array()`
+pub fn is_array_serde_supported(data_type: &DataType) -> bool {
+    match data_type {
+        DataType::Null
+        | DataType::Boolean
+        | DataType::Int8
+        | DataType::Int16
+        | DataType::Int32
+        | DataType::Int64
+        | DataType::UInt8
+        | DataType::UInt16
+        | DataType::UInt32
+        | DataType::UInt64
+        | DataType::Float32
+        | DataType::Float64
+        | DataType::Decimal128(..)
+        | DataType::Utf8
+        | DataType::Binary
+        | DataType::Date32
+        | DataType::Date64
+        | DataType::Timestamp(..) => true,
+        DataType::List(field) | DataType::Map(field, _) => {
+            is_array_serde_supported(field.data_type())
+        }
+        DataType::Struct(fields) => fields
+            .iter()
+            .all(|field| is_array_serde_supported(field.data_type())),
+        _ => false,
+    }
+}
+
+/// null buffers without nulls are not written, so that equal arrays are
+/// always serialized into the same bytes, which is required by deduplicating
+/// nested values in collect_set()
 pub fn write_array<W: Write>(array: &dyn Array, output: &mut W) -> Result<()> {
     macro_rules! write_primitive {
         ($ty:ident) => {{
@@ -272,7 +307,7 @@ fn write_primitive_array<W: Write, PT: ArrowPrimitiveType>(
     let offset = array.offset();
     let len = array.len();
     let array_data = array.to_data();
-    if let Some(null_buffer) = array_data.nulls() {
+    if let Some(null_buffer) = array_data.nulls().filter(|nulls| nulls.null_count() > 0) {
         write_len(1, output)?;
         write_bits_buffer(
             null_buffer.buffer(),
@@ -316,7 +351,11 @@ fn read_primitive_array<R: Read, PT: ArrowPrimitiveType>(
 }
 
 fn write_list_array<W: Write>(array: &ListArray, output: &mut W) -> Result<()> {
-    if let Some(null_buffer) = array.to_data().nulls() {
+    if let Some(null_buffer) = array
+        .to_data()
+        .nulls()
+        .filter(|nulls| nulls.null_count() > 0)
+    {
         write_len(1, output)?;
         write_bits_buffer(
             null_buffer.buffer(),
@@ -379,7 +418,7 @@ fn read_list_array<R: Read>(
 
 fn write_map_array<W: Write>(array: &MapArray, output: &mut W) -> Result<()> {
     let array_data = array.to_data();
-    if let Some(null_buffer) = array_data.nulls() {
+    if let Some(null_buffer) = array_data.nulls().filter(|nulls| nulls.null_count() > 0) {
         write_len(1, output)?;
         write_bits_buffer(
             null_buffer.buffer(),
@@ -468,7 +507,7 @@ fn read_map_array<R: Read>(
 
 fn write_struct_array<W: Write>(array: &StructArray, output: &mut W) -> Result<()> {
     let array_data = array.to_data();
-    if let Some(null_buffer) = array_data.nulls() {
+    if let Some(null_buffer) = array_data.nulls().filter(|nulls| nulls.null_count() > 0) {
         write_len(1, output)?;
         write_bits_buffer(
             null_buffer.buffer(),
@@ -511,7 +550,7 @@ fn read_struct_array<R: Read>(num_rows: usize, input: &mut R, fields: &Fields) -
 
 fn write_boolean_array<W: Write>(array: &BooleanArray, output: &mut W) -> Result<()> {
     let array_data = array.to_data();
-    if let Some(null_buffer) = array_data.nulls() {
+    if let Some(null_buffer) = array_data.nulls().filter(|nulls| nulls.null_count() > 0) {
         write_len(1, output)?;
         write_bits_buffer(
             null_buffer.buffer(),
@@ -559,7 +598,11 @@ fn write_bytes_array<T: ByteArrayType<Offset = i32>, W: Write>(
     array: &GenericByteArray<T>,
     output: &mut W,
 ) -> Result<()> {
-    if let Some(null_buffer) = array.to_data().nulls() {
+    if let Some(null_buffer) = array
+        .to_data()
+        .nulls()
+        .filter(|nulls| nulls.null_count() > 0)
+    {
         write_len(1, output)?;
         write_bits_buffer(
             null_buffer.buffer(),
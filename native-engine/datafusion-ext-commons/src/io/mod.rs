@@ -19,9 +19,11 @@ use arrow::{
     datatypes::{DataType, SchemaRef},
     record_batch::RecordBatch,
 };
-pub use batch_serde::{read_array, read_data_type, write_array, write_data_type};
+pub use batch_serde::{
+    is_array_serde_supported, read_array, read_data_type, write_array, write_data_type,
+};
 use datafusion::common::{cast::as_struct_array, Result};
-pub use scalar_serde::{read_scalar, write_scalar};
+pub use scalar_serde::{is_scalar_serde_supported, read_scalar, write_array_value, write_scalar};
 
 mod batch_serde;
 mod scalar_serde;
@@ -18,16 +18,35 @@ use std::{
 };
 
 use arrow::{
-    array::{AsArray, StructArray},
+    array::{Array, ArrayRef, AsArray, StructArray},
     datatypes::*,
 };
 use datafusion::{common::Result, parquet::data_type::AsBytes, scalar::ScalarValue};
 
 use crate::{
     df_unimplemented_err,
-    io::{read_array, read_bytes_slice, read_len, read_u8, write_array, write_len, write_u8},
+    io::{
+        is_array_serde_supported, read_array, read_bytes_slice, read_len, read_u8, write_array,
+        write_len, write_u8,
+    },
 };
 
+/// returns whether scalar values of the data type can be serialized with
+/// `write_scalar()` and `read_scalar()`. unsupported types include times,
+/// decimal256, fixed-sized binaries/lists, large lists, unions and
+/// dictionaries, and nested types containing them. intervals and durations
+/// are supported only as top-level values
+pub fn is_scalar_serde_supported(data_type: &DataType) -> bool {
+    match data_type {
+        DataType::LargeUtf8 | DataType::LargeBinary => true,
+        DataType::Interval(_) | DataType::Duration(_) => true,
+        // nested values are serialized as arrays
+        DataType::List(_) | DataType::Struct(_) => is_array_serde_supported(data_type),
+        DataType::Map(field, _) => is_scalar_serde_supported(field.data_type()),
+        other => is_array_serde_supported(other),
+    }
+}
+
 pub fn write_scalar<W: Write>(value: &ScalarValue, nullable: bool, output: &mut W) -> Result<()> {
     assert!(nullable || !value.is_null());
 
@@ -66,7 +85,14 @@ pub fn write_scalar<W: Write>(value: &ScalarValue, nullable: bool, output: &mut
         ScalarValue::TimestampMillisecond(v, ..) => write_prim!(v),
         ScalarValue::TimestampMicrosecond(v, ..) => write_prim!(v),
         ScalarValue::TimestampNanosecond(v, ..) => write_prim!(v),
-        ScalarValue::Utf8(v) => {
+        ScalarValue::IntervalYearMonth(v) => write_prim!(v),
+        ScalarValue::IntervalDayTime(v) => write_prim!(v),
+        ScalarValue::IntervalMonthDayNano(v) => write_prim!(v),
+        ScalarValue::DurationSecond(v) => write_prim!(v),
+        ScalarValue::DurationMillisecond(v) => write_prim!(v),
+        ScalarValue::DurationMicrosecond(v) => write_prim!(v),
+        ScalarValue::DurationNanosecond(v) => write_prim!(v),
+        ScalarValue::Utf8(v) | ScalarValue::LargeUtf8(v) => {
             if let Some(v) = v {
                 write_len(v.as_bytes().len() + 1, output)?;
                 output.write_all(v.as_bytes())?;
@@ -74,7 +100,7 @@ pub fn write_scalar<W: Write>(value: &ScalarValue, nullable: bool, output: &mut
                 write_len(0, output)?;
             }
         }
-        ScalarValue::Binary(v) => {
+        ScalarValue::Binary(v) | ScalarValue::LargeBinary(v) => {
             if let Some(v) = v {
                 write_len(v.as_bytes().len() + 1, output)?;
                 output.write_all(v.as_bytes())?;
@@ -98,6 +124,77 @@ pub fn write_scalar<W: Write>(value: &ScalarValue, nullable: bool, output: &mut
     Ok(())
 }
 
+/// serializes a value of the array in the same format as `write_scalar()` of
+/// `ScalarValue::try_from_array()`. primitive, string and binary values are
+/// written directly from the typed arrays without constructing scalars, which
+/// allocates for strings. other types fall back to the scalar path
+pub fn write_array_value<W: Write>(
+    array: &ArrayRef,
+    idx: usize,
+    nullable: bool,
+    output: &mut W,
+) -> Result<()> {
+    assert!(nullable || array.is_valid(idx));
+
+    macro_rules! write_prim {
+        ($arrowty:ty) => {{
+            let array = array.as_primitive::<$arrowty>();
+            if !nullable {
+                output.write_all(&array.value(idx).to_ne_bytes())?;
+            } else if array.is_valid(idx) {
+                write_u8(1, output)?;
+                output.write_all(&array.value(idx).to_ne_bytes())?;
+            } else {
+                write_u8(0, output)?;
+            }
+        }};
+    }
+    macro_rules! write_bytes {
+        ($v:expr) => {{
+            if array.is_valid(idx) {
+                let v: &[u8] = $v;
+                write_len(v.len() + 1, output)?;
+                output.write_all(v)?;
+            } else {
+                write_len(0, output)?;
+            }
+        }};
+    }
+
+    match array.data_type() {
+        DataType::Int8 => write_prim!(Int8Type),
+        DataType::Int16 => write_prim!(Int16Type),
+        DataType::Int32 => write_prim!(Int32Type),
+        DataType::Int64 => write_prim!(Int64Type),
+        DataType::UInt8 => write_prim!(UInt8Type),
+        DataType::UInt16 => write_prim!(UInt16Type),
+        DataType::UInt32 => write_prim!(UInt32Type),
+        DataType::UInt64 => write_prim!(UInt64Type),
+        DataType::Float32 => write_prim!(Float32Type),
+        DataType::Float64 => write_prim!(Float64Type),
+        DataType::Decimal128(..) => write_prim!(Decimal128Type),
+        DataType::Date32 => write_prim!(Date32Type),
+        DataType::Date64 => write_prim!(Date64Type),
+        DataType::Timestamp(TimeUnit::Second, _) => write_prim!(TimestampSecondType),
+        DataType::Timestamp(TimeUnit::Millisecond, _) => write_prim!(TimestampMillisecondType),
+        DataType::Timestamp(TimeUnit::Microsecond, _) => write_prim!(TimestampMicrosecondType),
+        DataType::Timestamp(TimeUnit::Nanosecond, _) => write_prim!(TimestampNanosecondType),
+        DataType::Interval(IntervalUnit::YearMonth) => write_prim!(IntervalYearMonthType),
+        DataType::Interval(IntervalUnit::DayTime) => write_prim!(IntervalDayTimeType),
+        DataType::Interval(IntervalUnit::MonthDayNano) => write_prim!(IntervalMonthDayNanoType),
+        DataType::Duration(TimeUnit::Second) => write_prim!(DurationSecondType),
+        DataType::Duration(TimeUnit::Millisecond) => write_prim!(DurationMillisecondType),
+        DataType::Duration(TimeUnit::Microsecond) => write_prim!(DurationMicrosecondType),
+        DataType::Duration(TimeUnit::Nanosecond) => write_prim!(DurationNanosecondType),
+        DataType::Utf8 => write_bytes!(array.as_string::<i32>().value(idx).as_bytes()),
+        DataType::LargeUtf8 => write_bytes!(array.as_string::<i64>().value(idx).as_bytes()),
+        DataType::Binary => write_bytes!(array.as_binary::<i32>().value(idx)),
+        DataType::LargeBinary => write_bytes!(array.as_binary::<i64>().value(idx)),
+        _ => write_scalar(&ScalarValue::try_from_array(array, idx)?, nullable, output)?,
+    }
+    Ok(())
+}
+
 pub fn read_scalar<R: Read>(
     input: &mut R,
     data_type: &DataType,
@@ -155,24 +252,48 @@ pub fn read_scalar<R: Read>(
         DataType::Timestamp(TimeUnit::Nanosecond, str) => {
             ScalarValue::TimestampNanosecond(read_prim!(i64), str.clone())
         }
-        DataType::Binary => {
+        DataType::Interval(IntervalUnit::YearMonth) => {
+            ScalarValue::IntervalYearMonth(read_prim!(i32))
+        }
+        DataType::Interval(IntervalUnit::DayTime) => ScalarValue::IntervalDayTime(read_prim!(i64)),
+        DataType::Interval(IntervalUnit::MonthDayNano) => {
+            ScalarValue::IntervalMonthDayNano(read_prim!(i128))
+        }
+        DataType::Duration(TimeUnit::Second) => ScalarValue::DurationSecond(read_prim!(i64)),
+        DataType::Duration(TimeUnit::Millisecond) => {
+            ScalarValue::DurationMillisecond(read_prim!(i64))
+        }
+        DataType::Duration(TimeUnit::Microsecond) => {
+            ScalarValue::DurationMicrosecond(read_prim!(i64))
+        }
+        DataType::Duration(TimeUnit::Nanosecond) => {
+            ScalarValue::DurationNanosecond(read_prim!(i64))
+        }
+        DataType::Binary | DataType::LargeBinary => {
             let data_len = read_len(input)?;
-            if data_len > 0 {
+            let value = if data_len > 0 {
                 let data_len = data_len - 1;
-                ScalarValue::Binary(Some(read_bytes_slice(input, data_len)?.into()))
+                Some(read_bytes_slice(input, data_len)?.into())
             } else {
-                ScalarValue::Binary(None)
+                None
+            };
+            match data_type {
+                DataType::Binary => ScalarValue::Binary(value),
+                _ => ScalarValue::LargeBinary(value),
             }
         }
-        DataType::Utf8 => {
+        DataType::Utf8 | DataType::LargeUtf8 => {
             let data_len = read_len(input)?;
-            if data_len > 0 {
+            let value = if data_len > 0 {
                 let data_len = data_len - 1;
                 let value_buf = read_bytes_slice(input, data_len)?;
-                let value = String::from_utf8_lossy(&value_buf);
-                ScalarValue::Utf8(Some(value.into()))
+                Some(String::from_utf8_lossy(&value_buf).into())
             } else {
-                ScalarValue::Utf8(None)
+                None
+            };
+            match data_type {
+                DataType::Utf8 => ScalarValue::Utf8(value),
+                _ => ScalarValue::LargeUtf8(value),
             }
         }
         DataType::List(_) => {
@@ -196,12 +317,16 @@ pub fn read_scalar<R: Read>(
 
 #[cfg(test)]
 mod test {
-    use std::io::Cursor;
+    use std::{io::Cursor, sync::Arc};
 
-    use arrow_schema::DataType;
+    use arrow::{
+        array::*,
+        datatypes::{IntervalDayTimeType, IntervalMonthDayNanoType},
+    };
+    use arrow_schema::{DataType, Field, Fields, IntervalUnit, TimeUnit};
     use datafusion::common::{Result, ScalarValue};
 
-    use crate::io::{read_scalar, write_scalar};
+    use crate::io::{is_scalar_serde_supported, read_scalar, write_array_value, write_scalar};
 
     #[test]
     fn test() -> Result<()> {
@@ -213,6 +338,11 @@ mod test {
         write_scalar(&ScalarValue::Utf8(None), true, &mut buf)?;
         write_scalar(&ScalarValue::Null, true, &mut buf)?;
         write_scalar(&ScalarValue::from(3.15), false, &mut buf)?;
+        write_scalar(
+            &ScalarValue::LargeUtf8(Some("Large".to_string())),
+            true,
+            &mut buf,
+        )?;
 
         let mut cur = Cursor::new(&buf);
         assert_eq!(
@@ -239,6 +369,121 @@ mod test {
             read_scalar(&mut cur, &DataType::Float64, false)?,
             ScalarValue::from(3.15)
         );
+        assert_eq!(
+            read_scalar(&mut cur, &DataType::LargeUtf8, true)?,
+            ScalarValue::LargeUtf8(Some("Large".to_string()))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_is_scalar_serde_supported() {
+        let supported = [
+            DataType::Null,
+            DataType::Int32,
+            DataType::LargeUtf8,
+            DataType::Decimal128(38, 10),
+            DataType::Timestamp(TimeUnit::Microsecond, Some("UTC".into())),
+            DataType::new_list(DataType::Utf8, true),
+            DataType::Struct(Fields::from(vec![Field::new("a", DataType::Binary, true)])),
+            DataType::Interval(IntervalUnit::DayTime),
+            DataType::Interval(IntervalUnit::MonthDayNano),
+            DataType::Duration(TimeUnit::Second),
+        ];
+        let unsupported = [
+            DataType::Time32(TimeUnit::Second),
+            DataType::FixedSizeBinary(16),
+            DataType::new_list(DataType::LargeUtf8, true),
+            DataType::Struct(Fields::from(vec![Field::new(
+                "a",
+                DataType::Duration(TimeUnit::Second),
+                true,
+            )])),
+        ];
+        for dt in supported {
+            assert!(is_scalar_serde_supported(&dt), "{dt}");
+        }
+        for dt in unsupported {
+            assert!(!is_scalar_serde_supported(&dt), "{dt}");
+        }
+    }
+
+    #[test]
+    fn test_interval_and_duration() -> Result<()> {
+        let values = [
+            ScalarValue::IntervalYearMonth(Some(-14)),
+            ScalarValue::IntervalDayTime(Some(IntervalDayTimeType::make_value(-3, 500))),
+            ScalarValue::IntervalMonthDayNano(Some(IntervalMonthDayNanoType::make_value(
+                -1, 2, -3_000,
+            ))),
+            ScalarValue::IntervalMonthDayNano(None),
+            ScalarValue::DurationSecond(Some(-1)),
+            ScalarValue::DurationMillisecond(Some(i64::MAX)),
+            ScalarValue::DurationMicrosecond(None),
+            ScalarValue::DurationNanosecond(Some(i64::MIN)),
+        ];
+        for value in values {
+            for nullable in [true, false] {
+                if !nullable && value.is_null() {
+                    continue;
+                }
+                let mut buf = vec![];
+                write_scalar(&value, nullable, &mut buf)?;
+                let read = read_scalar(&mut Cursor::new(&buf), &value.data_type(), nullable)?;
+                assert_eq!(read, value);
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_array_value() -> Result<()> {
+        let arrays: Vec<ArrayRef> = vec![
+            Arc::new(Int64Array::from(vec![Some(1), None, Some(-3)])),
+            Arc::new(Float32Array::from(vec![Some(1.5), None, Some(f32::NAN)])),
+            Arc::new(Decimal128Array::from(vec![Some(12345), None, Some(-1)])),
+            Arc::new(
+                TimestampMicrosecondArray::from(vec![Some(1), None, Some(2)]).with_timezone("UTC"),
+            ),
+            Arc::new(StringArray::from(vec![Some("a"), None, Some("")])),
+            Arc::new(LargeStringArray::from(vec![Some("large"), None, Some("b")])),
+            Arc::new(BinaryArray::from(vec![
+                Some(&b"x"[..]),
+                None,
+                Some(&b""[..]),
+            ])),
+            Arc::new(BooleanArray::from(vec![Some(true), None, Some(false)])),
+            Arc::new(IntervalMonthDayNanoArray::from(vec![
+                Some(IntervalMonthDayNanoType::make_value(1, -2, 3)),
+                None,
+                Some(IntervalMonthDayNanoType::make_value(-1, 0, -5)),
+            ])),
+            Arc::new(DurationMicrosecondArray::from(vec![
+                Some(-5),
+                None,
+                Some(7),
+            ])),
+            Arc::new(ListArray::from_iter_primitive::<
+                arrow::datatypes::Int32Type,
+                _,
+                _,
+            >(vec![Some(vec![Some(1)]), None, Some(vec![])])),
+        ];
+        for array in arrays {
+            for nullable in [true, false] {
+                for idx in 0..array.len() {
+                    if !nullable && array.is_null(idx) {
+                        continue;
+                    }
+                    let mut expected = vec![];
+                    let mut actual = vec![];
+                    let value = ScalarValue::try_from_array(&array, idx)?;
+                    write_scalar(&value, nullable, &mut expected)?;
+                    write_array_value(&array, idx, nullable, &mut actual)?;
+                    assert_eq!(actual, expected, "{value:?}");
+                }
+            }
+        }
         Ok(())
     }
 }
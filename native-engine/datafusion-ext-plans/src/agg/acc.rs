@@ -14,18 +14,23 @@
 
 use std::{
     any::Any,
+    cell::Cell,
     io::{Cursor, Read, Write},
     mem::{size_of, size_of_val},
+    sync::Arc,
 };
 
-use arrow::datatypes::DataType;
+use arrow::{array::*, datatypes::*};
 use datafusion::{
     common::{Result, ScalarValue},
     parquet::data_type::AsBytes,
 };
 use datafusion_ext_commons::{
     df_execution_err, downcast_any,
-    io::{read_bytes_slice, read_len, read_scalar, read_u8, write_len, write_scalar, write_u8},
+    io::{
+        read_bytes_slice, read_len, read_scalar, read_u8, write_array_value, write_len,
+        write_scalar, write_u8,
+    },
     slim_bytes::SlimBytes,
 };
 use hashbrown::raw::RawTable;
@@ -33,7 +38,7 @@ use itertools::Itertools;
 use slimmer_box::SlimmerBox;
 use smallvec::SmallVec;
 
-use crate::agg::agg_table::gx_hash;
+use crate::agg::agg_table::gx_hash_with_seed;
 
 pub type DynVal = Option<Box<dyn AggDynValue>>;
 
@@ -108,10 +113,16 @@ impl AccStore {
         let dyns_ptr = self.dyn_store[idx1][idx2 * self.dyns_len()..].as_ptr() as *mut DynVal;
         unsafe {
             // safety: skip borrow/mutable checking
-            RefAccumStateRow {
-                fixed: std::slice::from_raw_parts_mut(fixed_ptr, self.fixed_len()),
-                dyns: std::slice::from_raw_parts_mut(dyns_ptr, self.dyns_len()),
-            }
+            RefAccumStateRow::from_raw_parts(fixed_ptr, self.fixed_len(), dyns_ptr, self.dyns_len())
+        }
+    }
+
+    /// resets the accumulator to the initial value, like a newly created one
+    pub fn reset_acc(&mut self, idx: u32) {
+        let mut acc = self.get(idx);
+        acc.fixed_mut().copy_from_slice(self.initial.fixed());
+        for (v, initial) in acc.dyns_mut().iter_mut().zip(self.initial.dyns()) {
+            *v = initial.as_ref().map(|v| v.clone_boxed());
         }
     }
 
@@ -179,6 +190,57 @@ pub struct RefAccumStateRow<'a> {
     dyns: &'a mut [DynVal],
 }
 
+impl<'a> RefAccumStateRow<'a> {
+    /// creates a row from pointers to its fixed and dyn values
+    ///
+    /// # Safety
+    /// the pointers must be valid for `fixed_len` bytes and `dyns_len` values
+    /// during 'a. rows created from the same pointers alias each other, only
+    /// one of them can be accessed at a time
+    pub unsafe fn from_raw_parts(
+        fixed: *mut u8,
+        fixed_len: usize,
+        dyns: *mut DynVal,
+        dyns_len: usize,
+    ) -> Self {
+        Self {
+            fixed: std::slice::from_raw_parts_mut(fixed, fixed_len),
+            dyns: std::slice::from_raw_parts_mut(dyns, dyns_len),
+        }
+    }
+
+    /// returns pointers to the fixed and dyn values, which identify the
+    /// accumulator referred by this row
+    pub fn as_ptrs(&self) -> (*const u8, *const DynVal) {
+        (self.fixed.as_ptr(), self.dyns.as_ptr())
+    }
+
+    /// returns whether two rows refer to the same accumulator, which is
+    /// possible with rows got from `AccStore::get()` with the same address
+    pub fn ptr_eq(&self, other: &RefAccumStateRow) -> bool {
+        self.as_ptrs() == other.as_ptrs()
+    }
+
+    pub fn reborrow(&mut self) -> RefAccumStateRow {
+        RefAccumStateRow {
+            fixed: &mut *self.fixed,
+            dyns: &mut *self.dyns,
+        }
+    }
+
+    pub fn to_owned_row(&self) -> OwnedAccumStateRow {
+        OwnedAccumStateRow {
+            fixed: SlimBytes::from(&*self.fixed),
+            dyns: SlimmerBox::from_box(
+                self.dyns
+                    .iter()
+                    .map(|v| v.as_ref().map(|x| x.clone_boxed()))
+                    .collect::<Box<[DynVal]>>(),
+            ),
+        }
+    }
+}
+
 impl<'a> AccumStateRow for RefAccumStateRow<'a> {
     fn fixed(&self) -> &[u8] {
         self.fixed
@@ -220,7 +282,12 @@ pub trait AccumStateRow {
     fn set_fixed_valid(&mut self, addr: AccumStateValAddr, valid: bool) {
         let idx = addr.fixed_valid_idx();
         let fixed_len = self.fixed().len();
-        self.fixed_mut()[fixed_len - 1 - idx / 8] |= (valid as u8) << (idx % 8);
+        let valid_byte = &mut self.fixed_mut()[fixed_len - 1 - idx / 8];
+        if valid {
+            *valid_byte |= 1 << (idx % 8);
+        } else {
+            *valid_byte &= !(1 << (idx % 8));
+        }
     }
 
     fn fixed_value<T: Sized + Copy>(&self, addr: AccumStateValAddr) -> T {
@@ -290,11 +357,73 @@ pub trait AccumStateRow {
     }
 }
 
+/// loads accumulators from serialized rows of a BinaryArray, the same as
+/// calling `load_from_bytes()` on a clone of `initial` for each row, but all
+/// rows are read with a single reader over the contiguous value buffer. the
+/// end of each loaded row must match the array offsets
+pub fn load_accs_from_binary_array(
+    initial: &OwnedAccumStateRow,
+    array: &BinaryArray,
+    dyn_loaders: &[LoadFn],
+) -> Result<Vec<OwnedAccumStateRow>> {
+    if array.null_count() > 0 {
+        return df_execution_err!("loading accumulators from null rows");
+    }
+    let data = array.value_data();
+    let offsets = array.value_offsets();
+    let pos = Cell::new(offsets[0] as usize);
+    let mut reader = LoadReader(Box::new(SharedPosReader { data, pos: &pos }));
+
+    let mut accs = Vec::with_capacity(array.len());
+    for row_idx in 0..array.len() {
+        let mut acc = initial.clone();
+        let fixed_len = acc.fixed().len();
+        let row_end = offsets[row_idx + 1] as usize;
+        if pos.get() + fixed_len > row_end {
+            return df_execution_err!("accumulator row {row_idx} is truncated");
+        }
+        acc.fixed_mut()
+            .copy_from_slice(&data[pos.get()..][..fixed_len]);
+        pos.set(pos.get() + fixed_len);
+
+        for (v, load) in acc.dyns_mut().iter_mut().zip(dyn_loaders) {
+            *v = load(&mut reader)?;
+        }
+        if pos.get() != row_end {
+            return df_execution_err!(
+                "accumulator row {row_idx} is not fully loaded: end={}, expected={row_end}",
+                pos.get()
+            );
+        }
+        accs.push(acc);
+    }
+    Ok(accs)
+}
+
+/// reader over a shared buffer with externally visible position
+struct SharedPosReader<'a> {
+    data: &'a [u8],
+    pos: &'a Cell<usize>,
+}
+
+impl Read for SharedPosReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let pos = self.pos.get();
+        let len = buf.len().min(self.data.len() - pos);
+        buf[..len].copy_from_slice(&self.data[pos..][..len]);
+        self.pos.set(pos + len);
+        Ok(len)
+    }
+}
+
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub enum AccumInitialValue {
     Scalar(ScalarValue),
     DynList(DataType),
-    DynSet(DataType),
+    DynSet(DataType, i64, usize), // data type, hash seed and max slots
+    DynHistogram,
+    DynValueCounts(DataType, i64), // data type and hash seed
+    DynTDigest,
 }
 
 pub fn create_acc_from_initial_value(
@@ -374,9 +503,26 @@ pub fn create_acc_from_initial_value(
                 addrs.push(AccumStateValAddr::new_dyn(dyns.len()));
                 dyns.push(Some(Box::new(AggDynList::default())));
             }
-            AccumInitialValue::DynSet(_dt) => {
+            AccumInitialValue::DynSet(dt, hash_seed, max_slots) => {
+                addrs.push(AccumStateValAddr::new_dyn(dyns.len()));
+                let mut dyn_set = AggDynSet::with_hash_seed(*hash_seed)
+                    .with_hasher(AggDynSetHasher::for_type(dt));
+                dyn_set.set_max_slots(*max_slots);
+                dyns.push(Some(Box::new(dyn_set)));
+            }
+            AccumInitialValue::DynHistogram => {
+                addrs.push(AccumStateValAddr::new_dyn(dyns.len()));
+                dyns.push(Some(Box::new(AggDynHistogram::default())));
+            }
+            AccumInitialValue::DynValueCounts(_dt, hash_seed) => {
+                addrs.push(AccumStateValAddr::new_dyn(dyns.len()));
+                dyns.push(Some(Box::new(AggDynValueCounts::with_hash_seed(
+                    *hash_seed,
+                ))));
+            }
+            AccumInitialValue::DynTDigest => {
                 addrs.push(AccumStateValAddr::new_dyn(dyns.len()));
-                dyns.push(Some(Box::new(AggDynSet::default())));
+                dyns.push(Some(Box::new(AggDynTDigest::default())));
             }
         }
     }
@@ -463,41 +609,124 @@ pub fn create_dyn_loaders_from_initial_value(values: &[AccumInitialValue]) -> Re
                     }
                 })
             }),
-            AccumInitialValue::DynSet(_dt) => Box::new(move |r: &mut LoadReader| {
+            AccumInitialValue::DynSet(dt, hash_seed, max_slots) => {
+                let hasher = AggDynSetHasher::for_type(dt);
+                let hash_seed = *hash_seed;
+                let max_slots = *max_slots;
+                Box::new(move |r: &mut LoadReader| {
+                    Ok(match read_len(&mut r.0)? {
+                        0 => None,
+                        n => {
+                            let data_len = n - 1;
+                            let raw = read_bytes_slice(&mut r.0, data_len)?.into_vec();
+                            let num_items = read_len(&mut r.0)?;
+
+                            let uniform_len = match num_items {
+                                0 => 0,
+                                _ => read_len(&mut r.0)?,
+                            };
+
+                            let list = AggDynList { raw };
+                            let mut internal_set =
+                                if !InternalSet::small_exceeded(num_items, data_len) {
+                                    InternalSet::Small(SmallVec::new())
+                                } else {
+                                    InternalSet::Huge(RawTable::with_capacity(num_items))
+                                };
+
+                            let mut pos = 0;
+                            for _ in 0..num_items {
+                                let len = match uniform_len {
+                                    0 => read_len(&mut r.0)?,
+                                    n => n - 1,
+                                };
+                                let pos_len = (pos, len as u32);
+                                pos += pos_len.1;
+
+                                match &mut internal_set {
+                                    InternalSet::Small(s) => s.push(pos_len),
+                                    InternalSet::Huge(s) => {
+                                        let raw = list.ref_raw(pos_len);
+                                        let hash = hasher.hash(hash_seed, raw);
+                                        s.insert(hash, pos_len, |&pos_len| {
+                                            hasher.hash(hash_seed, list.ref_raw(pos_len))
+                                        });
+                                    }
+                                }
+                            }
+                            let mut dyn_set = AggDynSet {
+                                list,
+                                set: internal_set,
+                                hash_seed,
+                                hasher,
+                                last_seen: None,
+                                max_slots: 0,
+                                slots_exhausted: false,
+                            };
+                            dyn_set.set_max_slots(max_slots);
+                            Some(Box::new(dyn_set))
+                        }
+                    })
+                })
+            }
+            AccumInitialValue::DynHistogram => Box::new(move |r: &mut LoadReader| {
                 Ok(match read_len(&mut r.0)? {
                     0 => None,
                     n => {
-                        let data_len = n - 1;
-                        let raw = read_bytes_slice(&mut r.0, data_len)?.into_vec();
-                        let num_items = read_len(&mut r.0)?;
-
-                        let list = AggDynList { raw };
-                        let mut internal_set = if num_items <= 4 {
-                            InternalSet::Small(SmallVec::new())
-                        } else {
-                            InternalSet::Huge(RawTable::with_capacity(num_items))
-                        };
-
-                        let mut pos = 0;
-                        for _ in 0..num_items {
-                            let pos_len = (pos, read_len(&mut r.0)? as u32);
-                            pos += pos_len.1;
-
-                            match &mut internal_set {
-                                InternalSet::Small(s) => s.push(pos_len),
-                                InternalSet::Huge(s) => {
-                                    let raw = list.ref_raw(pos_len);
-                                    let hash = gx_hash::<AGG_DYN_SET_HASH_SEED>(raw);
-                                    s.insert(hash, pos_len, |&pos_len| {
-                                        gx_hash::<AGG_DYN_SET_HASH_SEED>(list.ref_raw(pos_len))
-                                    });
-                                }
+                        let mut bins = Vec::with_capacity(n - 1);
+                        let mut buf = [0u8; 8];
+                        for _ in 0..n - 1 {
+                            r.0.read_exact(&mut buf)?;
+                            let x = f64::from_le_bytes(buf);
+                            r.0.read_exact(&mut buf)?;
+                            let y = f64::from_le_bytes(buf);
+                            bins.push((x, y));
+                        }
+                        Some(Box::new(AggDynHistogram { bins }))
+                    }
+                })
+            }),
+            &AccumInitialValue::DynValueCounts(_, hash_seed) => {
+                Box::new(move |r: &mut LoadReader| {
+                    Ok(match read_len(&mut r.0)? {
+                        0 => None,
+                        n => {
+                            let mut value_counts = AggDynValueCounts::with_hash_seed(hash_seed);
+                            value_counts.entries.reserve(n - 1);
+                            value_counts.index.reserve(n - 1, |_| unreachable!());
+                            let mut buf = [0u8; 8];
+                            for _ in 0..n - 1 {
+                                let len = read_len(&mut r.0)?;
+                                let raw = read_bytes_slice(&mut r.0, len)?;
+                                r.0.read_exact(&mut buf)?;
+                                value_counts.add_raw(&raw, i64::from_le_bytes(buf));
                             }
+                            Some(Box::new(value_counts))
+                        }
+                    })
+                })
+            }
+            AccumInitialValue::DynTDigest => Box::new(move |r: &mut LoadReader| {
+                Ok(match read_len(&mut r.0)? {
+                    0 => None,
+                    n => {
+                        // centroids are loaded as buffered points and compressed
+                        // on the next update
+                        let mut buf = [0u8; 8];
+                        let mut read_f64 = || -> Result<f64> {
+                            r.0.read_exact(&mut buf)?;
+                            Ok(f64::from_le_bytes(buf))
+                        };
+                        let mut tdigest = AggDynTDigest::default();
+                        tdigest.min = read_f64()?;
+                        tdigest.max = read_f64()?;
+                        tdigest.buffer.reserve(n - 1);
+                        for _ in 0..n - 1 {
+                            let mean = read_f64()?;
+                            let weight = read_f64()?;
+                            tdigest.buffer.push((mean, weight));
                         }
-                        Some(Box::new(AggDynSet {
-                            list,
-                            set: internal_set,
-                        }))
+                        Some(Box::new(tdigest))
                     }
                 })
             }),
@@ -593,7 +822,7 @@ pub fn create_dyn_savers_from_initial_value(values: &[AccumInitialValue]) -> Res
                 let f: SaveFn = Box::new(f);
                 f
             }
-            AccumInitialValue::DynSet(_dt) => {
+            AccumInitialValue::DynSet(..) => {
                 let f: SaveFn = Box::new(move |w: &mut SaveWriter, v: DynVal| -> Result<()> {
                     if let Some(v) = v {
                         let mut set = v
@@ -603,13 +832,27 @@ pub fn create_dyn_savers_from_initial_value(values: &[AccumInitialValue]) -> Res
                         write_len(set.list.raw.len() + 1, &mut w.0)?;
                         w.0.write_all(&set.list.raw)?;
 
-                        write_len(set.set.len(), &mut w.0)?;
-                        for len in std::mem::take(&mut set.set)
+                        let lens = std::mem::take(&mut set.set)
                             .into_iter()
                             .sorted()
                             .map(|pos_len| pos_len.1)
-                        {
-                            write_len(len as usize, &mut w.0)?;
+                            .collect::<Vec<_>>();
+                        write_len(lens.len(), &mut w.0)?;
+
+                        // items of the same length (like primitive values) are
+                        // written with a single length, otherwise lengths of all
+                        // items are written after a zero marker
+                        match lens.first() {
+                            Some(&first) if lens.iter().all(|&len| len == first) => {
+                                write_len(first as usize + 1, &mut w.0)?;
+                            }
+                            Some(_) => {
+                                write_len(0, &mut w.0)?;
+                                for len in lens {
+                                    write_len(len as usize, &mut w.0)?;
+                                }
+                            }
+                            None => {}
                         }
                     } else {
                         write_len(0, &mut w.0)?;
@@ -618,6 +861,64 @@ pub fn create_dyn_savers_from_initial_value(values: &[AccumInitialValue]) -> Res
                 });
                 f
             }
+            AccumInitialValue::DynHistogram => {
+                fn f(w: &mut SaveWriter, v: DynVal) -> Result<()> {
+                    if let Some(v) = v {
+                        let histogram = downcast_any!(v, AggDynHistogram)?;
+                        write_len(histogram.bins.len() + 1, &mut w.0)?;
+                        for &(x, y) in &histogram.bins {
+                            w.0.write_all(&x.to_le_bytes())?;
+                            w.0.write_all(&y.to_le_bytes())?;
+                        }
+                    } else {
+                        write_len(0, &mut w.0)?;
+                    }
+                    Ok(())
+                }
+                let f: SaveFn = Box::new(f);
+                f
+            }
+            AccumInitialValue::DynValueCounts(..) => {
+                // values are written as (value, count) pairs
+                fn f(w: &mut SaveWriter, v: DynVal) -> Result<()> {
+                    if let Some(v) = v {
+                        let value_counts = downcast_any!(v, AggDynValueCounts)?;
+                        write_len(value_counts.entries.len() + 1, &mut w.0)?;
+                        for &(pos, len, count) in &value_counts.entries {
+                            write_len(len as usize, &mut w.0)?;
+                            w.0.write_all(value_counts.list.ref_raw((pos, len)))?;
+                            w.0.write_all(&count.to_le_bytes())?;
+                        }
+                    } else {
+                        write_len(0, &mut w.0)?;
+                    }
+                    Ok(())
+                }
+                let f: SaveFn = Box::new(f);
+                f
+            }
+            AccumInitialValue::DynTDigest => {
+                // min/max followed by (mean, weight) of centroids and buffered
+                // points
+                fn f(w: &mut SaveWriter, v: DynVal) -> Result<()> {
+                    if let Some(v) = v {
+                        let tdigest = downcast_any!(v, AggDynTDigest)?;
+                        let points = tdigest.centroids.iter().chain(&tdigest.buffer);
+                        write_len(tdigest.centroids.len() + tdigest.buffer.len() + 1, &mut w.0)?;
+                        w.0.write_all(&tdigest.min.to_le_bytes())?;
+                        w.0.write_all(&tdigest.max.to_le_bytes())?;
+                        for &(mean, weight) in points {
+                            w.0.write_all(&mean.to_le_bytes())?;
+                            w.0.write_all(&weight.to_le_bytes())?;
+                        }
+                    } else {
+                        write_len(0, &mut w.0)?;
+                    }
+                    Ok(())
+                }
+                let f: SaveFn = Box::new(f);
+                f
+            }
         };
         savers.push(saver);
     }
@@ -631,6 +932,9 @@ pub trait AggDynValue: Send + Sync {
     fn as_any_boxed(self: Box<Self>) -> Box<dyn Any>;
     fn mem_size(&self) -> usize;
     fn clone_boxed(&self) -> Box<dyn AggDynValue>;
+
+    /// releases over-allocated memory, no-op if the capacity is already tight
+    fn shrink_to_fit(&mut self) {}
 }
 
 #[derive(Clone, Eq, PartialEq)]
@@ -773,22 +1077,101 @@ pub struct AggDynList {
 }
 
 impl AggDynList {
-    pub fn append(&mut self, value: &ScalarValue, nullable: bool) {
-        write_scalar(&value, nullable, &mut self.raw).unwrap();
+    /// appends a value, the list is unchanged if the value cannot be
+    /// serialized
+    pub fn append(&mut self, value: &ScalarValue, nullable: bool) -> Result<()> {
+        let raw_start = self.raw.len();
+        if let Err(err) = write_scalar(value, nullable, &mut self.raw) {
+            self.raw.truncate(raw_start);
+            return Err(err);
+        }
+        Ok(())
+    }
+
+    /// like `append()` with the value from `ScalarValue::try_from_array()`,
+    /// but primitive and string values are appended without scalars
+    pub fn append_array_value(
+        &mut self,
+        array: &ArrayRef,
+        idx: usize,
+        nullable: bool,
+    ) -> Result<()> {
+        write_array_value(array, idx, nullable, &mut self.raw)
     }
 
     pub fn merge(&mut self, other: &mut Self) {
         self.raw.extend(std::mem::take(&mut other.raw));
     }
 
-    pub fn into_values(self, dt: DataType, nullable: bool) -> impl Iterator<Item = ScalarValue> {
+    /// removes all values, the allocated buffer is retained for reuse
+    pub fn clear(&mut self) {
+        self.raw.clear();
+    }
+
+    /// merges only the first `num_items` values of other list, the rest values
+    /// are discarded
+    pub fn merge_prefix(
+        &mut self,
+        other: &mut Self,
+        num_items: usize,
+        dt: &DataType,
+        nullable: bool,
+    ) -> Result<()> {
+        let other_raw = std::mem::take(&mut other.raw);
+        let mut cursor = Cursor::new(&other_raw);
+        for _ in 0..num_items {
+            if cursor.position() >= other_raw.len() as u64 {
+                break;
+            }
+            read_scalar(&mut cursor, dt, nullable)?;
+        }
+        let prefix_len = cursor.position() as usize;
+        self.raw.extend_from_slice(&other_raw[..prefix_len]);
+        Ok(())
+    }
+
+    /// merges other list whose values are in `other_dt`, the values are
+    /// re-encoded into `dt` if the data types are different (like Utf8 and
+    /// LargeUtf8), otherwise the raw bytes are directly concatenated
+    pub fn merge_with_type(
+        &mut self,
+        other: &mut Self,
+        dt: &DataType,
+        other_dt: &DataType,
+        nullable: bool,
+    ) -> Result<()> {
+        if dt == other_dt {
+            self.merge(other);
+            return Ok(());
+        }
+        let other_raw = std::mem::take(&mut other.raw);
+        let mut cursor = Cursor::new(&other_raw);
+        while cursor.position() < other_raw.len() as u64 {
+            let value = read_scalar(&mut cursor, other_dt, nullable)?.cast_to(dt)?;
+            write_scalar(&value, nullable, &mut self.raw)?;
+        }
+        Ok(())
+    }
+
+    /// iterates deserialized values, the iteration stops after the first
+    /// error
+    pub fn into_values(
+        self,
+        dt: DataType,
+        nullable: bool,
+    ) -> impl Iterator<Item = Result<ScalarValue>> {
         struct ValuesIterator(Cursor<Vec<u8>>, DataType, bool);
         impl Iterator for ValuesIterator {
-            type Item = ScalarValue;
+            type Item = Result<ScalarValue>;
 
             fn next(&mut self) -> Option<Self::Item> {
                 if self.0.position() < self.0.get_ref().len() as u64 {
-                    return Some(read_scalar(&mut self.0, &self.1, self.2).unwrap());
+                    let value = read_scalar(&mut self.0, &self.1, self.2);
+                    if value.is_err() {
+                        // skips the remaining bytes which cannot be decoded
+                        self.0.set_position(self.0.get_ref().len() as u64);
+                    }
+                    return Some(value);
                 }
                 None
             }
@@ -796,6 +1179,107 @@ impl AggDynList {
         ValuesIterator(Cursor::new(self.raw), dt, nullable)
     }
 
+    /// converts values into an array. primitive, string and binary values are
+    /// decoded from the raw bytes directly, other types fall back to building
+    /// from scalar values
+    pub fn into_array(self, dt: &DataType, nullable: bool) -> Result<ArrayRef> {
+        let raw = self.raw.as_slice();
+        let mut cursor = Cursor::new(raw);
+        let has_next = |cursor: &Cursor<&[u8]>| cursor.position() < raw.len() as u64;
+
+        macro_rules! prim {
+            ($arrowty:ty, $nativety:ty) => {{
+                let mut builder = PrimitiveBuilder::<$arrowty>::new();
+                while has_next(&cursor) {
+                    if nullable && read_u8(&mut cursor)? == 0 {
+                        builder.append_null();
+                        continue;
+                    }
+                    let mut buf = [0u8; size_of::<$nativety>()];
+                    cursor.read_exact(&mut buf)?;
+                    builder.append_value(<$nativety>::from_ne_bytes(buf));
+                }
+                Arc::new(builder.finish().with_data_type(dt.clone()))
+            }};
+        }
+        macro_rules! bytes {
+            ($builder:expr, $convert:expr) => {{
+                let mut builder = $builder;
+                while has_next(&cursor) {
+                    match read_len(&mut cursor)? {
+                        0 => builder.append_null(),
+                        n => {
+                            let pos = cursor.position() as usize;
+                            if pos + n - 1 > raw.len() {
+                                return df_execution_err!(
+                                    "AggDynList value out of bounds: {}+{} > {}",
+                                    pos,
+                                    n - 1,
+                                    raw.len()
+                                );
+                            }
+                            builder.append_value($convert(&raw[pos..][..n - 1]));
+                            cursor.set_position((pos + n - 1) as u64);
+                        }
+                    }
+                }
+                Arc::new(builder.finish())
+            }};
+        }
+
+        let array: ArrayRef = match dt {
+            DataType::Boolean => {
+                let mut builder = BooleanBuilder::new();
+                while has_next(&cursor) {
+                    if nullable && read_u8(&mut cursor)? == 0 {
+                        builder.append_null();
+                        continue;
+                    }
+                    builder.append_value(read_u8(&mut cursor)? != 0);
+                }
+                Arc::new(builder.finish())
+            }
+            DataType::Int8 => prim!(Int8Type, i8),
+            DataType::Int16 => prim!(Int16Type, i16),
+            DataType::Int32 => prim!(Int32Type, i32),
+            DataType::Int64 => prim!(Int64Type, i64),
+            DataType::UInt8 => prim!(UInt8Type, u8),
+            DataType::UInt16 => prim!(UInt16Type, u16),
+            DataType::UInt32 => prim!(UInt32Type, u32),
+            DataType::UInt64 => prim!(UInt64Type, u64),
+            DataType::Float32 => prim!(Float32Type, f32),
+            DataType::Float64 => prim!(Float64Type, f64),
+            DataType::Decimal128(..) => prim!(Decimal128Type, i128),
+            DataType::Date32 => prim!(Date32Type, i32),
+            DataType::Date64 => prim!(Date64Type, i64),
+            DataType::Timestamp(TimeUnit::Second, _) => prim!(TimestampSecondType, i64),
+            DataType::Timestamp(TimeUnit::Millisecond, _) => prim!(TimestampMillisecondType, i64),
+            DataType::Timestamp(TimeUnit::Microsecond, _) => prim!(TimestampMicrosecondType, i64),
+            DataType::Timestamp(TimeUnit::Nanosecond, _) => prim!(TimestampNanosecondType, i64),
+            DataType::Interval(IntervalUnit::YearMonth) => prim!(IntervalYearMonthType, i32),
+            DataType::Interval(IntervalUnit::DayTime) => prim!(IntervalDayTimeType, i64),
+            DataType::Interval(IntervalUnit::MonthDayNano) => {
+                prim!(IntervalMonthDayNanoType, i128)
+            }
+            DataType::Duration(TimeUnit::Second) => prim!(DurationSecondType, i64),
+            DataType::Duration(TimeUnit::Millisecond) => prim!(DurationMillisecondType, i64),
+            DataType::Duration(TimeUnit::Microsecond) => prim!(DurationMicrosecondType, i64),
+            DataType::Duration(TimeUnit::Nanosecond) => prim!(DurationNanosecondType, i64),
+            DataType::Utf8 => bytes!(StringBuilder::new(), String::from_utf8_lossy),
+            DataType::Binary => bytes!(BinaryBuilder::new(), std::convert::identity),
+            _ => {
+                let values = self
+                    .into_values(dt.clone(), nullable)
+                    .collect::<Result<Vec<_>>>()?;
+                if values.is_empty() {
+                    return Ok(new_empty_array(dt));
+                }
+                ScalarValue::iter_to_array(values)?
+            }
+        };
+        Ok(array)
+    }
+
     fn ref_raw(&self, pos_len: (u32, u32)) -> &[u8] {
         &self.raw[pos_len.0 as usize..][..pos_len.1 as usize]
     }
@@ -821,17 +1305,51 @@ impl AggDynValue for AggDynList {
     fn clone_boxed(&self) -> Box<dyn AggDynValue> {
         Box::new(self.clone())
     }
+
+    fn shrink_to_fit(&mut self) {
+        if self.raw.capacity() > self.raw.len() {
+            self.raw.shrink_to_fit();
+        }
+    }
 }
 
-#[derive(Clone, Default)]
+#[derive(Clone)]
 pub struct AggDynSet {
     list: AggDynList,
     set: InternalSet,
+    hash_seed: i64,
+    hasher: AggDynSetHasher,
+
+    /// the last appended or found value, back-to-back duplicates (common in
+    /// sorted or clustered input) are rejected without probing the set
+    last_seen: Option<(u32, u32)>,
+
+    /// max number of slots of the hash table, 0 for unlimited. once the table
+    /// is full and growing it would exceed the limit, new values are rejected
+    /// and `slots_exhausted` is set instead of reallocating the table. the
+    /// flag is kept by merging, since the merged set also misses values
+    max_slots: u32,
+    slots_exhausted: bool,
 }
 
+impl Default for AggDynSet {
+    fn default() -> Self {
+        Self::with_hash_seed(AGG_DYN_SET_HASH_SEED)
+    }
+}
+
+/// max number of values kept in the linear-scanned small set, which is also
+/// the inline capacity so the small set never allocates
+const SMALL_SET_CAPACITY: usize = 4;
+
+/// max total bytes of values kept in the small set. every probe compares
+/// against all existing values, so long values (like strings) are promoted
+/// to the hash table earlier even if the set has only a few values
+const SMALL_SET_MAX_SCAN_BYTES: usize = 256;
+
 #[derive(Clone)]
 enum InternalSet {
-    Small(SmallVec<[(u32, u32); 4]>),
+    Small(SmallVec<[(u32, u32); SMALL_SET_CAPACITY]>),
     Huge(RawTable<(u32, u32)>),
 }
 
@@ -849,6 +1367,13 @@ impl InternalSet {
         }
     }
 
+    fn clear(&mut self) {
+        match self {
+            InternalSet::Small(s) => s.clear(),
+            InternalSet::Huge(s) => s.clear(),
+        }
+    }
+
     fn into_iter(self) -> impl Iterator<Item = (u32, u32)> {
         let iter: Box<dyn Iterator<Item = (u32, u32)>> = match self {
             InternalSet::Small(s) => Box::new(s.into_iter()),
@@ -857,15 +1382,38 @@ impl InternalSet {
         iter
     }
 
-    fn convert_to_huge_if_needed(&mut self, list: &mut AggDynList) {
+    /// whether a small set with the given number of values and total bytes
+    /// should be promoted to a hash table
+    fn small_exceeded(num_items: usize, raw_len: usize) -> bool {
+        num_items > SMALL_SET_CAPACITY || raw_len > SMALL_SET_MAX_SCAN_BYTES
+    }
+
+    /// returns whether inserting a new value into the full hash table would
+    /// grow it beyond `max_slots` slots, 0 for unlimited
+    fn huge_growth_exceeded(s: &RawTable<(u32, u32)>, max_slots: u32) -> bool {
+        max_slots > 0 && s.len() == s.capacity() && s.buckets() * 2 > max_slots as usize
+    }
+
+    /// promotes a small set to a hash table once it grows beyond the
+    /// thresholds. only the index is rebuilt, values in the list are
+    /// untouched so the insertion order is kept
+    fn convert_to_huge_if_needed(
+        &mut self,
+        list: &mut AggDynList,
+        hasher: AggDynSetHasher,
+        hash_seed: i64,
+    ) {
         if let Self::Small(s) = self {
-            let mut huge = RawTable::default();
+            if !Self::small_exceeded(s.len(), list.raw.len()) {
+                return;
+            }
+            let mut huge = RawTable::with_capacity(s.len());
 
             for &mut pos_len in s {
                 let raw = list.ref_raw(pos_len);
-                let hash = gx_hash::<AGG_DYN_SET_HASH_SEED>(raw);
+                let hash = hasher.hash(hash_seed, raw);
                 huge.insert(hash, pos_len, |&pos_len| {
-                    gx_hash::<AGG_DYN_SET_HASH_SEED>(list.ref_raw(pos_len))
+                    hasher.hash(hash_seed, list.ref_raw(pos_len))
                 });
             }
             *self = Self::Huge(huge);
@@ -873,62 +1421,500 @@ impl InternalSet {
     }
 }
 
-const AGG_DYN_SET_HASH_SEED: i64 = 0x7BCB48DA4C72B4F2;
+/// normalizes floating-point values in the array and all its nested lists and
+/// structs like `write_normalized_scalar()`, so that nested values are also
+/// deduplicated by typed equality. arrays without floats are returned as is
+pub fn normalize_nested_floats(array: &ArrayRef) -> Result<ArrayRef> {
+    Ok(match array.data_type() {
+        DataType::Float32 => Arc::new(array.as_primitive::<Float32Type>().unary::<_, Float32Type>(
+            |v| match v {
+                v if v.is_nan() => f32::NAN,
+                v if v == 0.0 => 0.0,
+                v => v,
+            },
+        )),
+        DataType::Float64 => Arc::new(array.as_primitive::<Float64Type>().unary::<_, Float64Type>(
+            |v| match v {
+                v if v.is_nan() => f64::NAN,
+                v if v == 0.0 => 0.0,
+                v => v,
+            },
+        )),
+        DataType::List(field) => {
+            let list = array.as_list::<i32>();
+            Arc::new(ListArray::try_new(
+                field.clone(),
+                list.offsets().clone(),
+                normalize_nested_floats(list.values())?,
+                list.nulls().cloned(),
+            )?)
+        }
+        DataType::LargeList(field) => {
+            let list = array.as_list::<i64>();
+            Arc::new(LargeListArray::try_new(
+                field.clone(),
+                list.offsets().clone(),
+                normalize_nested_floats(list.values())?,
+                list.nulls().cloned(),
+            )?)
+        }
+        DataType::Struct(fields) => {
+            let struct_array = array.as_struct();
+            Arc::new(StructArray::try_new(
+                fields.clone(),
+                struct_array
+                    .columns()
+                    .iter()
+                    .map(normalize_nested_floats)
+                    .collect::<Result<_>>()?,
+                struct_array.nulls().cloned(),
+            )?)
+        }
+        _ => array.clone(),
+    })
+}
+
+/// serializes a value like `write_scalar()`, but all NaNs are written as the
+/// canonical NaN and -0.0 is written as 0.0, following spark's semantics that
+/// all NaNs are equal and -0.0 equals 0.0. values deduplicated by serialized
+/// bytes must be written with this function. nested values are not normalized
+pub fn write_normalized_scalar<W: Write>(
+    value: &ScalarValue,
+    nullable: bool,
+    output: &mut W,
+) -> Result<()> {
+    match value {
+        &ScalarValue::Float32(Some(v)) if v.is_nan() || v == 0.0 => {
+            let v = if v.is_nan() { f32::NAN } else { 0.0 };
+            write_scalar(&ScalarValue::Float32(Some(v)), nullable, output)
+        }
+        &ScalarValue::Float64(Some(v)) if v.is_nan() || v == 0.0 => {
+            let v = if v.is_nan() { f64::NAN } else { 0.0 };
+            write_scalar(&ScalarValue::Float64(Some(v)), nullable, output)
+        }
+        value => write_scalar(value, nullable, output),
+    }
+}
+
+/// allocated size of a RawTable, which has one slot and one control byte for
+/// each bucket, plus trailing control bytes for group probing. unused buckets
+/// due to the load factor are counted
+fn raw_table_mem_size<T>(table: &RawTable<T>) -> usize {
+    const MAX_GROUP_WIDTH: usize = 16;
+    table.buckets() * (size_of::<T>() + 1) + MAX_GROUP_WIDTH
+}
+
+/// hasher of serialized values of AggDynSet, chosen by the value type when
+/// the aggregate is built
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum AggDynSetHasher {
+    /// gxhash of the serialized bytes, for any type
+    #[default]
+    Bytes,
+
+    /// cheap multiply-fold mix, for types whose non-null values are serialized
+    /// into 4 bytes (like Int32 and Date32)
+    Fixed4,
+
+    /// cheap multiply-fold mix, for types whose non-null values are serialized
+    /// into 8 bytes (like Int64 and Timestamp)
+    Fixed8,
+}
+
+impl AggDynSetHasher {
+    pub fn for_type(dt: &DataType) -> Self {
+        match dt.primitive_width() {
+            Some(4) => Self::Fixed4,
+            Some(8) => Self::Fixed8,
+            _ => Self::Bytes,
+        }
+    }
+
+    /// hashes a serialized value. values of unexpected width (like nulls
+    /// serialized with a null flag) fall back to gxhash, which only depends on
+    /// the bytes, so equal values always get equal hashes
+    #[inline]
+    pub fn hash(self, hash_seed: i64, raw: impl AsRef<[u8]>) -> u64 {
+        let raw = raw.as_ref();
+        match self {
+            Self::Fixed4 if raw.len() == 4 => mix_u64(
+                hash_seed,
+                u32::from_ne_bytes(raw.try_into().unwrap()) as u64,
+            ),
+            Self::Fixed8 if raw.len() == 8 => {
+                mix_u64(hash_seed, u64::from_ne_bytes(raw.try_into().unwrap()))
+            }
+            _ => gx_hash_with_seed(hash_seed, raw),
+        }
+    }
+}
+
+#[inline]
+fn mix_u64(hash_seed: i64, v: u64) -> u64 {
+    const K: u128 = 0x9E37_79B9_7F4A_7C15;
+    let m = ((v ^ hash_seed as u64) as u128).wrapping_mul(K);
+    (m as u64) ^ ((m >> 64) as u64)
+}
+
+pub const AGG_DYN_SET_HASH_SEED: i64 = 0x7BCB48DA4C72B4F2;
+
+/// derives an independent hash seed for AggDynSet, so that different
+/// aggregation operators (like the ones in different stages) hashing the same
+/// data do not share collision patterns
+pub fn derive_agg_dyn_set_hash_seed(id: u64) -> i64 {
+    if id == 0 {
+        return AGG_DYN_SET_HASH_SEED;
+    }
+    gx_hash_with_seed(AGG_DYN_SET_HASH_SEED, id.to_ne_bytes()) as i64
+}
 
 impl AggDynSet {
-    pub fn append(&mut self, value: &ScalarValue, nullable: bool) {
+    pub fn with_hash_seed(hash_seed: i64) -> Self {
+        Self {
+            list: AggDynList::default(),
+            set: InternalSet::default(),
+            hash_seed,
+            hasher: AggDynSetHasher::default(),
+            last_seen: None,
+            max_slots: 0,
+            slots_exhausted: false,
+        }
+    }
+
+    /// sets the hasher of values, which must be set before any value is
+    /// appended
+    pub fn with_hasher(mut self, hasher: AggDynSetHasher) -> Self {
+        debug_assert!(self.is_empty(), "setting hasher of a non-empty AggDynSet");
+        self.hasher = hasher;
+        self
+    }
+
+    /// creates a set for about `capacity` values, a set expected to be large
+    /// starts directly as a hash table, skipping promotion and rehashing
+    pub fn with_capacity(hash_seed: i64, capacity: usize) -> Self {
+        let mut dyn_set = Self::with_hash_seed(hash_seed);
+        if capacity > SMALL_SET_CAPACITY {
+            dyn_set.set = InternalSet::Huge(RawTable::with_capacity(capacity));
+        }
+        dyn_set
+    }
+
+    pub fn len(&self) -> usize {
+        self.set.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.set.len() == 0
+    }
+
+    /// returns whether the set has been promoted from a small set to a hash
+    /// table
+    pub fn is_huge(&self) -> bool {
+        matches!(self.set, InternalSet::Huge(_))
+    }
+
+    /// removes all values, allocations of the list and hash table are retained
+    /// for reuse, so `mem_size()` is kept at the retained capacity
+    pub fn clear(&mut self) {
+        self.list.clear();
+        self.set.clear();
+        self.last_seen = None;
+        self.slots_exhausted = false;
+    }
+
+    /// limits the number of slots of the hash table, 0 for unlimited. the
+    /// limit is not part of the serialized set, loaders and initial values
+    /// created from `AccumInitialValue::DynSet` restore it
+    pub fn set_max_slots(&mut self, max_slots: usize) {
+        self.max_slots = max_slots.min(u32::MAX as usize) as u32;
+    }
+
+    /// returns whether some values were rejected because growing the hash
+    /// table would exceed the max number of slots
+    pub fn slots_exhausted(&self) -> bool {
+        self.slots_exhausted
+    }
+
+    /// appends a value, floating-point values are normalized with
+    /// `write_normalized_scalar()` so that they are deduplicated like spark
+    pub fn append(&mut self, value: &ScalarValue, nullable: bool) -> Result<()> {
         let old_raw_len = self.list.raw.len();
-        write_scalar(value, nullable, &mut self.list.raw).unwrap();
+        if let Err(err) = write_normalized_scalar(value, nullable, &mut self.list.raw) {
+            self.list.raw.truncate(old_raw_len);
+            return Err(err);
+        }
         self.append_raw_inline(old_raw_len);
+        Ok(())
     }
 
     pub fn merge(&mut self, other: &mut Self) {
         if self.set.len() < other.set.len() {
-            // ensure the probed set is smaller
+            // ensure the probed set is smaller, the slot limit is kept
             std::mem::swap(self, other);
+            std::mem::swap(&mut self.max_slots, &mut other.max_slots);
         }
         for pos_len in std::mem::take(&mut other.set).into_iter() {
             self.append_raw(other.list.ref_raw(pos_len));
         }
+        self.slots_exhausted |= other.slots_exhausted;
+    }
+
+    /// merges many sets at once, like calling `merge()` (or `merge_ordered()`
+    /// if `ordered` is true) for each of them in order, but space for all
+    /// values is reserved once instead of growing the union repeatedly
+    pub fn merge_many(&mut self, others: &mut [&mut Self], ordered: bool) {
+        if !ordered {
+            // ensure the probed sets are smaller
+            if let Some(largest) = others.iter_mut().max_by_key(|other| other.set.len()) {
+                if self.set.len() < largest.set.len() {
+                    std::mem::swap(self, *largest);
+                    std::mem::swap(&mut self.max_slots, &mut largest.max_slots);
+                }
+            }
+        }
+        let additional = others.iter().map(|other| other.set.len()).sum();
+        let additional_bytes = others.iter().map(|other| other.list.raw.len()).sum();
+        self.reserve(additional, additional_bytes);
+
+        for other in others {
+            let pos_lens = std::mem::take(&mut other.set).into_iter();
+            if ordered {
+                for pos_len in pos_lens.sorted() {
+                    self.append_raw(other.list.ref_raw(pos_len));
+                }
+            } else {
+                for pos_len in pos_lens {
+                    self.append_raw(other.list.ref_raw(pos_len));
+                }
+            }
+            self.slots_exhausted |= other.slots_exhausted;
+        }
+    }
+
+    /// merges other set whose values are in `other_dt`, the values are
+    /// re-encoded into `dt` if the data types are different
+    pub fn merge_with_type(
+        &mut self,
+        other: &mut Self,
+        dt: &DataType,
+        other_dt: &DataType,
+        nullable: bool,
+    ) -> Result<()> {
+        if dt == other_dt {
+            self.merge(other);
+            return Ok(());
+        }
+        for value in std::mem::take(other).into_values(other_dt.clone(), nullable) {
+            self.append(&value?.cast_to(dt)?, nullable)?;
+        }
+        Ok(())
+    }
+
+    /// like merge(), but keeps the first-seen order of values, values of self
+    /// always go before the new values from other
+    pub fn merge_ordered(&mut self, other: &mut Self) {
+        for pos_len in std::mem::take(&mut other.set).into_iter().sorted() {
+            self.append_raw(other.list.ref_raw(pos_len));
+        }
+        self.slots_exhausted |= other.slots_exhausted;
     }
 
-    pub fn into_values(self, dt: DataType, nullable: bool) -> impl Iterator<Item = ScalarValue> {
+    /// values are returned in insertion order, since the underlying list is
+    /// only appended with distinct values
+    pub fn into_values(
+        self,
+        dt: DataType,
+        nullable: bool,
+    ) -> impl Iterator<Item = Result<ScalarValue>> {
         self.list.into_values(dt, nullable)
     }
 
-    fn append_raw(&mut self, raw: &[u8]) {
-        let new_len = raw.len();
-        let new_pos_len = (self.list.raw.len() as u32, new_len as u32);
+    /// see `AggDynList::into_array()`, values are in insertion order
+    pub fn into_array(self, dt: &DataType, nullable: bool) -> Result<ArrayRef> {
+        self.list.into_array(dt, nullable)
+    }
 
-        match &mut self.set {
+    /// appends a value which is already serialized with `write_scalar()`
+    pub fn append_raw(&mut self, raw: &[u8]) {
+        self.append_raw_with_hash_opt(raw, None);
+    }
+
+    /// like `append_raw()`, with the hash of `raw` precomputed in the same way
+    /// as `self.hasher().hash(self.hash_seed(), raw)`, so the value is not
+    /// rehashed. a wrong hash breaks deduplication, so callers taking hashes
+    /// from outside should verify them, like
+    /// `AggCollectSet::partial_batch_update_with_hashes()`
+    pub fn append_raw_with_hash(&mut self, raw: &[u8], hash: u64) {
+        debug_assert_eq!(
+            hash,
+            self.hasher.hash(self.hash_seed, raw),
+            "precomputed hash does not match the hasher of AggDynSet",
+        );
+        self.append_raw_with_hash_opt(raw, Some(hash));
+    }
+
+    pub fn hash_seed(&self) -> i64 {
+        self.hash_seed
+    }
+
+    pub fn hasher(&self) -> AggDynSetHasher {
+        self.hasher
+    }
+
+    /// returns whether the set contains a value which is already serialized
+    /// with `write_normalized_scalar()`
+    pub fn contains_raw(&self, raw: &[u8]) -> bool {
+        match &self.set {
+            InternalSet::Small(s) => s.iter().any(|&pos_len| self.list.ref_raw(pos_len) == raw),
+            InternalSet::Huge(s) => {
+                let hash = self.hasher.hash(self.hash_seed, raw);
+                s.get(hash, |&pos_len| {
+                    raw.len() == pos_len.1 as usize && raw == self.list.ref_raw(pos_len)
+                })
+                .is_some()
+            }
+        }
+    }
+
+    /// consumes the set and visits each value serialized with
+    /// `write_normalized_scalar()`, in no particular order
+    pub fn for_each_raw(self, mut f: impl FnMut(&[u8])) {
+        let Self { list, set, .. } = self;
+        for pos_len in set.into_iter() {
+            f(list.ref_raw(pos_len));
+        }
+    }
+
+    /// consumes the set and returns the values sorted by their serialized
+    /// bytes, along with the insertion index of each sorted value. the byte
+    /// order is a total order independent of hashing, but may differ from
+    /// the logical order of the type (like negative numbers and nested values)
+    pub fn into_raw_sorted(self) -> (AggDynList, Vec<u32>) {
+        let Self { list, set, .. } = self;
+        let pos_lens = set.into_iter().sorted().collect::<Vec<_>>();
+        let mut indices = (0..pos_lens.len() as u32).collect::<Vec<_>>();
+        indices.sort_unstable_by_key(|&i| list.ref_raw(pos_lens[i as usize]));
+
+        let mut sorted = AggDynList {
+            raw: Vec::with_capacity(list.raw.len()),
+        };
+        for &i in &indices {
+            sorted
+                .raw
+                .extend_from_slice(list.ref_raw(pos_lens[i as usize]));
+        }
+        (sorted, indices)
+    }
+
+    /// retains only values also contained in `other`, like array_intersect(),
+    /// the insertion order of retained values is kept
+    pub fn intersect(&mut self, other: &AggDynSet) {
+        let mut intersected = AggDynSet::with_hash_seed(self.hash_seed).with_hasher(self.hasher);
+        for pos_len in std::mem::take(&mut self.set).into_iter().sorted() {
+            let raw = self.list.ref_raw(pos_len);
+            if other.contains_raw(raw) {
+                intersected.append_raw(raw);
+            }
+        }
+        *self = intersected;
+    }
+
+    /// reserves space for about `additional` values of `additional_bytes`
+    /// serialized bytes. a small set expected to exceed the thresholds is
+    /// promoted directly with the reserved capacity
+    fn reserve(&mut self, additional: usize, additional_bytes: usize) {
+        self.list.raw.reserve(additional_bytes);
+        let hash_seed = self.hash_seed;
+        let hasher = self.hasher;
+        let list = &self.list;
+
+        // never reserve beyond the max number of slots, values exceeding the
+        // limit are rejected one by one on inserting
+        let reserved = self.set.len() + additional;
+        if self.max_slots > 0 && reserved.saturating_mul(8) / 7 > self.max_slots as usize {
+            return;
+        }
+        let promoted = match &mut self.set {
             InternalSet::Small(s) => {
-                let mut found = false;
+                if !InternalSet::small_exceeded(
+                    s.len() + additional,
+                    list.raw.len() + additional_bytes,
+                ) {
+                    return;
+                }
+                let mut huge = RawTable::with_capacity(s.len() + additional);
+                for &mut pos_len in s {
+                    let hash = hasher.hash(hash_seed, list.ref_raw(pos_len));
+                    huge.insert(hash, pos_len, |&pos_len| {
+                        hasher.hash(hash_seed, list.ref_raw(pos_len))
+                    });
+                }
+                huge
+            }
+            InternalSet::Huge(s) => {
+                s.reserve(additional, |&pos_len| {
+                    hasher.hash(hash_seed, list.ref_raw(pos_len))
+                });
+                return;
+            }
+        };
+        self.set = InternalSet::Huge(promoted);
+    }
+
+    fn append_raw_with_hash_opt(&mut self, raw: &[u8], hash: Option<u64>) {
+        let new_len = raw.len();
+        let new_pos_len = (self.list.raw.len() as u32, new_len as u32);
+        if self.is_last_seen(raw) {
+            return;
+        }
+
+        match &mut self.set {
+            InternalSet::Small(s) => {
+                let mut found = None;
                 for &mut pos_len in &mut *s {
                     if self.list.ref_raw(pos_len) == raw {
-                        found = true;
+                        found = Some(pos_len);
                         break;
                     }
                 }
-                if !found {
+                self.last_seen = Some(found.unwrap_or(new_pos_len));
+                if found.is_none() {
                     s.push(new_pos_len);
                     self.list.raw.extend(raw);
-                    self.set.convert_to_huge_if_needed(&mut self.list);
+                    self.set
+                        .convert_to_huge_if_needed(&mut self.list, self.hasher, self.hash_seed);
                 }
             }
             InternalSet::Huge(s) => {
-                let hash = gx_hash::<AGG_DYN_SET_HASH_SEED>(raw);
+                let hash = hash.unwrap_or_else(|| self.hasher.hash(self.hash_seed, raw));
+
+                // the table is full and inserting would reallocate it beyond the
+                // limit, only probe for an existing value
+                if InternalSet::huge_growth_exceeded(s, self.max_slots) {
+                    match s.get(hash, |&pos_len| {
+                        new_len == pos_len.1 as usize && raw == self.list.ref_raw(pos_len)
+                    }) {
+                        Some(&found) => self.last_seen = Some(found),
+                        None => self.slots_exhausted = true,
+                    }
+                    return;
+                }
                 match s.find_or_find_insert_slot(
                     hash,
                     |&pos_len| new_len == pos_len.1 as usize && raw == self.list.ref_raw(pos_len),
-                    |&pos_len| gx_hash::<AGG_DYN_SET_HASH_SEED>(self.list.ref_raw(pos_len)),
+                    |&pos_len| self.hasher.hash(self.hash_seed, self.list.ref_raw(pos_len)),
                 ) {
-                    Ok(_found) => {}
+                    Ok(found) => {
+                        // safety: the bucket is just found in the table
+                        self.last_seen = Some(unsafe { *found.as_ref() });
+                    }
                     Err(slot) => {
                         unsafe {
                             // safety: call unsafe `insert_in_slot` method
                             self.list.raw.extend(raw);
                             s.insert_in_slot(hash, slot, new_pos_len);
                         }
+                        self.last_seen = Some(new_pos_len);
                     }
                 }
             }
@@ -938,33 +1924,54 @@ impl AggDynSet {
     fn append_raw_inline(&mut self, raw_start: usize) {
         let new_len = self.list.raw.len() - raw_start;
         let new_pos_len = (raw_start as u32, new_len as u32);
+        if self.is_last_seen(self.list.ref_raw(new_pos_len)) {
+            self.list.raw.truncate(raw_start);
+            return;
+        }
         let mut inserted = true;
+        let mut found_pos_len = new_pos_len;
 
         match &mut self.set {
             InternalSet::Small(s) => {
                 for &mut pos_len in &mut *s {
                     if self.list.ref_raw(pos_len) == self.list.ref_raw(new_pos_len) {
                         inserted = false;
+                        found_pos_len = pos_len;
                         break;
                     }
                 }
                 if inserted {
                     s.push(new_pos_len);
-                    self.set.convert_to_huge_if_needed(&mut self.list);
+                    self.set
+                        .convert_to_huge_if_needed(&mut self.list, self.hasher, self.hash_seed);
                 }
             }
             InternalSet::Huge(s) => {
                 let new_value = self.list.ref_raw(new_pos_len);
-                let hash = gx_hash::<AGG_DYN_SET_HASH_SEED>(new_value);
+                let hash = self.hasher.hash(self.hash_seed, new_value);
+
+                // see `append_raw_with_hash_opt()`
+                if InternalSet::huge_growth_exceeded(s, self.max_slots) {
+                    match s.get(hash, |&pos_len| {
+                        new_len == pos_len.1 as usize && new_value == self.list.ref_raw(pos_len)
+                    }) {
+                        Some(&found) => self.last_seen = Some(found),
+                        None => self.slots_exhausted = true,
+                    }
+                    self.list.raw.truncate(raw_start);
+                    return;
+                }
                 match s.find_or_find_insert_slot(
                     hash,
                     |&pos_len| {
                         new_len == pos_len.1 as usize && new_value == self.list.ref_raw(pos_len)
                     },
-                    |&pos_len| gx_hash::<AGG_DYN_SET_HASH_SEED>(self.list.ref_raw(pos_len)),
+                    |&pos_len| self.hasher.hash(self.hash_seed, self.list.ref_raw(pos_len)),
                 ) {
-                    Ok(_found) => {
+                    Ok(found) => {
                         inserted = false;
+                        // safety: the bucket is just found in the table
+                        found_pos_len = unsafe { *found.as_ref() };
                     }
                     Err(slot) => {
                         unsafe {
@@ -980,6 +1987,14 @@ impl AggDynSet {
         if !inserted {
             self.list.raw.truncate(raw_start);
         }
+        self.last_seen = Some(found_pos_len);
+    }
+
+    #[inline]
+    fn is_last_seen(&self, raw: &[u8]) -> bool {
+        self.last_seen
+            .map(|pos_len| pos_len.1 as usize == raw.len() && self.list.ref_raw(pos_len) == raw)
+            .unwrap_or(false)
     }
 }
 
@@ -1001,13 +2016,437 @@ impl AggDynValue for AggDynSet {
             + self.list.raw.capacity()
             + match &self.set {
                 InternalSet::Small(_) => 0,
-                InternalSet::Huge(s) => s.capacity() * size_of::<(u32, u32, u8)>(),
+                InternalSet::Huge(s) => raw_table_mem_size(s),
+            }
+    }
+
+    fn clone_boxed(&self) -> Box<dyn AggDynValue> {
+        Box::new(self.clone())
+    }
+
+    fn shrink_to_fit(&mut self) {
+        // the internal set is not shrunk since it requires rehashing
+        self.list.shrink_to_fit();
+    }
+}
+
+/// distinct values with their number of occurrences, like `AggDynSet` whose
+/// values carry counts. values are serialized with `write_normalized_scalar()`
+/// and kept in insertion order
+#[derive(Clone)]
+pub struct AggDynValueCounts {
+    list: AggDynList,
+    entries: Vec<(u32, u32, i64)>, // pos, len and count of each value
+    index: RawTable<u32>,          // indices into entries
+    hash_seed: i64,
+}
+
+impl AggDynValueCounts {
+    pub fn with_hash_seed(hash_seed: i64) -> Self {
+        Self {
+            list: AggDynList::default(),
+            entries: vec![],
+            index: RawTable::new(),
+            hash_seed,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// adds `count` occurrences of a value
+    pub fn add(&mut self, value: &ScalarValue, nullable: bool, count: i64) -> Result<()> {
+        let raw_start = self.list.raw.len();
+        if let Err(err) = write_normalized_scalar(value, nullable, &mut self.list.raw) {
+            self.list.raw.truncate(raw_start);
+            return Err(err);
+        }
+        self.add_inline(raw_start, count);
+        Ok(())
+    }
+
+    /// adds `count` occurrences of a value which is already serialized with
+    /// `write_normalized_scalar()`
+    pub fn add_raw(&mut self, raw: &[u8], count: i64) {
+        let raw_start = self.list.raw.len();
+        self.list.raw.extend_from_slice(raw);
+        self.add_inline(raw_start, count);
+    }
+
+    /// sums counts of matching values from other
+    pub fn merge(&mut self, other: &mut Self) {
+        if self.len() < other.len() {
+            // ensure the probed table is smaller
+            std::mem::swap(self, other);
+        }
+        let hash_seed = other.hash_seed;
+        let other = std::mem::replace(other, Self::with_hash_seed(hash_seed));
+        for &(pos, len, count) in &other.entries {
+            self.add_raw(other.list.ref_raw((pos, len)), count);
+        }
+    }
+
+    /// returns distinct values and their counts, in insertion order
+    pub fn into_arrays(self, dt: &DataType, nullable: bool) -> Result<(ArrayRef, Int64Array)> {
+        let counts = Int64Array::from_iter_values(self.entries.iter().map(|e| e.2));
+        Ok((self.list.into_array(dt, nullable)?, counts))
+    }
+
+    fn add_inline(&mut self, raw_start: usize, count: i64) {
+        let Self {
+            list,
+            entries,
+            index,
+            hash_seed,
+        } = self;
+        let new_pos_len = (raw_start as u32, (list.raw.len() - raw_start) as u32);
+        let new_value = list.ref_raw(new_pos_len);
+        let hash = AggDynSetHasher::Bytes.hash(*hash_seed, new_value);
+
+        match index.find_or_find_insert_slot(
+            hash,
+            |&i| {
+                let (pos, len, _) = entries[i as usize];
+                new_pos_len.1 == len && new_value == list.ref_raw((pos, len))
+            },
+            |&i| {
+                let (pos, len, _) = entries[i as usize];
+                AggDynSetHasher::Bytes.hash(*hash_seed, list.ref_raw((pos, len)))
+            },
+        ) {
+            Ok(found) => {
+                // remove the value from list since it is already present
+                let i = unsafe {
+                    // safety: the bucket is just found in the table
+                    *found.as_ref() as usize
+                };
+                entries[i].2 += count;
+                list.raw.truncate(raw_start);
+            }
+            Err(slot) => {
+                unsafe {
+                    // safety: call unsafe `insert_in_slot` method
+                    index.insert_in_slot(hash, slot, entries.len() as u32);
+                }
+                entries.push((new_pos_len.0, new_pos_len.1, count));
             }
+        }
+    }
+}
+
+impl AggDynValue for AggDynValueCounts {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+
+    fn as_any_boxed(self: Box<Self>) -> Box<dyn Any> {
+        self
+    }
+
+    fn mem_size(&self) -> usize {
+        size_of::<Self>()
+            + self.list.raw.capacity()
+            + self.entries.capacity() * size_of::<(u32, u32, i64)>()
+            + raw_table_mem_size(&self.index)
     }
 
     fn clone_boxed(&self) -> Box<dyn AggDynValue> {
         Box::new(self.clone())
     }
+
+    fn shrink_to_fit(&mut self) {
+        // the index is not shrunk since it requires rehashing
+        self.list.shrink_to_fit();
+        self.entries.shrink_to_fit();
+    }
+}
+
+/// streaming histogram of numeric values, bins of (x, y) are kept sorted by x
+/// and the closest pair of bins is coalesced once the number of bins exceeds
+/// the limit. see: Ben-Haim & Tom-Tov, "A Streaming Parallel Decision Tree
+/// Algorithm"
+#[derive(Clone, Default)]
+pub struct AggDynHistogram {
+    bins: Vec<(f64, f64)>,
+}
+
+impl AggDynHistogram {
+    pub fn bins(&self) -> &[(f64, f64)] {
+        &self.bins
+    }
+
+    pub fn into_bins(self) -> Vec<(f64, f64)> {
+        self.bins
+    }
+
+    pub fn add(&mut self, x: f64, num_bins: usize) {
+        match self.bins.binary_search_by(|bin| bin.0.total_cmp(&x)) {
+            Ok(i) => self.bins[i].1 += 1.0,
+            Err(i) => {
+                self.bins.insert(i, (x, 1.0));
+                self.trim(num_bins);
+            }
+        }
+    }
+
+    pub fn merge(&mut self, other: &mut Self, num_bins: usize) {
+        if self.bins.is_empty() {
+            std::mem::swap(&mut self.bins, &mut other.bins);
+            return;
+        }
+        let bins = std::mem::take(&mut self.bins);
+        let other_bins = std::mem::take(&mut other.bins);
+        self.bins = bins
+            .into_iter()
+            .merge_by(other_bins, |a, b| a.0.total_cmp(&b.0).is_le())
+            .collect();
+        self.trim(num_bins);
+    }
+
+    fn trim(&mut self, num_bins: usize) {
+        while self.bins.len() > num_bins.max(1) {
+            // find the closest pair, the first one wins if ties
+            let (i, _) = self
+                .bins
+                .windows(2)
+                .map(|w| w[1].0 - w[0].0)
+                .enumerate()
+                .min_by(|(_, d1), (_, d2)| d1.total_cmp(d2))
+                .expect("at least two bins");
+            let (x1, y1) = self.bins[i];
+            let (x2, y2) = self.bins[i + 1];
+            self.bins[i] = ((x1 * y1 + x2 * y2) / (y1 + y2), y1 + y2);
+            self.bins.remove(i + 1);
+        }
+    }
+}
+
+impl AggDynValue for AggDynHistogram {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+
+    fn as_any_boxed(self: Box<Self>) -> Box<dyn Any> {
+        self
+    }
+
+    fn mem_size(&self) -> usize {
+        size_of::<Self>() + self.bins.capacity() * size_of::<(f64, f64)>()
+    }
+
+    fn clone_boxed(&self) -> Box<dyn AggDynValue> {
+        Box::new(self.clone())
+    }
+
+    fn shrink_to_fit(&mut self) {
+        self.bins.shrink_to_fit();
+    }
+}
+
+/// merging t-digest of numeric values, see: Dunning & Ertl, "Computing
+/// Extremely Accurate Quantiles Using t-Digests". centroids of (mean, weight)
+/// are kept sorted by mean, new values are buffered and merged into centroids
+/// in batches. the k1 scale function bounds the size of centroids near the
+/// tails, so that extreme quantiles are estimated accurately
+#[derive(Clone)]
+pub struct AggDynTDigest {
+    centroids: Vec<(f64, f64)>,
+    buffer: Vec<(f64, f64)>,
+    min: f64,
+    max: f64,
+}
+
+impl Default for AggDynTDigest {
+    fn default() -> Self {
+        Self {
+            centroids: vec![],
+            buffer: vec![],
+            min: f64::INFINITY,
+            max: f64::NEG_INFINITY,
+        }
+    }
+}
+
+impl AggDynTDigest {
+    pub fn is_empty(&self) -> bool {
+        self.centroids.is_empty() && self.buffer.is_empty()
+    }
+
+    pub fn centroids(&self) -> &[(f64, f64)] {
+        &self.centroids
+    }
+
+    pub fn count(&self) -> f64 {
+        let centroids = self.centroids.iter().chain(&self.buffer);
+        centroids.map(|&(_, weight)| weight).sum()
+    }
+
+    /// adds a value, NaN values are ignored
+    pub fn add(&mut self, x: f64, compression: f64) {
+        if x.is_nan() {
+            return;
+        }
+        self.min = self.min.min(x);
+        self.max = self.max.max(x);
+        self.buffer.push((x, 1.0));
+        if self.buffer.len() >= Self::buffer_limit(compression) {
+            self.compress(compression);
+        }
+    }
+
+    pub fn merge(&mut self, other: &mut Self, compression: f64) {
+        if other.is_empty() {
+            return;
+        }
+        self.min = self.min.min(other.min);
+        self.max = self.max.max(other.max);
+        self.buffer.append(&mut other.centroids);
+        self.buffer.append(&mut other.buffer);
+        self.compress(compression);
+    }
+
+    /// merges buffered points into centroids
+    pub fn compress(&mut self, compression: f64) {
+        if self.buffer.is_empty() {
+            return;
+        }
+        let mut points = std::mem::take(&mut self.centroids);
+        points.append(&mut self.buffer);
+        points.sort_unstable_by(|a, b| a.0.total_cmp(&b.0));
+
+        // merge adjacent points while the k-size of the centroid is at most 1
+        let total_weight: f64 = points.iter().map(|&(_, weight)| weight).sum();
+        let weight_limit = |weight_so_far: f64| {
+            let k = k1_scale(weight_so_far / total_weight, compression);
+            total_weight * k1_scale_inv(k + 1.0, compression)
+        };
+        let mut centroids = Vec::with_capacity(compression.ceil() as usize);
+        let mut points = points.into_iter();
+        let mut cur = points.next().expect("non-empty points");
+        let mut weight_so_far = 0.0;
+        let mut limit = weight_limit(weight_so_far);
+        for (mean, weight) in points {
+            if weight_so_far + cur.1 + weight <= limit {
+                cur.1 += weight;
+                cur.0 += (mean - cur.0) * weight / cur.1;
+            } else {
+                weight_so_far += cur.1;
+                centroids.push(cur);
+                limit = weight_limit(weight_so_far);
+                cur = (mean, weight);
+            }
+        }
+        centroids.push(cur);
+        self.centroids = centroids;
+    }
+
+    /// estimates the q-th quantile by interpolating between centroids, None
+    /// if no values are added
+    pub fn quantile(&mut self, q: f64, compression: f64) -> Option<f64> {
+        self.compress(compression);
+        let centroids = &self.centroids;
+        let (first, last) = (centroids.first()?, centroids.last()?);
+        let total_weight: f64 = centroids.iter().map(|&(_, weight)| weight).sum();
+        let index = q.clamp(0.0, 1.0) * total_weight;
+
+        // values at both ends are known exactly
+        if index < 1.0 {
+            return Some(self.min);
+        }
+        if index > total_weight - 1.0 {
+            return Some(self.max);
+        }
+
+        // between min and the first centroid
+        if first.1 > 1.0 && index < first.1 / 2.0 {
+            let ratio = (index - 1.0) / (first.1 / 2.0 - 1.0);
+            return Some(self.min + ratio * (first.0 - self.min));
+        }
+
+        // between the last centroid and max
+        if last.1 > 1.0 && total_weight - index <= last.1 / 2.0 {
+            let ratio = (total_weight - index - 1.0) / (last.1 / 2.0 - 1.0);
+            return Some(self.max - ratio * (self.max - last.0));
+        }
+
+        // between two adjacent centroids, singleton centroids are not
+        // interpolated since they represent exact values
+        let mut weight_so_far = first.1 / 2.0;
+        for (&(mean1, weight1), &(mean2, weight2)) in centroids.iter().tuple_windows() {
+            let dw = (weight1 + weight2) / 2.0;
+            if weight_so_far + dw > index {
+                let left_unit = if weight1 == 1.0 { 0.5 } else { 0.0 };
+                let right_unit = if weight2 == 1.0 { 0.5 } else { 0.0 };
+                if index - weight_so_far < left_unit {
+                    return Some(mean1);
+                }
+                if weight_so_far + dw - index <= right_unit {
+                    return Some(mean2);
+                }
+                let z1 = index - weight_so_far - left_unit;
+                let z2 = weight_so_far + dw - index - right_unit;
+                return Some((mean1 * z2 + mean2 * z1) / (z1 + z2));
+            }
+            weight_so_far += dw;
+        }
+        Some(last.0)
+    }
+
+    fn buffer_limit(compression: f64) -> usize {
+        (compression * 5.0).ceil() as usize
+    }
+}
+
+/// k1 scale function: k(q) = compression / 2pi * asin(2q - 1)
+fn k1_scale(q: f64, compression: f64) -> f64 {
+    compression / (2.0 * std::f64::consts::PI) * (2.0 * q - 1.0).clamp(-1.0, 1.0).asin()
+}
+
+fn k1_scale_inv(k: f64, compression: f64) -> f64 {
+    let x = (k * 2.0 * std::f64::consts::PI / compression)
+        .clamp(-std::f64::consts::FRAC_PI_2, std::f64::consts::FRAC_PI_2);
+    (x.sin() + 1.0) / 2.0
+}
+
+impl AggDynValue for AggDynTDigest {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+
+    fn as_any_boxed(self: Box<Self>) -> Box<dyn Any> {
+        self
+    }
+
+    fn mem_size(&self) -> usize {
+        size_of::<Self>()
+            + (self.centroids.capacity() + self.buffer.capacity()) * size_of::<(f64, f64)>()
+    }
+
+    fn clone_boxed(&self) -> Box<dyn AggDynValue> {
+        Box::new(self.clone())
+    }
+
+    fn shrink_to_fit(&mut self) {
+        self.centroids.shrink_to_fit();
+        self.buffer.shrink_to_fit();
+    }
 }
 
 #[derive(Default, Clone, Copy)]
@@ -1041,49 +2480,854 @@ impl AccumStateValAddr {
 
 #[cfg(test)]
 mod test {
-    use std::{collections::HashSet, io::Cursor};
+    use std::{collections::HashSet, io::Cursor, mem::size_of};
 
-    use arrow::datatypes::DataType;
+    use arrow::{
+        array::{Array, BinaryArray},
+        datatypes::DataType,
+    };
     use datafusion::common::{Result, ScalarValue};
-    use datafusion_ext_commons::downcast_any;
-
-    use crate::agg::acc::{
-        create_acc_from_initial_value, create_dyn_loaders_from_initial_value,
-        create_dyn_savers_from_initial_value, AccumInitialValue, AccumStateRow, AggDynSet,
-        AggDynStr, LoadReader, SaveWriter,
+    use datafusion_ext_commons::{downcast_any, io::write_scalar};
+    use itertools::Itertools;
+
+    use crate::agg::{
+        acc::{
+            create_acc_from_initial_value, create_dyn_loaders_from_initial_value,
+            create_dyn_savers_from_initial_value, derive_agg_dyn_set_hash_seed,
+            load_accs_from_binary_array, AccumInitialValue, AccumStateRow, AggDynList, AggDynSet,
+            AggDynSetHasher, AggDynStr, AggDynValue, InternalSet, LoadReader, SaveWriter,
+            AGG_DYN_SET_HASH_SEED, SMALL_SET_CAPACITY, SMALL_SET_MAX_SCAN_BYTES,
+        },
+        agg_table::gx_hash_with_seed,
     };
 
+    #[test]
+    fn test_dyn_set_hash_seed() {
+        let mut dyn_set1 = AggDynSet::default();
+        let mut dyn_set2 = AggDynSet::with_hash_seed(derive_agg_dyn_set_hash_seed(42));
+        for i in [1, 3, 5, 7, 9, 3, 2, 4, 6, 8, 10, 1, 5] {
+            dyn_set1.append(&ScalarValue::from(i), false).unwrap();
+            dyn_set2.append(&ScalarValue::from(i), false).unwrap();
+        }
+        let values1 = dyn_set1
+            .into_values(DataType::Int32, false)
+            .collect::<Result<Vec<_>>>()
+            .unwrap();
+        let values2 = dyn_set2
+            .into_values(DataType::Int32, false)
+            .collect::<Result<Vec<_>>>()
+            .unwrap();
+        assert_eq!(values1.len(), 10);
+        assert_eq!(values1, values2);
+    }
+
+    #[test]
+    fn test_dyn_set_hash_dispatch() {
+        // the hasher is chosen by the value type, not by the serialized width
+        assert_eq!(
+            AggDynSetHasher::for_type(&DataType::Int32),
+            AggDynSetHasher::Fixed4
+        );
+        assert_eq!(
+            AggDynSetHasher::for_type(&DataType::Int64),
+            AggDynSetHasher::Fixed8
+        );
+        assert_eq!(
+            AggDynSetHasher::for_type(&DataType::Utf8),
+            AggDynSetHasher::Bytes
+        );
+        let seed = derive_agg_dyn_set_hash_seed(42);
+        assert_eq!(
+            AggDynSetHasher::for_type(&DataType::Binary).hash(seed, b"8 bytes!"),
+            gx_hash_with_seed(seed, b"8 bytes!"),
+        );
+
+        // fixed-width and variable-width values are deduplicated in huge sets
+        let mut int_set =
+            AggDynSet::default().with_hasher(AggDynSetHasher::for_type(&DataType::Int64));
+        let mut str_set =
+            AggDynSet::default().with_hasher(AggDynSetHasher::for_type(&DataType::Utf8));
+        for i in (0..10000i64).chain(0..10000) {
+            int_set
+                .append(&ScalarValue::from(i * 65536), false)
+                .unwrap();
+            str_set
+                .append(&ScalarValue::from(format!("{i}")), false)
+                .unwrap();
+        }
+        assert!(int_set.is_huge() && str_set.is_huge());
+        assert_eq!(int_set.len(), 10000);
+        assert_eq!(str_set.len(), 10000);
+
+        // integer hashes depend on the seed and are well distributed
+        let hasher = AggDynSetHasher::Fixed8;
+        let hashes = (0..10000i64)
+            .map(|i| hasher.hash(seed, (i * 65536).to_ne_bytes()))
+            .collect::<HashSet<_>>();
+        assert_eq!(hashes.len(), 10000);
+        assert_ne!(
+            hasher.hash(seed, 1i64.to_ne_bytes()),
+            hasher.hash(AGG_DYN_SET_HASH_SEED, 1i64.to_ne_bytes()),
+        );
+        let num_buckets = 1024;
+        let num_used_buckets = hashes
+            .iter()
+            .map(|h| h % num_buckets)
+            .collect::<HashSet<_>>()
+            .len();
+        assert!(num_used_buckets > 1000, "used buckets: {num_used_buckets}");
+    }
+
+    #[test]
+    fn test_dyn_set_save_uniform_lens() {
+        let initial_value = AccumInitialValue::DynSet(DataType::Utf8, AGG_DYN_SET_HASH_SEED, 0);
+        let savers = create_dyn_savers_from_initial_value(&[initial_value.clone()]).unwrap();
+        let loaders = create_dyn_loaders_from_initial_value(&[initial_value]).unwrap();
+        let save_load = |values: &[&str]| {
+            let mut dyn_set = AggDynSet::default();
+            for &value in values {
+                dyn_set.append(&ScalarValue::from(value), false).unwrap();
+            }
+            let mut buf = vec![];
+            let mut save_writer = SaveWriter(Box::new(Cursor::new(&mut buf)));
+            savers[0](&mut save_writer, Some(Box::new(dyn_set))).unwrap();
+            drop(save_writer);
+
+            let mut load_reader = LoadReader(Box::new(Cursor::new(&buf)));
+            let dyn_set = loaders[0](&mut load_reader)
+                .unwrap()
+                .unwrap()
+                .as_any_boxed()
+                .downcast::<AggDynSet>()
+                .unwrap();
+            drop(load_reader);
+            let values = dyn_set
+                .into_values(DataType::Utf8, false)
+                .collect::<Result<Vec<_>>>()
+                .unwrap();
+            (buf.len(), values)
+        };
+
+        // (values, saved size), each value is serialized as 1 byte of length and
+        // the string bytes
+        let cases: [(&[&str], usize); 5] = [
+            // raw len + raw + num items + uniform len
+            (&["ab", "cd", "ef"], 1 + 9 + 1 + 1),
+            (&["ab", "cd", "ab", "ef", "gh", "ij", "kl"], 1 + 18 + 1 + 1),
+            // raw len + raw + num items + zero marker + lens of items
+            (&["a", "bc", "def"], 1 + 9 + 1 + 1 + 3),
+            (&["abc"], 1 + 4 + 1 + 1),
+            // raw len + num items
+            (&[], 1 + 1),
+        ];
+        for (values, expected_size) in cases {
+            let (size, actual) = save_load(values);
+            assert_eq!(size, expected_size, "{values:?}");
+            let expected = values
+                .iter()
+                .unique()
+                .map(|&v| ScalarValue::from(v))
+                .collect::<Vec<_>>();
+            assert_eq!(actual, expected);
+        }
+    }
+
+    #[test]
+    fn test_dyn_set_intersect() {
+        let create_set = |values: &[i32]| {
+            let mut dyn_set = AggDynSet::default();
+            for &v in values {
+                dyn_set.append(&ScalarValue::from(v), false).unwrap();
+            }
+            dyn_set
+        };
+
+        // small and huge sets on both sides
+        let cases: [(&[i32], &[i32], &[i32]); 5] = [
+            (&[3, 1, 2], &[2, 3], &[3, 2]),
+            (&[3, 1, 2], &[4, 5], &[]),
+            (&[9, 8, 7, 6, 5, 4, 3, 2, 1], &[1, 3, 5, 7], &[7, 5, 3, 1]),
+            (&[1, 5], &[9, 8, 7, 6, 5, 4, 3, 2, 1], &[1, 5]),
+            (&[], &[1, 2], &[]),
+        ];
+        for (values1, values2, expected) in cases {
+            let mut dyn_set = create_set(values1);
+            dyn_set.intersect(&create_set(values2));
+            assert!(!dyn_set.contains_raw(&[0xff]));
+            for &v in values1 {
+                let mut raw = vec![];
+                write_scalar(&ScalarValue::from(v), false, &mut raw).unwrap();
+                assert_eq!(dyn_set.contains_raw(&raw), expected.contains(&v));
+            }
+            assert_eq!(
+                dyn_set
+                    .into_values(DataType::Int32, false)
+                    .collect::<Result<Vec<_>>>()
+                    .unwrap(),
+                expected
+                    .iter()
+                    .map(|&v| ScalarValue::from(v))
+                    .collect::<Vec<_>>(),
+            );
+        }
+    }
+
+    #[test]
+    fn test_dyn_set_small_promotion() {
+        let values = [3, 1, 3, 4, 1, 5, 9, 2, 6, 5, 3, 5, 8, 9, 7, 9];
+        let mut dyn_set = AggDynSet::default();
+        for (i, &v) in values.iter().enumerate() {
+            dyn_set.append(&ScalarValue::from(v), false).unwrap();
+            let is_small = matches!(dyn_set.set, InternalSet::Small(_));
+            assert_eq!(is_small, dyn_set.set.len() <= SMALL_SET_CAPACITY, "{i}");
+        }
+        assert_eq!(
+            dyn_set
+                .into_values(DataType::Int32, false)
+                .collect::<Result<Vec<_>>>()
+                .unwrap(),
+            [3, 1, 4, 5, 9, 2, 6, 8, 7].map(ScalarValue::from),
+        );
+
+        // long values are promoted by scanned bytes, before reaching capacity
+        let long_value = ScalarValue::from("x".repeat(SMALL_SET_MAX_SCAN_BYTES).as_str());
+        let mut dyn_set = AggDynSet::default();
+        dyn_set.append(&ScalarValue::from("a"), false).unwrap();
+        assert!(matches!(dyn_set.set, InternalSet::Small(_)));
+        dyn_set.append(&long_value, false).unwrap();
+        dyn_set.append(&ScalarValue::from("a"), false).unwrap();
+        dyn_set.append(&long_value, false).unwrap();
+        assert!(matches!(dyn_set.set, InternalSet::Huge(_)));
+        assert_eq!(
+            dyn_set
+                .into_values(DataType::Utf8, false)
+                .collect::<Result<Vec<_>>>()
+                .unwrap(),
+            [ScalarValue::from("a"), long_value],
+        );
+    }
+
+    #[test]
+    fn test_dyn_set_mem_size() {
+        let mut dyn_set = AggDynSet::default();
+        for i in 0..10000 {
+            dyn_set.append(&ScalarValue::from(i), false).unwrap();
+
+            if let InternalSet::Huge(s) = &dyn_set.set {
+                let (_, layout) = s.allocation_info();
+                let reported_set_size =
+                    dyn_set.mem_size() - size_of::<AggDynSet>() - dyn_set.list.raw.capacity();
+                assert!(reported_set_size >= layout.size());
+                assert!(reported_set_size <= layout.size() + 64);
+            }
+        }
+        assert!(matches!(dyn_set.set, InternalSet::Huge(_)));
+    }
+
+    #[test]
+    fn test_dyn_set_clear() {
+        let mut dyn_set = AggDynSet::default();
+        for i in 0..1000 {
+            dyn_set.append(&ScalarValue::from(i), false).unwrap();
+        }
+        let mem_size = dyn_set.mem_size();
+        let raw_capacity = dyn_set.list.raw.capacity();
+
+        dyn_set.clear();
+        assert!(dyn_set.is_empty());
+        assert_eq!(dyn_set.list.raw.capacity(), raw_capacity);
+        assert_eq!(dyn_set.mem_size(), mem_size);
+
+        // reused after clearing
+        for i in [3, 1, 3, 2] {
+            dyn_set.append(&ScalarValue::from(i), false).unwrap();
+        }
+        assert_eq!(dyn_set.len(), 3);
+        assert_eq!(dyn_set.mem_size(), mem_size);
+        assert_eq!(
+            dyn_set
+                .into_values(DataType::Int32, false)
+                .collect::<Result<Vec<_>>>()
+                .unwrap(),
+            [3, 1, 2].map(ScalarValue::from),
+        );
+    }
+
+    #[test]
+    fn test_dyn_set_merge_ordered() {
+        let mut dyn_set1 = AggDynSet::default();
+        let mut dyn_set2 = AggDynSet::default();
+        for i in [5, 1, 3] {
+            dyn_set1.append(&ScalarValue::from(i), false).unwrap();
+        }
+        for i in (0..20).rev() {
+            dyn_set2.append(&ScalarValue::from(i), false).unwrap();
+        }
+        dyn_set1.merge_ordered(&mut dyn_set2);
+
+        let expected = [5, 1, 3]
+            .into_iter()
+            .chain((0..20).rev().filter(|i| ![5, 1, 3].contains(i)))
+            .map(ScalarValue::from)
+            .collect::<Vec<_>>();
+        let actual = dyn_set1
+            .into_values(DataType::Int32, false)
+            .collect::<Result<Vec<_>>>()
+            .unwrap();
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn test_dyn_set_merge_fuzz() {
+        // xorshift random generator
+        let mut seed = 0x9E3779B97F4A7C15u64;
+        let mut rand = move || {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            seed as usize
+        };
+
+        // values are drawn from a small domain so that sets overlap, strings of
+        // various lengths trigger promotions by scanned bytes
+        let random_values = |rand: &mut dyn FnMut() -> usize| {
+            let num_values = rand() % 40;
+            (0..num_values)
+                .map(|_| {
+                    let v = rand() % 50;
+                    if v % 2 == 0 {
+                        ScalarValue::from(format!("{v}").repeat(rand() % 30 + 1))
+                    } else {
+                        ScalarValue::from(format!("{v}"))
+                    }
+                })
+                .collect::<Vec<_>>()
+        };
+        let create_set = |values: &[ScalarValue]| {
+            let mut dyn_set = AggDynSet::default();
+            for value in values {
+                dyn_set.append(value, false).unwrap();
+            }
+            dyn_set
+        };
+        let distinct = |values: &[ScalarValue]| {
+            let mut seen = HashSet::new();
+            values
+                .iter()
+                .filter(|&v| seen.insert(v.clone()))
+                .cloned()
+                .collect::<Vec<_>>()
+        };
+
+        for _ in 0..500 {
+            let values1 = random_values(&mut rand);
+            let values2 = random_values(&mut rand);
+            let expected_ordered = distinct(&[values1.clone(), values2.clone()].concat());
+            let expected = expected_ordered.iter().cloned().collect::<HashSet<_>>();
+
+            for (lhs, rhs) in [(&values1, &values2), (&values2, &values1)] {
+                let mut dyn_set1 = create_set(lhs);
+                let mut dyn_set2 = create_set(rhs);
+                dyn_set1.merge(&mut dyn_set2);
+                assert_eq!(dyn_set1.len(), expected.len());
+                let actual = dyn_set1
+                    .into_values(DataType::Utf8, false)
+                    .collect::<Result<Vec<_>>>()
+                    .unwrap();
+                assert_eq!(actual.len(), expected.len());
+                assert_eq!(actual.into_iter().collect::<HashSet<_>>(), expected);
+            }
+
+            let mut dyn_set1 = create_set(&values1);
+            let mut dyn_set2 = create_set(&values2);
+            dyn_set1.merge_ordered(&mut dyn_set2);
+            let actual = dyn_set1
+                .into_values(DataType::Utf8, false)
+                .collect::<Result<Vec<_>>>()
+                .unwrap();
+            assert_eq!(actual, expected_ordered);
+        }
+    }
+
+    #[test]
+    fn test_dyn_set_no_dead_bytes() {
+        // the list must only contain the values referenced by the set, so
+        // that rejected duplicates never take space in memory or in spills
+        let assert_compact = |dyn_set: &AggDynSet| {
+            let mut pos_lens = match dyn_set.set.clone() {
+                InternalSet::Small(s) => s.into_iter().collect::<Vec<_>>(),
+                InternalSet::Huge(s) => s.into_iter().collect::<Vec<_>>(),
+            };
+            pos_lens.sort();
+            let mut expected_pos = 0;
+            for (pos, len) in pos_lens {
+                assert_eq!(pos, expected_pos);
+                expected_pos += len;
+            }
+            assert_eq!(expected_pos as usize, dyn_set.list.raw.len());
+        };
+
+        let mut dyn_set = AggDynSet::default();
+        for round in 0..200 {
+            let mut merging_set = AggDynSet::default();
+            for i in 0..round % 13 {
+                let value = ScalarValue::from(format!("{}", (round * 7 + i) % 100));
+                merging_set.append(&value, false).unwrap();
+                merging_set.append(&value, false).unwrap();
+                dyn_set.append(&value, false).unwrap();
+            }
+            assert_compact(&merging_set);
+
+            // merging raw values from another set, with duplicates
+            let mut raw = vec![];
+            write_scalar(
+                &ScalarValue::from(format!("{}", round % 100)),
+                false,
+                &mut raw,
+            )
+            .unwrap();
+            dyn_set.append_raw(&raw);
+            dyn_set.append_raw(&raw);
+            dyn_set.merge(&mut merging_set);
+            assert_compact(&dyn_set);
+        }
+        assert!(dyn_set.is_huge());
+        assert_eq!(dyn_set.len(), 100);
+    }
+
+    #[test]
+    fn test_dyn_set_last_seen() {
+        // bursts of repeated values, interleaved with values seen long ago
+        let values = (0..2000)
+            .map(|i| ScalarValue::from(format!("{}", (i / 7) % 40 + (i % 3) * 100)))
+            .collect::<Vec<_>>();
+        let mut expected = vec![];
+        for value in &values {
+            if !expected.contains(value) {
+                expected.push(value.clone());
+            }
+        }
+
+        let mut dyn_set = AggDynSet::default();
+        let mut raw_set = AggDynSet::default();
+        for value in &values {
+            dyn_set.append(value, false).unwrap();
+            let mut raw = vec![];
+            write_scalar(value, false, &mut raw).unwrap();
+            raw_set.append_raw(&raw);
+        }
+        assert!(dyn_set.is_huge());
+        for dyn_set in [dyn_set.clone(), raw_set] {
+            assert_eq!(
+                dyn_set
+                    .into_values(DataType::Utf8, false)
+                    .collect::<Result<Vec<_>>>()
+                    .unwrap(),
+                expected,
+            );
+        }
+
+        // the cached value is forgotten after clearing
+        dyn_set.clear();
+        dyn_set.append(&values[0], false).unwrap();
+        dyn_set.append(&values[0], false).unwrap();
+        assert_eq!(dyn_set.len(), 1);
+    }
+
+    #[test]
+    fn test_dyn_set_max_slots() {
+        let assert_slots = |dyn_set: &AggDynSet| match &dyn_set.set {
+            InternalSet::Huge(s) => assert!(s.buckets() <= 64),
+            InternalSet::Small(_) => unreachable!(),
+        };
+        let values = (0..100)
+            .map(|i| ScalarValue::from(i as i32))
+            .collect::<Vec<_>>();
+
+        let mut dyn_set = AggDynSet::default();
+        let mut raw_set = AggDynSet::default();
+        dyn_set.set_max_slots(64);
+        raw_set.set_max_slots(64);
+        for value in &values {
+            dyn_set.append(value, false).unwrap();
+            let mut raw = vec![];
+            write_scalar(value, false, &mut raw).unwrap();
+            raw_set.append_raw(&raw);
+        }
+
+        // a full table of 64 slots holds 56 values, later values are rejected
+        for dyn_set in [&dyn_set, &raw_set] {
+            assert!(dyn_set.slots_exhausted());
+            assert_eq!(dyn_set.len(), 56);
+            assert_slots(dyn_set);
+            assert_eq!(
+                dyn_set
+                    .clone()
+                    .into_values(DataType::Int32, false)
+                    .collect::<Result<Vec<_>>>()
+                    .unwrap(),
+                values[..56],
+            );
+        }
+
+        // existing values are still found in the full table
+        let mut dyn_set = AggDynSet::default();
+        dyn_set.set_max_slots(64);
+        for value in &values[..56] {
+            dyn_set.append(value, false).unwrap();
+        }
+        for value in values[..56].iter().rev() {
+            dyn_set.append(value, false).unwrap();
+        }
+        assert!(!dyn_set.slots_exhausted());
+        assert_eq!(dyn_set.len(), 56);
+
+        // the limit is kept by merging, and space beyond the limit is not reserved
+        let mut merging_sets = (0..2)
+            .map(|i| {
+                let mut merging_set = AggDynSet::default();
+                for value in &values[i * 50..][..50] {
+                    merging_set.append(value, false).unwrap();
+                }
+                merging_set
+            })
+            .collect::<Vec<_>>();
+        let mut dyn_set = AggDynSet::default();
+        dyn_set.set_max_slots(64);
+        dyn_set.merge_many(&mut merging_sets.iter_mut().collect::<Vec<_>>(), false);
+        assert!(dyn_set.slots_exhausted());
+        assert_eq!(dyn_set.len(), 56);
+        assert_slots(&dyn_set);
+
+        // the flag is kept even if the set is swapped with a larger one in merging
+        let mut exhausted_set = AggDynSet::default();
+        exhausted_set.set_max_slots(64);
+        let mut larger_set = AggDynSet::default();
+        for value in &values {
+            exhausted_set.append(value, false).unwrap();
+            larger_set.append(value, false).unwrap();
+        }
+        assert!(exhausted_set.slots_exhausted());
+        exhausted_set.merge(&mut larger_set);
+        assert!(exhausted_set.slots_exhausted());
+
+        // unlimited by default, and the flag is reset after clearing
+        dyn_set.clear();
+        assert!(!dyn_set.slots_exhausted());
+        let mut unlimited_set = AggDynSet::default();
+        for value in &values {
+            unlimited_set.append(value, false).unwrap();
+        }
+        assert!(!unlimited_set.slots_exhausted());
+        assert_eq!(unlimited_set.len(), 100);
+    }
+
+    #[test]
+    fn test_dyn_set_max_slots_from_initial_value() {
+        let initial_value = AccumInitialValue::DynSet(DataType::Int32, AGG_DYN_SET_HASH_SEED, 64);
+        let savers = create_dyn_savers_from_initial_value(&[initial_value.clone()]).unwrap();
+        let loaders = create_dyn_loaders_from_initial_value(&[initial_value.clone()]).unwrap();
+
+        // new groups are cloned from the initial set with the limit
+        let (initial_acc, _) = create_acc_from_initial_value(&[initial_value]).unwrap();
+        let mut dyn_set = initial_acc.dyns()[0]
+            .as_ref()
+            .unwrap()
+            .as_any()
+            .downcast_ref::<AggDynSet>()
+            .unwrap()
+            .clone();
+        assert_eq!(dyn_set.max_slots, 64);
+
+        // loaded sets get the limit from the loader
+        for i in 0..10 {
+            dyn_set.append(&ScalarValue::from(i), false).unwrap();
+        }
+        let mut buf = vec![];
+        let mut save_writer = SaveWriter(Box::new(Cursor::new(&mut buf)));
+        savers[0](&mut save_writer, Some(Box::new(dyn_set))).unwrap();
+        drop(save_writer);
+
+        let mut load_reader = LoadReader(Box::new(Cursor::new(&buf)));
+        let dyn_set = loaders[0](&mut load_reader)
+            .unwrap()
+            .unwrap()
+            .as_any_boxed()
+            .downcast::<AggDynSet>()
+            .unwrap();
+        drop(load_reader);
+        assert_eq!(dyn_set.max_slots, 64);
+        assert_eq!(dyn_set.len(), 10);
+    }
+
+    #[test]
+    fn test_dyn_list_shrink_to_fit() {
+        let mut dyn_list = AggDynList::default();
+        dyn_list.raw.reserve(1024);
+        dyn_list.append(&ScalarValue::from("Panda"), false).unwrap();
+        let mem_size = dyn_list.mem_size();
+
+        dyn_list.shrink_to_fit();
+        assert!(dyn_list.mem_size() < mem_size);
+        assert_eq!(dyn_list.raw.capacity(), dyn_list.raw.len());
+        assert_eq!(
+            dyn_list
+                .into_values(DataType::Utf8, false)
+                .collect::<Result<Vec<_>>>()
+                .unwrap(),
+            vec![ScalarValue::from("Panda")],
+        );
+    }
+
+    #[test]
+    fn test_dyn_list_merge_with_type() -> Result<()> {
+        let mut dyn_list = AggDynList::default();
+        dyn_list
+            .append(&ScalarValue::LargeUtf8(Some("Panda".to_string())), false)
+            .unwrap();
+        let mut dyn_list2 = AggDynList::default();
+        dyn_list2
+            .append(&ScalarValue::from("Tiger"), false)
+            .unwrap();
+        dyn_list.merge_with_type(&mut dyn_list2, &DataType::LargeUtf8, &DataType::Utf8, false)?;
+        assert_eq!(
+            dyn_list
+                .into_values(DataType::LargeUtf8, false)
+                .collect::<Result<Vec<_>>>()
+                .unwrap(),
+            vec![
+                ScalarValue::LargeUtf8(Some("Panda".to_string())),
+                ScalarValue::LargeUtf8(Some("Tiger".to_string())),
+            ],
+        );
+
+        let mut dyn_list = AggDynList::default();
+        dyn_list.append(&ScalarValue::Int32(Some(1)), true).unwrap();
+        let mut dyn_list2 = AggDynList::default();
+        dyn_list2.append(&ScalarValue::Int64(None), true).unwrap();
+        dyn_list2
+            .append(&ScalarValue::Int64(Some(2)), true)
+            .unwrap();
+        dyn_list.merge_with_type(&mut dyn_list2, &DataType::Int32, &DataType::Int64, true)?;
+        assert_eq!(
+            dyn_list
+                .into_values(DataType::Int32, true)
+                .collect::<Result<Vec<_>>>()
+                .unwrap(),
+            vec![
+                ScalarValue::Int32(Some(1)),
+                ScalarValue::Int32(None),
+                ScalarValue::Int32(Some(2)),
+            ],
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_dyn_set_merge_with_type() -> Result<()> {
+        let mut dyn_set = AggDynSet::default();
+        dyn_set.append(&ScalarValue::from("Panda"), false).unwrap();
+        dyn_set.append(&ScalarValue::from("Tiger"), false).unwrap();
+        let mut dyn_set2 = AggDynSet::default();
+        dyn_set2
+            .append(&ScalarValue::LargeUtf8(Some("Tiger".to_string())), false)
+            .unwrap();
+        dyn_set2
+            .append(&ScalarValue::LargeUtf8(Some("Lion".to_string())), false)
+            .unwrap();
+        dyn_set.merge_with_type(&mut dyn_set2, &DataType::Utf8, &DataType::LargeUtf8, false)?;
+        assert_eq!(
+            dyn_set
+                .into_values(DataType::Utf8, false)
+                .collect::<Result<HashSet<_>>>()
+                .unwrap(),
+            HashSet::from_iter(
+                ["Panda", "Tiger", "Lion"]
+                    .into_iter()
+                    .map(ScalarValue::from)
+            ),
+        );
+
+        let mut dyn_set = AggDynSet::default();
+        dyn_set.append(&ScalarValue::Int64(Some(1)), false).unwrap();
+        let mut dyn_set2 = AggDynSet::default();
+        dyn_set2
+            .append(&ScalarValue::Int32(Some(1)), false)
+            .unwrap();
+        dyn_set2
+            .append(&ScalarValue::Int32(Some(2)), false)
+            .unwrap();
+        dyn_set.merge_with_type(&mut dyn_set2, &DataType::Int64, &DataType::Int32, false)?;
+        assert_eq!(
+            dyn_set
+                .into_values(DataType::Int64, false)
+                .collect::<Result<Vec<_>>>()
+                .unwrap(),
+            vec![ScalarValue::Int64(Some(1)), ScalarValue::Int64(Some(2))],
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_dyn_list_into_array() -> Result<()> {
+        let test_cases = [
+            (
+                DataType::Int32,
+                vec![ScalarValue::Int32(Some(1)), ScalarValue::Int32(None)],
+            ),
+            (
+                DataType::Boolean,
+                vec![ScalarValue::Boolean(Some(true)), ScalarValue::Boolean(None)],
+            ),
+            (
+                DataType::Decimal128(20, 2),
+                vec![
+                    ScalarValue::Decimal128(Some(12345), 20, 2),
+                    ScalarValue::Decimal128(None, 20, 2),
+                ],
+            ),
+            (
+                DataType::Utf8,
+                vec![ScalarValue::from("Panda"), ScalarValue::Utf8(None)],
+            ),
+            (
+                DataType::Binary,
+                vec![
+                    ScalarValue::Binary(Some(b"Panda".to_vec())),
+                    ScalarValue::Binary(None),
+                ],
+            ),
+            (
+                // fallback to scalar values
+                DataType::LargeUtf8,
+                vec![ScalarValue::LargeUtf8(Some("Panda".to_string()))],
+            ),
+        ];
+
+        for (dt, values) in test_cases {
+            for nullable in [true, false] {
+                if !nullable && values.iter().any(|value| value.is_null()) {
+                    continue;
+                }
+                let mut dyn_list = AggDynList::default();
+                for value in &values {
+                    dyn_list.append(value, nullable).unwrap();
+                }
+                let array = dyn_list.clone().into_array(&dt, nullable)?;
+                assert_eq!(array.data_type(), &dt);
+                assert_eq!(
+                    array.to_data(),
+                    ScalarValue::iter_to_array(
+                        dyn_list
+                            .into_values(dt.clone(), nullable)
+                            .collect::<Result<Vec<_>>>()?,
+                    )?
+                    .to_data(),
+                );
+            }
+        }
+
+        // empty list
+        let array = AggDynList::default().into_array(&DataType::Int64, false)?;
+        assert_eq!(array.data_type(), &DataType::Int64);
+        assert!(array.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_dyn_list_serde_errors() {
+        // unsupported values are rejected without changing the list or set
+        let mut dyn_list = AggDynList::default();
+        dyn_list.append(&ScalarValue::from("Panda"), false).unwrap();
+        let raw_len = dyn_list.raw.len();
+        let unsupported = ScalarValue::Time64Nanosecond(Some(1));
+        assert!(dyn_list.append(&unsupported, false).is_err());
+        assert_eq!(dyn_list.raw.len(), raw_len);
+
+        let mut dyn_set = AggDynSet::default();
+        assert!(dyn_set.append(&unsupported, false).is_err());
+        assert!(dyn_set.is_empty());
+        assert!(dyn_set.list.raw.is_empty());
+
+        // truncated bytes fail decoding instead of panicking
+        dyn_list.raw.truncate(raw_len - 1);
+        assert!(dyn_list.clone().into_array(&DataType::Utf8, false).is_err());
+        let values = dyn_list
+            .into_values(DataType::Utf8, false)
+            .collect::<Vec<_>>();
+        assert_eq!(values.len(), 1);
+        assert!(values[0].is_err());
+    }
+
+    #[test]
+    fn test_dyn_list_merge_prefix() -> Result<()> {
+        let mut dyn_list = AggDynList::default();
+        dyn_list.append(&ScalarValue::from("Hello"), false).unwrap();
+
+        let mut dyn_list2 = AggDynList::default();
+        dyn_list2
+            .append(&ScalarValue::from("Wooden"), false)
+            .unwrap();
+        dyn_list2.append(&ScalarValue::from("Bird"), false).unwrap();
+        dyn_list2
+            .append(&ScalarValue::from("Snake"), false)
+            .unwrap();
+        dyn_list.merge_prefix(&mut dyn_list2, 2, &DataType::Utf8, false)?;
+
+        let actual = dyn_list
+            .into_values(DataType::Utf8, false)
+            .collect::<Result<Vec<_>>>()
+            .unwrap();
+        assert_eq!(
+            actual,
+            vec![
+                ScalarValue::from("Hello"),
+                ScalarValue::from("Wooden"),
+                ScalarValue::from("Bird"),
+            ]
+        );
+        Ok(())
+    }
+
     #[test]
     fn test_dyn_set() {
         let mut dyn_set = AggDynSet::default();
-        dyn_set.append(&ScalarValue::from("Hello"), false);
-        dyn_set.append(&ScalarValue::from("Wooden"), false);
-        dyn_set.append(&ScalarValue::from("Bird"), false);
-        dyn_set.append(&ScalarValue::from("Snake"), false);
-        dyn_set.append(&ScalarValue::from("Wooden"), false);
-        dyn_set.append(&ScalarValue::from("Bird"), false);
+        dyn_set.append(&ScalarValue::from("Hello"), false).unwrap();
+        dyn_set.append(&ScalarValue::from("Wooden"), false).unwrap();
+        dyn_set.append(&ScalarValue::from("Bird"), false).unwrap();
+        dyn_set.append(&ScalarValue::from("Snake"), false).unwrap();
+        dyn_set.append(&ScalarValue::from("Wooden"), false).unwrap();
+        dyn_set.append(&ScalarValue::from("Bird"), false).unwrap();
 
         // test merge
         let mut dyn_set2 = AggDynSet::default();
-        dyn_set2.append(&ScalarValue::from("Hello"), false);
-        dyn_set2.append(&ScalarValue::from("Batman"), false);
-        dyn_set2.append(&ScalarValue::from("Candy"), false);
+        dyn_set2.append(&ScalarValue::from("Hello"), false).unwrap();
+        dyn_set2
+            .append(&ScalarValue::from("Batman"), false)
+            .unwrap();
+        dyn_set2.append(&ScalarValue::from("Candy"), false).unwrap();
         dyn_set.merge(&mut dyn_set2);
 
         // test save
         let mut buf = vec![];
         let mut save_writer = SaveWriter(Box::new(Cursor::new(&mut buf)));
-        let savers =
-            create_dyn_savers_from_initial_value(&[AccumInitialValue::DynSet(DataType::Utf8)])
-                .unwrap();
+        let savers = create_dyn_savers_from_initial_value(&[AccumInitialValue::DynSet(
+            DataType::Utf8,
+            AGG_DYN_SET_HASH_SEED,
+            0,
+        )])
+        .unwrap();
         savers[0](&mut save_writer, Some(Box::new(dyn_set))).unwrap();
         drop(save_writer);
 
         // test load
         let mut load_reader = LoadReader(Box::new(Cursor::new(&buf)));
-        let loaders =
-            create_dyn_loaders_from_initial_value(&[AccumInitialValue::DynSet(DataType::Utf8)])
-                .unwrap();
+        let loaders = create_dyn_loaders_from_initial_value(&[AccumInitialValue::DynSet(
+            DataType::Utf8,
+            AGG_DYN_SET_HASH_SEED,
+            0,
+        )])
+        .unwrap();
         let dyn_set = loaders[0](&mut load_reader)
             .unwrap()
             .unwrap()
@@ -1092,7 +3336,10 @@ mod test {
             .unwrap();
         drop(load_reader);
 
-        let actual_set: HashSet<ScalarValue> = dyn_set.into_values(DataType::Utf8, false).collect();
+        let actual_set: HashSet<ScalarValue> = dyn_set
+            .into_values(DataType::Utf8, false)
+            .collect::<Result<_>>()
+            .unwrap();
         assert_eq!(actual_set.len(), 6);
         assert!(actual_set.contains(&ScalarValue::from("Hello")));
         assert!(actual_set.contains(&ScalarValue::from("Wooden")));
@@ -1147,4 +3394,59 @@ mod test {
             "test",
         );
     }
+
+    #[test]
+    fn test_load_accs_from_binary_array() -> Result<()> {
+        let initial_values = [
+            AccumInitialValue::Scalar(ScalarValue::Int64(None)),
+            AccumInitialValue::DynList(DataType::Int32),
+            AccumInitialValue::Scalar(ScalarValue::Utf8(None)),
+        ];
+        let (initial_acc, addrs) = create_acc_from_initial_value(&initial_values)?;
+        let dyn_loaders = create_dyn_loaders_from_initial_value(&initial_values)?;
+        let dyn_savers = create_dyn_savers_from_initial_value(&initial_values)?;
+
+        let mut rows = vec![];
+        for i in 0..1000 {
+            let mut acc = initial_acc.clone();
+            if i % 3 != 0 {
+                acc.set_fixed_value(addrs[0], i as i64);
+                acc.set_fixed_valid(addrs[0], true);
+            }
+            if i % 5 != 0 {
+                let mut list = AggDynList::default();
+                for j in 0..i % 7 {
+                    list.append(&ScalarValue::Int32(Some(j)), false).unwrap();
+                }
+                *acc.dyn_value_mut(addrs[1]) = Some(Box::new(list));
+            }
+            if i % 2 == 0 {
+                *acc.dyn_value_mut(addrs[2]) =
+                    Some(Box::new(AggDynStr::from_str(&format!("str{i}"))));
+            }
+            rows.push(acc.save_to_bytes(&dyn_savers)?);
+        }
+
+        // batch loading equals per-row loading, also with sliced array
+        let array = BinaryArray::from_iter_values(rows.iter());
+        for array in [array.clone(), array.slice(10, 500)] {
+            let accs = load_accs_from_binary_array(&initial_acc, &array, &dyn_loaders)?;
+            assert_eq!(accs.len(), array.len());
+            for (row_idx, mut acc) in accs.into_iter().enumerate() {
+                let mut expected_acc = initial_acc.clone();
+                expected_acc.load_from_bytes(array.value(row_idx), &dyn_loaders)?;
+                assert_eq!(
+                    &acc.save_to_bytes(&dyn_savers)?[..],
+                    &expected_acc.save_to_bytes(&dyn_savers)?[..],
+                );
+            }
+        }
+
+        // rows with trailing garbage
+        let mut corrupted_row = rows[1].to_vec();
+        corrupted_row.push(0);
+        let array = BinaryArray::from_iter_values([&rows[0][..], &corrupted_row[..], &rows[2][..]]);
+        assert!(load_accs_from_binary_array(&initial_acc, &array, &dyn_loaders).is_err());
+        Ok(())
+    }
 }
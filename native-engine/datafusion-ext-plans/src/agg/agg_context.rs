@@ -25,7 +25,8 @@ use arrow::{
 };
 use blaze_jni_bridge::{
     conf,
-    conf::{DoubleConf, IntConf},
+    conf::{BooleanConf, DoubleConf, IntConf},
+    is_jni_bridge_inited,
 };
 use datafusion::{
     common::{cast::as_binary_array, Result},
@@ -39,10 +40,11 @@ use crate::{
     agg::{
         acc::{
             create_acc_from_initial_value, create_dyn_loaders_from_initial_value,
-            create_dyn_savers_from_initial_value, AccumInitialValue, AccumStateRow, LoadFn,
-            OwnedAccumStateRow, RefAccumStateRow, SaveFn,
+            create_dyn_savers_from_initial_value, load_accs_from_binary_array, AccumInitialValue,
+            AccumStateRow, LoadFn, OwnedAccumStateRow, RefAccumStateRow, SaveFn,
         },
-        Agg, AggExecMode, AggExpr, AggMode, GroupingExpr, AGG_BUF_COLUMN_NAME,
+        partial_batch_merge_aliasing_safe, Agg, AggExecMode, AggExpr, AggMode, GroupingExpr,
+        AGG_BUF_COLUMN_NAME,
     },
     common::cached_exprs_evaluator::CachedExprsEvaluator,
 };
@@ -68,6 +70,7 @@ pub struct AggContext {
     pub supports_partial_skipping: bool,
     pub partial_skipping_ratio: f64,
     pub partial_skipping_min_rows: usize,
+    pub spill_disabled: bool,
     pub agg_expr_evaluator: CachedExprsEvaluator,
     pub acc_dyn_loaders: Vec<LoadFn>,
     pub acc_dyn_savers: Vec<SaveFn>,
@@ -222,6 +225,7 @@ impl AggContext {
         } else {
             Default::default()
         };
+        let spill_disabled = is_jni_bridge_inited() && !conf::AGG_SPILL_ENABLE.value()?;
 
         Ok(Self {
             exec_mode,
@@ -246,6 +250,7 @@ impl AggContext {
             supports_partial_skipping,
             partial_skipping_ratio,
             partial_skipping_min_rows,
+            spill_disabled,
         })
     }
 
@@ -404,14 +409,11 @@ impl AggContext {
         acc_array: &BinaryArray,
     ) -> Result<()> {
         if self.need_partial_merge {
-            let mut input_accs = acc_array
-                .iter()
-                .map(|value| {
-                    let mut input_acc = self.initial_input_acc.clone();
-                    input_acc.load_from_bytes(value.unwrap(), &self.acc_dyn_loaders)?;
-                    Ok(input_acc)
-                })
-                .collect::<Result<Vec<_>>>()?;
+            let mut input_accs = load_accs_from_binary_array(
+                &self.initial_input_acc,
+                acc_array,
+                &self.acc_dyn_loaders,
+            )?;
             let mut input_ref_accs = input_accs
                 .iter_mut()
                 .map(|acc| acc.as_mut())
@@ -420,7 +422,7 @@ impl AggContext {
                 for input_acc in &mut input_ref_accs {
                     agg.increase_acc_mem_used(input_acc);
                 }
-                agg.partial_batch_merge(accs, &mut input_ref_accs)?;
+                partial_batch_merge_aliasing_safe(agg.as_ref(), accs, &mut input_ref_accs)?;
             }
         }
         Ok(())
@@ -457,6 +459,18 @@ impl AggContext {
         Ok(())
     }
 
+    pub fn shrink_acc_to_fit(&self, acc: &mut RefAccumStateRow) {
+        for agg in &self.aggs {
+            agg.agg.shrink_acc_to_fit(acc);
+        }
+    }
+
+    pub fn decrease_acc_mem_used(&self, acc: &mut RefAccumStateRow) {
+        for agg in &self.aggs {
+            agg.agg.decrease_acc_mem_used(acc);
+        }
+    }
+
     pub fn acc_dyn_mem_used(&self) -> usize {
         self.aggs
             .iter()
@@ -13,6 +13,7 @@
 // limitations under the License.
 
 use std::{
+    cmp::Reverse,
     hash::Hasher,
     io::{Cursor, Write},
     sync::{Arc, Weak},
@@ -29,14 +30,14 @@ use blaze_jni_bridge::{
 };
 use bytes::Buf;
 use datafusion::{
-    common::Result,
+    common::{DataFusionError, Result},
     execution::context::TaskContext,
     physical_plan::metrics::{BaselineMetrics, ExecutionPlanMetricsSet},
 };
 use datafusion_ext_commons::{
     array_size::ArraySize,
     bytes_arena::{BytesArena, BytesArenaAddr},
-    downcast_any,
+    df_execution_err, downcast_any,
     ds::rdx_tournament_tree::{KeyForRadixTournamentTree, RadixTournamentTree},
     io::{read_bytes_slice, read_len, write_len},
     rdxsort::radix_sort_u16_ranged_by,
@@ -67,6 +68,52 @@ const SPILL_OFFHEAP_MEM_COST: usize = 200000;
 // number of buckets used in merging/spilling
 const NUM_SPILL_BUCKETS: usize = 64000;
 
+// marks the version header at the start of a spill
+const SPILL_FORMAT_MAGIC: usize = 0x424c5a53;
+
+/// format of agg spills, written once at the start of each spill so that
+/// spills left by older binaries (like in rolling upgrades) are decoded
+/// correctly or rejected, instead of being silently misread
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SpillFormatVersion {
+    /// headerless buckets of records in a legacy lz4 stream
+    V1,
+    /// buckets of records after a version header
+    V2,
+}
+
+impl SpillFormatVersion {
+    pub const CURRENT: Self = Self::V2;
+
+    fn write_header<W: Write>(self, output: &mut W) -> Result<()> {
+        match self {
+            Self::V1 => return df_execution_err!("agg spills of V1 format cannot be written"),
+            Self::V2 => {
+                write_len(SPILL_FORMAT_MAGIC, output)?;
+                write_len(2, output)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// reads the header and returns the version. spills in legacy streams
+    /// (see `SpillCompressedReader::is_legacy()`) are written by older binaries
+    /// and have no header
+    fn read_header(input: &mut SpillCompressedReader) -> Result<Self> {
+        if input.is_legacy() {
+            return Ok(Self::V1);
+        }
+        let magic = read_len(input)?;
+        if magic != SPILL_FORMAT_MAGIC {
+            return df_execution_err!("invalid agg spill format magic: {magic:#x}");
+        }
+        match read_len(input)? {
+            2 => Ok(Self::V2),
+            version => df_execution_err!("unsupported agg spill format version: {version}"),
+        }
+    }
+}
+
 pub struct AggTable {
     name: String,
     mem_consumer_info: Option<Weak<MemConsumerInfo>>,
@@ -151,6 +198,71 @@ impl AggTable {
         !self.spills.lock().await.is_empty()
     }
 
+    /// spills the `k` largest groups of the in-mem table instead of the whole
+    /// table, which frees the most memory per spilled record and keeps small
+    /// groups in memory. works only in hashing mode and when spilling is
+    /// enabled, returns the freed memory
+    pub async fn spill_largest_groups(&self, k: usize) -> Result<usize> {
+        let mut in_mem = self.in_mem.lock().await;
+        if in_mem.mode != InMemMode::Hashing || self.agg_ctx.spill_disabled {
+            return Ok(0);
+        }
+        let old_mem_used = in_mem.mem_used();
+        let groups = in_mem.hashing_data.largest_groups(k);
+        self.spill_groups(&mut in_mem, &groups).await?;
+
+        let mem_used = in_mem.mem_used();
+        drop(in_mem);
+        self.update_mem_used(mem_used).await?;
+        Ok(old_mem_used.saturating_sub(mem_used))
+    }
+
+    /// reduces memory of the in-mem table to `budget` bytes, which lets the
+    /// memory manager free a given amount of memory instead of spilling the
+    /// whole table. accumulators are shrunk to fit first since releasing
+    /// over-allocated capacity needs no io, then the largest groups are
+    /// spilled until the budget is reached. spilled groups are reset to new
+    /// initial values, so they need no shrinking. memory of keys and
+    /// accumulator slots is never freed, so the result may stay above the
+    /// budget. only shrinks if spilling is disabled, returns the freed memory
+    pub async fn spill_to_budget(&self, budget: usize) -> Result<usize> {
+        let mut in_mem = self.in_mem.lock().await;
+        let old_mem_used = in_mem.mem_used();
+        if in_mem.mode != InMemMode::Hashing || old_mem_used <= budget {
+            return Ok(0);
+        }
+        in_mem.hashing_data.shrink_accs_to_fit();
+
+        let mut mem_used = in_mem.mem_used();
+        if mem_used > budget && !self.agg_ctx.spill_disabled {
+            let groups = in_mem
+                .hashing_data
+                .largest_groups_to_free(mem_used - budget);
+            self.spill_groups(&mut in_mem, &groups).await?;
+            mem_used = in_mem.mem_used();
+        }
+        drop(in_mem);
+        self.update_mem_used(mem_used).await?;
+        Ok(old_mem_used.saturating_sub(mem_used))
+    }
+
+    async fn spill_groups(
+        &self,
+        in_mem: &mut InMemTable,
+        groups: &[(BytesArenaAddr, u32)],
+    ) -> Result<()> {
+        if !groups.is_empty() {
+            let mut spill = try_new_spill(&self.spill_metrics)?;
+            in_mem.hashing_data.try_spill_groups(groups, &mut spill)?;
+            self.spills.lock().await.push(spill);
+
+            // like renewing after spilling the whole table, partial skipping
+            // must not be triggered once spills exist
+            in_mem.id += 1;
+        }
+        Ok(())
+    }
+
     pub async fn mode(&self) -> InMemMode {
         self.in_mem.lock().await.mode
     }
@@ -293,6 +405,7 @@ impl AggTable {
             cursors.push(RecordsSpillCursor::try_from_spill(spill, &self.agg_ctx)?);
         }
         let mut current_bucket_idx = 0;
+        let mut current_bucket_acc_addrs = vec![];
         let mut hashing = HashingData::new(
             self.agg_ctx.clone(),
             self.context.clone(),
@@ -333,6 +446,12 @@ impl AggTable {
 
             // meets next bucket -- flush records of current bucket
             if min_cursor.cur_bucket_idx > current_bucket_idx {
+                // records of current bucket are fully merged, release their
+                // over-allocated memory before they are staged for output
+                for acc_addr in std::mem::take(&mut current_bucket_acc_addrs) {
+                    self.agg_ctx
+                        .shrink_acc_to_fit(&mut hashing.acc_store.get(acc_addr));
+                }
                 if hashing.num_records() >= batch_size
                     || hashing.mem_used() + self.agg_ctx.acc_dyn_mem_used() >= target_batch_mem_size
                 {
@@ -365,6 +484,7 @@ impl AggTable {
                         let key_addr = hashing.map_key_store.add(key.as_ref());
                         let acc_addr = hashing.acc_store.new_acc_from(&acc);
                         hashing.map.insert_in_slot(hash, slot, (key_addr, acc_addr));
+                        current_bucket_acc_addrs.push(acc_addr);
                     },
                 }
             }
@@ -406,6 +526,13 @@ impl MemConsumer for AggTable {
         // regardless minRows configuration
         in_mem.check_trigger_partial_skipping();
         if in_mem.mode != InMemMode::PartialSkipped {
+            if self.agg_ctx.spill_disabled {
+                return Err(DataFusionError::ResourcesExhausted(format!(
+                    "{} exceeds memory budget (mem_used: {}) and spilling is disabled",
+                    self.name,
+                    in_mem.mem_used(),
+                )));
+            }
             let mut next_in_mem_mode = InMemMode::Merging;
             if in_mem.mode == InMemMode::Hashing {
                 // use pre-merging if cardinality is low
@@ -537,6 +664,13 @@ pub fn gx_hash<const SEED: i64>(value: impl AsRef<[u8]>) -> u64 {
     h.finish()
 }
 
+#[inline]
+pub fn gx_hash_with_seed(seed: i64, value: impl AsRef<[u8]>) -> u64 {
+    let mut h = GxHasher::with_seed(seed);
+    h.write(value.as_ref());
+    h.finish()
+}
+
 #[inline]
 pub fn gx_merging_bucket_id(value: impl AsRef<[u8]>) -> u16 {
     (gx_hash::<GX_HASH_SEED_MERGING>(value) % NUM_SPILL_BUCKETS as u64) as u16
@@ -646,49 +780,147 @@ impl HashingData {
     }
 
     fn try_into_spill(self, spill: &mut Box<dyn Spill>) -> Result<()> {
-        // sort all records using radix sort on hashcodes of keys
-        let mut bucketed_records = self
+        let records = self
             .map
             .into_iter()
             .map(|(key_addr, acc_addr)| {
                 let key = self.map_key_store.get(key_addr);
                 let acc = self.acc_store.get(acc_addr);
-                let bucket_id = gx_merging_bucket_id(key);
-                (key, acc, bucket_id)
+                (key, acc)
             })
             .collect::<Vec<_>>();
+        write_records_to_spill(records, &self.agg_ctx, spill)
+    }
 
-        let bucket_counts =
-            radix_sort_u16_ranged_by(&mut bucketed_records, NUM_SPILL_BUCKETS, |v| v.2);
+    fn shrink_accs_to_fit(&mut self) {
+        for (_, acc_addr) in unsafe {
+            // safety: accessing hashbrown raw table
+            self.map.iter().map(|bucket| *bucket.as_ref())
+        } {
+            self.agg_ctx
+                .shrink_acc_to_fit(&mut self.acc_store.get(acc_addr));
+        }
+    }
 
-        let mut writer = spill.get_compressed_writer();
-        let mut beg = 0;
+    /// returns addresses and memory sizes of accumulators of all groups
+    fn groups_with_mem_size(&self) -> Vec<(BytesArenaAddr, u32, usize)> {
+        unsafe {
+            // safety: accessing hashbrown raw table
+            self.map.iter().map(|bucket| *bucket.as_ref())
+        }
+        .map(|(key_addr, acc_addr)| {
+            let mem_size = self.acc_store.get(acc_addr).mem_size();
+            (key_addr, acc_addr, mem_size)
+        })
+        .collect()
+    }
 
-        for i in 0..NUM_SPILL_BUCKETS {
-            if bucket_counts[i] > 0 {
-                // write bucket id and number of records in this bucket
-                write_len(i, &mut writer)?;
-                write_len(bucket_counts[i], &mut writer)?;
+    /// returns the `k` groups whose accumulators use the most memory
+    fn largest_groups(&self, k: usize) -> Vec<(BytesArenaAddr, u32)> {
+        let mut groups = self.groups_with_mem_size();
+        let k = k.min(groups.len());
+        if k > 0 && k < groups.len() {
+            groups.select_nth_unstable_by_key(k - 1, |&(_, _, mem_size)| Reverse(mem_size));
+        }
+        groups[..k]
+            .iter()
+            .map(|&(key_addr, acc_addr, _)| (key_addr, acc_addr))
+            .collect()
+    }
 
-                // write records in this bucket
-                for (key, acc, _) in &mut bucketed_records[beg..][..bucket_counts[i]] {
-                    // write key
-                    let key = key.as_ref();
-                    write_len(key.len(), &mut writer)?;
-                    writer.write_all(key)?;
+    /// returns the fewest largest groups whose accumulators free at least
+    /// `mem_to_free` bytes of dyn values if they are reset, or all groups
+    fn largest_groups_to_free(&self, mem_to_free: usize) -> Vec<(BytesArenaAddr, u32)> {
+        let mut groups = self.groups_with_mem_size();
+        groups.sort_unstable_by_key(|&(_, _, mem_size)| Reverse(mem_size));
 
-                    // write value
-                    acc.save(&mut writer, &self.agg_ctx.acc_dyn_savers)?;
-                }
-                beg += bucket_counts[i];
-            }
+        let initial_mem_size = self.agg_ctx.initial_acc.mem_size();
+        let mut freed = 0;
+        groups
+            .into_iter()
+            .take_while(|&(_, _, mem_size)| {
+                let taken = freed < mem_to_free;
+                freed += mem_size.saturating_sub(initial_mem_size);
+                taken
+            })
+            .map(|(key_addr, acc_addr, _)| (key_addr, acc_addr))
+            .collect()
+    }
+
+    /// spills records of the given groups, whose accumulators are then reset
+    /// to the initial values. the groups stay in the table, later updates go
+    /// to the reset accumulators and the spilled states are merged back by key
+    /// on output, like records of whole-table spills
+    fn try_spill_groups(
+        &mut self,
+        groups: &[(BytesArenaAddr, u32)],
+        spill: &mut Box<dyn Spill>,
+    ) -> Result<()> {
+        let records = groups
+            .iter()
+            .map(|&(key_addr, acc_addr)| {
+                let key = self.map_key_store.get(key_addr);
+                let mut acc = self.acc_store.get(acc_addr);
+                self.agg_ctx.decrease_acc_mem_used(&mut acc);
+                (key, acc)
+            })
+            .collect::<Vec<_>>();
+        write_records_to_spill(records, &self.agg_ctx, spill)?;
+
+        for &(_, acc_addr) in groups {
+            self.acc_store.reset_acc(acc_addr);
         }
-        write_len(NUM_SPILL_BUCKETS, &mut writer)?; // EOF
-        write_len(0, &mut writer)?;
         Ok(())
     }
 }
 
+/// writes records into a spill in buckets of key hashes, the dyn values of
+/// the accumulators are moved into the spill
+fn write_records_to_spill(
+    records: Vec<(&[u8], RefAccumStateRow)>,
+    agg_ctx: &AggContext,
+    spill: &mut Box<dyn Spill>,
+) -> Result<()> {
+    // sort all records using radix sort on hashcodes of keys
+    let mut bucketed_records = records
+        .into_iter()
+        .map(|(key, acc)| {
+            let bucket_id = gx_merging_bucket_id(key);
+            (key, acc, bucket_id)
+        })
+        .collect::<Vec<_>>();
+
+    let bucket_counts = radix_sort_u16_ranged_by(&mut bucketed_records, NUM_SPILL_BUCKETS, |v| v.2);
+
+    let mut writer = spill.get_compressed_writer()?;
+    SpillFormatVersion::CURRENT.write_header(&mut writer)?;
+    let mut beg = 0;
+
+    for i in 0..NUM_SPILL_BUCKETS {
+        if bucket_counts[i] > 0 {
+            // write bucket id and number of records in this bucket
+            write_len(i, &mut writer)?;
+            write_len(bucket_counts[i], &mut writer)?;
+
+            // write records in this bucket
+            for (key, acc, _) in &mut bucketed_records[beg..][..bucket_counts[i]] {
+                // write key
+                let key = key.as_ref();
+                write_len(key.len(), &mut writer)?;
+                writer.write_all(key)?;
+
+                // write value
+                acc.save(&mut writer, &agg_ctx.acc_dyn_savers)?;
+            }
+            beg += bucket_counts[i];
+        }
+    }
+    write_len(NUM_SPILL_BUCKETS, &mut writer)?; // EOF
+    write_len(0, &mut writer)?;
+    writer.finish()?;
+    Ok(())
+}
+
 pub struct MergingData {
     agg_ctx: Arc<AggContext>,
     task_ctx: Arc<TaskContext>,
@@ -865,7 +1097,8 @@ impl MergingData {
             NUM_SPILL_BUCKETS,
         );
 
-        let mut writer = spill.get_compressed_writer();
+        let mut writer = spill.get_compressed_writer()?;
+        SpillFormatVersion::CURRENT.write_header(&mut writer)?;
         for bucket_id in 0..NUM_SPILL_BUCKETS {
             let bucket_count = self.bucket_counts[bucket_id];
             if bucket_count == 0 {
@@ -889,6 +1122,7 @@ impl MergingData {
         }
         write_len(NUM_SPILL_BUCKETS, &mut writer)?; // EOF
         write_len(0, &mut writer)?;
+        writer.finish()?;
         Ok(())
     }
 }
@@ -896,6 +1130,7 @@ impl MergingData {
 pub struct RecordsSpillCursor<'a> {
     input: SpillCompressedReader<'a>,
     agg_ctx: Arc<AggContext>,
+    version: SpillFormatVersion,
     cur_bucket_idx: usize,
     cur_bucket_count: usize,
     cur_row_idx: usize,
@@ -903,19 +1138,54 @@ pub struct RecordsSpillCursor<'a> {
 
 impl<'a> RecordsSpillCursor<'a> {
     fn try_from_spill(spill: &'a mut Box<dyn Spill>, agg_ctx: &Arc<AggContext>) -> Result<Self> {
-        let mut input = spill.get_compressed_reader();
-        Ok(Self {
-            agg_ctx: agg_ctx.clone(),
-            cur_bucket_idx: read_len(&mut input)?,
-            cur_bucket_count: read_len(&mut input)?,
+        let mut input = spill.get_compressed_reader()?;
+        let version = SpillFormatVersion::read_header(&mut input).or_else(|err| {
+            df_execution_err!(
+                "error reading agg spill header, spill may be truncated or corrupted: {err}"
+            )
+        })?;
+        let mut cursor = Self {
             input,
+            agg_ctx: agg_ctx.clone(),
+            version,
+            cur_bucket_idx: 0,
+            cur_bucket_count: 0,
             cur_row_idx: 0,
-        })
+        };
+        cursor.read_bucket_header()?;
+        Ok(cursor)
     }
 
     fn next_record(&mut self) -> Result<(SlimBytes, OwnedAccumStateRow)> {
-        assert!(self.cur_bucket_idx < NUM_SPILL_BUCKETS);
+        if self.cur_bucket_idx >= NUM_SPILL_BUCKETS {
+            return df_execution_err!("reading agg spill records after EOF");
+        }
+        let record = self.read_record().or_else(|err| {
+            df_execution_err!(
+                "error reading agg spill record {}/{} of bucket {}, \
+                 spill may be truncated or corrupted: {err}",
+                self.cur_row_idx,
+                self.cur_bucket_count,
+                self.cur_bucket_idx,
+            )
+        })?;
+
+        // forward next row, load next bucket if current bucket is finished
+        self.cur_row_idx += 1;
+        if self.cur_row_idx == self.cur_bucket_count {
+            self.read_bucket_header()?;
+        }
+        Ok(record)
+    }
 
+    fn read_record(&mut self) -> Result<(SlimBytes, OwnedAccumStateRow)> {
+        match self.version {
+            // records are not changed since the version header is introduced
+            SpillFormatVersion::V1 | SpillFormatVersion::V2 => self.read_record_v1(),
+        }
+    }
+
+    fn read_record_v1(&mut self) -> Result<(SlimBytes, OwnedAccumStateRow)> {
         // read key
         let key_len = read_len(&mut self.input)?;
         let key = read_bytes_slice(&mut self.input, key_len)?.into();
@@ -923,16 +1193,42 @@ impl<'a> RecordsSpillCursor<'a> {
         // read value
         let mut value = self.agg_ctx.initial_acc.clone();
         value.load(&mut self.input, &self.agg_ctx.acc_dyn_loaders)?;
+        Ok((key, value))
+    }
 
-        // forward next row, load next bucket if current bucket is finished
-        self.cur_row_idx += 1;
-        if self.cur_row_idx == self.cur_bucket_count {
-            assert!(self.cur_bucket_idx < NUM_SPILL_BUCKETS);
-            self.cur_row_idx = 0;
-            self.cur_bucket_idx = read_len(&mut self.input)?;
-            self.cur_bucket_count = read_len(&mut self.input)?;
+    /// reads header of the next bucket, bucket indices must be increasing and
+    /// the last header must be EOF (NUM_SPILL_BUCKETS, 0), which ensures all
+    /// records are consumed
+    fn read_bucket_header(&mut self) -> Result<()> {
+        let mut read_header = || -> Result<(usize, usize)> {
+            Ok((read_len(&mut self.input)?, read_len(&mut self.input)?))
+        };
+        let (bucket_idx, bucket_count) = read_header().or_else(|err| {
+            df_execution_err!(
+                "error reading agg spill bucket header after bucket {}, \
+                 spill may be truncated or corrupted: {err}",
+                self.cur_bucket_idx,
+            )
+        })?;
+
+        let is_first_bucket = self.cur_bucket_count == 0;
+        let is_valid = match bucket_idx {
+            NUM_SPILL_BUCKETS => bucket_count == 0,
+            idx if idx < NUM_SPILL_BUCKETS => {
+                bucket_count > 0 && (is_first_bucket || idx > self.cur_bucket_idx)
+            }
+            _ => false,
+        };
+        if !is_valid {
+            return df_execution_err!(
+                "invalid agg spill bucket header after bucket {}: ({bucket_idx}, {bucket_count})",
+                self.cur_bucket_idx,
+            );
         }
-        Ok((key, value))
+        self.cur_bucket_idx = bucket_idx;
+        self.cur_bucket_count = bucket_count;
+        self.cur_row_idx = 0;
+        Ok(())
     }
 }
 
@@ -941,3 +1237,372 @@ impl<'a> KeyForRadixTournamentTree for RecordsSpillCursor<'a> {
         self.cur_bucket_idx
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::{io::Write, sync::Arc};
+
+    use arrow::{
+        array::{Array, Int32Array, ListArray},
+        datatypes::{DataType, Field, Schema},
+        record_batch::RecordBatch,
+    };
+    use datafusion::{
+        common::{DataFusionError, Result, ScalarValue},
+        physical_expr::expressions::Column,
+        physical_plan::metrics::ExecutionPlanMetricsSet,
+        prelude::SessionContext,
+    };
+    use datafusion_ext_commons::{downcast_any, io::write_len};
+
+    use crate::{
+        agg::{
+            acc::AccumStateRow,
+            agg_context::AggContext,
+            agg_table::{
+                AggTable, InMemMode, RecordsSpillCursor, SpillFormatVersion, NUM_SPILL_BUCKETS,
+                SPILL_FORMAT_MAGIC,
+            },
+            collect_list::AggCollectList,
+            Agg, AggExecMode, AggExpr, AggMode, GroupingExpr, WithMemTracking,
+        },
+        common::output::WrappedRecordBatchSender,
+        memmgr::{spill::Spill, MemConsumer, MemManager},
+    };
+
+    fn create_agg_ctx() -> Result<Arc<AggContext>> {
+        let input_schema = Arc::new(Schema::new(vec![Field::new("a", DataType::Int32, false)]));
+        let agg = AggCollectList::try_new(
+            Arc::new(Column::new("a", 0)),
+            DataType::new_list(DataType::Int32, true),
+            DataType::Int32,
+        )?;
+        Ok(Arc::new(AggContext::try_new(
+            AggExecMode::HashAgg,
+            input_schema,
+            vec![GroupingExpr {
+                field_name: "a".to_string(),
+                expr: Arc::new(Column::new("a", 0)),
+            }],
+            vec![AggExpr {
+                field_name: "b".to_string(),
+                mode: AggMode::Partial,
+                agg: Arc::new(agg),
+            }],
+            0,
+            false,
+        )?))
+    }
+
+    fn create_spill(
+        agg_ctx: &AggContext,
+        bucket_count: usize,
+        num_records: usize,
+        with_eof: bool,
+    ) -> Result<Box<dyn Spill>> {
+        let mut spill: Box<dyn Spill> = Box::new(vec![]);
+        let mut writer = spill.get_compressed_writer()?;
+        SpillFormatVersion::CURRENT.write_header(&mut writer)?;
+        write_spill_records(agg_ctx, &mut writer, bucket_count, num_records, with_eof)?;
+        writer.finish()?;
+        Ok(spill)
+    }
+
+    fn write_spill_records<W: Write>(
+        agg_ctx: &AggContext,
+        output: &mut W,
+        bucket_count: usize,
+        num_records: usize,
+        with_eof: bool,
+    ) -> Result<()> {
+        write_len(3, output)?;
+        write_len(bucket_count, output)?;
+        for i in 0..num_records {
+            let key = format!("key{i}");
+            write_len(key.len(), output)?;
+            output.write_all(key.as_bytes())?;
+            let mut acc = agg_ctx.initial_acc.clone();
+            acc.save(&mut *output, &agg_ctx.acc_dyn_savers)?;
+        }
+        if with_eof {
+            write_len(NUM_SPILL_BUCKETS, output)?;
+            write_len(0, output)?;
+        }
+        Ok(())
+    }
+
+    fn assert_execution_error<T>(result: Result<T>) {
+        assert!(matches!(result, Err(DataFusionError::Execution(_))));
+    }
+
+    #[test]
+    fn test_read_spill() -> Result<()> {
+        let agg_ctx = create_agg_ctx()?;
+        let mut spill = create_spill(&agg_ctx, 2, 2, true)?;
+        let mut cursor = RecordsSpillCursor::try_from_spill(&mut spill, &agg_ctx)?;
+        assert_eq!(cursor.cur_bucket_idx, 3);
+        assert_eq!(cursor.next_record()?.0.as_ref(), b"key0");
+        assert_eq!(cursor.next_record()?.0.as_ref(), b"key1");
+        assert_eq!(cursor.cur_bucket_idx, NUM_SPILL_BUCKETS);
+        assert_execution_error(cursor.next_record());
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_truncated_spill() -> Result<()> {
+        let agg_ctx = create_agg_ctx()?;
+
+        // truncated in the middle of a bucket
+        let mut spill = create_spill(&agg_ctx, 2, 1, false)?;
+        let mut cursor = RecordsSpillCursor::try_from_spill(&mut spill, &agg_ctx)?;
+        assert_eq!(cursor.next_record()?.0.as_ref(), b"key0");
+        assert_execution_error(cursor.next_record());
+
+        // truncated before EOF
+        let mut spill = create_spill(&agg_ctx, 1, 1, false)?;
+        let mut cursor = RecordsSpillCursor::try_from_spill(&mut spill, &agg_ctx)?;
+        assert_execution_error(cursor.next_record());
+
+        // empty spill
+        let mut spill: Box<dyn Spill> = Box::new(vec![]);
+        assert_execution_error(RecordsSpillCursor::try_from_spill(&mut spill, &agg_ctx));
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_spill_versions() -> Result<()> {
+        let agg_ctx = create_agg_ctx()?;
+
+        // older binaries write headerless spills into plain lz4 streams
+        let mut legacy_spill: Vec<u8> = vec![];
+        let mut encoder = lz4_flex::frame::FrameEncoder::new(&mut legacy_spill);
+        write_spill_records(&agg_ctx, &mut encoder, 2, 2, true)?;
+        encoder.finish().unwrap();
+        let legacy_spill: Box<dyn Spill> = Box::new(legacy_spill);
+
+        let current_spill = create_spill(&agg_ctx, 2, 2, true)?;
+        for (mut spill, version) in [
+            (legacy_spill, SpillFormatVersion::V1),
+            (current_spill, SpillFormatVersion::V2),
+        ] {
+            let mut cursor = RecordsSpillCursor::try_from_spill(&mut spill, &agg_ctx)?;
+            assert_eq!(cursor.version, version);
+            assert_eq!(cursor.next_record()?.0.as_ref(), b"key0");
+            assert_eq!(cursor.next_record()?.0.as_ref(), b"key1");
+            assert_eq!(cursor.cur_bucket_idx, NUM_SPILL_BUCKETS);
+        }
+
+        // headerless records in a current stream are rejected
+        let mut spill: Box<dyn Spill> = Box::new(vec![]);
+        let mut writer = spill.get_compressed_writer()?;
+        write_spill_records(&agg_ctx, &mut writer, 2, 2, true)?;
+        writer.finish()?;
+        assert_execution_error(RecordsSpillCursor::try_from_spill(&mut spill, &agg_ctx));
+
+        // unknown versions are rejected
+        let mut spill: Box<dyn Spill> = Box::new(vec![]);
+        let mut writer = spill.get_compressed_writer()?;
+        write_len(SPILL_FORMAT_MAGIC, &mut writer)?;
+        write_len(3, &mut writer)?;
+        write_len(NUM_SPILL_BUCKETS, &mut writer)?;
+        write_len(0, &mut writer)?;
+        writer.finish()?;
+        assert_execution_error(RecordsSpillCursor::try_from_spill(&mut spill, &agg_ctx));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_spill_largest_groups() -> Result<()> {
+        MemManager::init(10000);
+
+        let agg_ctx = create_agg_ctx()?;
+        let agg = downcast_any!(agg_ctx.aggs[0].agg, AggCollectList)?;
+        let values = [vec![0; 30], vec![1; 20], (2..10).collect()].concat();
+        let batch = RecordBatch::try_new(
+            agg_ctx.input_schema.clone(),
+            vec![Arc::new(Int32Array::from(values))],
+        )?;
+        let table = Arc::new(AggTable::new(
+            0,
+            agg_ctx.clone(),
+            SessionContext::new().task_ctx(),
+            &ExecutionPlanMetricsSet::new(),
+        ));
+        // unspillable, so the mem manager never spills the table by itself
+        MemManager::register_consumer(table.clone(), false);
+        table.process_input_batch(batch).await?;
+        let total_raw_bytes = agg.total_raw_bytes();
+
+        let freed = table.spill_largest_groups(2).await?;
+        assert!(freed > 0);
+        assert!(table.has_spill().await);
+        assert!(agg.total_raw_bytes() < total_raw_bytes);
+
+        // tracked memory matches the remaining groups, whose values are all kept
+        let in_mem = table.in_mem.lock().await;
+        assert_eq!(in_mem.num_records(), 10);
+        let mut remaining_accs = unsafe {
+            // safety: accessing hashbrown raw table
+            in_mem
+                .hashing_data
+                .map
+                .iter()
+                .map(|bucket| bucket.as_ref().1)
+        }
+        .map(|acc_addr| in_mem.hashing_data.acc_store.get(acc_addr))
+        .collect::<Vec<_>>();
+        let tracked = (agg.mem_used(), agg.total_raw_bytes());
+        agg.reset_mem_used();
+        for acc in &mut remaining_accs {
+            agg.increase_acc_mem_used(acc);
+        }
+        assert_eq!((agg.mem_used(), agg.total_raw_bytes()), tracked);
+        let remaining = agg.final_batch_merge(&mut remaining_accs)?;
+        let remaining = downcast_any!(remaining, ListArray)?;
+        assert_eq!(remaining.values().len(), 8);
+        drop(in_mem);
+
+        // the two largest groups are spilled
+        let mut spills = table.spills.lock().await;
+        let mut cursor = RecordsSpillCursor::try_from_spill(&mut spills[0], &agg_ctx)?;
+        let mut spilled_lens = vec![];
+        while cursor.cur_bucket_idx < NUM_SPILL_BUCKETS {
+            let (_key, mut acc) = cursor.next_record()?;
+            let spilled = agg.final_merge(&mut acc.as_mut())?;
+            let ScalarValue::List(spilled) = spilled else {
+                unreachable!("collect_list outputs list scalars");
+            };
+            spilled_lens.push(spilled.values().len());
+        }
+        spilled_lens.sort();
+        assert_eq!(spilled_lens, vec![20, 30]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_no_partial_skipping_after_spilling_groups() -> Result<()> {
+        MemManager::init(10000);
+
+        let mut agg_ctx = create_agg_ctx()?;
+        let agg_ctx_mut = Arc::get_mut(&mut agg_ctx).unwrap();
+        agg_ctx_mut.supports_partial_skipping = true;
+        agg_ctx_mut.partial_skipping_ratio = 0.5;
+        agg_ctx_mut.partial_skipping_min_rows = 1;
+        let create_batch = |values: Vec<i32>| {
+            RecordBatch::try_new(
+                agg_ctx.input_schema.clone(),
+                vec![Arc::new(Int32Array::from(values))],
+            )
+        };
+        let task_ctx = SessionContext::new().task_ctx();
+        let table = Arc::new(AggTable::new(
+            0,
+            agg_ctx.clone(),
+            task_ctx.clone(),
+            &ExecutionPlanMetricsSet::new(),
+        ));
+        // unspillable, so the mem manager never spills the table by itself
+        MemManager::register_consumer(table.clone(), false);
+
+        let values = [vec![0; 30], vec![1; 20], (2..10).collect()].concat();
+        table.process_input_batch(create_batch(values)?).await?;
+        table.spill_largest_groups(2).await?;
+        assert!(table.has_spill().await);
+
+        // high cardinality input would trigger partial skipping without spills
+        table
+            .process_input_batch(create_batch((100..200).collect())?)
+            .await?;
+        assert_eq!(table.mode().await, InMemMode::Hashing);
+
+        // spilled and in-mem groups are merged by key
+        let (tx, mut rx) = tokio::sync::mpsc::channel(16);
+        table
+            .output(WrappedRecordBatchSender::new(task_ctx, tx))
+            .await?;
+        let mut num_rows = 0;
+        while let Ok(batch) = rx.try_recv() {
+            num_rows += batch?.num_rows();
+        }
+        assert_eq!(num_rows, 110);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_spill_to_budget() -> Result<()> {
+        MemManager::init(10000);
+
+        let agg_ctx = create_agg_ctx()?;
+        let agg = downcast_any!(agg_ctx.aggs[0].agg, AggCollectList)?;
+        let values = [vec![0; 30], vec![1; 20], vec![2; 10], (3..10).collect()].concat();
+        let batch = RecordBatch::try_new(
+            agg_ctx.input_schema.clone(),
+            vec![Arc::new(Int32Array::from(values))],
+        )?;
+        let table = Arc::new(AggTable::new(
+            0,
+            agg_ctx.clone(),
+            SessionContext::new().task_ctx(),
+            &ExecutionPlanMetricsSet::new(),
+        ));
+        // unspillable, so the mem manager never spills the table by itself
+        MemManager::register_consumer(table.clone(), false);
+        table.process_input_batch(batch).await?;
+        let mem_used = table.in_mem.lock().await.mem_used();
+
+        // nothing to do within the budget
+        assert_eq!(table.spill_to_budget(mem_used).await?, 0);
+        assert!(!table.has_spill().await);
+
+        // frees half of the memory of dyn values
+        let budget = mem_used - agg.mem_used() / 2;
+        let freed = table.spill_to_budget(budget).await?;
+        let cur_mem_used = table.in_mem.lock().await.mem_used();
+        assert!(cur_mem_used <= budget);
+        assert_eq!(freed, mem_used - cur_mem_used);
+
+        // memory of keys and accumulator slots is kept with a zero budget
+        table.spill_to_budget(0).await?;
+        assert!(table.has_spill().await);
+        assert_eq!(agg.mem_used(), 0);
+        assert_eq!(agg.total_raw_bytes(), 0);
+        assert_eq!(table.in_mem.lock().await.num_records(), 10);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_spill_disabled() -> Result<()> {
+        MemManager::init(10000);
+
+        for spill_disabled in [false, true] {
+            let mut agg_ctx = create_agg_ctx()?;
+            Arc::get_mut(&mut agg_ctx).unwrap().spill_disabled = spill_disabled;
+            let batch = RecordBatch::try_new(
+                agg_ctx.input_schema.clone(),
+                vec![Arc::new(Int32Array::from_iter_values(0..100))],
+            )?;
+            let table = Arc::new(AggTable::new(
+                0,
+                agg_ctx,
+                SessionContext::new().task_ctx(),
+                &ExecutionPlanMetricsSet::new(),
+            ));
+            MemManager::register_consumer(table.clone(), true);
+            table.process_input_batch(batch).await?;
+
+            // the mem manager triggers spilling when the budget is reached
+            let result = table.spill().await;
+            if spill_disabled {
+                assert!(matches!(
+                    result,
+                    Err(DataFusionError::ResourcesExhausted(_))
+                ));
+                assert!(!table.has_spill().await);
+            } else {
+                result?;
+                assert!(table.has_spill().await);
+            }
+        }
+        Ok(())
+    }
+}
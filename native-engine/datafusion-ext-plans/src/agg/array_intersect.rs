@@ -0,0 +1,318 @@
+// Copyright 2022 The Blaze Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::{
+    any::Any,
+    fmt::{Debug, Formatter},
+    sync::{atomic::AtomicUsize, Arc},
+};
+
+use arrow::{array::*, datatypes::*};
+use datafusion::{
+    common::{Result, ScalarValue},
+    physical_expr::PhysicalExpr,
+};
+use datafusion_ext_commons::{
+    df_execution_err, df_unimplemented_err, downcast_any, io::is_scalar_serde_supported,
+};
+
+use crate::agg::{
+    acc::{
+        AccumInitialValue, AccumStateRow, AccumStateValAddr, AggDynSet, AggDynValue,
+        RefAccumStateRow, AGG_DYN_SET_HASH_SEED,
+    },
+    collect_list::{as_merging_lists, build_list},
+    Agg, WithAggBufAddrs, WithMemTracking,
+};
+
+/// intersection of all input arrays, like array_intersect() of all arrays in
+/// a group. the states are the set of values contained in all arrays seen so
+/// far, and a flag of whether any array is seen, since a group without arrays
+/// is the identity of intersection. null arrays and null elements are skipped,
+/// and values are output in the order of the first array seen
+pub struct AggArrayIntersect {
+    child: Arc<dyn PhysicalExpr>,
+    data_type: DataType,
+    arg_type: DataType,
+    accums_initial: [AccumInitialValue; 2],
+    accum_state_val_addrs: [AccumStateValAddr; 2],
+    mem_used_tracker: AtomicUsize,
+}
+
+impl WithAggBufAddrs for AggArrayIntersect {
+    fn set_accum_state_val_addrs(&mut self, accum_state_val_addrs: &[AccumStateValAddr]) {
+        self.accum_state_val_addrs
+            .copy_from_slice(&accum_state_val_addrs[..2]);
+    }
+}
+
+impl WithMemTracking for AggArrayIntersect {
+    fn mem_used_tracker(&self) -> &AtomicUsize {
+        &self.mem_used_tracker
+    }
+}
+
+impl AggArrayIntersect {
+    /// the input is a List of `arg_type`, and `data_type` is the same List
+    /// type
+    pub fn try_new(
+        child: Arc<dyn PhysicalExpr>,
+        data_type: DataType,
+        arg_type: DataType,
+    ) -> Result<Self> {
+        if !is_scalar_serde_supported(&arg_type) {
+            return df_unimplemented_err!(
+                "array_intersect does not support argument type: {arg_type}"
+            );
+        }
+        Ok(Self {
+            child,
+            data_type,
+            accums_initial: [
+                AccumInitialValue::DynSet(arg_type.clone(), AGG_DYN_SET_HASH_SEED, 0),
+                AccumInitialValue::Scalar(ScalarValue::Boolean(None)),
+            ],
+            arg_type,
+            accum_state_val_addrs: [AccumStateValAddr::default(); 2],
+            mem_used_tracker: AtomicUsize::new(0),
+        })
+    }
+
+    pub fn arg_type(&self) -> &DataType {
+        &self.arg_type
+    }
+
+    fn intersect_values(&self, acc: &mut RefAccumStateRow, values: &ArrayRef) -> Result<()> {
+        let mut values_set = AggDynSet::with_hash_seed(AGG_DYN_SET_HASH_SEED);
+        for i in 0..values.len() {
+            if values.is_valid(i) {
+                values_set.append(&ScalarValue::try_from_array(values, i)?, false)?;
+            }
+        }
+        self.intersect_set(acc, values_set)
+    }
+
+    fn intersect_set(&self, acc: &mut RefAccumStateRow, other: AggDynSet) -> Result<()> {
+        let [set_addr, seen_addr] = self.accum_state_val_addrs;
+        let seen = acc.is_fixed_valid(seen_addr);
+        acc.set_fixed_valid(seen_addr, true);
+
+        match acc.dyn_value_mut(set_addr) {
+            Some(dyn_set) => {
+                let set = downcast_any!(dyn_set, mut AggDynSet)?;
+                self.sub_mem_used(set.mem_size());
+                if seen {
+                    set.intersect(&other);
+                } else {
+                    *set = other;
+                }
+                self.add_mem_used(set.mem_size());
+            }
+            w => {
+                self.add_mem_used(other.mem_size());
+                *w = Some(Box::new(other));
+            }
+        }
+        Ok(())
+    }
+
+    /// takes the set, returns None if no array is seen
+    fn take_set(&self, acc: &mut RefAccumStateRow) -> Result<Option<AggDynSet>> {
+        let [set_addr, seen_addr] = self.accum_state_val_addrs;
+        let set = match std::mem::take(acc.dyn_value_mut(set_addr)) {
+            Some(w) => {
+                let set = w
+                    .as_any_boxed()
+                    .downcast::<AggDynSet>()
+                    .or_else(|_| df_execution_err!("error downcasting to AggDynSet"))?;
+                self.sub_mem_used(set.mem_size());
+                *set
+            }
+            None => AggDynSet::with_hash_seed(AGG_DYN_SET_HASH_SEED),
+        };
+        Ok(acc.is_fixed_valid(seen_addr).then_some(set))
+    }
+
+    fn take_array(&self, acc: &mut RefAccumStateRow) -> Result<Option<ArrayRef>> {
+        self.take_set(acc)?
+            .map(|set| set.into_array(&self.arg_type, false))
+            .transpose()
+    }
+}
+
+impl Debug for AggArrayIntersect {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "ArrayIntersect({:?})", self.child)
+    }
+}
+
+impl Agg for AggArrayIntersect {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn exprs(&self) -> Vec<Arc<dyn PhysicalExpr>> {
+        vec![self.child.clone()]
+    }
+
+    fn with_new_exprs(&self, exprs: Vec<Arc<dyn PhysicalExpr>>) -> Result<Arc<dyn Agg>> {
+        Ok(Arc::new(Self::try_new(
+            exprs[0].clone(),
+            self.data_type.clone(),
+            self.arg_type.clone(),
+        )?))
+    }
+
+    fn data_type(&self) -> &DataType {
+        &self.data_type
+    }
+
+    fn nullable(&self) -> bool {
+        true
+    }
+
+    fn accums_initial(&self) -> &[AccumInitialValue] {
+        &self.accums_initial
+    }
+
+    fn increase_acc_mem_used(&self, acc: &mut RefAccumStateRow) {
+        if let Some(v) = acc.dyn_value(self.accum_state_val_addrs[0]) {
+            self.add_mem_used(v.mem_size());
+        }
+    }
+
+    fn decrease_acc_mem_used(&self, acc: &mut RefAccumStateRow) {
+        if let Some(v) = acc.dyn_value(self.accum_state_val_addrs[0]) {
+            self.sub_mem_used(v.mem_size());
+        }
+    }
+
+    fn partial_update(
+        &self,
+        acc: &mut RefAccumStateRow,
+        values: &[ArrayRef],
+        row_idx: usize,
+    ) -> Result<()> {
+        let lists = as_merging_lists(&values[0])?;
+        if lists.is_valid(row_idx) {
+            self.intersect_values(acc, &lists.value(row_idx))?;
+        }
+        Ok(())
+    }
+
+    fn partial_update_all(&self, acc: &mut RefAccumStateRow, values: &[ArrayRef]) -> Result<()> {
+        let lists = as_merging_lists(&values[0])?;
+        for i in 0..lists.len() {
+            if lists.is_valid(i) {
+                self.intersect_values(acc, &lists.value(i))?;
+            }
+        }
+        Ok(())
+    }
+
+    fn partial_merge(
+        &self,
+        acc: &mut RefAccumStateRow,
+        merging_acc: &mut RefAccumStateRow,
+    ) -> Result<()> {
+        // a merging group without any arrays does not change the intersection
+        if let Some(merging_set) = self.take_set(merging_acc)? {
+            self.intersect_set(acc, merging_set)?;
+        }
+        Ok(())
+    }
+
+    fn final_merge(&self, acc: &mut RefAccumStateRow) -> Result<ScalarValue> {
+        let array = build_list(&self.data_type, vec![self.take_array(acc)?])?;
+        ScalarValue::try_from_array(&array, 0)
+    }
+
+    fn final_batch_merge(&self, accs: &mut [RefAccumStateRow]) -> Result<ArrayRef> {
+        let groups = accs
+            .iter_mut()
+            .map(|acc| self.take_array(acc))
+            .collect::<Result<Vec<_>>>()?;
+        build_list(&self.data_type, groups)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use arrow::{array::*, datatypes::*};
+    use datafusion::{common::Result, physical_expr::expressions::Column};
+
+    use crate::agg::{
+        acc::create_acc_from_initial_value, array_intersect::AggArrayIntersect, Agg,
+        WithAggBufAddrs,
+    };
+
+    #[test]
+    fn test_array_intersect() -> Result<()> {
+        let data_type = DataType::new_list(DataType::Int32, true);
+        let mut agg = AggArrayIntersect::try_new(
+            Arc::new(Column::new("a", 0)),
+            data_type.clone(),
+            DataType::Int32,
+        )?;
+        let (initial_acc, addrs) = create_acc_from_initial_value(agg.accums_initial())?;
+        agg.set_accum_state_val_addrs(&addrs);
+
+        let lists: ArrayRef = Arc::new(ListArray::from_iter_primitive::<Int32Type, _, _>(vec![
+            Some(vec![Some(5), Some(1), Some(3), Some(2), Some(4), Some(6)]),
+            Some(vec![Some(1), None, Some(2), Some(3), Some(5), Some(2)]),
+            None,
+            Some(vec![Some(3), Some(5), Some(7)]),
+            Some(vec![Some(9)]),
+            Some(vec![]),
+        ]));
+
+        // group 0: three-way intersect of merged partials, with an empty partial
+        // group 1: three-way intersect updated row by row, with a null array
+        // group 2: intersect with an empty array
+        // group 3: only a null array
+        let mut accs = vec![initial_acc.clone(); 4];
+        let mut merging_accs = vec![initial_acc.clone(); 3];
+        agg.partial_update_all(&mut merging_accs[0].as_mut(), &[lists.slice(0, 1)])?;
+        agg.partial_update_all(&mut merging_accs[1].as_mut(), &[lists.slice(1, 2)])?;
+        agg.partial_update_all(&mut merging_accs[2].as_mut(), &[lists.slice(3, 1)])?;
+        let mut empty_acc = initial_acc.clone();
+        agg.partial_merge(&mut merging_accs[1].as_mut(), &mut empty_acc.as_mut())?;
+        agg.partial_merge(&mut accs[0].as_mut(), &mut merging_accs[0].as_mut())?;
+        agg.partial_merge(&mut merging_accs[2].as_mut(), &mut merging_accs[1].as_mut())?;
+        agg.partial_merge(&mut accs[0].as_mut(), &mut merging_accs[2].as_mut())?;
+
+        for row_idx in 0..4 {
+            agg.partial_update(&mut accs[1].as_mut(), &[lists.clone()], row_idx)?;
+        }
+        agg.partial_update_all(&mut accs[2].as_mut(), &[lists.slice(4, 2)])?;
+        agg.partial_update_all(&mut accs[3].as_mut(), &[lists.slice(2, 1)])?;
+
+        let mut ref_accs = accs.iter_mut().map(|acc| acc.as_mut()).collect::<Vec<_>>();
+        let results = agg.final_batch_merge(&mut ref_accs)?;
+        assert_eq!(results.data_type(), &data_type);
+        assert_eq!(
+            results.to_data(),
+            ListArray::from_iter_primitive::<Int32Type, _, _>(vec![
+                Some(vec![Some(5), Some(3)]),
+                Some(vec![Some(5), Some(3)]),
+                Some(vec![]),
+                None,
+            ])
+            .to_data(),
+        );
+        Ok(())
+    }
+}
@@ -125,6 +125,10 @@ impl Agg for AggAvg {
         // do nothing
     }
 
+    fn decrease_acc_mem_used(&self, _acc: &mut RefAccumStateRow) {
+        // do nothing
+    }
+
     fn partial_update(
         &self,
         acc: &mut RefAccumStateRow,
@@ -0,0 +1,449 @@
+// Copyright 2022 The Blaze Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::{
+    any::Any,
+    fmt::{Debug, Formatter},
+    sync::{atomic::AtomicUsize, Arc},
+};
+
+use arrow::{array::*, datatypes::*};
+use datafusion::{
+    common::{Result, ScalarValue},
+    physical_expr::PhysicalExpr,
+};
+use datafusion_ext_commons::{
+    df_execution_err, df_unimplemented_err, downcast_any, io::is_scalar_serde_supported,
+};
+
+use crate::agg::{
+    acc::{
+        AccumInitialValue, AccumStateRow, AccumStateValAddr, AggDynList, AggDynValue,
+        RefAccumStateRow,
+    },
+    Agg, WithAggBufAddrs, WithMemTracking,
+};
+
+/// collect_list() which keeps only the first `limit` values of each group,
+/// used for `slice(collect_list(x), 1, limit)`
+pub struct AggBoundedCollectList {
+    child: Arc<dyn PhysicalExpr>,
+    data_type: DataType,
+    list_type: DataType,
+    arg_type: DataType,
+    limit: usize,
+    report_truncated: bool,
+    accum_initial: [AccumInitialValue; 3],
+    list_addr: AccumStateValAddr,
+    count_addr: AccumStateValAddr,
+    truncated_addr: AccumStateValAddr,
+    mem_used_tracker: AtomicUsize,
+}
+
+impl WithAggBufAddrs for AggBoundedCollectList {
+    fn set_accum_state_val_addrs(&mut self, accum_state_val_addrs: &[AccumStateValAddr]) {
+        self.list_addr = accum_state_val_addrs[0];
+        self.count_addr = accum_state_val_addrs[1];
+        self.truncated_addr = accum_state_val_addrs[2];
+    }
+}
+
+impl WithMemTracking for AggBoundedCollectList {
+    fn mem_used_tracker(&self) -> &AtomicUsize {
+        &self.mem_used_tracker
+    }
+}
+
+impl AggBoundedCollectList {
+    /// `list_type` is the List type of collected values. if `report_truncated`
+    /// is true, the output is a struct of {values, truncated}, where truncated
+    /// indicates that the group had more than `limit` values
+    pub fn try_new(
+        child: Arc<dyn PhysicalExpr>,
+        list_type: DataType,
+        arg_type: DataType,
+        limit: usize,
+        report_truncated: bool,
+    ) -> Result<Self> {
+        if !is_scalar_serde_supported(&arg_type) {
+            return df_unimplemented_err!(
+                "bounded_collect_list does not support argument type: {arg_type}"
+            );
+        }
+        let data_type = if report_truncated {
+            DataType::Struct(Fields::from(vec![
+                Field::new("values", list_type.clone(), true),
+                Field::new("truncated", DataType::Boolean, false),
+            ]))
+        } else {
+            list_type.clone()
+        };
+        Ok(Self {
+            child,
+            data_type,
+            list_type,
+            accum_initial: [
+                AccumInitialValue::DynList(arg_type.clone()),
+                AccumInitialValue::Scalar(ScalarValue::Int64(Some(0))),
+                AccumInitialValue::Scalar(ScalarValue::Boolean(Some(false))),
+            ],
+            arg_type,
+            limit,
+            report_truncated,
+            list_addr: AccumStateValAddr::default(),
+            count_addr: AccumStateValAddr::default(),
+            truncated_addr: AccumStateValAddr::default(),
+            mem_used_tracker: AtomicUsize::new(0),
+        })
+    }
+
+    pub fn arg_type(&self) -> &DataType {
+        &self.arg_type
+    }
+
+    pub fn limit(&self) -> usize {
+        self.limit
+    }
+
+    pub fn report_truncated(&self) -> bool {
+        self.report_truncated
+    }
+
+    fn num_items(&self, acc: &RefAccumStateRow) -> usize {
+        acc.fixed_value::<i64>(self.count_addr) as usize
+    }
+
+    fn is_truncated(&self, acc: &RefAccumStateRow) -> bool {
+        acc.fixed_value::<bool>(self.truncated_addr)
+    }
+
+    fn set_truncated(&self, acc: &mut RefAccumStateRow) {
+        acc.set_fixed_value::<bool>(self.truncated_addr, true);
+    }
+
+    fn take_list(&self, acc: &mut RefAccumStateRow) -> Result<ScalarValue> {
+        match std::mem::take(acc.dyn_value_mut(self.list_addr)) {
+            Some(w) => {
+                let list = w
+                    .as_any_boxed()
+                    .downcast::<AggDynList>()
+                    .or_else(|_| df_execution_err!("error downcasting to AggDynList"))?;
+                self.sub_mem_used(list.mem_size());
+
+                Ok(ScalarValue::List(ScalarValue::new_list(
+                    &list
+                        .into_values(self.arg_type.clone(), false)
+                        .collect::<Vec<_>>(),
+                    &self.arg_type,
+                )))
+            }
+            None => ScalarValue::try_from(&self.list_type),
+        }
+    }
+}
+
+impl Debug for AggBoundedCollectList {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "BoundedCollectList({:?}, {})", self.child, self.limit)
+    }
+}
+
+impl Agg for AggBoundedCollectList {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn exprs(&self) -> Vec<Arc<dyn PhysicalExpr>> {
+        vec![self.child.clone()]
+    }
+
+    fn with_new_exprs(&self, exprs: Vec<Arc<dyn PhysicalExpr>>) -> Result<Arc<dyn Agg>> {
+        Ok(Arc::new(Self::try_new(
+            exprs[0].clone(),
+            self.list_type.clone(),
+            self.arg_type.clone(),
+            self.limit,
+            self.report_truncated,
+        )?))
+    }
+
+    fn data_type(&self) -> &DataType {
+        &self.data_type
+    }
+
+    fn nullable(&self) -> bool {
+        false
+    }
+
+    fn accums_initial(&self) -> &[AccumInitialValue] {
+        &self.accum_initial
+    }
+
+    fn increase_acc_mem_used(&self, acc: &mut RefAccumStateRow) {
+        if let Some(v) = acc.dyn_value(self.list_addr) {
+            self.add_mem_used(v.mem_size());
+        }
+    }
+
+    fn decrease_acc_mem_used(&self, acc: &mut RefAccumStateRow) {
+        if let Some(v) = acc.dyn_value(self.list_addr) {
+            self.sub_mem_used(v.mem_size());
+        }
+    }
+
+    fn partial_update(
+        &self,
+        acc: &mut RefAccumStateRow,
+        values: &[ArrayRef],
+        row_idx: usize,
+    ) -> Result<()> {
+        if values[0].is_valid(row_idx) {
+            // group is full, skip without touching the list
+            let num_items = self.num_items(acc);
+            if num_items >= self.limit {
+                self.set_truncated(acc);
+                return Ok(());
+            }
+            acc.set_fixed_value::<i64>(self.count_addr, num_items as i64 + 1);
+
+            match acc.dyn_value_mut(self.list_addr) {
+                Some(dyn_list) => {
+                    let list = downcast_any!(dyn_list, mut AggDynList)?;
+                    self.sub_mem_used(list.mem_size());
+
+                    list.append(&ScalarValue::try_from_array(&values[0], row_idx)?, false)?;
+                    self.add_mem_used(list.mem_size());
+                }
+                w => {
+                    let mut new_list = AggDynList::default();
+                    new_list.append(&ScalarValue::try_from_array(&values[0], row_idx)?, false)?;
+                    self.add_mem_used(new_list.mem_size());
+                    *w = Some(Box::new(new_list));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn partial_update_all(&self, acc: &mut RefAccumStateRow, values: &[ArrayRef]) -> Result<()> {
+        let mut num_items = self.num_items(acc);
+        if num_items >= self.limit {
+            if values[0].null_count() < values[0].len() {
+                self.set_truncated(acc);
+            }
+            return Ok(());
+        }
+
+        let dyn_list = match acc.dyn_value_mut(self.list_addr) {
+            Some(dyn_list) => dyn_list,
+            w => {
+                let new_list = AggDynList::default();
+                self.add_mem_used(new_list.mem_size());
+                *w = Some(Box::new(new_list));
+                w.as_mut().unwrap()
+            }
+        };
+        let list = downcast_any!(dyn_list, mut AggDynList)?;
+        self.sub_mem_used(list.mem_size());
+
+        let mut truncated = false;
+        for i in 0..values[0].len() {
+            if values[0].is_valid(i) {
+                if num_items >= self.limit {
+                    truncated = true;
+                    break;
+                }
+                list.append(&ScalarValue::try_from_array(&values[0], i)?, false)?;
+                num_items += 1;
+            }
+        }
+        self.add_mem_used(list.mem_size());
+        acc.set_fixed_value::<i64>(self.count_addr, num_items as i64);
+        if truncated {
+            self.set_truncated(acc);
+        }
+        Ok(())
+    }
+
+    fn partial_merge(
+        &self,
+        acc: &mut RefAccumStateRow,
+        merging_acc: &mut RefAccumStateRow,
+    ) -> Result<()> {
+        let num_items = self.num_items(acc);
+        let merging_num_items = self.num_items(merging_acc);
+        let num_taken = merging_num_items.min(self.limit.saturating_sub(num_items));
+        if num_taken < merging_num_items || self.is_truncated(merging_acc) {
+            self.set_truncated(acc);
+        }
+        if num_taken == 0 {
+            // all merging values are discarded
+            if let Some(v) = std::mem::take(merging_acc.dyn_value_mut(self.list_addr)) {
+                self.sub_mem_used(v.mem_size());
+            }
+            return Ok(());
+        }
+
+        match (
+            acc.dyn_value_mut(self.list_addr),
+            merging_acc.dyn_value_mut(self.list_addr),
+        ) {
+            (Some(w), Some(v)) => {
+                let w = downcast_any!(w, mut AggDynList)?;
+                let v = downcast_any!(v, mut AggDynList)?;
+                self.sub_mem_used(w.mem_size());
+                self.sub_mem_used(v.mem_size());
+
+                if num_taken == merging_num_items {
+                    w.merge(v);
+                } else {
+                    w.merge_prefix(v, num_taken, &self.arg_type, false)?;
+                }
+                self.add_mem_used(w.mem_size());
+            }
+            (w_none, v @ Some(_)) => {
+                if num_taken == merging_num_items {
+                    *w_none = std::mem::take(v);
+                } else {
+                    let v = downcast_any!(v.as_mut().unwrap(), mut AggDynList)?;
+                    self.sub_mem_used(v.mem_size());
+
+                    let mut new_list = AggDynList::default();
+                    new_list.merge_prefix(v, num_taken, &self.arg_type, false)?;
+                    self.add_mem_used(new_list.mem_size());
+                    *w_none = Some(Box::new(new_list));
+                }
+            }
+            (None, _) => {}
+            (_, None) => {}
+        }
+        acc.set_fixed_value::<i64>(self.count_addr, (num_items + num_taken) as i64);
+        Ok(())
+    }
+
+    fn shrink_acc_to_fit(&self, acc: &mut RefAccumStateRow) {
+        if let Some(v) = acc.dyn_value_mut(self.list_addr) {
+            self.sub_mem_used(v.mem_size());
+            v.shrink_to_fit();
+            self.add_mem_used(v.mem_size());
+        }
+    }
+
+    fn final_merge(&self, acc: &mut RefAccumStateRow) -> Result<ScalarValue> {
+        let truncated = self.is_truncated(acc);
+        let list = self.take_list(acc)?;
+        if let DataType::Struct(fields) = &self.data_type {
+            return Ok(ScalarValue::Struct(Arc::new(StructArray::try_new(
+                fields.clone(),
+                vec![
+                    list.to_array()?,
+                    Arc::new(BooleanArray::from(vec![truncated])),
+                ],
+                None,
+            )?)));
+        }
+        Ok(list)
+    }
+
+    fn final_batch_merge(&self, accs: &mut [RefAccumStateRow]) -> Result<ArrayRef> {
+        let truncated = accs
+            .iter()
+            .map(|acc| self.is_truncated(acc))
+            .collect::<Vec<_>>();
+        let values: Vec<ScalarValue> = accs
+            .iter_mut()
+            .map(|acc| self.take_list(acc))
+            .collect::<Result<_>>()?;
+
+        let lists = if values.is_empty() {
+            new_empty_array(&self.list_type)
+        } else {
+            ScalarValue::iter_to_array(values)?
+        };
+        if let DataType::Struct(fields) = &self.data_type {
+            return Ok(Arc::new(StructArray::try_new(
+                fields.clone(),
+                vec![lists, Arc::new(BooleanArray::from(truncated))],
+                None,
+            )?));
+        }
+        Ok(lists)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use arrow::{array::*, datatypes::*};
+    use datafusion::{common::Result, physical_expr::expressions::Column};
+
+    use crate::agg::{
+        acc::create_acc_from_initial_value, bounded_collect_list::AggBoundedCollectList, Agg,
+        WithAggBufAddrs,
+    };
+
+    #[test]
+    fn test_report_truncated() -> Result<()> {
+        let mut agg = AggBoundedCollectList::try_new(
+            Arc::new(Column::new("a", 0)),
+            DataType::new_list(DataType::Int32, true),
+            DataType::Int32,
+            3,
+            true,
+        )?;
+        let (initial_acc, addrs) = create_acc_from_initial_value(agg.accums_initial())?;
+        agg.set_accum_state_val_addrs(&addrs);
+
+        let values: ArrayRef = Arc::new(Int32Array::from(vec![
+            Some(1),
+            Some(2),
+            None,
+            Some(3),
+            None,
+            Some(4),
+        ]));
+
+        // group 0: exactly at the cap, trailing nulls are not counted
+        // group 1: one value past the cap
+        // group 2: at the cap in each partial, past the cap after merging
+        let mut accs = vec![initial_acc.clone(); 3];
+        agg.partial_update_all(&mut accs[0].as_mut(), &[values.slice(0, 5)])?;
+        for row_idx in 0..values.len() {
+            agg.partial_update(&mut accs[1].as_mut(), &[values.clone()], row_idx)?;
+        }
+        let mut merging_acc = initial_acc.clone();
+        agg.partial_update_all(&mut accs[2].as_mut(), &[values.slice(0, 4)])?;
+        agg.partial_update_all(&mut merging_acc.as_mut(), &[values.slice(3, 1)])?;
+        agg.partial_merge(&mut accs[2].as_mut(), &mut merging_acc.as_mut())?;
+
+        let mut ref_accs = accs.iter_mut().map(|acc| acc.as_mut()).collect::<Vec<_>>();
+        let output = agg.final_batch_merge(&mut ref_accs)?;
+        let output = output.as_struct();
+        assert_eq!(
+            output.column(0).to_data(),
+            ListArray::from_iter_primitive::<Int32Type, _, _>(vec![
+                Some(vec![Some(1), Some(2), Some(3)]),
+                Some(vec![Some(1), Some(2), Some(3)]),
+                Some(vec![Some(1), Some(2), Some(3)]),
+            ])
+            .to_data(),
+        );
+        assert_eq!(
+            output.column(1).as_boolean(),
+            &BooleanArray::from(vec![false, true, true]),
+        );
+        Ok(())
+    }
+}
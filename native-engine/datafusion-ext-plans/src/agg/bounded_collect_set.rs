@@ -0,0 +1,468 @@
+// Copyright 2022 The Blaze Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::{
+    any::Any,
+    fmt::{Debug, Formatter},
+    sync::{atomic::AtomicUsize, Arc},
+};
+
+use arrow::{array::*, datatypes::*};
+use datafusion::{
+    common::{Result, ScalarValue},
+    physical_expr::PhysicalExpr,
+};
+use datafusion_ext_commons::{
+    df_execution_err, df_unimplemented_err, downcast_any, io::is_scalar_serde_supported,
+};
+
+use crate::agg::{
+    acc::{
+        write_normalized_scalar, AccumInitialValue, AccumStateRow, AccumStateValAddr, AggDynBinary,
+        AggDynSet, AggDynValue, RefAccumStateRow, AGG_DYN_SET_HASH_SEED,
+    },
+    agg_table::gx_hash_with_seed,
+    collect_list::build_list,
+    Agg, WithAggBufAddrs, WithMemTracking,
+};
+
+const HLL_HASH_SEED: i64 = 0x484C_4C53;
+const HLL_PRECISION: u32 = 12;
+const HLL_NUM_REGISTERS: usize = 1 << HLL_PRECISION;
+
+/// collect_set() which keeps exact distinct values of a group until the set
+/// grows beyond `max_set_mem_size` bytes, then the set is converted into a
+/// hyperloglog sketch and the group only counts distinct values afterwards.
+/// output is a struct of {values, num_distinct, is_approximate}, where values
+/// is null and num_distinct is estimated for approximate groups
+pub struct AggBoundedCollectSet {
+    child: Arc<dyn PhysicalExpr>,
+    data_type: DataType,
+    list_type: DataType,
+    arg_type: DataType,
+    max_set_mem_size: usize,
+    accum_initial: [AccumInitialValue; 2],
+    set_addr: AccumStateValAddr,
+    hll_addr: AccumStateValAddr,
+    mem_used_tracker: AtomicUsize,
+}
+
+impl WithAggBufAddrs for AggBoundedCollectSet {
+    fn set_accum_state_val_addrs(&mut self, accum_state_val_addrs: &[AccumStateValAddr]) {
+        self.set_addr = accum_state_val_addrs[0];
+        self.hll_addr = accum_state_val_addrs[1];
+    }
+}
+
+impl WithMemTracking for AggBoundedCollectSet {
+    fn mem_used_tracker(&self) -> &AtomicUsize {
+        &self.mem_used_tracker
+    }
+}
+
+impl AggBoundedCollectSet {
+    /// `list_type` is the List type of collected values
+    pub fn try_new(
+        child: Arc<dyn PhysicalExpr>,
+        list_type: DataType,
+        arg_type: DataType,
+        max_set_mem_size: usize,
+    ) -> Result<Self> {
+        if !is_scalar_serde_supported(&arg_type) {
+            return df_unimplemented_err!(
+                "bounded_collect_set does not support argument type: {arg_type}"
+            );
+        }
+        Ok(Self {
+            child,
+            data_type: DataType::Struct(Fields::from(vec![
+                Field::new("values", list_type.clone(), true),
+                Field::new("num_distinct", DataType::Int64, false),
+                Field::new("is_approximate", DataType::Boolean, false),
+            ])),
+            list_type,
+            accum_initial: [
+                AccumInitialValue::DynSet(arg_type.clone(), AGG_DYN_SET_HASH_SEED, 0),
+                AccumInitialValue::Scalar(ScalarValue::Binary(None)),
+            ],
+            arg_type,
+            max_set_mem_size,
+            set_addr: AccumStateValAddr::default(),
+            hll_addr: AccumStateValAddr::default(),
+            mem_used_tracker: AtomicUsize::new(0),
+        })
+    }
+
+    pub fn arg_type(&self) -> &DataType {
+        &self.arg_type
+    }
+
+    pub fn max_set_mem_size(&self) -> usize {
+        self.max_set_mem_size
+    }
+
+    fn is_approximate(&self, acc: &RefAccumStateRow) -> bool {
+        acc.dyn_value(self.hll_addr).is_some()
+    }
+
+    fn take_set(&self, acc: &mut RefAccumStateRow) -> Result<Option<AggDynSet>> {
+        match std::mem::take(acc.dyn_value_mut(self.set_addr)) {
+            Some(w) => {
+                self.sub_mem_used(w.mem_size());
+                let set = w
+                    .as_any_boxed()
+                    .downcast::<AggDynSet>()
+                    .or_else(|_| df_execution_err!("error downcasting to AggDynSet"))?;
+                Ok(Some(*set))
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn take_hll(&self, acc: &mut RefAccumStateRow) -> Result<Option<Vec<u8>>> {
+        match std::mem::take(acc.dyn_value_mut(self.hll_addr)) {
+            Some(w) => {
+                self.sub_mem_used(w.mem_size());
+                let hll = w
+                    .as_any_boxed()
+                    .downcast::<AggDynBinary>()
+                    .or_else(|_| df_execution_err!("error downcasting to AggDynBinary"))?;
+                Ok(Some(hll.into_value().into_vec()))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// returns registers of the sketch, the exact set is converted into the
+    /// sketch if the group is not approximate yet
+    fn hll_mut<'a>(&self, acc: &'a mut RefAccumStateRow) -> Result<&'a mut [u8]> {
+        if !self.is_approximate(acc) {
+            let mut registers = vec![0; HLL_NUM_REGISTERS];
+            if let Some(set) = self.take_set(acc)? {
+                set.for_each_raw(|raw| hll_add(&mut registers, raw));
+            }
+            let hll = AggDynBinary::new(registers.into());
+            self.add_mem_used(hll.mem_size());
+            *acc.dyn_value_mut(self.hll_addr) = Some(Box::new(hll));
+        }
+        let hll = downcast_any!(
+            acc.dyn_value_mut(self.hll_addr).as_mut().unwrap(),
+            mut AggDynBinary
+        )?;
+        Ok(&mut hll.value[..])
+    }
+
+    fn set_mut<'a>(&self, acc: &'a mut RefAccumStateRow) -> Result<&'a mut AggDynSet> {
+        let set = match acc.dyn_value_mut(self.set_addr) {
+            Some(set) => set,
+            w => {
+                let new_set = AggDynSet::with_hash_seed(AGG_DYN_SET_HASH_SEED);
+                self.add_mem_used(new_set.mem_size());
+                *w = Some(Box::new(new_set));
+                w.as_mut().unwrap()
+            }
+        };
+        downcast_any!(set, mut AggDynSet)
+    }
+
+    /// converts the group into a sketch if the exact set exceeds the budget
+    fn convert_if_exceeded(&self, acc: &mut RefAccumStateRow) -> Result<()> {
+        let exceeded = acc
+            .dyn_value(self.set_addr)
+            .map(|set| set.mem_size() > self.max_set_mem_size)
+            .unwrap_or(false);
+        if exceeded {
+            self.hll_mut(acc)?;
+        }
+        Ok(())
+    }
+
+    fn add_value(&self, acc: &mut RefAccumStateRow, values: &ArrayRef, i: usize) -> Result<()> {
+        let value = ScalarValue::try_from_array(values, i)?;
+        if self.is_approximate(acc) {
+            let mut raw = vec![];
+            write_normalized_scalar(&value, false, &mut raw)?;
+            hll_add(self.hll_mut(acc)?, &raw);
+            return Ok(());
+        }
+        let set = self.set_mut(acc)?;
+        self.sub_mem_used(set.mem_size());
+        set.append(&value, false)?;
+        self.add_mem_used(set.mem_size());
+        self.convert_if_exceeded(acc)
+    }
+}
+
+fn hll_add(registers: &mut [u8], raw: &[u8]) {
+    let hash = gx_hash_with_seed(HLL_HASH_SEED, raw);
+    let idx = (hash >> (64 - HLL_PRECISION)) as usize;
+    let rho = ((hash << HLL_PRECISION) | (1 << (HLL_PRECISION - 1))).leading_zeros() + 1;
+    registers[idx] = registers[idx].max(rho as u8);
+}
+
+/// estimates number of distinct values, with linear counting for small
+/// cardinalities
+fn hll_estimate(registers: &[u8]) -> i64 {
+    let m = registers.len() as f64;
+    let alpha = 0.7213 / (1.0 + 1.079 / m);
+    let sum: f64 = registers.iter().map(|&r| 2f64.powi(-(r as i32))).sum();
+    let num_zeros = registers.iter().filter(|&&r| r == 0).count();
+
+    let estimate = alpha * m * m / sum;
+    if estimate <= 2.5 * m && num_zeros > 0 {
+        return (m * (m / num_zeros as f64).ln()).round() as i64;
+    }
+    estimate.round() as i64
+}
+
+impl Debug for AggBoundedCollectSet {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "BoundedCollectSet({:?}, {})",
+            self.child, self.max_set_mem_size
+        )
+    }
+}
+
+impl Agg for AggBoundedCollectSet {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn exprs(&self) -> Vec<Arc<dyn PhysicalExpr>> {
+        vec![self.child.clone()]
+    }
+
+    fn with_new_exprs(&self, exprs: Vec<Arc<dyn PhysicalExpr>>) -> Result<Arc<dyn Agg>> {
+        Ok(Arc::new(Self::try_new(
+            exprs[0].clone(),
+            self.list_type.clone(),
+            self.arg_type.clone(),
+            self.max_set_mem_size,
+        )?))
+    }
+
+    fn data_type(&self) -> &DataType {
+        &self.data_type
+    }
+
+    fn nullable(&self) -> bool {
+        false
+    }
+
+    fn accums_initial(&self) -> &[AccumInitialValue] {
+        &self.accum_initial
+    }
+
+    fn increase_acc_mem_used(&self, acc: &mut RefAccumStateRow) {
+        for addr in [self.set_addr, self.hll_addr] {
+            if let Some(v) = acc.dyn_value(addr) {
+                self.add_mem_used(v.mem_size());
+            }
+        }
+    }
+
+    fn decrease_acc_mem_used(&self, acc: &mut RefAccumStateRow) {
+        for addr in [self.set_addr, self.hll_addr] {
+            if let Some(v) = acc.dyn_value(addr) {
+                self.sub_mem_used(v.mem_size());
+            }
+        }
+    }
+
+    fn partial_update(
+        &self,
+        acc: &mut RefAccumStateRow,
+        values: &[ArrayRef],
+        row_idx: usize,
+    ) -> Result<()> {
+        if values[0].is_valid(row_idx) {
+            self.add_value(acc, &values[0], row_idx)?;
+        }
+        Ok(())
+    }
+
+    fn partial_update_all(&self, acc: &mut RefAccumStateRow, values: &[ArrayRef]) -> Result<()> {
+        for i in 0..values[0].len() {
+            if values[0].is_valid(i) {
+                self.add_value(acc, &values[0], i)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn partial_merge(
+        &self,
+        acc: &mut RefAccumStateRow,
+        merging_acc: &mut RefAccumStateRow,
+    ) -> Result<()> {
+        // approximate merging group, the result is always approximate
+        if let Some(merging_hll) = self.take_hll(merging_acc)? {
+            self.take_set(merging_acc)?;
+            let hll = self.hll_mut(acc)?;
+            for (r, merging_r) in hll.iter_mut().zip(merging_hll) {
+                *r = (*r).max(merging_r);
+            }
+            return Ok(());
+        }
+
+        let Some(mut merging_set) = self.take_set(merging_acc)? else {
+            return Ok(());
+        };
+        if self.is_approximate(acc) {
+            let hll = self.hll_mut(acc)?;
+            merging_set.for_each_raw(|raw| hll_add(hll, raw));
+            return Ok(());
+        }
+        let set = self.set_mut(acc)?;
+        self.sub_mem_used(set.mem_size());
+        set.merge(&mut merging_set);
+        self.add_mem_used(set.mem_size());
+        self.convert_if_exceeded(acc)
+    }
+
+    fn shrink_acc_to_fit(&self, acc: &mut RefAccumStateRow) {
+        if let Some(v) = acc.dyn_value_mut(self.set_addr) {
+            self.sub_mem_used(v.mem_size());
+            v.shrink_to_fit();
+            self.add_mem_used(v.mem_size());
+        }
+    }
+
+    fn final_merge(&self, acc: &mut RefAccumStateRow) -> Result<ScalarValue> {
+        let array = self.final_batch_merge(std::slice::from_mut(acc))?;
+        ScalarValue::try_from_array(&array, 0)
+    }
+
+    fn final_batch_merge(&self, accs: &mut [RefAccumStateRow]) -> Result<ArrayRef> {
+        let mut lists = Vec::with_capacity(accs.len());
+        let mut num_distincts = Vec::with_capacity(accs.len());
+        let mut is_approximates = Vec::with_capacity(accs.len());
+
+        for acc in accs {
+            if let Some(hll) = self.take_hll(acc)? {
+                self.take_set(acc)?;
+                lists.push(None);
+                num_distincts.push(hll_estimate(&hll));
+                is_approximates.push(true);
+                continue;
+            }
+            let set = self
+                .take_set(acc)?
+                .unwrap_or_else(|| AggDynSet::with_hash_seed(AGG_DYN_SET_HASH_SEED));
+            num_distincts.push(set.len() as i64);
+            lists.push(Some(set.into_array(&self.arg_type, false)?));
+            is_approximates.push(false);
+        }
+
+        let fields = match &self.data_type {
+            DataType::Struct(fields) => fields.clone(),
+            _ => unreachable!(),
+        };
+        Ok(Arc::new(StructArray::try_new(
+            fields,
+            vec![
+                build_list(&self.list_type, lists)?,
+                Arc::new(Int64Array::from(num_distincts)),
+                Arc::new(BooleanArray::from(is_approximates)),
+            ],
+            None,
+        )?))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use arrow::{array::*, datatypes::*};
+    use datafusion::{common::Result, physical_expr::expressions::Column};
+
+    use crate::agg::{
+        acc::create_acc_from_initial_value, bounded_collect_set::AggBoundedCollectSet, Agg,
+        WithAggBufAddrs,
+    };
+
+    #[test]
+    fn test_bounded_collect_set() -> Result<()> {
+        let mut agg = AggBoundedCollectSet::try_new(
+            Arc::new(Column::new("a", 0)),
+            DataType::new_list(DataType::Int64, true),
+            DataType::Int64,
+            4096,
+        )?;
+        let (initial_acc, addrs) = create_acc_from_initial_value(agg.accums_initial())?;
+        agg.set_accum_state_val_addrs(&addrs);
+
+        let small: ArrayRef = Arc::new(Int64Array::from(vec![
+            Some(3),
+            None,
+            Some(1),
+            Some(3),
+            Some(2),
+        ]));
+        let large: ArrayRef = Arc::new(Int64Array::from_iter_values(0..20000));
+
+        // group 0: small group kept exact
+        // group 1: large group updated row by row
+        // group 2: small partial merged with a large partial
+        // group 3: two large partials with duplicated values
+        // group 4: no values
+        let mut accs = vec![initial_acc.clone(); 5];
+        agg.partial_update_all(&mut accs[0].as_mut(), &[small.clone()])?;
+        for row_idx in 0..large.len() {
+            agg.partial_update(&mut accs[1].as_mut(), &[large.clone()], row_idx)?;
+        }
+        let mut merging_acc = initial_acc.clone();
+        agg.partial_update_all(&mut accs[2].as_mut(), &[small.clone()])?;
+        agg.partial_update_all(&mut merging_acc.as_mut(), &[large.clone()])?;
+        agg.partial_merge(&mut accs[2].as_mut(), &mut merging_acc.as_mut())?;
+        let mut merging_acc = initial_acc.clone();
+        agg.partial_update_all(&mut accs[3].as_mut(), &[large.slice(0, 15000)])?;
+        agg.partial_update_all(&mut merging_acc.as_mut(), &[large.slice(5000, 15000)])?;
+        agg.partial_merge(&mut accs[3].as_mut(), &mut merging_acc.as_mut())?;
+
+        let mut ref_accs = accs.iter_mut().map(|acc| acc.as_mut()).collect::<Vec<_>>();
+        let output = agg.final_batch_merge(&mut ref_accs)?;
+        let output = output.as_struct();
+
+        let values = output.column(0).as_list::<i32>();
+        assert!(values.is_valid(0) && values.is_valid(4));
+        assert!(values.is_null(1) && values.is_null(2) && values.is_null(3));
+        let mut small_values = values
+            .value(0)
+            .as_primitive::<Int64Type>()
+            .values()
+            .to_vec();
+        small_values.sort();
+        assert_eq!(small_values, vec![1, 2, 3]);
+        assert_eq!(values.value(4).len(), 0);
+
+        let is_approximate = output.column(2).as_boolean();
+        assert_eq!(
+            is_approximate,
+            &BooleanArray::from(vec![false, true, true, true, false]),
+        );
+
+        let num_distinct = output.column(1).as_primitive::<Int64Type>();
+        assert_eq!(num_distinct.value(0), 3);
+        assert_eq!(num_distinct.value(4), 0);
+        for i in 1..4 {
+            let error = (num_distinct.value(i) - 20000).abs() as f64 / 20000.0;
+            assert!(error < 0.05, "estimated: {}", num_distinct.value(i));
+        }
+        Ok(())
+    }
+}
@@ -98,6 +98,10 @@ impl Agg for AggCollect {
         self.innert_collect_list.increase_acc_mem_used(acc);
     }
 
+    fn decrease_acc_mem_used(&self, acc: &mut RefAccumStateRow) {
+        self.innert_collect_list.decrease_acc_mem_used(acc);
+    }
+
     fn partial_update(
         &self,
         acc: &mut RefAccumStateRow,
@@ -124,6 +128,10 @@ impl Agg for AggCollect {
         self.innert_collect_list.partial_merge(acc, merging_acc)
     }
 
+    fn shrink_acc_to_fit(&self, acc: &mut RefAccumStateRow) {
+        self.innert_collect_list.shrink_acc_to_fit(acc)
+    }
+
     fn final_merge(&self, acc: &mut RefAccumStateRow) -> Result<ScalarValue> {
         self.innert_collect_list.final_merge(acc)
     }
@@ -98,6 +98,10 @@ impl Agg for AggCombineUnique {
         self.innert_collect_set.increase_acc_mem_used(acc);
     }
 
+    fn decrease_acc_mem_used(&self, acc: &mut RefAccumStateRow) {
+        self.innert_collect_set.decrease_acc_mem_used(acc);
+    }
+
     fn partial_update(
         &self,
         acc: &mut RefAccumStateRow,
@@ -124,6 +128,10 @@ impl Agg for AggCombineUnique {
         self.innert_collect_set.partial_merge(acc, merging_acc)
     }
 
+    fn shrink_acc_to_fit(&self, acc: &mut RefAccumStateRow) {
+        self.innert_collect_set.shrink_acc_to_fit(acc)
+    }
+
     fn final_merge(&self, acc: &mut RefAccumStateRow) -> Result<ScalarValue> {
         self.innert_collect_set.final_merge(acc)
     }
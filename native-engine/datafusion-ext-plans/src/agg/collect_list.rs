@@ -15,15 +15,26 @@
 use std::{
     any::Any,
     fmt::{Debug, Formatter},
-    sync::{atomic::AtomicUsize, Arc},
+    io::Cursor,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc,
+    },
 };
 
-use arrow::{array::*, datatypes::*};
+use arrow::{
+    array::*,
+    buffer::{NullBuffer, OffsetBuffer},
+    datatypes::*,
+};
 use datafusion::{
     common::{Result, ScalarValue},
     physical_expr::PhysicalExpr,
 };
-use datafusion_ext_commons::{df_execution_err, downcast_any};
+use datafusion_ext_commons::{
+    df_execution_err, df_unimplemented_err, downcast_any,
+    io::{is_scalar_serde_supported, read_len, read_scalar, write_array_value, write_len},
+};
 
 use crate::agg::{
     acc::{
@@ -34,17 +45,27 @@ use crate::agg::{
 };
 
 pub struct AggCollectList {
-    child: Arc<dyn PhysicalExpr>,
+    children: Vec<Arc<dyn PhysicalExpr>>,
     data_type: DataType,
     arg_type: DataType,
-    accum_initial: [AccumInitialValue; 1],
+    include_nulls: bool,
+    merge_lists: bool,
+    run_length_encoding: bool,
+    accum_initial: Vec<AccumInitialValue>,
     accum_state_val_addr: AccumStateValAddr,
+    last_run_addr: AccumStateValAddr,
     mem_used_tracker: AtomicUsize,
+    total_raw_bytes: AtomicUsize,
+    max_group_raw_bytes: AtomicUsize,
+    arg_type_checked: AtomicBool,
 }
 
 impl WithAggBufAddrs for AggCollectList {
     fn set_accum_state_val_addrs(&mut self, accum_state_val_addrs: &[AccumStateValAddr]) {
         self.accum_state_val_addr = accum_state_val_addrs[0];
+        if self.run_length_encoding {
+            self.last_run_addr = accum_state_val_addrs[1];
+        }
     }
 }
 
@@ -52,32 +73,590 @@ impl WithMemTracking for AggCollectList {
     fn mem_used_tracker(&self) -> &AtomicUsize {
         &self.mem_used_tracker
     }
+
+    fn reset_mem_used(&self) {
+        self.mem_used_tracker.store(0, Ordering::SeqCst);
+        self.total_raw_bytes.store(0, Ordering::SeqCst);
+    }
 }
 
 impl AggCollectList {
+    /// `data_type` can be a FixedSizeList, in which case every group must
+    /// collect exactly the fixed number of values.
     pub fn try_new(
         child: Arc<dyn PhysicalExpr>,
         data_type: DataType,
         arg_type: DataType,
     ) -> Result<Self> {
+        Self::try_new_internal(vec![child], data_type, arg_type)
+    }
+
+    /// collects multiple arguments like collect_list(struct(a, b, ...)),
+    /// arguments are packed into a struct without copying. `arg_type` is the
+    /// Struct type with a field for each child
+    pub fn try_new_with_multiple_args(
+        children: Vec<Arc<dyn PhysicalExpr>>,
+        data_type: DataType,
+        arg_type: DataType,
+    ) -> Result<Self> {
+        match &arg_type {
+            DataType::Struct(fields) if fields.len() == children.len() => {}
+            other => {
+                return df_execution_err!(
+                    "collect_list expect Struct type of {} fields, got: {other}",
+                    children.len()
+                )
+            }
+        }
+        Self::try_new_internal(children, data_type, arg_type)
+    }
+
+    fn try_new_internal(
+        children: Vec<Arc<dyn PhysicalExpr>>,
+        data_type: DataType,
+        arg_type: DataType,
+    ) -> Result<Self> {
+        if !is_scalar_serde_supported(&arg_type) {
+            return df_unimplemented_err!(
+                "collect_list does not support argument type: {arg_type}"
+            );
+        }
         Ok(Self {
-            child,
+            children,
             data_type,
-            accum_initial: [AccumInitialValue::DynList(arg_type.clone())],
+            accum_initial: vec![AccumInitialValue::DynList(arg_type.clone())],
             arg_type,
+            include_nulls: false,
+            merge_lists: false,
+            run_length_encoding: false,
             accum_state_val_addr: AccumStateValAddr::default(),
+            last_run_addr: AccumStateValAddr::default(),
             mem_used_tracker: AtomicUsize::new(0),
+            total_raw_bytes: AtomicUsize::new(0),
+            max_group_raw_bytes: AtomicUsize::new(0),
+            arg_type_checked: AtomicBool::new(false),
         })
     }
 
+    fn check_arg_type(&self, values: &ArrayRef) -> Result<()> {
+        check_collect_arg_type(
+            "collect_list",
+            &self.arg_type,
+            self.merge_lists,
+            values,
+            &self.arg_type_checked,
+        )
+    }
+
+    /// keeps null values in the output lists, like array_agg()
+    pub fn with_include_nulls(mut self) -> Self {
+        self.include_nulls = true;
+        self
+    }
+
+    /// takes a List of `arg_type` as input and appends all elements of each
+    /// input list, like flatten(collect_list())
+    pub fn with_merge_lists(mut self) -> Self {
+        self.merge_lists = true;
+        self
+    }
+
+    /// stores consecutive equal values as runs of a single value and the
+    /// number of repeats, which saves memory for run-heavy inputs like time
+    /// series. the output is the same as without encoding
+    pub fn with_run_length_encoding(mut self) -> Self {
+        self.run_length_encoding = true;
+        self.accum_initial = vec![
+            AccumInitialValue::DynList(self.arg_type.clone()),
+            AccumInitialValue::Scalar(ScalarValue::Int64(None)),
+        ];
+        self
+    }
+
     pub fn arg_type(&self) -> &DataType {
         &self.arg_type
     }
+
+    pub fn run_length_encoding(&self) -> bool {
+        self.run_length_encoding
+    }
+
+    /// whether null values are kept in the collected list (like array_agg()),
+    /// or skipped (like collect_list())
+    pub fn include_nulls(&self) -> bool {
+        self.include_nulls
+    }
+
+    pub fn merge_lists(&self) -> bool {
+        self.merge_lists
+    }
+
+    /// total serialized bytes of all lists in memory, shared by all partitions
+    /// using this aggregate. it is reset along with `mem_used()` on spilling
+    pub fn total_raw_bytes(&self) -> usize {
+        self.total_raw_bytes.load(Ordering::SeqCst)
+    }
+
+    /// peak serialized bytes of a single group's list, a value close to
+    /// `total_raw_bytes()` suggests that memory is dominated by a skewed group
+    pub fn max_group_raw_bytes(&self) -> usize {
+        self.max_group_raw_bytes.load(Ordering::SeqCst)
+    }
+
+    /// tracks memory and serialized bytes of a list added to the groups
+    fn add_list_used(&self, list: &AggDynList) {
+        self.add_mem_used(list.mem_size());
+        self.add_raw_bytes(list.raw.len(), list.raw.len());
+    }
+
+    /// tracks memory and serialized bytes of a list removed from the groups
+    fn sub_list_used(&self, list: &AggDynList) {
+        self.sub_mem_used(list.mem_size());
+        self.sub_raw_bytes(list.raw.len());
+    }
+
+    fn add_raw_bytes(&self, raw_bytes: usize, max_group_raw_bytes: usize) {
+        self.total_raw_bytes.fetch_add(raw_bytes, Ordering::SeqCst);
+        self.max_group_raw_bytes
+            .fetch_max(max_group_raw_bytes, Ordering::SeqCst);
+    }
+
+    fn sub_raw_bytes(&self, raw_bytes: usize) {
+        let _ = self
+            .total_raw_bytes
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |v| {
+                Some(v.saturating_sub(raw_bytes))
+            });
+    }
+
+    /// pre-allocates list buffers of the groups with estimated numbers of
+    /// serialized bytes, so that large groups skip repeated reallocations
+    /// while appending. hints are advisory, groups with zero hints or
+    /// non-empty lists are untouched
+    pub fn presize_lists(
+        &self,
+        accs: &mut [RefAccumStateRow],
+        byte_capacity_hints: &[usize],
+    ) -> Result<()> {
+        for (acc, &capacity) in accs.iter_mut().zip(byte_capacity_hints) {
+            if capacity == 0 {
+                continue;
+            }
+            let list = self.runs_mut(acc)?;
+            if list.raw.is_empty() {
+                self.sub_mem_used(list.mem_size());
+                list.raw.reserve(capacity);
+                self.add_mem_used(list.mem_size());
+            }
+        }
+        Ok(())
+    }
+
+    fn append_values(&self, acc: &mut RefAccumStateRow, values: &ArrayRef) -> Result<()> {
+        if self.run_length_encoding {
+            for i in 0..values.len() {
+                if self.include_nulls || values.is_valid(i) {
+                    self.append_to_runs(acc, values, i)?;
+                }
+            }
+            return Ok(());
+        }
+        let dyn_list = match acc.dyn_value_mut(self.accum_state_val_addr) {
+            Some(dyn_list) => dyn_list,
+            w => {
+                let new_list = AggDynList::default();
+                self.add_mem_used(new_list.mem_size());
+                *w = Some(Box::new(new_list));
+                w.as_mut().unwrap()
+            }
+        };
+        let list = downcast_any!(dyn_list, mut AggDynList)?;
+        self.sub_list_used(list);
+
+        for i in 0..values.len() {
+            if self.include_nulls || values.is_valid(i) {
+                list.append_array_value(values, i, self.include_nulls)?;
+            }
+        }
+        self.add_list_used(list);
+        Ok(())
+    }
+
+    /// start offset of the last run in the list, or None if the list is empty
+    fn last_run_start(&self, acc: &RefAccumStateRow) -> Option<usize> {
+        acc.is_fixed_valid(self.last_run_addr)
+            .then(|| acc.fixed_value::<i64>(self.last_run_addr) as usize)
+    }
+
+    fn set_last_run_start(&self, acc: &mut RefAccumStateRow, start: usize) {
+        acc.set_fixed_value::<i64>(self.last_run_addr, start as i64);
+        acc.set_fixed_valid(self.last_run_addr, true);
+    }
+
+    fn runs_mut<'a>(&self, acc: &'a mut RefAccumStateRow) -> Result<&'a mut AggDynList> {
+        let dyn_list = match acc.dyn_value_mut(self.accum_state_val_addr) {
+            Some(dyn_list) => dyn_list,
+            w => {
+                let new_list = AggDynList::default();
+                self.add_mem_used(new_list.mem_size());
+                *w = Some(Box::new(new_list));
+                w.as_mut().unwrap()
+            }
+        };
+        downcast_any!(dyn_list, mut AggDynList)
+    }
+
+    fn append_to_runs(
+        &self,
+        acc: &mut RefAccumStateRow,
+        values: &ArrayRef,
+        row_idx: usize,
+    ) -> Result<()> {
+        let mut raw = vec![];
+        write_array_value(values, row_idx, self.include_nulls, &mut raw)?;
+        let last_run_start = self.last_run_start(acc);
+        let runs = self.runs_mut(acc)?;
+        self.sub_list_used(runs);
+        let start = append_run(runs, last_run_start, &raw, 1);
+        self.add_list_used(runs);
+        self.set_last_run_start(acc, start);
+        Ok(())
+    }
+
+    /// merges runs of merging group, the first run is joined with the last
+    /// run of the group if they have the same value
+    fn merge_runs(
+        &self,
+        acc: &mut RefAccumStateRow,
+        merging_acc: &mut RefAccumStateRow,
+    ) -> Result<()> {
+        let merging_runs =
+            match std::mem::take(merging_acc.dyn_value_mut(self.accum_state_val_addr)) {
+                Some(w) => {
+                    let merging_runs = w
+                        .as_any_boxed()
+                        .downcast::<AggDynList>()
+                        .or_else(|_| df_execution_err!("error downcasting to AggDynList"))?;
+                    self.sub_list_used(&merging_runs);
+                    merging_runs
+                }
+                None => return Ok(()),
+            };
+        let Some(merging_last_run_start) = self.last_run_start(merging_acc) else {
+            return Ok(());
+        };
+
+        // split the first run of merging runs
+        let mut cursor = Cursor::new(&merging_runs.raw);
+        read_scalar(&mut cursor, &self.arg_type, self.include_nulls)?;
+        let value_len = cursor.position() as usize;
+        let count = read_len(&mut cursor)?;
+        let first_run_len = cursor.position() as usize;
+
+        let last_run_start = self.last_run_start(acc);
+        let runs = self.runs_mut(acc)?;
+        self.sub_list_used(runs);
+        let mut start = append_run(runs, last_run_start, &merging_runs.raw[..value_len], count);
+        if first_run_len < merging_runs.raw.len() {
+            start = runs.raw.len() + merging_last_run_start - first_run_len;
+            runs.raw
+                .extend_from_slice(&merging_runs.raw[first_run_len..]);
+        }
+        self.add_list_used(runs);
+        self.set_last_run_start(acc, start);
+        Ok(())
+    }
+
+    /// expands runs into values
+    fn runs_into_values(&self, runs: &AggDynList) -> Result<Vec<ScalarValue>> {
+        let mut values = vec![];
+        let mut cursor = Cursor::new(&runs.raw);
+        while cursor.position() < runs.raw.len() as u64 {
+            let value = read_scalar(&mut cursor, &self.arg_type, self.include_nulls)?;
+            let count = read_len(&mut cursor)?;
+            values.extend(std::iter::repeat(value).take(count));
+        }
+        Ok(values)
+    }
+
+    fn take_values(&self, acc: &mut RefAccumStateRow) -> Result<Option<Vec<ScalarValue>>> {
+        match std::mem::take(acc.dyn_value_mut(self.accum_state_val_addr)) {
+            Some(w) => {
+                let list = w
+                    .as_any_boxed()
+                    .downcast::<AggDynList>()
+                    .or_else(|_| df_execution_err!("error downcasting to AggDynList"))?;
+                self.sub_list_used(&list);
+                if self.run_length_encoding {
+                    return Ok(Some(self.runs_into_values(&list)?));
+                }
+                Ok(Some(
+                    list.into_values(self.arg_type.clone(), self.include_nulls)
+                        .collect::<Result<_>>()?,
+                ))
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn take_array(&self, acc: &mut RefAccumStateRow) -> Result<Option<ArrayRef>> {
+        match std::mem::take(acc.dyn_value_mut(self.accum_state_val_addr)) {
+            Some(w) => {
+                let list = w
+                    .as_any_boxed()
+                    .downcast::<AggDynList>()
+                    .or_else(|_| df_execution_err!("error downcasting to AggDynList"))?;
+                self.sub_list_used(&list);
+                if self.run_length_encoding {
+                    let values = self.runs_into_values(&list)?;
+                    if values.is_empty() {
+                        return Ok(Some(new_empty_array(&self.arg_type)));
+                    }
+                    return Ok(Some(ScalarValue::iter_to_array(values)?));
+                }
+                Ok(Some(list.into_array(&self.arg_type, self.include_nulls)?))
+            }
+            None => Ok(None),
+        }
+    }
+}
+
+/// appends `count` repeats of a serialized value to run-length encoded list,
+/// where each run is the serialized value followed by the number of repeats.
+/// returns the start offset of the last run after appending
+fn append_run(
+    runs: &mut AggDynList,
+    last_run_start: Option<usize>,
+    value_raw: &[u8],
+    count: usize,
+) -> usize {
+    if let Some(start) = last_run_start {
+        // serialized values are self-delimiting, so the last run has the same
+        // value if it starts with the serialized value
+        if runs.raw[start..].starts_with(value_raw) {
+            let count_pos = start + value_raw.len();
+            let last_count = read_len(&mut &runs.raw[count_pos..]).unwrap();
+            runs.raw.truncate(count_pos);
+            write_len(last_count + count, &mut runs.raw).unwrap();
+            return start;
+        }
+    }
+    let start = runs.raw.len();
+    runs.raw.extend_from_slice(value_raw);
+    write_len(count, &mut runs.raw).unwrap();
+    start
+}
+
+/// checks that the input of a collect aggregate has the declared `arg_type`
+/// (or List of `arg_type` when merging lists), since values serialized from
+/// another type are misinterpreted when read back with `arg_type`. dictionary
+/// input is checked by its value type. the check runs on every call in debug
+/// builds, and only until the first success in release builds
+pub fn check_collect_arg_type(
+    name: &str,
+    arg_type: &DataType,
+    merge_lists: bool,
+    values: &ArrayRef,
+    checked: &AtomicBool,
+) -> Result<()> {
+    if !cfg!(debug_assertions) && checked.load(Ordering::Relaxed) {
+        return Ok(());
+    }
+    let input_type = match values.data_type() {
+        DataType::List(field) | DataType::LargeList(field) if merge_lists => field.data_type(),
+        DataType::Dictionary(_, value_type) if !merge_lists => value_type.as_ref(),
+        other if !merge_lists => other,
+        other => return df_execution_err!("{name} expects List of {arg_type}, got: {other}"),
+    };
+    if !is_compatible_arg_type(input_type, arg_type) {
+        return df_execution_err!(
+            "{name} expects argument type {arg_type}, got: {}",
+            values.data_type()
+        );
+    }
+    checked.store(true, Ordering::Relaxed);
+    Ok(())
+}
+
+/// whether values of `input_type` are serialized in the same way as
+/// `arg_type`, field names and nullability of nested types are ignored
+fn is_compatible_arg_type(input_type: &DataType, arg_type: &DataType) -> bool {
+    match (input_type, arg_type) {
+        // timestamps are serialized as instants, the timezone is taken from arg_type
+        (DataType::Timestamp(unit1, _), DataType::Timestamp(unit2, _)) => unit1 == unit2,
+        (DataType::List(field1), DataType::List(field2))
+        | (DataType::LargeList(field1), DataType::LargeList(field2))
+        | (DataType::Map(field1, _), DataType::Map(field2, _)) => {
+            is_compatible_arg_type(field1.data_type(), field2.data_type())
+        }
+        (DataType::FixedSizeList(field1, size1), DataType::FixedSizeList(field2, size2)) => {
+            size1 == size2 && is_compatible_arg_type(field1.data_type(), field2.data_type())
+        }
+        (DataType::Struct(fields1), DataType::Struct(fields2)) => {
+            fields1.len() == fields2.len()
+                && fields1
+                    .iter()
+                    .zip(fields2)
+                    .all(|(f1, f2)| is_compatible_arg_type(f1.data_type(), f2.data_type()))
+        }
+        _ => input_type == arg_type,
+    }
+}
+
+/// returns the input of collect aggregates in list merging mode, null lists
+/// are skipped by the callers
+pub fn as_merging_lists(values: &ArrayRef) -> Result<MergingLists> {
+    if let Some(lists) = values.as_list_opt::<i32>() {
+        return Ok(MergingLists::List(lists));
+    }
+    if let Some(lists) = values.as_list_opt::<i64>() {
+        return Ok(MergingLists::LargeList(lists));
+    }
+    df_execution_err!("merging lists, but got {}", values.data_type())
+}
+
+/// input of collect aggregates in list merging mode, either List or LargeList
+pub enum MergingLists<'a> {
+    List(&'a ListArray),
+    LargeList(&'a LargeListArray),
+}
+
+impl MergingLists<'_> {
+    pub fn len(&self) -> usize {
+        match self {
+            Self::List(lists) => lists.len(),
+            Self::LargeList(lists) => lists.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn is_valid(&self, i: usize) -> bool {
+        match self {
+            Self::List(lists) => lists.is_valid(i),
+            Self::LargeList(lists) => lists.is_valid(i),
+        }
+    }
+
+    pub fn value(&self, i: usize) -> ArrayRef {
+        match self {
+            Self::List(lists) => lists.value(i),
+            Self::LargeList(lists) => lists.value(i),
+        }
+    }
+}
+
+/// builds the output of collect aggregates with List or LargeList data type
+/// from the values array of each group, a missing group is output as null
+pub fn build_list(data_type: &DataType, groups: Vec<Option<ArrayRef>>) -> Result<ArrayRef> {
+    let field = match data_type {
+        DataType::List(field) | DataType::LargeList(field) => field,
+        other => return df_execution_err!("collecting into List, but got {other}"),
+    };
+    let lens = groups
+        .iter()
+        .map(|group| group.as_ref().map(|values| values.len()).unwrap_or(0))
+        .collect::<Vec<_>>();
+    let valids = NullBuffer::from(
+        groups
+            .iter()
+            .map(|group| group.is_some())
+            .collect::<Vec<_>>(),
+    );
+
+    let arrays = groups
+        .iter()
+        .flatten()
+        .map(|values| values.as_ref())
+        .collect::<Vec<_>>();
+    let values = if arrays.is_empty() {
+        new_empty_array(field.data_type())
+    } else {
+        arrow::compute::concat(&arrays)?
+    };
+    if let DataType::LargeList(_) = data_type {
+        return Ok(Arc::new(LargeListArray::try_new(
+            field.clone(),
+            build_offsets(&lens)?,
+            values,
+            Some(valids),
+        )?));
+    }
+    Ok(Arc::new(ListArray::try_new(
+        field.clone(),
+        build_offsets(&lens)?,
+        values,
+        Some(valids),
+    )?))
+}
+
+/// builds offsets from numbers of values of the groups, fails instead of
+/// silently wrapping around if the total number overflows the offset type
+fn build_offsets<O: OffsetSizeTrait>(lens: &[usize]) -> Result<OffsetBuffer<O>> {
+    let mut offsets = Vec::with_capacity(lens.len() + 1);
+    offsets.push(O::usize_as(0));
+    let mut total_len = 0usize;
+    for &len in lens {
+        total_len += len;
+        match O::from_usize(total_len) {
+            Some(offset) => offsets.push(offset),
+            None => {
+                return df_execution_err!(
+                    "collecting {total_len} values overflows offsets of {}, LargeList is required",
+                    if O::IS_LARGE { "LargeList" } else { "List" },
+                );
+            }
+        }
+    }
+    Ok(OffsetBuffer::new(offsets.into()))
+}
+
+/// builds the output of collect aggregates with FixedSizeList data type, a
+/// missing group is output as null
+pub fn build_fixed_size_list(
+    field: &FieldRef,
+    size: i32,
+    groups: Vec<Option<Vec<ScalarValue>>>,
+) -> Result<ArrayRef> {
+    let mut valids = Vec::with_capacity(groups.len());
+    let mut flatten_values = Vec::with_capacity(groups.len() * size as usize);
+    for group in groups {
+        match group {
+            Some(values) => {
+                if values.len() != size as usize {
+                    return df_execution_err!(
+                        "collecting into FixedSizeList({size}), but got {} values",
+                        values.len()
+                    );
+                }
+                flatten_values.extend(values);
+                valids.push(true);
+            }
+            None => {
+                let null_value = ScalarValue::try_from(field.data_type())?;
+                flatten_values.extend(std::iter::repeat(null_value).take(size as usize));
+                valids.push(false);
+            }
+        }
+    }
+    let values = if flatten_values.is_empty() {
+        new_empty_array(field.data_type())
+    } else {
+        ScalarValue::iter_to_array(flatten_values)?
+    };
+    Ok(Arc::new(FixedSizeListArray::try_new(
+        field.clone(),
+        size,
+        values,
+        Some(NullBuffer::from(valids)),
+    )?))
 }
 
 impl Debug for AggCollectList {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        write!(f, "CollectList({:?})", self.child)
+        write!(f, "CollectList({:?})", self.children)
     }
 }
 
@@ -87,15 +666,40 @@ impl Agg for AggCollectList {
     }
 
     fn exprs(&self) -> Vec<Arc<dyn PhysicalExpr>> {
-        vec![self.child.clone()]
+        self.children.clone()
     }
 
     fn with_new_exprs(&self, exprs: Vec<Arc<dyn PhysicalExpr>>) -> Result<Arc<dyn Agg>> {
-        Ok(Arc::new(Self::try_new(
-            exprs[0].clone(),
-            self.data_type.clone(),
-            self.arg_type.clone(),
-        )?))
+        if exprs.len() != self.children.len() {
+            return df_execution_err!(
+                "collect_list expect {} exprs, got: {}",
+                self.children.len(),
+                exprs.len()
+            );
+        }
+        let mut agg = Self::try_new_internal(exprs, self.data_type.clone(), self.arg_type.clone())?;
+        agg.include_nulls = self.include_nulls;
+        agg.merge_lists = self.merge_lists;
+        if self.run_length_encoding {
+            return Ok(Arc::new(agg.with_run_length_encoding()));
+        }
+        Ok(Arc::new(agg))
+    }
+
+    fn prepare_partial_args(&self, partial_inputs: &[ArrayRef]) -> Result<Vec<ArrayRef>> {
+        if self.children.len() == 1 {
+            return Ok(partial_inputs.to_vec());
+        }
+        // pack multiple arguments into a struct, which is never null
+        let fields = match &self.arg_type {
+            DataType::Struct(fields) => fields.clone(),
+            other => return df_execution_err!("collect_list expect Struct type, got: {other}"),
+        };
+        Ok(vec![Arc::new(StructArray::try_new(
+            fields,
+            partial_inputs.to_vec(),
+            None,
+        )?)])
     }
 
     fn data_type(&self) -> &DataType {
@@ -112,7 +716,17 @@ impl Agg for AggCollectList {
 
     fn increase_acc_mem_used(&self, acc: &mut RefAccumStateRow) {
         if let Some(v) = acc.dyn_value(self.accum_state_val_addr) {
-            self.add_mem_used(v.mem_size());
+            if let Some(list) = v.as_any().downcast_ref::<AggDynList>() {
+                self.add_list_used(list);
+            }
+        }
+    }
+
+    fn decrease_acc_mem_used(&self, acc: &mut RefAccumStateRow) {
+        if let Some(v) = acc.dyn_value(self.accum_state_val_addr) {
+            if let Some(list) = v.as_any().downcast_ref::<AggDynList>() {
+                self.sub_list_used(list);
+            }
         }
     }
 
@@ -122,19 +736,30 @@ impl Agg for AggCollectList {
         values: &[ArrayRef],
         row_idx: usize,
     ) -> Result<()> {
-        if values[0].is_valid(row_idx) {
+        self.check_arg_type(&values[0])?;
+        if self.merge_lists {
+            let lists = as_merging_lists(&values[0])?;
+            if lists.is_valid(row_idx) {
+                self.append_values(acc, &lists.value(row_idx))?;
+            }
+            return Ok(());
+        }
+        if self.include_nulls || values[0].is_valid(row_idx) {
+            if self.run_length_encoding {
+                return self.append_to_runs(acc, &values[0], row_idx);
+            }
             match acc.dyn_value_mut(self.accum_state_val_addr) {
                 Some(dyn_list) => {
                     let list = downcast_any!(dyn_list, mut AggDynList)?;
-                    self.sub_mem_used(list.mem_size());
+                    self.sub_list_used(list);
 
-                    list.append(&ScalarValue::try_from_array(&values[0], row_idx)?, false);
-                    self.add_mem_used(list.mem_size());
+                    list.append_array_value(&values[0], row_idx, self.include_nulls)?;
+                    self.add_list_used(list);
                 }
                 w => {
                     let mut new_list = AggDynList::default();
-                    new_list.append(&ScalarValue::try_from_array(&values[0], row_idx)?, false);
-                    self.add_mem_used(new_list.mem_size());
+                    new_list.append_array_value(&values[0], row_idx, self.include_nulls)?;
+                    self.add_list_used(&new_list);
                     *w = Some(Box::new(new_list));
                 }
             }
@@ -143,25 +768,17 @@ impl Agg for AggCollectList {
     }
 
     fn partial_update_all(&self, acc: &mut RefAccumStateRow, values: &[ArrayRef]) -> Result<()> {
-        let dyn_list = match acc.dyn_value_mut(self.accum_state_val_addr) {
-            Some(dyn_list) => dyn_list,
-            w => {
-                let new_list = AggDynList::default();
-                self.add_mem_used(new_list.mem_size());
-                *w = Some(Box::new(new_list));
-                w.as_mut().unwrap()
-            }
-        };
-        let list = downcast_any!(dyn_list, mut AggDynList)?;
-        self.sub_mem_used(list.mem_size());
-
-        for i in 0..values[0].len() {
-            if values[0].is_valid(i) {
-                list.append(&ScalarValue::try_from_array(&values[0], i)?, false);
+        self.check_arg_type(&values[0])?;
+        if self.merge_lists {
+            let lists = as_merging_lists(&values[0])?;
+            for i in 0..lists.len() {
+                if lists.is_valid(i) {
+                    self.append_values(acc, &lists.value(i))?;
+                }
             }
+            return Ok(());
         }
-        self.add_mem_used(list.mem_size());
-        Ok(())
+        self.append_values(acc, &values[0])
     }
 
     fn partial_merge(
@@ -169,6 +786,9 @@ impl Agg for AggCollectList {
         acc: &mut RefAccumStateRow,
         merging_acc: &mut RefAccumStateRow,
     ) -> Result<()> {
+        if self.run_length_encoding {
+            return self.merge_runs(acc, merging_acc);
+        }
         match (
             acc.dyn_value_mut(self.accum_state_val_addr),
             merging_acc.dyn_value_mut(self.accum_state_val_addr),
@@ -176,11 +796,11 @@ impl Agg for AggCollectList {
             (Some(w), Some(v)) => {
                 let w = downcast_any!(w, mut AggDynList)?;
                 let v = downcast_any!(v, mut AggDynList)?;
-                self.sub_mem_used(w.mem_size());
-                self.sub_mem_used(v.mem_size());
+                self.sub_list_used(w);
+                self.sub_list_used(v);
 
                 w.merge(v);
-                self.add_mem_used(w.mem_size());
+                self.add_list_used(w);
             }
             (w_none, v @ Some(_)) => *w_none = std::mem::take(v),
             (None, _) => {}
@@ -189,35 +809,844 @@ impl Agg for AggCollectList {
         Ok(())
     }
 
-    fn final_merge(&self, acc: &mut RefAccumStateRow) -> Result<ScalarValue> {
-        match std::mem::take(acc.dyn_value_mut(self.accum_state_val_addr)) {
-            Some(w) => {
-                let list = w
-                    .as_any_boxed()
-                    .downcast::<AggDynList>()
-                    .or_else(|_| df_execution_err!("error downcasting to AggDynList"))?;
-                self.sub_mem_used(list.mem_size());
+    fn partial_merge_many(
+        &self,
+        acc: &mut RefAccumStateRow,
+        merging_accs: &mut [RefAccumStateRow],
+    ) -> Result<()> {
+        if self.run_length_encoding {
+            for merging_acc in merging_accs {
+                self.merge_runs(acc, merging_acc)?;
+            }
+            return Ok(());
+        }
+        let mut merging_lists = vec![];
+        for merging_acc in merging_accs {
+            if let Some(v) = std::mem::take(merging_acc.dyn_value_mut(self.accum_state_val_addr)) {
+                self.sub_list_used(downcast_any!(v, AggDynList)?);
+                merging_lists.push(v);
+            }
+        }
+        if merging_lists.is_empty() {
+            return Ok(());
+        }
+
+        let w = acc.dyn_value_mut(self.accum_state_val_addr);
+        if w.is_none() {
+            let first = merging_lists.remove(0);
+            self.add_list_used(downcast_any!(first, AggDynList)?);
+            *w = Some(first);
+        }
+        let w = downcast_any!(w.as_mut().unwrap(), mut AggDynList)?;
+        self.sub_list_used(w);
 
-                Ok(ScalarValue::List(ScalarValue::new_list(
-                    &list
-                        .into_values(self.arg_type.clone(), false)
-                        .collect::<Vec<_>>(),
-                    &self.arg_type,
-                )))
+        // concatenates all partials with a single allocation
+        let mut additional_bytes = 0;
+        for v in &mut merging_lists {
+            additional_bytes += downcast_any!(v, mut AggDynList)?.raw.len();
+        }
+        w.raw.reserve(additional_bytes);
+        for v in &mut merging_lists {
+            w.merge(downcast_any!(v, mut AggDynList)?);
+        }
+        self.add_list_used(w);
+        Ok(())
+    }
+
+    fn partial_batch_merge(
+        &self,
+        accs: &mut [RefAccumStateRow],
+        merging_accs: &mut [RefAccumStateRow],
+    ) -> Result<()> {
+        if self.run_length_encoding {
+            for (acc, merging_acc) in accs.iter_mut().zip(merging_accs) {
+                self.merge_runs(acc, merging_acc)?;
+            }
+            return Ok(());
+        }
+
+        // fast path of partial_merge() for merging many spilled partials, memory
+        // tracking is updated once for the whole batch instead of per row
+        let mut mem_added = 0;
+        let mut mem_subbed = 0;
+        let mut max_group_raw_bytes = 0;
+        for (acc, merging_acc) in accs.iter_mut().zip(merging_accs) {
+            match (
+                acc.dyn_value_mut(self.accum_state_val_addr),
+                merging_acc.dyn_value_mut(self.accum_state_val_addr),
+            ) {
+                (Some(w), Some(v)) => {
+                    let w = downcast_any!(w, mut AggDynList)?;
+                    let v = downcast_any!(v, mut AggDynList)?;
+                    mem_subbed += w.mem_size() + v.mem_size();
+
+                    if w.raw.is_empty() {
+                        std::mem::swap(&mut w.raw, &mut v.raw);
+                    } else {
+                        w.merge(v);
+                    }
+                    mem_added += w.mem_size();
+                    max_group_raw_bytes = max_group_raw_bytes.max(w.raw.len());
+                }
+                (w_none, v @ Some(_)) => *w_none = std::mem::take(v),
+                (None, _) => {}
+                (_, None) => {}
             }
-            None => ScalarValue::try_from(&self.data_type),
         }
+        self.add_mem_used(mem_added);
+        self.sub_mem_used(mem_subbed);
+
+        // serialized bytes are only moved between groups, the total is unchanged
+        self.add_raw_bytes(0, max_group_raw_bytes);
+        Ok(())
+    }
+
+    fn shrink_acc_to_fit(&self, acc: &mut RefAccumStateRow) {
+        if let Some(v) = acc.dyn_value_mut(self.accum_state_val_addr) {
+            self.sub_mem_used(v.mem_size());
+            v.shrink_to_fit();
+            self.add_mem_used(v.mem_size());
+        }
+    }
+
+    fn final_merge(&self, acc: &mut RefAccumStateRow) -> Result<ScalarValue> {
+        if let DataType::FixedSizeList(field, size) = &self.data_type {
+            let array = build_fixed_size_list(field, *size, vec![self.take_values(acc)?])?;
+            return ScalarValue::try_from_array(&array, 0);
+        }
+        let array = build_list(&self.data_type, vec![self.take_array(acc)?])?;
+        ScalarValue::try_from_array(&array, 0)
     }
 
     fn final_batch_merge(&self, accs: &mut [RefAccumStateRow]) -> Result<ArrayRef> {
-        let values: Vec<ScalarValue> = accs
+        if let DataType::FixedSizeList(field, size) = &self.data_type {
+            let groups = accs
+                .iter_mut()
+                .map(|acc| self.take_values(acc))
+                .collect::<Result<Vec<_>>>()?;
+            return build_fixed_size_list(field, *size, groups);
+        }
+        let groups = accs
+            .iter_mut()
+            .map(|acc| self.take_array(acc))
+            .collect::<Result<Vec<_>>>()?;
+        build_list(&self.data_type, groups)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{io::Cursor, sync::Arc};
+
+    use arrow::{
+        array::*,
+        datatypes::{
+            DataType, Field, Fields, Int32Type, Int64Type, IntervalMonthDayNanoType, IntervalUnit,
+        },
+    };
+    use datafusion::{
+        common::{Result, ScalarValue},
+        physical_expr::expressions::Column,
+    };
+    use datafusion_ext_commons::downcast_any;
+
+    use crate::agg::{
+        acc::{
+            create_acc_from_initial_value, create_dyn_loaders_from_initial_value,
+            create_dyn_savers_from_initial_value, AccumStateRow, AggDynList, AggDynValue,
+            LoadReader, OwnedAccumStateRow, SaveWriter,
+        },
+        collect_list::{build_list, build_offsets, AggCollectList},
+        Agg, WithAggBufAddrs, WithMemTracking,
+    };
+
+    #[test]
+    fn test_partial_batch_merge_equals_partial_merge() -> Result<()> {
+        let mut agg = AggCollectList::try_new(
+            Arc::new(Column::new("a", 0)),
+            DataType::new_list(DataType::Int32, true),
+            DataType::Int32,
+        )?;
+        let (initial_acc, addrs) = create_acc_from_initial_value(agg.accums_initial())?;
+        agg.set_accum_state_val_addrs(&addrs);
+
+        // xorshift random generator
+        let mut seed = 0x2545F4914F6CDD1Du64;
+        let mut rand = move || {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            seed
+        };
+        let mut random_accs = |num_rows: usize| {
+            (0..num_rows)
+                .map(|_| {
+                    let mut acc = initial_acc.clone();
+                    *acc.as_mut().dyn_value_mut(addrs[0]) = match rand() % 4 {
+                        0 => None,
+                        n => {
+                            let mut list = AggDynList::default();
+                            for _ in 0..(rand() % (n * 3)) {
+                                list.append(&ScalarValue::from(rand() as i32), false)
+                                    .unwrap();
+                            }
+                            Some(Box::new(list))
+                        }
+                    };
+                    acc
+                })
+                .collect::<Vec<OwnedAccumStateRow>>()
+        };
+
+        for num_rows in [0, 1, 10, 1000] {
+            let mut accs1 = random_accs(num_rows);
+            let mut accs2 = accs1.clone();
+            let mut merging_accs1 = random_accs(num_rows);
+            let mut merging_accs2 = merging_accs1.clone();
+
+            // general path
+            for (acc, merging_acc) in accs1.iter_mut().zip(&mut merging_accs1) {
+                agg.partial_merge(&mut acc.as_mut(), &mut merging_acc.as_mut())?;
+            }
+
+            // fast path
+            let mut ref_accs2 = accs2.iter_mut().map(|acc| acc.as_mut()).collect::<Vec<_>>();
+            let mut ref_merging_accs2 = merging_accs2
+                .iter_mut()
+                .map(|acc| acc.as_mut())
+                .collect::<Vec<_>>();
+            agg.partial_batch_merge(&mut ref_accs2, &mut ref_merging_accs2)?;
+
+            let mut ref_accs1 = accs1.iter_mut().map(|acc| acc.as_mut()).collect::<Vec<_>>();
+            assert_eq!(
+                agg.final_batch_merge(&mut ref_accs1)?.to_data(),
+                agg.final_batch_merge(&mut ref_accs2)?.to_data(),
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_include_nulls() -> Result<()> {
+        let mut agg = AggCollectList::try_new(
+            Arc::new(Column::new("a", 0)),
+            DataType::new_list(DataType::Int32, true),
+            DataType::Int32,
+        )?
+        .with_include_nulls();
+        let (initial_acc, addrs) = create_acc_from_initial_value(agg.accums_initial())?;
+        agg.set_accum_state_val_addrs(&addrs);
+
+        let values: ArrayRef = Arc::new(Int32Array::from(vec![
+            None,
+            Some(1),
+            None,
+            Some(2),
+            None,
+            None,
+            Some(3),
+        ]));
+        let mut acc = initial_acc.clone();
+        agg.partial_update(&mut acc.as_mut(), &[values.clone()], 0)?;
+        agg.partial_update(&mut acc.as_mut(), &[values.clone()], 1)?;
+        agg.partial_update_all(&mut acc.as_mut(), &[values.slice(2, 5)])?;
+
+        // round-trip through spill
+        let mut buf = vec![];
+        let savers = create_dyn_savers_from_initial_value(agg.accums_initial())?;
+        let loaders = create_dyn_loaders_from_initial_value(agg.accums_initial())?;
+        let mut save_writer = SaveWriter(Box::new(Cursor::new(&mut buf)));
+        savers[0](
+            &mut save_writer,
+            std::mem::take(acc.as_mut().dyn_value_mut(addrs[0])),
+        )?;
+        drop(save_writer);
+        let mut load_reader = LoadReader(Box::new(Cursor::new(&buf)));
+        let mut loaded_acc = initial_acc.clone();
+        *loaded_acc.as_mut().dyn_value_mut(addrs[0]) = loaders[0](&mut load_reader)?;
+
+        // round-trip through the row format of partial aggregation output
+        let bytes = loaded_acc.save_to_bytes(&savers)?;
+        let mut loaded_acc = initial_acc.clone();
+        loaded_acc.load_from_bytes(bytes.as_ref(), &loaders)?;
+
+        assert_eq!(
+            agg.final_merge(&mut loaded_acc.as_mut())?,
+            ScalarValue::List(ScalarValue::new_list(
+                &[
+                    ScalarValue::Int32(None),
+                    ScalarValue::Int32(Some(1)),
+                    ScalarValue::Int32(None),
+                    ScalarValue::Int32(Some(2)),
+                    ScalarValue::Int32(None),
+                    ScalarValue::Int32(None),
+                    ScalarValue::Int32(Some(3)),
+                ],
+                &DataType::Int32,
+            )),
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_fixed_size_list_output() -> Result<()> {
+        let field = Arc::new(Field::new("item", DataType::Int32, true));
+        let mut agg = AggCollectList::try_new(
+            Arc::new(Column::new("a", 0)),
+            DataType::FixedSizeList(field.clone(), 2),
+            DataType::Int32,
+        )?;
+        let (initial_acc, addrs) = create_acc_from_initial_value(agg.accums_initial())?;
+        agg.set_accum_state_val_addrs(&addrs);
+
+        let values: ArrayRef = Arc::new(Int32Array::from(vec![1, 2, 3, 4, 5]));
+        let mut accs = vec![initial_acc.clone(); 2];
+        agg.partial_update_all(&mut accs[0].as_mut(), &[values.slice(0, 2)])?;
+        agg.partial_update_all(&mut accs[1].as_mut(), &[values.slice(2, 2)])?;
+        let mut ref_accs = accs.iter_mut().map(|acc| acc.as_mut()).collect::<Vec<_>>();
+        assert_eq!(
+            agg.final_batch_merge(&mut ref_accs)?.to_data(),
+            FixedSizeListArray::try_new(
+                field,
+                2,
+                Arc::new(Int32Array::from(vec![1, 2, 3, 4])),
+                Some(vec![true, true].into()),
+            )?
+            .to_data(),
+        );
+
+        // group size mismatch
+        let mut acc = initial_acc.clone();
+        agg.partial_update_all(&mut acc.as_mut(), &[values.slice(0, 3)])?;
+        assert!(agg.final_merge(&mut acc.as_mut()).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_merge_lists_include_nulls() -> Result<()> {
+        let mut agg = AggCollectList::try_new(
+            Arc::new(Column::new("a", 0)),
+            DataType::new_list(DataType::Int32, true),
+            DataType::Int32,
+        )?
+        .with_include_nulls()
+        .with_merge_lists();
+        let (initial_acc, addrs) = create_acc_from_initial_value(agg.accums_initial())?;
+        agg.set_accum_state_val_addrs(&addrs);
+
+        // null lists are skipped, null elements are kept
+        let lists: ArrayRef = Arc::new(ListArray::from_iter_primitive::<Int32Type, _, _>(vec![
+            Some(vec![Some(1), None]),
+            None,
+            Some(vec![Some(1), Some(2)]),
+        ]));
+        let mut acc = initial_acc.clone();
+        agg.partial_update_all(&mut acc.as_mut(), &[lists.slice(0, 2)])?;
+        agg.partial_update(&mut acc.as_mut(), &[lists.clone()], 2)?;
+        assert_eq!(
+            agg.final_batch_merge(&mut [acc.as_mut()])?.to_data(),
+            ListArray::from_iter_primitive::<Int32Type, _, _>(vec![Some(vec![
+                Some(1),
+                None,
+                Some(1),
+                Some(2),
+            ])])
+            .to_data(),
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_unsupported_arg_type() {
+        let arg_type = DataType::FixedSizeBinary(16);
+        let err = AggCollectList::try_new(
+            Arc::new(Column::new("a", 0)),
+            DataType::new_list(arg_type.clone(), true),
+            arg_type,
+        )
+        .err()
+        .expect("fixed-size binary type is not supported by write_scalar()");
+        assert!(err
+            .to_string()
+            .contains("collect_list does not support argument type"));
+    }
+
+    #[test]
+    fn test_interval_arg() -> Result<()> {
+        let arg_type = DataType::Interval(IntervalUnit::MonthDayNano);
+        let mut agg = AggCollectList::try_new(
+            Arc::new(Column::new("a", 0)),
+            DataType::new_list(arg_type.clone(), true),
+            arg_type.clone(),
+        )?
+        .with_include_nulls();
+        let (initial_acc, addrs) = create_acc_from_initial_value(agg.accums_initial())?;
+        agg.set_accum_state_val_addrs(&addrs);
+
+        // negative and multi-component intervals
+        let values: ArrayRef = Arc::new(IntervalMonthDayNanoArray::from(vec![
+            Some(IntervalMonthDayNanoType::make_value(1, 2, 3)),
+            None,
+            Some(IntervalMonthDayNanoType::make_value(-1, -2, -3)),
+            Some(IntervalMonthDayNanoType::make_value(0, 0, i64::MIN)),
+            Some(IntervalMonthDayNanoType::make_value(1, 2, 3)),
+        ]));
+        let mut acc = initial_acc.clone();
+        agg.partial_update_all(&mut acc.as_mut(), &[values.slice(0, 3)])?;
+        for row_idx in 3..values.len() {
+            agg.partial_update(&mut acc.as_mut(), &[values.clone()], row_idx)?;
+        }
+
+        // round-trip through spill
+        let mut buf = vec![];
+        let savers = create_dyn_savers_from_initial_value(agg.accums_initial())?;
+        let loaders = create_dyn_loaders_from_initial_value(agg.accums_initial())?;
+        let mut save_writer = SaveWriter(Box::new(Cursor::new(&mut buf)));
+        savers[0](
+            &mut save_writer,
+            std::mem::take(acc.as_mut().dyn_value_mut(addrs[0])),
+        )?;
+        drop(save_writer);
+        let mut load_reader = LoadReader(Box::new(Cursor::new(&buf)));
+        let mut loaded_acc = initial_acc.clone();
+        *loaded_acc.as_mut().dyn_value_mut(addrs[0]) = loaders[0](&mut load_reader)?;
+
+        let output = agg.final_batch_merge(&mut [loaded_acc.as_mut()])?;
+        let output = output.as_list::<i32>();
+        assert_eq!(output.value(0).data_type(), &arg_type);
+        assert_eq!(&output.value(0), &values);
+        Ok(())
+    }
+
+    #[test]
+    fn test_nested_list_arg() -> Result<()> {
+        let arg_type = DataType::new_list(DataType::Int32, true);
+        let data_type = DataType::new_list(arg_type.clone(), true);
+        let mut agg =
+            AggCollectList::try_new(Arc::new(Column::new("a", 0)), data_type.clone(), arg_type)?;
+        let (initial_acc, addrs) = create_acc_from_initial_value(agg.accums_initial())?;
+        agg.set_accum_state_val_addrs(&addrs);
+
+        // inner lists of varying lengths, null inner lists are skipped
+        let values: ArrayRef = Arc::new(ListArray::from_iter_primitive::<Int32Type, _, _>(vec![
+            Some(vec![Some(1), Some(2), Some(3)]),
+            Some(vec![]),
+            None,
+            Some(vec![None, Some(4)]),
+            Some(vec![Some(5)]),
+            Some(vec![]),
+        ]));
+        let mut accs = vec![initial_acc.clone(); 3];
+        let mut merging_acc = initial_acc.clone();
+        agg.partial_update_all(&mut accs[0].as_mut(), &[values.slice(0, 3)])?;
+        for row_idx in 3..values.len() {
+            agg.partial_update(&mut merging_acc.as_mut(), &[values.clone()], row_idx)?;
+        }
+        agg.partial_merge(&mut accs[0].as_mut(), &mut merging_acc.as_mut())?;
+        agg.partial_update_all(&mut accs[1].as_mut(), &[values.slice(1, 1)])?;
+
+        let mut ref_accs = accs.iter_mut().map(|acc| acc.as_mut()).collect::<Vec<_>>();
+        let results = agg.final_batch_merge(&mut ref_accs)?;
+        assert_eq!(results.data_type(), &data_type);
+
+        let results = results.as_list::<i32>();
+        assert_eq!(
+            results.value(0).to_data(),
+            ListArray::from_iter_primitive::<Int32Type, _, _>(vec![
+                Some(vec![Some(1), Some(2), Some(3)]),
+                Some(vec![]),
+                Some(vec![None, Some(4)]),
+                Some(vec![Some(5)]),
+                Some(vec![]),
+            ])
+            .to_data(),
+        );
+        assert_eq!(
+            results.value(1).to_data(),
+            ListArray::from_iter_primitive::<Int32Type, _, _>(vec![Some(vec![])]).to_data(),
+        );
+        assert!(results.is_null(2));
+        Ok(())
+    }
+
+    #[test]
+    fn test_multiple_args() -> Result<()> {
+        let fields = Fields::from(vec![
+            Field::new("col1", DataType::Int32, true),
+            Field::new("col2", DataType::Utf8, true),
+        ]);
+        let arg_type = DataType::Struct(fields.clone());
+        let data_type = DataType::new_list(arg_type.clone(), true);
+        let mut agg = AggCollectList::try_new_with_multiple_args(
+            vec![Arc::new(Column::new("a", 0)), Arc::new(Column::new("b", 1))],
+            data_type.clone(),
+            arg_type.clone(),
+        )?;
+        let (initial_acc, addrs) = create_acc_from_initial_value(agg.accums_initial())?;
+        agg.set_accum_state_val_addrs(&addrs);
+
+        // rows with null arguments are kept since the packed struct is not null
+        let a: ArrayRef = Arc::new(Int32Array::from(vec![Some(1), None, Some(3)]));
+        let b: ArrayRef = Arc::new(StringArray::from(vec![Some("x"), Some("y"), None]));
+        let values = agg.prepare_partial_args(&[a.clone(), b.clone()])?;
+        let mut acc = initial_acc.clone();
+        agg.partial_update_all(&mut acc.as_mut(), &[values[0].slice(0, 2)])?;
+        agg.partial_update(&mut acc.as_mut(), &values, 2)?;
+
+        let results = agg.final_batch_merge(&mut [acc.as_mut()])?;
+        assert_eq!(results.data_type(), &data_type);
+        assert_eq!(
+            results.as_list::<i32>().value(0).to_data(),
+            StructArray::try_new(fields, vec![a, b], None)?.to_data(),
+        );
+
+        // arity must be kept
+        assert!(agg
+            .with_new_exprs(vec![Arc::new(Column::new("a", 0))])
+            .is_err());
+        assert!(AggCollectList::try_new_with_multiple_args(
+            vec![Arc::new(Column::new("a", 0)), Arc::new(Column::new("b", 1))],
+            DataType::new_list(DataType::Int32, true),
+            DataType::Int32
+        )
+        .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_raw_bytes_tracking() -> Result<()> {
+        let values: ArrayRef = Arc::new(StringArray::from_iter(
+            (0..1000).map(|i| (i % 7 != 0).then(|| format!("value{}", i % 13))),
+        ));
+        for run_length_encoding in [false, true] {
+            let mut agg = AggCollectList::try_new(
+                Arc::new(Column::new("a", 0)),
+                DataType::new_list(DataType::Utf8, true),
+                DataType::Utf8,
+            )?;
+            if run_length_encoding {
+                agg = agg.with_run_length_encoding();
+            }
+            let (initial_acc, addrs) = create_acc_from_initial_value(agg.accums_initial())?;
+            agg.set_accum_state_val_addrs(&addrs);
+            let raw_lens = |accs: &mut [OwnedAccumStateRow]| {
+                accs.iter_mut()
+                    .map(|acc| {
+                        acc.as_mut()
+                            .dyn_value(addrs[0])
+                            .as_ref()
+                            .map(|v| downcast_any!(v, AggDynList).unwrap().raw.len())
+                            .unwrap_or_default()
+                    })
+                    .collect::<Vec<_>>()
+            };
+
+            // group 0: skewed group updated all at once and row by row
+            // group 1/2: merged from partials, pairwise and in batch
+            let mut accs = vec![initial_acc.clone(); 3];
+            let mut merging_accs = vec![initial_acc.clone(); 3];
+            agg.partial_update_all(&mut accs[0].as_mut(), &[values.clone()])?;
+            for row_idx in 0..values.len() {
+                agg.partial_update(&mut accs[0].as_mut(), &[values.clone()], row_idx)?;
+            }
+            for (i, merging_acc) in merging_accs.iter_mut().enumerate() {
+                let partial = values.slice(i * 100, 100);
+                agg.partial_update_all(&mut merging_acc.as_mut(), &[partial])?;
+            }
+            agg.partial_update_all(&mut accs[1].as_mut(), &[values.slice(500, 50)])?;
+            agg.partial_merge(&mut accs[1].as_mut(), &mut merging_accs[0].as_mut())?;
+            let mut ref_accs = accs[1..]
+                .iter_mut()
+                .map(|acc| acc.as_mut())
+                .collect::<Vec<_>>();
+            let mut ref_merging_accs = merging_accs[1..]
+                .iter_mut()
+                .map(|acc| acc.as_mut())
+                .collect::<Vec<_>>();
+            agg.partial_batch_merge(&mut ref_accs, &mut ref_merging_accs)?;
+
+            // counters equal recomputation over all groups
+            let lens = [raw_lens(&mut accs), raw_lens(&mut merging_accs)].concat();
+            assert_eq!(agg.total_raw_bytes(), lens.iter().sum::<usize>());
+            assert_eq!(
+                agg.max_group_raw_bytes(),
+                lens.iter().copied().max().unwrap()
+            );
+            assert!(lens[0] > lens[1] + lens[2]);
+
+            // outputs are no longer tracked, but the peak is kept
+            let mut ref_accs = accs.iter_mut().map(|acc| acc.as_mut()).collect::<Vec<_>>();
+            agg.final_batch_merge(&mut ref_accs)?;
+            assert_eq!(agg.total_raw_bytes(), 0);
+            assert_eq!(agg.max_group_raw_bytes(), lens[0]);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_partial_merge_many() -> Result<()> {
+        let values: ArrayRef = Arc::new(Int32Array::from_iter(
+            (0..1600).map(|i| Some(i % 37).filter(|v| v % 5 != 0)),
+        ));
+        for run_length_encoding in [false, true] {
+            let mut agg = AggCollectList::try_new(
+                Arc::new(Column::new("a", 0)),
+                DataType::new_list(DataType::Int32, true),
+                DataType::Int32,
+            )?
+            .with_include_nulls();
+            if run_length_encoding {
+                agg = agg.with_run_length_encoding();
+            }
+            let (initial_acc, addrs) = create_acc_from_initial_value(agg.accums_initial())?;
+            agg.set_accum_state_val_addrs(&addrs);
+
+            // 16 partials with an empty one, merged pairwise/at once
+            let mut accs = vec![initial_acc.clone(); 2];
+            for (i, acc) in accs.iter_mut().enumerate() {
+                let mut merging_accs = vec![initial_acc.clone(); 16];
+                for (j, merging_acc) in merging_accs.iter_mut().enumerate() {
+                    if j != 3 {
+                        let partial = values.slice(j * 100, 100);
+                        agg.partial_update_all(&mut merging_acc.as_mut(), &[partial])?;
+                    }
+                }
+                let mut ref_merging_accs = merging_accs
+                    .iter_mut()
+                    .map(|acc| acc.as_mut())
+                    .collect::<Vec<_>>();
+                if i == 0 {
+                    for merging_acc in &mut ref_merging_accs {
+                        agg.partial_merge(&mut acc.as_mut(), merging_acc)?;
+                    }
+                } else {
+                    agg.partial_merge_many(&mut acc.as_mut(), &mut ref_merging_accs)?;
+                }
+            }
+            let mut ref_accs = accs.iter_mut().map(|acc| acc.as_mut()).collect::<Vec<_>>();
+            let output = agg.final_batch_merge(&mut ref_accs)?;
+            let output = output.as_list::<i32>();
+            assert_eq!(output.value(0).len(), 1500);
+            assert_eq!(output.value(0).to_data(), output.value(1).to_data());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_run_length_encoding() -> Result<()> {
+        let new_agg = |run_length_encoding: bool| -> Result<AggCollectList> {
+            let agg = AggCollectList::try_new(
+                Arc::new(Column::new("a", 0)),
+                DataType::new_list(DataType::Int32, true),
+                DataType::Int32,
+            )?
+            .with_include_nulls();
+            Ok(match run_length_encoding {
+                true => agg.with_run_length_encoding(),
+                false => agg,
+            })
+        };
+
+        // runs of 100 values, with runs of nulls
+        let values: ArrayRef = Arc::new(Int32Array::from_iter(
+            (0..2000).map(|i| Some(i / 100).filter(|v| v % 3 != 2)),
+        ));
+
+        let mut outputs = vec![];
+        let mut mem_useds = vec![];
+        for run_length_encoding in [false, true] {
+            let mut agg = new_agg(run_length_encoding)?;
+            let (initial_acc, addrs) = create_acc_from_initial_value(agg.accums_initial())?;
+            agg.set_accum_state_val_addrs(&addrs);
+
+            // group 0: updated all at once
+            // group 1: updated row by row
+            // group 2: merged from partials split inside runs and at run boundaries
+            let mut accs = vec![initial_acc.clone(); 3];
+            agg.partial_update_all(&mut accs[0].as_mut(), &[values.clone()])?;
+            for row_idx in 0..values.len() {
+                agg.partial_update(&mut accs[1].as_mut(), &[values.clone()], row_idx)?;
+            }
+            let mut merging_accs = vec![initial_acc.clone(); 4];
+            agg.partial_update_all(&mut accs[2].as_mut(), &[values.slice(0, 250)])?;
+            agg.partial_update_all(&mut merging_accs[0].as_mut(), &[values.slice(250, 50)])?;
+            agg.partial_update_all(&mut merging_accs[1].as_mut(), &[values.slice(300, 1000)])?;
+            agg.partial_update_all(&mut merging_accs[2].as_mut(), &[values.slice(1300, 700)])?;
+            for merging_acc in &mut merging_accs {
+                agg.partial_merge(&mut accs[2].as_mut(), &mut merging_acc.as_mut())?;
+            }
+            mem_useds.push(agg.mem_used());
+
+            let mut ref_accs = accs.iter_mut().map(|acc| acc.as_mut()).collect::<Vec<_>>();
+            outputs.push(agg.final_batch_merge(&mut ref_accs)?);
+        }
+
+        let expected = ListArray::from_iter_primitive::<Int32Type, _, _>(vec![
+            Some(
+                values
+                    .as_primitive::<Int32Type>()
+                    .iter()
+                    .collect::<Vec<_>>()
+            );
+            3
+        ]);
+        assert_eq!(outputs[0].to_data(), expected.to_data());
+        assert_eq!(outputs[1].to_data(), expected.to_data());
+        assert!(mem_useds[1] * 10 < mem_useds[0]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_presize_lists() -> Result<()> {
+        let mut agg = AggCollectList::try_new(
+            Arc::new(Column::new("a", 0)),
+            DataType::new_list(DataType::Int32, true),
+            DataType::Int32,
+        )?;
+        let (initial_acc, addrs) = create_acc_from_initial_value(agg.accums_initial())?;
+        agg.set_accum_state_val_addrs(&addrs);
+
+        // group 0: presized large group
+        // group 1: large group without hint
+        // group 2: presized after values are appended
+        let values: ArrayRef = Arc::new(Int32Array::from_iter_values(0..10000));
+        let mut accs = vec![initial_acc.clone(); 3];
+        let mut ref_accs = accs.iter_mut().map(|acc| acc.as_mut()).collect::<Vec<_>>();
+        agg.partial_update_all(&mut ref_accs[2], &[values.slice(0, 1)])?;
+        agg.presize_lists(&mut ref_accs, &[100000, 0, 100000])?;
+        let list_mem_sizes = ref_accs
             .iter_mut()
-            .map(|acc| self.final_merge(acc))
-            .collect::<Result<_>>()?;
+            .map(|acc| {
+                acc.dyn_value(addrs[0])
+                    .as_ref()
+                    .map(|list| list.mem_size())
+                    .unwrap_or_default()
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(agg.mem_used(), list_mem_sizes.iter().sum::<usize>());
+        assert_eq!(list_mem_sizes[1], 0);
+
+        let presized_capacity = list_mem_sizes[0];
+        assert!(presized_capacity >= 100000);
+        agg.partial_update_all(&mut ref_accs[0], &[values.clone()])?;
+        agg.partial_update_all(&mut ref_accs[1], &[values.clone()])?;
+        agg.partial_update_all(&mut ref_accs[2], &[values.slice(1, 9999)])?;
 
-        if values.is_empty() {
-            return Ok(new_empty_array(self.data_type()));
+        // the presized buffer is large enough, so it is never reallocated
+        assert_eq!(
+            ref_accs[0]
+                .dyn_value(addrs[0])
+                .as_ref()
+                .map(|list| list.mem_size()),
+            Some(presized_capacity),
+        );
+        let output = agg.final_batch_merge(&mut ref_accs)?;
+        let output = output.as_list::<i32>();
+        for i in 0..3 {
+            assert_eq!(&output.value(i), &values);
         }
-        Ok(ScalarValue::iter_to_array(values)?)
+        Ok(())
+    }
+
+    #[test]
+    fn test_large_list_output() -> Result<()> {
+        // offsets overflow is detected without materializing large groups
+        let lens = [i32::MAX as usize - 1, 1];
+        assert!(build_offsets::<i32>(&lens).is_ok());
+        assert!(build_offsets::<i32>(&[i32::MAX as usize, 1]).is_err());
+        assert_eq!(
+            build_offsets::<i64>(&[i32::MAX as usize, 1])?.last(),
+            Some(&(i32::MAX as i64 + 1)),
+        );
+
+        let data_type = DataType::LargeList(Arc::new(Field::new("item", DataType::Int32, true)));
+        let mut agg = AggCollectList::try_new(
+            Arc::new(Column::new("a", 0)),
+            data_type.clone(),
+            DataType::Int32,
+        )?;
+        let (initial_acc, addrs) = create_acc_from_initial_value(agg.accums_initial())?;
+        agg.set_accum_state_val_addrs(&addrs);
+
+        let values: ArrayRef = Arc::new(Int32Array::from(vec![Some(1), None, Some(2)]));
+        let mut acc = initial_acc.clone();
+        agg.partial_update_all(&mut acc.as_mut(), &[values])?;
+        let output = agg.final_batch_merge(&mut [acc.as_mut()])?;
+        assert_eq!(output.data_type(), &data_type);
+        assert_eq!(
+            output.as_list::<i64>().value(0).as_primitive::<Int32Type>(),
+            &Int32Array::from(vec![1, 2]),
+        );
+
+        let output = build_list(&data_type, vec![None])?;
+        assert_eq!(output.data_type(), &data_type);
+        assert!(output.is_null(0));
+        Ok(())
+    }
+
+    #[test]
+    fn test_merge_large_lists() -> Result<()> {
+        let mut agg = AggCollectList::try_new(
+            Arc::new(Column::new("a", 0)),
+            DataType::new_list(DataType::Int32, true),
+            DataType::Int32,
+        )?
+        .with_merge_lists();
+        let (initial_acc, addrs) = create_acc_from_initial_value(agg.accums_initial())?;
+        agg.set_accum_state_val_addrs(&addrs);
+
+        let lists: ArrayRef = Arc::new(LargeListArray::from_iter_primitive::<Int32Type, _, _>(
+            vec![Some(vec![Some(1), None]), None, Some(vec![Some(3)])],
+        ));
+        let mut acc = initial_acc.clone();
+        agg.partial_update_all(&mut acc.as_mut(), &[lists.slice(0, 2)])?;
+        agg.partial_update(&mut acc.as_mut(), &[lists.clone()], 2)?;
+        assert_eq!(
+            agg.final_batch_merge(&mut [acc.as_mut()])?.to_data(),
+            ListArray::from_iter_primitive::<Int32Type, _, _>(vec![Some(vec![Some(1), Some(3)])])
+                .to_data(),
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_mismatched_arg_type() -> Result<()> {
+        let mut agg = AggCollectList::try_new(
+            Arc::new(Column::new("a", 0)),
+            DataType::new_list(DataType::Int32, true),
+            DataType::Int32,
+        )?;
+        let (initial_acc, addrs) = create_acc_from_initial_value(agg.accums_initial())?;
+        agg.set_accum_state_val_addrs(&addrs);
+
+        let mismatched: ArrayRef = Arc::new(Int64Array::from(vec![1, 2, 3]));
+        let mut acc = initial_acc.clone();
+        let err = agg
+            .partial_update_all(&mut acc.as_mut(), &[mismatched.clone()])
+            .unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("collect_list expects argument type Int32, got: Int64"));
+        assert!(agg
+            .partial_update(&mut acc.as_mut(), &[mismatched], 0)
+            .is_err());
+
+        // merging lists expect List of arg_type
+        let agg = AggCollectList::try_new(
+            Arc::new(Column::new("a", 0)),
+            DataType::new_list(DataType::Int32, true),
+            DataType::Int32,
+        )?
+        .with_merge_lists();
+        let lists: ArrayRef = Arc::new(ListArray::from_iter_primitive::<Int64Type, _, _>(vec![
+            Some(vec![Some(1), Some(2)]),
+        ]));
+        let err = agg
+            .partial_update_all(&mut acc.as_mut(), &[lists])
+            .unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("collect_list expects argument type Int32"));
+        Ok(())
     }
 }
@@ -14,37 +14,106 @@
 
 use std::{
     any::Any,
+    borrow::Cow,
     fmt::{Debug, Formatter},
-    sync::{atomic::AtomicUsize, Arc},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize},
+        Arc,
+    },
 };
 
-use arrow::{array::*, datatypes::*};
+use arrow::{
+    array::*,
+    compute::SortOptions,
+    datatypes::*,
+    row::{RowConverter, SortField},
+};
+use blaze_jni_bridge::{
+    conf::{self, IntConf},
+    is_jni_bridge_inited,
+};
 use datafusion::{
-    common::{Result, ScalarValue},
+    common::{DataFusionError, Result, ScalarValue},
     physical_expr::PhysicalExpr,
+    physical_plan::metrics::Count,
+};
+use datafusion_ext_commons::{
+    df_execution_err, df_unimplemented_err, downcast_any, io::is_scalar_serde_supported,
 };
-use datafusion_ext_commons::{df_execution_err, downcast_any};
 
 use crate::agg::{
     acc::{
-        AccumInitialValue, AccumStateRow, AccumStateValAddr, AggDynSet, AggDynValue,
-        RefAccumStateRow,
+        normalize_nested_floats, write_normalized_scalar, AccumInitialValue, AccumStateRow,
+        AccumStateValAddr, AggDynList, AggDynSet, AggDynSetHasher, AggDynValue, RefAccumStateRow,
+        AGG_DYN_SET_HASH_SEED,
     },
+    collect_list::{as_merging_lists, build_fixed_size_list, build_list, check_collect_arg_type},
     Agg, WithAggBufAddrs, WithMemTracking,
 };
 
+/// minimum number of groups to merge in parallel
+const PARALLEL_MERGE_MIN_NUM_GROUPS: usize = 4096;
+
+/// normalization of string values before deduplication, for legacy rules
+/// treating strings equal after case folding or trimming
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SetNormalization {
+    CaseInsensitive,
+    Trim,
+    TrimCaseInsensitive,
+}
+
+impl SetNormalization {
+    pub fn normalize<'a>(&self, s: &'a str) -> Cow<'a, str> {
+        match self {
+            SetNormalization::CaseInsensitive => Cow::Owned(s.to_lowercase()),
+            SetNormalization::Trim => Cow::Borrowed(s.trim()),
+            SetNormalization::TrimCaseInsensitive => Cow::Owned(s.trim().to_lowercase()),
+        }
+    }
+
+    fn normalize_scalar(&self, value: &ScalarValue) -> Result<ScalarValue> {
+        Ok(match value {
+            ScalarValue::Utf8(Some(s)) => ScalarValue::Utf8(Some(self.normalize(s).into_owned())),
+            ScalarValue::LargeUtf8(Some(s)) => {
+                ScalarValue::LargeUtf8(Some(self.normalize(s).into_owned()))
+            }
+            other => return df_execution_err!("cannot normalize non-string value: {other:?}"),
+        })
+    }
+}
+
+/// collect_set(), values are deduplicated by their serialized bytes. timestamps
+/// are serialized as instants without timezone, so equal instants are always
+/// deduplicated, and the output timezone is taken from `arg_type`
 pub struct AggCollectSet {
     child: Arc<dyn PhysicalExpr>,
     data_type: DataType,
     arg_type: DataType,
-    accum_initial: [AccumInitialValue; 1],
+    insertion_order: bool,
+    merge_lists: bool,
+    hash_seed: i64,
+    hasher: AggDynSetHasher,
+    sort_options: Option<SortOptions>,
+    raw_sorted_output: bool,
+    merge_num_threads: usize,
+    normalization: Option<SetNormalization>,
+    typed_equality: bool,
+    max_slots: usize,
+    accum_initial: Vec<AccumInitialValue>,
     accum_state_val_addr: AccumStateValAddr,
+    originals_addr: AccumStateValAddr,
     mem_used_tracker: AtomicUsize,
+    num_set_promotions: Count,
+    arg_type_checked: AtomicBool,
 }
 
 impl WithAggBufAddrs for AggCollectSet {
     fn set_accum_state_val_addrs(&mut self, accum_state_val_addrs: &[AccumStateValAddr]) {
         self.accum_state_val_addr = accum_state_val_addrs[0];
+        if self.normalization.is_some() {
+            self.originals_addr = accum_state_val_addrs[1];
+        }
     }
 }
 
@@ -55,24 +124,662 @@ impl WithMemTracking for AggCollectSet {
 }
 
 impl AggCollectSet {
+    /// `data_type` can be a FixedSizeList, in which case every group must
+    /// collect exactly the fixed number of distinct values.
     pub fn try_new(
         child: Arc<dyn PhysicalExpr>,
         data_type: DataType,
         arg_type: DataType,
     ) -> Result<Self> {
+        Self::try_new_with_hash_seed(child, data_type, arg_type, AGG_DYN_SET_HASH_SEED)
+    }
+
+    /// creates with a specified hash seed for the internal sets, which can be
+    /// derived with `derive_agg_dyn_set_hash_seed()`
+    pub fn try_new_with_hash_seed(
+        child: Arc<dyn PhysicalExpr>,
+        data_type: DataType,
+        arg_type: DataType,
+        hash_seed: i64,
+    ) -> Result<Self> {
+        if !is_scalar_serde_supported(&arg_type) {
+            return df_unimplemented_err!("collect_set does not support argument type: {arg_type}");
+        }
+        let merge_num_threads = if is_jni_bridge_inited() {
+            conf::COLLECT_SET_MERGE_NUM_THREADS.value()?.max(1) as usize
+        } else {
+            1
+        };
+        let max_slots = if is_jni_bridge_inited() {
+            conf::COLLECT_SET_MAX_SLOTS.value()?.max(0) as usize
+        } else {
+            0
+        };
+        let hasher = AggDynSetHasher::for_type(&arg_type);
         Ok(Self {
             child,
             data_type,
-            accum_initial: [AccumInitialValue::DynSet(arg_type.clone())],
+            accum_initial: vec![AccumInitialValue::DynSet(
+                arg_type.clone(),
+                hash_seed,
+                max_slots,
+            )],
             arg_type,
+            insertion_order: false,
+            merge_lists: false,
+            hash_seed,
+            hasher,
+            sort_options: None,
+            raw_sorted_output: false,
+            merge_num_threads,
+            normalization: None,
+            typed_equality: false,
+            max_slots,
             accum_state_val_addr: AccumStateValAddr::default(),
+            originals_addr: AccumStateValAddr::default(),
             mem_used_tracker: AtomicUsize::new(0),
+            arg_type_checked: AtomicBool::new(false),
+            num_set_promotions: Count::new(),
         })
     }
 
+    /// outputs values in first-seen order, which is reproducible but makes
+    /// merging a bit slower
+    pub fn with_insertion_order(mut self) -> Self {
+        self.insertion_order = true;
+        self
+    }
+
+    /// takes a List of `arg_type` as input and adds all elements of each input
+    /// list, like array_union() of all lists
+    pub fn with_merge_lists(mut self) -> Self {
+        self.merge_lists = true;
+        self
+    }
+
+    /// outputs values sorted in natural ordering of `arg_type`, like
+    /// sort_array(collect_set(x)). nulls inside nested values go first in
+    /// ascending order and last in descending order, following sort_array()
+    pub fn with_sorted_output(mut self, descending: bool) -> Self {
+        self.sort_options = Some(SortOptions {
+            descending,
+            nulls_first: !descending,
+        });
+        self
+    }
+
+    /// outputs values sorted by their serialized bytes, a cheap total order
+    /// making the output reproducible regardless of the internal sets. the
+    /// byte order may differ from the natural ordering of `arg_type` (like
+    /// negative numbers), which is still given by `with_sorted_output()`.
+    /// with normalization, values are sorted by their normalized forms
+    pub fn with_raw_sorted_output(mut self) -> Self {
+        self.raw_sorted_output = true;
+        self
+    }
+
+    /// deduplicates string values by their normalized forms, the first-seen
+    /// original values are output. the internal set holds normalized values
+    /// and the originals are kept in another list
+    pub fn with_normalization(mut self, normalization: SetNormalization) -> Result<Self> {
+        if !matches!(self.arg_type, DataType::Utf8 | DataType::LargeUtf8) {
+            return df_unimplemented_err!(
+                "collect_set does not support normalization of type: {}",
+                self.arg_type
+            );
+        }
+        self.normalization = Some(normalization);
+        self.accum_initial = vec![
+            AccumInitialValue::DynSet(self.arg_type.clone(), self.hash_seed, self.max_slots),
+            AccumInitialValue::DynList(self.arg_type.clone()),
+        ];
+        Ok(self)
+    }
+
     pub fn arg_type(&self) -> &DataType {
         &self.arg_type
     }
+
+    pub fn normalization(&self) -> Option<SetNormalization> {
+        self.normalization
+    }
+
+    /// deduplicates values by typed equality instead of serialized bytes.
+    /// top-level floats are always deduplicated like spark (all NaNs are
+    /// equal and -0.0 equals 0.0), this also normalizes floats nested in
+    /// lists and structs, at the cost of rebuilding every input array. other
+    /// types (including decimals, whose scale is fixed by the type) are
+    /// already equal by bytes iff equal by value. normalized floats are output
+    pub fn with_typed_equality(mut self) -> Self {
+        self.typed_equality = true;
+        self
+    }
+
+    pub fn typed_equality(&self) -> bool {
+        self.typed_equality
+    }
+
+    /// limits the number of slots of the hash table of each group, 0 for
+    /// unlimited. a group whose table would grow beyond the limit fails the
+    /// aggregation with `ResourcesExhausted`, instead of risking a huge
+    /// reallocation of a single table. spilling cannot help here: spilled
+    /// states of the same group are merged back into one set on output
+    pub fn with_max_slots(mut self, max_slots: usize) -> Self {
+        self.max_slots = max_slots;
+        self.accum_initial[0] =
+            AccumInitialValue::DynSet(self.arg_type.clone(), self.hash_seed, max_slots);
+        self
+    }
+
+    pub fn max_slots(&self) -> usize {
+        self.max_slots
+    }
+
+    pub fn insertion_order(&self) -> bool {
+        self.insertion_order
+    }
+
+    pub fn merge_lists(&self) -> bool {
+        self.merge_lists
+    }
+
+    /// number of groups whose sets are promoted from small sets to hash
+    /// tables, shared by all partitions using this aggregate. a high number
+    /// suggests that the small set thresholds do not fit the data
+    pub fn num_set_promotions(&self) -> &Count {
+        &self.num_set_promotions
+    }
+
+    /// pre-sizes sets of the groups with estimated numbers of distinct values,
+    /// so that sets of large groups skip promotions and rehashing. hints are
+    /// advisory, groups with zero hints or non-empty sets are untouched
+    pub fn presize_sets(
+        &self,
+        accs: &mut [RefAccumStateRow],
+        capacity_hints: &[usize],
+    ) -> Result<()> {
+        for (acc, &capacity) in accs.iter_mut().zip(capacity_hints) {
+            if capacity == 0 {
+                continue;
+            }
+            let set = self.dyn_set_mut(acc)?;
+            if set.is_empty() {
+                self.sub_mem_used(set.mem_size());
+                // hints never pre-size a table beyond the slot limit
+                let capacity = match self.max_slots {
+                    0 => capacity,
+                    max_slots => capacity.min(max_slots / 8 * 7),
+                };
+                *set = AggDynSet::with_capacity(self.hash_seed, capacity).with_hasher(self.hasher);
+                set.set_max_slots(self.max_slots);
+                self.add_mem_used(set.mem_size());
+            }
+        }
+        Ok(())
+    }
+
+    /// rehydrates groups from finalized outputs of collect_set(), like the
+    /// ones persisted for incremental aggregation. elements of the i-th list
+    /// are added into the set of the i-th group, so that later updates and
+    /// merges extend it. null lists are skipped
+    pub fn rehydrate_from_lists(
+        &self,
+        accs: &mut [RefAccumStateRow],
+        lists: &ListArray,
+    ) -> Result<()> {
+        if accs.len() != lists.len() {
+            return df_execution_err!(
+                "collect_set rehydrating {} groups from {} lists",
+                accs.len(),
+                lists.len()
+            );
+        }
+        check_collect_arg_type(
+            "collect_set",
+            &self.arg_type,
+            true,
+            &(Arc::new(lists.clone()) as ArrayRef),
+            &AtomicBool::new(false),
+        )?;
+
+        if self.normalization.is_none() {
+            let capacity_hints = (0..lists.len())
+                .map(|i| lists.value_length(i) as usize)
+                .collect::<Vec<_>>();
+            self.presize_sets(accs, &capacity_hints)?;
+        }
+        for (i, acc) in accs.iter_mut().enumerate() {
+            if lists.is_valid(i) {
+                self.append_values(acc, &lists.value(i))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// outputs distinct values of each group as separate rows, like
+    /// `explode(collect_set(x))` without building the intermediate lists.
+    /// returns the group index of each row and the values, groups without
+    /// values produce no rows. since the number of rows differs from the
+    /// number of groups, this is not a part of the `Agg` contract and is
+    /// called by operators emitting one row per value
+    pub fn final_batch_merge_exploded(
+        &self,
+        accs: &mut [RefAccumStateRow],
+    ) -> Result<(UInt32Array, ArrayRef)> {
+        let mut sort_converter = self.create_sort_converter()?;
+        let mut group_indices = vec![];
+        let mut arrays = vec![];
+        for (group_idx, acc) in accs.iter_mut().enumerate() {
+            if let Some(array) = self.take_array(acc, &mut sort_converter)? {
+                group_indices.extend(std::iter::repeat(group_idx as u32).take(array.len()));
+                arrays.push(array);
+            }
+        }
+        let values = match arrays.len() {
+            0 => new_empty_array(&self.arg_type),
+            1 => arrays.pop().unwrap(),
+            _ => arrow::compute::concat(&arrays.iter().map(|a| a.as_ref()).collect::<Vec<_>>())?,
+        };
+        Ok((UInt32Array::from(group_indices), values))
+    }
+
+    fn count_set_promotion(&self, was_huge: bool, set: &AggDynSet) {
+        if !was_huge && set.is_huge() {
+            self.num_set_promotions.add(1);
+        }
+    }
+
+    fn check_slots_exhausted(&self, set: &AggDynSet) -> Result<()> {
+        if set.slots_exhausted() {
+            return Err(DataFusionError::ResourcesExhausted(format!(
+                "collect_set: hash table of a group with {} values exceeds {} slots",
+                set.len(),
+                self.max_slots,
+            )));
+        }
+        Ok(())
+    }
+
+    pub fn hash_seed(&self) -> i64 {
+        self.hash_seed
+    }
+
+    pub fn sort_options(&self) -> Option<SortOptions> {
+        self.sort_options
+    }
+
+    pub fn raw_sorted_output(&self) -> bool {
+        self.raw_sorted_output
+    }
+
+    /// hashes values in the same way as the internal sets, so that they can
+    /// be computed once and passed to `partial_batch_update_with_hashes()`.
+    /// null values are hashed as 0
+    pub fn hash_values(&self, values: &ArrayRef) -> Result<Vec<u64>> {
+        let mut hashes = vec![0; values.len()];
+        for_each_value_raw(values, |row_idx, raw| {
+            hashes[row_idx] = self.hasher.hash(self.hash_seed, raw);
+            Ok(())
+        })?;
+        Ok(hashes)
+    }
+
+    /// like `partial_batch_update()`, but values are not rehashed when
+    /// appending into the internal sets. `hashes` must be computed by
+    /// `hash_values()` of an aggregate with the same hash seed, which is
+    /// verified on the first non-null value of each batch
+    pub fn partial_batch_update_with_hashes(
+        &self,
+        accs: &mut [RefAccumStateRow],
+        values: &[ArrayRef],
+        hashes: &[u64],
+    ) -> Result<()> {
+        if self.merge_lists || self.normalization.is_some() {
+            return df_execution_err!(
+                "collect_set cannot use precomputed hashes of merging lists or normalized values"
+            );
+        }
+        self.check_arg_type(&values[0])?;
+        if hashes.len() != values[0].len() {
+            return df_execution_err!(
+                "collect_set expect {} precomputed hashes, got: {}",
+                values[0].len(),
+                hashes.len()
+            );
+        }
+        let mut verified = false;
+        for_each_value_raw(&values[0], |row_idx, raw| {
+            if !verified {
+                if hashes[row_idx] != self.hasher.hash(self.hash_seed, raw) {
+                    return df_execution_err!(
+                        "collect_set got precomputed hashes not matching its hash seed"
+                    );
+                }
+                verified = true;
+            }
+            let set = self.dyn_set_mut(&mut accs[row_idx])?;
+            let was_huge = set.is_huge();
+            self.sub_mem_used(set.mem_size());
+            set.append_raw_with_hash(raw, hashes[row_idx]);
+            self.add_mem_used(set.mem_size());
+            self.count_set_promotion(was_huge, set);
+            self.check_slots_exhausted(set)?;
+            Ok(())
+        })
+    }
+
+    fn check_arg_type(&self, values: &ArrayRef) -> Result<()> {
+        check_collect_arg_type(
+            "collect_set",
+            &self.arg_type,
+            self.merge_lists,
+            values,
+            &self.arg_type_checked,
+        )
+    }
+
+    fn dyn_set_mut<'a>(&self, acc: &'a mut RefAccumStateRow) -> Result<&'a mut AggDynSet> {
+        let dyn_set = match acc.dyn_value_mut(self.accum_state_val_addr) {
+            Some(dyn_set) => dyn_set,
+            w => {
+                let mut new_set =
+                    AggDynSet::with_hash_seed(self.hash_seed).with_hasher(self.hasher);
+                new_set.set_max_slots(self.max_slots);
+                self.add_mem_used(new_set.mem_size());
+                *w = Some(Box::new(new_set));
+                w.as_mut().unwrap()
+            }
+        };
+        downcast_any!(dyn_set, mut AggDynSet)
+    }
+
+    fn append_values(&self, acc: &mut RefAccumStateRow, values: &ArrayRef) -> Result<()> {
+        if let Some(normalization) = self.normalization {
+            for i in 0..values.len() {
+                if let Some(value) = non_null_value(values, i)? {
+                    self.append_normalized(acc, normalization, &value)?;
+                }
+            }
+            return Ok(());
+        }
+        let set = self.dyn_set_mut(acc)?;
+        let was_huge = set.is_huge();
+        self.sub_mem_used(set.mem_size());
+
+        match DictValueRaws::try_new(values) {
+            Some(mut dict_value_raws) => {
+                for i in 0..values.len() {
+                    if let Some(raw) = dict_value_raws.get(i)? {
+                        set.append_raw(raw);
+                    }
+                }
+            }
+            None => {
+                for i in 0..values.len() {
+                    if values.is_valid(i) {
+                        set.append(&ScalarValue::try_from_array(values, i)?, false)?;
+                    }
+                }
+            }
+        }
+        self.add_mem_used(set.mem_size());
+        self.count_set_promotion(was_huge, set);
+        self.check_slots_exhausted(set)?;
+        Ok(())
+    }
+
+    /// appends the normalized value into the set, and the original value into
+    /// the list if the normalized value is new
+    fn append_normalized(
+        &self,
+        acc: &mut RefAccumStateRow,
+        normalization: SetNormalization,
+        value: &ScalarValue,
+    ) -> Result<()> {
+        let set = self.dyn_set_mut(acc)?;
+        let was_huge = set.is_huge();
+        let old_len = set.len();
+        self.sub_mem_used(set.mem_size());
+        set.append(&normalization.normalize_scalar(value)?, false)?;
+        self.add_mem_used(set.mem_size());
+        self.count_set_promotion(was_huge, set);
+        self.check_slots_exhausted(set)?;
+        if set.len() == old_len {
+            return Ok(());
+        }
+
+        let originals = match acc.dyn_value_mut(self.originals_addr) {
+            Some(originals) => originals,
+            w => {
+                let new_list = AggDynList::default();
+                self.add_mem_used(new_list.mem_size());
+                *w = Some(Box::new(new_list));
+                w.as_mut().unwrap()
+            }
+        };
+        let originals = downcast_any!(originals, mut AggDynList)?;
+        self.sub_mem_used(originals.mem_size());
+        originals.append(value, false)?;
+        self.add_mem_used(originals.mem_size());
+        Ok(())
+    }
+
+    /// merges by re-appending the originals of merging group in their order,
+    /// normalized values of the merging group are discarded
+    fn merge_normalized(
+        &self,
+        acc: &mut RefAccumStateRow,
+        merging_acc: &mut RefAccumStateRow,
+        normalization: SetNormalization,
+    ) -> Result<()> {
+        if let Some(w) = std::mem::take(merging_acc.dyn_value_mut(self.accum_state_val_addr)) {
+            self.sub_mem_used(w.mem_size());
+        }
+        if let Some(w) = std::mem::take(merging_acc.dyn_value_mut(self.originals_addr)) {
+            self.sub_mem_used(w.mem_size());
+            let originals = w
+                .as_any_boxed()
+                .downcast::<AggDynList>()
+                .or_else(|_| df_execution_err!("error downcasting to AggDynList"))?;
+            for value in originals.into_values(self.arg_type.clone(), false) {
+                self.append_normalized(acc, normalization, &value?)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// merges sets of the groups, returns memory size added and subtracted
+    fn merge_sets(
+        &self,
+        accs: &mut [RefAccumStateRow],
+        merging_accs: &mut [RefAccumStateRow],
+    ) -> Result<(usize, usize)> {
+        let mut mem_added = 0;
+        let mut mem_subbed = 0;
+        for (acc, merging_acc) in accs.iter_mut().zip(merging_accs) {
+            match (
+                acc.dyn_value_mut(self.accum_state_val_addr),
+                merging_acc.dyn_value_mut(self.accum_state_val_addr),
+            ) {
+                (Some(w), Some(v)) => {
+                    let w = downcast_any!(w, mut AggDynSet)?;
+                    let v = downcast_any!(v, mut AggDynSet)?;
+                    let was_huge = w.is_huge() || v.is_huge();
+                    mem_subbed += w.mem_size() + v.mem_size();
+                    if self.insertion_order {
+                        w.merge_ordered(v);
+                    } else {
+                        w.merge(v);
+                    }
+                    mem_added += w.mem_size();
+                    self.count_set_promotion(was_huge, w);
+                    self.check_slots_exhausted(w)?;
+                }
+                (w_none, v @ Some(_)) => *w_none = std::mem::take(v),
+                (None, _) => {}
+                (_, None) => {}
+            }
+        }
+        Ok((mem_added, mem_subbed))
+    }
+
+    fn take_values(
+        &self,
+        acc: &mut RefAccumStateRow,
+        sort_converter: &mut Option<RowConverter>,
+    ) -> Result<Option<Vec<ScalarValue>>> {
+        if sort_converter.is_some() || self.raw_sorted_output || self.normalization.is_some() {
+            return self
+                .take_array(acc, sort_converter)?
+                .map(|array| {
+                    (0..array.len())
+                        .map(|i| ScalarValue::try_from_array(&array, i))
+                        .collect()
+                })
+                .transpose();
+        }
+        match std::mem::take(acc.dyn_value_mut(self.accum_state_val_addr)) {
+            Some(w) => {
+                self.sub_mem_used(w.mem_size());
+                let set = w
+                    .as_any_boxed()
+                    .downcast::<AggDynSet>()
+                    .or_else(|_| df_execution_err!("error downcasting to AggDynSet"))?;
+                Ok(Some(
+                    set.into_values(self.arg_type.clone(), false)
+                        .collect::<Result<_>>()?,
+                ))
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn take_array(
+        &self,
+        acc: &mut RefAccumStateRow,
+        sort_converter: &mut Option<RowConverter>,
+    ) -> Result<Option<ArrayRef>> {
+        let set = std::mem::take(acc.dyn_value_mut(self.accum_state_val_addr));
+        if self.normalization.is_some() {
+            // originals are output, the normalized values are only for
+            // deduplication and raw sorting
+            let mut raw_sorted_indices = None;
+            if let Some(set) = set {
+                self.sub_mem_used(set.mem_size());
+                if sort_converter.is_none() && self.raw_sorted_output {
+                    let set = set
+                        .as_any_boxed()
+                        .downcast::<AggDynSet>()
+                        .or_else(|_| df_execution_err!("error downcasting to AggDynSet"))?;
+                    raw_sorted_indices = Some(set.into_raw_sorted().1);
+                }
+            }
+            return match std::mem::take(acc.dyn_value_mut(self.originals_addr)) {
+                Some(w) => {
+                    self.sub_mem_used(w.mem_size());
+                    let originals = w
+                        .as_any_boxed()
+                        .downcast::<AggDynList>()
+                        .or_else(|_| df_execution_err!("error downcasting to AggDynList"))?;
+                    let array = originals.into_array(&self.arg_type, false)?;
+                    match (sort_converter, raw_sorted_indices) {
+                        (Some(converter), _) => Ok(Some(sort_array(converter, array)?)),
+                        (None, Some(indices)) => Ok(Some(arrow::compute::take(
+                            &array,
+                            &UInt32Array::from(indices),
+                            None,
+                        )?)),
+                        (None, None) => Ok(Some(array)),
+                    }
+                }
+                None => Ok(None),
+            };
+        }
+        match set {
+            Some(w) => {
+                self.sub_mem_used(w.mem_size());
+                let set = w
+                    .as_any_boxed()
+                    .downcast::<AggDynSet>()
+                    .or_else(|_| df_execution_err!("error downcasting to AggDynSet"))?;
+                if sort_converter.is_none() && self.raw_sorted_output {
+                    let (sorted, _) = set.into_raw_sorted();
+                    return Ok(Some(sorted.into_array(&self.arg_type, false)?));
+                }
+                let array = set.into_array(&self.arg_type, false)?;
+                match sort_converter {
+                    Some(converter) => Ok(Some(sort_array(converter, array)?)),
+                    None => Ok(Some(array)),
+                }
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn create_sort_converter(&self) -> Result<Option<RowConverter>> {
+        self.sort_options
+            .map(|sort_options| {
+                Ok(RowConverter::new(vec![SortField::new_with_options(
+                    self.arg_type.clone(),
+                    sort_options,
+                )])?)
+            })
+            .transpose()
+    }
+}
+
+/// returns the value of a row, with dictionary unpacked, or None if the value
+/// is null
+fn non_null_value(values: &ArrayRef, row_idx: usize) -> Result<Option<ScalarValue>> {
+    if values.is_null(row_idx) {
+        return Ok(None);
+    }
+    let value = match ScalarValue::try_from_array(values, row_idx)? {
+        ScalarValue::Dictionary(_, value) => *value,
+        value => value,
+    };
+    // value can still be null in a dictionary array with valid key
+    Ok(Some(value).filter(|value| !value.is_null()))
+}
+
+/// calls `f` with serialized bytes of each non-null value
+fn for_each_value_raw(
+    values: &ArrayRef,
+    mut f: impl FnMut(usize, &[u8]) -> Result<()>,
+) -> Result<()> {
+    if let Some(mut dict_value_raws) = DictValueRaws::try_new(values) {
+        for row_idx in 0..values.len() {
+            if let Some(raw) = dict_value_raws.get(row_idx)? {
+                f(row_idx, raw)?;
+            }
+        }
+        return Ok(());
+    }
+    let mut raw = vec![];
+    for row_idx in 0..values.len() {
+        if values.is_valid(row_idx) {
+            raw.clear();
+            write_normalized_scalar(
+                &ScalarValue::try_from_array(values, row_idx)?,
+                false,
+                &mut raw,
+            )?;
+            f(row_idx, &raw)?;
+        }
+    }
+    Ok(())
+}
+
+/// sorts values with the row format, which supports nested types
+fn sort_array(converter: &mut RowConverter, array: ArrayRef) -> Result<ArrayRef> {
+    let rows = converter.convert_columns(&[array.clone()])?;
+    let mut indices = (0..array.len() as u32).collect::<Vec<_>>();
+    indices.sort_unstable_by(|&i, &j| rows.row(i as usize).cmp(&rows.row(j as usize)));
+    Ok(arrow::compute::take(
+        &array,
+        &UInt32Array::from(indices),
+        None,
+    )?)
 }
 
 impl Debug for AggCollectSet {
@@ -91,11 +798,29 @@ impl Agg for AggCollectSet {
     }
 
     fn with_new_exprs(&self, exprs: Vec<Arc<dyn PhysicalExpr>>) -> Result<Arc<dyn Agg>> {
-        Ok(Arc::new(Self::try_new(
+        let mut agg = Self::try_new_with_hash_seed(
             exprs[0].clone(),
             self.data_type.clone(),
             self.arg_type.clone(),
-        )?))
+            self.hash_seed,
+        )?;
+        agg.insertion_order = self.insertion_order;
+        agg.merge_lists = self.merge_lists;
+        agg.sort_options = self.sort_options;
+        agg.raw_sorted_output = self.raw_sorted_output;
+        agg.typed_equality = self.typed_equality;
+        agg = agg.with_max_slots(self.max_slots);
+        if let Some(normalization) = self.normalization {
+            agg = agg.with_normalization(normalization)?;
+        }
+        Ok(Arc::new(agg))
+    }
+
+    fn prepare_partial_args(&self, partial_inputs: &[ArrayRef]) -> Result<Vec<ArrayRef>> {
+        if self.typed_equality {
+            return partial_inputs.iter().map(normalize_nested_floats).collect();
+        }
+        Ok(partial_inputs.to_vec())
     }
 
     fn data_type(&self) -> &DataType {
@@ -114,6 +839,22 @@ impl Agg for AggCollectSet {
         if let Some(v) = acc.dyn_value(self.accum_state_val_addr) {
             self.add_mem_used(v.mem_size());
         }
+        if self.normalization.is_some() {
+            if let Some(v) = acc.dyn_value(self.originals_addr) {
+                self.add_mem_used(v.mem_size());
+            }
+        }
+    }
+
+    fn decrease_acc_mem_used(&self, acc: &mut RefAccumStateRow) {
+        if let Some(v) = acc.dyn_value(self.accum_state_val_addr) {
+            self.sub_mem_used(v.mem_size());
+        }
+        if self.normalization.is_some() {
+            if let Some(v) = acc.dyn_value(self.originals_addr) {
+                self.sub_mem_used(v.mem_size());
+            }
+        }
     }
 
     fn partial_update(
@@ -122,20 +863,53 @@ impl Agg for AggCollectSet {
         values: &[ArrayRef],
         row_idx: usize,
     ) -> Result<()> {
-        if values[0].is_valid(row_idx) {
-            match acc.dyn_value_mut(self.accum_state_val_addr) {
-                Some(dyn_set) => {
-                    let set = downcast_any!(dyn_set, mut AggDynSet)?;
-                    self.sub_mem_used(set.mem_size());
+        self.check_arg_type(&values[0])?;
+        if self.merge_lists {
+            let lists = as_merging_lists(&values[0])?;
+            if lists.is_valid(row_idx) {
+                self.append_values(acc, &lists.value(row_idx))?;
+            }
+            return Ok(());
+        }
+        if let Some(value) = non_null_value(&values[0], row_idx)? {
+            if let Some(normalization) = self.normalization {
+                return self.append_normalized(acc, normalization, &value);
+            }
+            let set = self.dyn_set_mut(acc)?;
+            let was_huge = set.is_huge();
+            self.sub_mem_used(set.mem_size());
+            set.append(&value, false)?;
+            self.add_mem_used(set.mem_size());
+            self.count_set_promotion(was_huge, set);
+            self.check_slots_exhausted(set)?;
+        }
+        Ok(())
+    }
 
-                    set.append(&ScalarValue::try_from_array(&values[0], row_idx)?, false);
-                    self.add_mem_used(set.mem_size());
+    fn partial_batch_update(
+        &self,
+        accs: &mut [RefAccumStateRow],
+        values: &[ArrayRef],
+    ) -> Result<()> {
+        self.check_arg_type(&values[0])?;
+        let use_dict_raws = !self.merge_lists && self.normalization.is_none();
+        match DictValueRaws::try_new(&values[0]).filter(|_| use_dict_raws) {
+            Some(mut dict_value_raws) => {
+                for (row_idx, acc) in accs.iter_mut().enumerate() {
+                    if let Some(raw) = dict_value_raws.get(row_idx)? {
+                        let set = self.dyn_set_mut(acc)?;
+                        let was_huge = set.is_huge();
+                        self.sub_mem_used(set.mem_size());
+                        set.append_raw(raw);
+                        self.add_mem_used(set.mem_size());
+                        self.count_set_promotion(was_huge, set);
+                        self.check_slots_exhausted(set)?;
+                    }
                 }
-                w => {
-                    let mut new_set = AggDynSet::default();
-                    new_set.append(&ScalarValue::try_from_array(&values[0], row_idx)?, false);
-                    self.add_mem_used(new_set.mem_size());
-                    *w = Some(Box::new(new_set));
+            }
+            None => {
+                for (row_idx, acc) in accs.iter_mut().enumerate() {
+                    self.partial_update(acc, values, row_idx)?;
                 }
             }
         }
@@ -143,25 +917,17 @@ impl Agg for AggCollectSet {
     }
 
     fn partial_update_all(&self, acc: &mut RefAccumStateRow, values: &[ArrayRef]) -> Result<()> {
-        let dyn_set = match acc.dyn_value_mut(self.accum_state_val_addr) {
-            Some(dyn_set) => dyn_set,
-            w => {
-                let new_set = AggDynSet::default();
-                self.add_mem_used(new_set.mem_size());
-                *w = Some(Box::new(new_set));
-                w.as_mut().unwrap()
-            }
-        };
-        let set = downcast_any!(dyn_set, mut AggDynSet)?;
-        self.sub_mem_used(set.mem_size());
-
-        for i in 0..values[0].len() {
-            if values[0].is_valid(i) {
-                set.append(&ScalarValue::try_from_array(&values[0], i)?, false);
+        self.check_arg_type(&values[0])?;
+        if self.merge_lists {
+            let lists = as_merging_lists(&values[0])?;
+            for i in 0..lists.len() {
+                if lists.is_valid(i) {
+                    self.append_values(acc, &lists.value(i))?;
+                }
             }
+            return Ok(());
         }
-        self.add_mem_used(set.mem_size());
-        Ok(())
+        self.append_values(acc, &values[0])
     }
 
     fn partial_merge(
@@ -169,6 +935,9 @@ impl Agg for AggCollectSet {
         acc: &mut RefAccumStateRow,
         merging_acc: &mut RefAccumStateRow,
     ) -> Result<()> {
+        if let Some(normalization) = self.normalization {
+            return self.merge_normalized(acc, merging_acc, normalization);
+        }
         match (
             acc.dyn_value_mut(self.accum_state_val_addr),
             merging_acc.dyn_value_mut(self.accum_state_val_addr),
@@ -176,10 +945,17 @@ impl Agg for AggCollectSet {
             (Some(w), Some(v)) => {
                 let w = downcast_any!(w, mut AggDynSet)?;
                 let v = downcast_any!(v, mut AggDynSet)?;
+                let was_huge = w.is_huge() || v.is_huge();
                 self.sub_mem_used(w.mem_size());
                 self.sub_mem_used(v.mem_size());
-                w.merge(v);
+                if self.insertion_order {
+                    w.merge_ordered(v);
+                } else {
+                    w.merge(v);
+                }
                 self.add_mem_used(w.mem_size());
+                self.count_set_promotion(was_huge, w);
+                self.check_slots_exhausted(w)?;
             }
             (w_none, v @ Some(_)) => *w_none = std::mem::take(v),
             (None, _) => {}
@@ -188,33 +964,1199 @@ impl Agg for AggCollectSet {
         Ok(())
     }
 
-    fn final_merge(&self, acc: &mut RefAccumStateRow) -> Result<ScalarValue> {
-        match std::mem::take(acc.dyn_value_mut(self.accum_state_val_addr)) {
-            Some(w) => {
-                self.sub_mem_used(w.mem_size());
-                let set = w
-                    .as_any_boxed()
-                    .downcast::<AggDynSet>()
-                    .or_else(|_| df_execution_err!("error downcasting to AggDynSet"))?
-                    .into_values(self.arg_type.clone(), false);
-                Ok(ScalarValue::List(ScalarValue::new_list(
-                    &set.into_iter().collect::<Vec<_>>(),
-                    &self.arg_type,
-                )))
+    fn partial_merge_many(
+        &self,
+        acc: &mut RefAccumStateRow,
+        merging_accs: &mut [RefAccumStateRow],
+    ) -> Result<()> {
+        if self.normalization.is_some() {
+            for merging_acc in merging_accs {
+                self.partial_merge(acc, merging_acc)?;
+            }
+            return Ok(());
+        }
+        let mut merging_sets = vec![];
+        for merging_acc in merging_accs {
+            if let Some(v) = std::mem::take(merging_acc.dyn_value_mut(self.accum_state_val_addr)) {
+                self.sub_mem_used(v.mem_size());
+                merging_sets.push(v);
+            }
+        }
+        if merging_sets.is_empty() {
+            return Ok(());
+        }
+
+        let w = acc.dyn_value_mut(self.accum_state_val_addr);
+        if w.is_none() {
+            let first = merging_sets.remove(0);
+            self.add_mem_used(first.mem_size());
+            *w = Some(first);
+        }
+        let w = downcast_any!(w.as_mut().unwrap(), mut AggDynSet)?;
+        let mut others = merging_sets
+            .iter_mut()
+            .map(|v| downcast_any!(v, mut AggDynSet))
+            .collect::<Result<Vec<_>>>()?;
+        let was_huge = w.is_huge() || others.iter().any(|v| v.is_huge());
+        self.sub_mem_used(w.mem_size());
+        w.merge_many(&mut others, self.insertion_order);
+        self.add_mem_used(w.mem_size());
+        self.count_set_promotion(was_huge, w);
+        self.check_slots_exhausted(w)?;
+        Ok(())
+    }
+
+    fn partial_batch_merge(
+        &self,
+        accs: &mut [RefAccumStateRow],
+        merging_accs: &mut [RefAccumStateRow],
+    ) -> Result<()> {
+        if self.normalization.is_some() {
+            for (acc, merging_acc) in accs.iter_mut().zip(merging_accs) {
+                self.partial_merge(acc, merging_acc)?;
+            }
+            return Ok(());
+        }
+
+        // groups are independent, so they can be merged in parallel with
+        // disjoint chunks. only enabled with large number of groups since
+        // spawning threads is not free
+        let (mem_added, mem_subbed) =
+            if self.merge_num_threads > 1 && accs.len() >= PARALLEL_MERGE_MIN_NUM_GROUPS {
+                let chunk_size = (accs.len() + self.merge_num_threads - 1) / self.merge_num_threads;
+                std::thread::scope(|scope| {
+                    let handles = accs
+                        .chunks_mut(chunk_size)
+                        .zip(merging_accs.chunks_mut(chunk_size))
+                        .map(|(accs, merging_accs)| {
+                            scope.spawn(move || self.merge_sets(accs, merging_accs))
+                        })
+                        .collect::<Vec<_>>();
+
+                    let mut mem_added = 0;
+                    let mut mem_subbed = 0;
+                    for handle in handles {
+                        let (added, subbed) = handle.join().or_else(|_| {
+                            df_execution_err!("collect_set merging thread panicked")
+                        })??;
+                        mem_added += added;
+                        mem_subbed += subbed;
+                    }
+                    Ok::<_, DataFusionError>((mem_added, mem_subbed))
+                })?
+            } else {
+                self.merge_sets(accs, merging_accs)?
+            };
+        self.add_mem_used(mem_added);
+        self.sub_mem_used(mem_subbed);
+        Ok(())
+    }
+
+    fn shrink_acc_to_fit(&self, acc: &mut RefAccumStateRow) {
+        let mut addrs = vec![self.accum_state_val_addr];
+        if self.normalization.is_some() {
+            addrs.push(self.originals_addr);
+        }
+        for addr in addrs {
+            if let Some(v) = acc.dyn_value_mut(addr) {
+                self.sub_mem_used(v.mem_size());
+                v.shrink_to_fit();
+                self.add_mem_used(v.mem_size());
             }
-            None => ScalarValue::try_from(&self.data_type),
         }
     }
 
+    fn final_merge(&self, acc: &mut RefAccumStateRow) -> Result<ScalarValue> {
+        let mut sort_converter = self.create_sort_converter()?;
+        if let DataType::FixedSizeList(field, size) = &self.data_type {
+            let values = self.take_values(acc, &mut sort_converter)?;
+            let array = build_fixed_size_list(field, *size, vec![values])?;
+            return ScalarValue::try_from_array(&array, 0);
+        }
+        let values = self.take_array(acc, &mut sort_converter)?;
+        let array = build_list(&self.data_type, vec![values])?;
+        ScalarValue::try_from_array(&array, 0)
+    }
+
     fn final_batch_merge(&self, accs: &mut [RefAccumStateRow]) -> Result<ArrayRef> {
-        let values: Vec<ScalarValue> = accs
+        let mut sort_converter = self.create_sort_converter()?;
+        if let DataType::FixedSizeList(field, size) = &self.data_type {
+            let groups = accs
+                .iter_mut()
+                .map(|acc| self.take_values(acc, &mut sort_converter))
+                .collect::<Result<Vec<_>>>()?;
+            return build_fixed_size_list(field, *size, groups);
+        }
+        let groups = accs
             .iter_mut()
-            .map(|acc| self.final_merge(acc))
-            .collect::<Result<_>>()?;
+            .map(|acc| self.take_array(acc, &mut sort_converter))
+            .collect::<Result<Vec<_>>>()?;
+        build_list(&self.data_type, groups)
+    }
+}
+
+/// serialized values of a dictionary array, each distinct dictionary value is
+/// materialized and serialized only once when it is first referenced, so unused
+/// dictionary entries are never touched
+struct DictValueRaws<'a> {
+    dict: &'a dyn AnyDictionaryArray,
+    keys: Vec<usize>,
+    raws: Vec<Option<Vec<u8>>>,
+}
+
+impl<'a> DictValueRaws<'a> {
+    fn try_new(array: &'a ArrayRef) -> Option<Self> {
+        let dict = array.as_any_dictionary_opt()?;
+        let num_values = dict.values().len();
+        Some(Self {
+            dict,
+            keys: if num_values > 0 {
+                dict.normalized_keys()
+            } else {
+                vec![] // all keys are null
+            },
+            raws: vec![None; num_values],
+        })
+    }
+
+    /// returns serialized value of the specified row, or None if the key or
+    /// the referenced dictionary value is null
+    fn get(&mut self, row_idx: usize) -> Result<Option<&[u8]>> {
+        if self.dict.keys().is_null(row_idx) {
+            return Ok(None);
+        }
+        let key = self.keys[row_idx];
+        let values = self.dict.values();
+        if values.is_null(key) {
+            return Ok(None);
+        }
+        if self.raws[key].is_none() {
+            let mut raw = vec![];
+            write_normalized_scalar(&ScalarValue::try_from_array(values, key)?, false, &mut raw)?;
+            self.raws[key] = Some(raw);
+        }
+        Ok(self.raws[key].as_deref())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use arrow::{array::*, buffer::OffsetBuffer, datatypes::*};
+    use datafusion::{
+        common::{DataFusionError, Result, ScalarValue},
+        physical_expr::expressions::Column,
+    };
+
+    use crate::agg::{
+        acc::{
+            create_acc_from_initial_value, derive_agg_dyn_set_hash_seed, AccStore, AccumStateRow,
+            AggDynSet, OwnedAccumStateRow, AGG_DYN_SET_HASH_SEED,
+        },
+        collect_set::{AggCollectSet, SetNormalization, PARALLEL_MERGE_MIN_NUM_GROUPS},
+        Agg, WithAggBufAddrs, WithMemTracking,
+    };
+
+    #[test]
+    fn test_dictionary_input() -> Result<()> {
+        let mut agg = AggCollectSet::try_new(
+            Arc::new(Column::new("a", 0)),
+            DataType::new_list(DataType::Utf8, true),
+            DataType::Utf8,
+        )?;
+        let (initial_acc, addrs) = create_acc_from_initial_value(agg.accums_initial())?;
+        agg.set_accum_state_val_addrs(&addrs);
+
+        // dictionary with null keys, null values and unused entries
+        let dict: ArrayRef = Arc::new(DictionaryArray::<Int32Type>::try_new(
+            Int32Array::from(vec![Some(0), None, Some(2), Some(0), Some(3), Some(2)]),
+            Arc::new(StringArray::from(vec![
+                Some("a"),
+                Some("unused"),
+                Some("b"),
+                None,
+            ])),
+        )?);
+        let plain: ArrayRef = Arc::new(StringArray::from(vec![
+            Some("a"),
+            None,
+            Some("b"),
+            Some("a"),
+            None,
+            Some("b"),
+        ]));
+
+        // partial_update_all()
+        let mut acc1 = initial_acc.clone();
+        let mut acc2 = initial_acc.clone();
+        agg.partial_update_all(&mut acc1.as_mut(), &[dict.clone()])?;
+        agg.partial_update_all(&mut acc2.as_mut(), &[plain.clone()])?;
+        assert_eq!(
+            agg.final_merge(&mut acc1.as_mut())?,
+            agg.final_merge(&mut acc2.as_mut())?,
+        );
+
+        // partial_update()
+        let mut accs1 = vec![initial_acc.clone(); 2];
+        let mut accs2 = vec![initial_acc.clone(); 2];
+        for row_idx in 0..dict.len() {
+            agg.partial_update(&mut accs1[row_idx % 2].as_mut(), &[dict.clone()], row_idx)?;
+            agg.partial_update(&mut accs2[row_idx % 2].as_mut(), &[plain.clone()], row_idx)?;
+        }
+        for (acc1, acc2) in accs1.iter_mut().zip(&mut accs2) {
+            assert_eq!(
+                agg.final_merge(&mut acc1.as_mut())?,
+                agg.final_merge(&mut acc2.as_mut())?,
+            );
+        }
+
+        // partial_batch_update()
+        let mut accs1 = vec![initial_acc.clone(); dict.len()];
+        let mut accs2 = vec![initial_acc.clone(); dict.len()];
+        let mut ref_accs1 = accs1.iter_mut().map(|acc| acc.as_mut()).collect::<Vec<_>>();
+        let mut ref_accs2 = accs2.iter_mut().map(|acc| acc.as_mut()).collect::<Vec<_>>();
+        agg.partial_batch_update(&mut ref_accs1, &[dict.clone()])?;
+        agg.partial_batch_update(&mut ref_accs2, &[plain.clone()])?;
+        assert_eq!(
+            agg.final_batch_merge(&mut ref_accs1)?.to_data(),
+            agg.final_batch_merge(&mut ref_accs2)?.to_data(),
+        );
+        Ok(())
+    }
 
-        if values.is_empty() {
-            return Ok(new_empty_array(self.data_type()));
+    #[test]
+    fn test_merge_lists() -> Result<()> {
+        let mut agg = AggCollectSet::try_new(
+            Arc::new(Column::new("a", 0)),
+            DataType::new_list(DataType::Int32, true),
+            DataType::Int32,
+        )?
+        .with_insertion_order()
+        .with_merge_lists();
+        let (initial_acc, addrs) = create_acc_from_initial_value(agg.accums_initial())?;
+        agg.set_accum_state_val_addrs(&addrs);
+
+        // null lists, empty lists and lists containing nulls
+        let lists: ArrayRef = Arc::new(ListArray::from_iter_primitive::<Int32Type, _, _>(vec![
+            Some(vec![Some(1), None, Some(2)]),
+            None,
+            Some(vec![]),
+            Some(vec![Some(2), Some(3), Some(1)]),
+        ]));
+
+        // partial_update_all()
+        let mut acc = initial_acc.clone();
+        agg.partial_update_all(&mut acc.as_mut(), &[lists.clone()])?;
+        assert_eq!(
+            agg.final_merge(&mut acc.as_mut())?,
+            ScalarValue::List(Arc::new(ListArray::from_iter_primitive::<Int32Type, _, _>(
+                vec![Some(vec![Some(1), Some(2), Some(3),])]
+            ))),
+        );
+
+        // partial_update()
+        let mut accs = vec![initial_acc.clone(); 3];
+        for (row_idx, acc_idx) in [(0, 0), (1, 2), (2, 1), (3, 1)] {
+            agg.partial_update(&mut accs[acc_idx].as_mut(), &[lists.clone()], row_idx)?;
+        }
+        let mut ref_accs = accs.iter_mut().map(|acc| acc.as_mut()).collect::<Vec<_>>();
+        assert_eq!(
+            agg.final_batch_merge(&mut ref_accs)?.to_data(),
+            ListArray::from_iter_primitive::<Int32Type, _, _>(vec![
+                Some(vec![Some(1), Some(2)]),
+                Some(vec![Some(2), Some(3), Some(1)]),
+                None,
+            ])
+            .to_data(),
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_timestamp_and_date() -> Result<()> {
+        let tz: Arc<str> = Arc::from("Asia/Shanghai");
+        let instants = vec![Some(1_000_000i64), None, Some(2_000_000), Some(1_000_000)];
+        let cases: [(DataType, [ArrayRef; 2], ArrayRef); 3] = [
+            (
+                DataType::Timestamp(TimeUnit::Microsecond, Some(tz.clone())),
+                [
+                    Arc::new(
+                        TimestampMicrosecondArray::from(instants.clone()).with_timezone(tz.clone()),
+                    ),
+                    // same instants with another representation of the timezone
+                    Arc::new(
+                        TimestampMicrosecondArray::from(instants.clone()).with_timezone("+08:00"),
+                    ),
+                ],
+                Arc::new(
+                    TimestampMicrosecondArray::from(vec![1_000_000, 2_000_000])
+                        .with_timezone(tz.clone()),
+                ),
+            ),
+            (
+                DataType::Timestamp(TimeUnit::Microsecond, None),
+                [
+                    Arc::new(TimestampMicrosecondArray::from(instants.clone())),
+                    Arc::new(TimestampMicrosecondArray::from(instants.clone())),
+                ],
+                Arc::new(TimestampMicrosecondArray::from(vec![1_000_000, 2_000_000])),
+            ),
+            (
+                DataType::Date32,
+                [
+                    Arc::new(Date32Array::from(vec![Some(19000), None, Some(19001)])),
+                    Arc::new(Date32Array::from(vec![Some(19001), Some(19000), None])),
+                ],
+                Arc::new(Date32Array::from(vec![19000, 19001])),
+            ),
+        ];
+
+        for (arg_type, [values1, values2], expected) in cases {
+            let mut agg = AggCollectSet::try_new(
+                Arc::new(Column::new("a", 0)),
+                DataType::new_list(arg_type.clone(), true),
+                arg_type.clone(),
+            )?
+            .with_insertion_order();
+            let (initial_acc, addrs) = create_acc_from_initial_value(agg.accums_initial())?;
+            agg.set_accum_state_val_addrs(&addrs);
+
+            let mut acc = initial_acc.clone();
+            let mut merging_acc = initial_acc.clone();
+            agg.partial_update_all(&mut acc.as_mut(), &[values1])?;
+            for row_idx in 0..values2.len() {
+                agg.partial_update(&mut merging_acc.as_mut(), &[values2.clone()], row_idx)?;
+            }
+            agg.partial_merge(&mut acc.as_mut(), &mut merging_acc.as_mut())?;
+
+            let output = agg.final_batch_merge(&mut [acc.as_mut()])?;
+            assert_eq!(output.data_type(), agg.data_type());
+            assert_eq!(
+                output.as_list::<i32>().value(0).to_data(),
+                expected.to_data(),
+                "{arg_type}"
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_parallel_batch_merge() -> Result<()> {
+        let mut agg = AggCollectSet::try_new(
+            Arc::new(Column::new("a", 0)),
+            DataType::new_list(DataType::Int32, true),
+            DataType::Int32,
+        )?;
+        let (initial_acc, addrs) = create_acc_from_initial_value(agg.accums_initial())?;
+        agg.set_accum_state_val_addrs(&addrs);
+
+        // xorshift random generator
+        let mut seed = 0x2545F4914F6CDD1Du64;
+        let mut rand = move || {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            seed
+        };
+        let mut random_accs = |num_rows: usize| {
+            (0..num_rows)
+                .map(|_| {
+                    let mut acc = initial_acc.clone();
+                    *acc.as_mut().dyn_value_mut(addrs[0]) = match rand() % 4 {
+                        0 => None,
+                        n => {
+                            let mut set = AggDynSet::with_hash_seed(AGG_DYN_SET_HASH_SEED);
+                            for _ in 0..(rand() % (n * 3)) {
+                                set.append(&ScalarValue::from((rand() % 16) as i32), false)
+                                    .unwrap();
+                            }
+                            Some(Box::new(set))
+                        }
+                    };
+                    acc
+                })
+                .collect::<Vec<OwnedAccumStateRow>>()
+        };
+
+        for num_rows in [10, PARALLEL_MERGE_MIN_NUM_GROUPS + 3] {
+            let mut accs1 = random_accs(num_rows);
+            let mut accs2 = accs1.clone();
+            let mut merging_accs1 = random_accs(num_rows);
+            let mut merging_accs2 = merging_accs1.clone();
+
+            // sequential
+            agg.merge_num_threads = 1;
+            let mut ref_accs1 = accs1.iter_mut().map(|acc| acc.as_mut()).collect::<Vec<_>>();
+            let mut ref_merging_accs1 = merging_accs1
+                .iter_mut()
+                .map(|acc| acc.as_mut())
+                .collect::<Vec<_>>();
+            agg.partial_batch_merge(&mut ref_accs1, &mut ref_merging_accs1)?;
+
+            // parallel
+            agg.merge_num_threads = 4;
+            let mut ref_accs2 = accs2.iter_mut().map(|acc| acc.as_mut()).collect::<Vec<_>>();
+            let mut ref_merging_accs2 = merging_accs2
+                .iter_mut()
+                .map(|acc| acc.as_mut())
+                .collect::<Vec<_>>();
+            agg.partial_batch_merge(&mut ref_accs2, &mut ref_merging_accs2)?;
+
+            assert_eq!(
+                agg.final_batch_merge(&mut ref_accs1)?.to_data(),
+                agg.final_batch_merge(&mut ref_accs2)?.to_data(),
+            );
         }
-        Ok(ScalarValue::iter_to_array(values)?)
+        Ok(())
+    }
+
+    #[test]
+    fn test_typed_equality() -> Result<()> {
+        let floats = vec![0.0, -0.0, f64::NAN, -f64::NAN, 1.5];
+        let top_level: ArrayRef = Arc::new(Float64Array::from(floats.clone()));
+        let nested: ArrayRef = Arc::new(StructArray::from(vec![(
+            Arc::new(Field::new("f", DataType::Float64, true)),
+            Arc::new(Float64Array::from(floats)) as ArrayRef,
+        )]));
+        let nested_list: ArrayRef = Arc::new(ListArray::new(
+            Arc::new(Field::new("item", DataType::Float64, true)),
+            OffsetBuffer::from_lengths([1, 1, 1, 1, 1]),
+            top_level.clone(),
+            None,
+        ));
+
+        // (values, typed_equality, expected number of distinct values)
+        let cases = [
+            (top_level.clone(), false, 3),
+            (top_level, true, 3),
+            (nested.clone(), false, 5),
+            (nested, true, 3),
+            (nested_list.clone(), false, 5),
+            (nested_list, true, 3),
+        ];
+        for (values, typed_equality, expected) in cases {
+            let arg_type = values.data_type().clone();
+            let mut agg = AggCollectSet::try_new(
+                Arc::new(Column::new("a", 0)),
+                DataType::new_list(arg_type.clone(), true),
+                arg_type,
+            )?;
+            if typed_equality {
+                agg = agg.with_typed_equality();
+            }
+            let (initial_acc, addrs) = create_acc_from_initial_value(agg.accums_initial())?;
+            agg.set_accum_state_val_addrs(&addrs);
+
+            let mut acc = initial_acc.clone();
+            let values = agg.prepare_partial_args(&[values])?;
+            agg.partial_update_all(&mut acc.as_mut(), &values)?;
+            let output = agg.final_batch_merge(&mut [acc.as_mut()])?;
+            assert_eq!(output.as_list::<i32>().value(0).len(), expected);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_sorted_output() -> Result<()> {
+        let ints: ArrayRef = Arc::new(Int32Array::from(vec![
+            Some(3),
+            Some(-1),
+            None,
+            Some(7),
+            Some(3),
+            Some(0),
+        ]));
+        let strs: ArrayRef = Arc::new(StringArray::from(vec![
+            Some("pear"),
+            Some("apple"),
+            Some("fig"),
+            None,
+            Some("apple"),
+            Some("banana"),
+        ]));
+        let cases: [(ArrayRef, bool, ArrayRef); 4] = [
+            (
+                ints.clone(),
+                false,
+                Arc::new(Int32Array::from(vec![-1, 0, 3, 7])),
+            ),
+            (ints, true, Arc::new(Int32Array::from(vec![7, 3, 0, -1]))),
+            (
+                strs.clone(),
+                false,
+                Arc::new(StringArray::from(vec!["apple", "banana", "fig", "pear"])),
+            ),
+            (
+                strs,
+                true,
+                Arc::new(StringArray::from(vec!["pear", "fig", "banana", "apple"])),
+            ),
+        ];
+
+        for (values, descending, expected) in cases {
+            let arg_type = values.data_type().clone();
+            let mut agg = AggCollectSet::try_new(
+                Arc::new(Column::new("a", 0)),
+                DataType::new_list(arg_type.clone(), true),
+                arg_type,
+            )?
+            .with_sorted_output(descending);
+            let (initial_acc, addrs) = create_acc_from_initial_value(agg.accums_initial())?;
+            agg.set_accum_state_val_addrs(&addrs);
+
+            // values are split into two merged partials
+            let mut accs = vec![initial_acc.clone(); 2];
+            let mut merging_acc = initial_acc.clone();
+            agg.partial_update_all(&mut accs[0].as_mut(), &[values.slice(0, 3)])?;
+            agg.partial_update_all(&mut merging_acc.as_mut(), &[values.slice(3, 3)])?;
+            agg.partial_merge(&mut accs[0].as_mut(), &mut merging_acc.as_mut())?;
+
+            let mut ref_accs = accs.iter_mut().map(|acc| acc.as_mut()).collect::<Vec<_>>();
+            let results = agg.final_batch_merge(&mut ref_accs)?;
+            let results = results.as_list::<i32>();
+            assert_eq!(&results.value(0), &expected);
+            assert!(results.is_null(1));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_raw_sorted_output() -> Result<()> {
+        let values: ArrayRef =
+            Arc::new(StringArray::from_iter((0..500).map(|i| {
+                (i % 7 != 0).then(|| format!("Value{}", (i * 37) % 101))
+            })));
+        let reversed = arrow::compute::take(
+            &values,
+            &UInt32Array::from_iter_values((0..values.len() as u32).rev()),
+            None,
+        )?;
+
+        // runs with different hash seeds, input orders and partial splits
+        let run = |values: &ArrayRef,
+                   hash_seed: i64,
+                   num_partials: usize,
+                   normalization: Option<SetNormalization>|
+         -> Result<ArrayRef> {
+            let mut agg = AggCollectSet::try_new_with_hash_seed(
+                Arc::new(Column::new("a", 0)),
+                DataType::new_list(DataType::Utf8, true),
+                DataType::Utf8,
+                hash_seed,
+            )?
+            .with_raw_sorted_output();
+            if let Some(normalization) = normalization {
+                agg = agg.with_normalization(normalization)?;
+            }
+            let (initial_acc, addrs) = create_acc_from_initial_value(agg.accums_initial())?;
+            agg.set_accum_state_val_addrs(&addrs);
+
+            let mut acc = initial_acc.clone();
+            let partial_len = (values.len() + num_partials - 1) / num_partials;
+            for offset in (0..values.len()).step_by(partial_len) {
+                let len = partial_len.min(values.len() - offset);
+                let mut merging_acc = initial_acc.clone();
+                agg.partial_update_all(&mut merging_acc.as_mut(), &[values.slice(offset, len)])?;
+                agg.partial_merge(&mut acc.as_mut(), &mut merging_acc.as_mut())?;
+            }
+            let mut ref_accs = vec![acc.as_mut()];
+            agg.final_batch_merge(&mut ref_accs)
+        };
+
+        let expected = run(&values, AGG_DYN_SET_HASH_SEED, 1, None)?;
+        assert_eq!(expected.as_list::<i32>().value(0).len(), 101);
+        assert_eq!(
+            run(&values, AGG_DYN_SET_HASH_SEED, 1, None)?.to_data(),
+            expected.to_data()
+        );
+        assert_eq!(
+            run(&reversed, 12345, 3, None)?.to_data(),
+            expected.to_data()
+        );
+
+        // normalized values are distinct in the same way, so the originals are
+        // output in the same order
+        let normalization = Some(SetNormalization::CaseInsensitive);
+        let expected_normalized = run(&values, AGG_DYN_SET_HASH_SEED, 1, normalization)?;
+        assert_eq!(
+            run(&reversed, 12345, 3, normalization)?.to_data(),
+            expected_normalized.to_data(),
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_precomputed_hashes() -> Result<()> {
+        let mut agg = AggCollectSet::try_new(
+            Arc::new(Column::new("a", 0)),
+            DataType::new_list(DataType::Utf8, true),
+            DataType::Utf8,
+        )?
+        .with_insertion_order();
+        let (initial_acc, addrs) = create_acc_from_initial_value(agg.accums_initial())?;
+        agg.set_accum_state_val_addrs(&addrs);
+
+        // high-cardinality strings with duplicates and nulls, rows are updated
+        // into 3 groups
+        let values: ArrayRef =
+            Arc::new(StringArray::from_iter((0..3000).map(|i| {
+                (i % 7 != 0).then(|| format!("value-{}", i * 31 % 1000))
+            })));
+        let hashes = agg.hash_values(&values)?;
+
+        let mut store1 = AccStore::new(initial_acc.clone());
+        let mut store2 = AccStore::new(initial_acc.clone());
+        let addrs1 = (0..3).map(|_| store1.new_acc()).collect::<Vec<_>>();
+        let addrs2 = (0..3).map(|_| store2.new_acc()).collect::<Vec<_>>();
+        let mut row_accs1 = (0..values.len())
+            .map(|i| store1.get(addrs1[i % 3]))
+            .collect::<Vec<_>>();
+        let mut row_accs2 = (0..values.len())
+            .map(|i| store2.get(addrs2[i % 3]))
+            .collect::<Vec<_>>();
+        agg.partial_batch_update(&mut row_accs1, &[values.clone()])?;
+        agg.partial_batch_update_with_hashes(&mut row_accs2, &[values.clone()], &hashes)?;
+
+        let mut accs1 = addrs1
+            .iter()
+            .map(|&addr| store1.get(addr))
+            .collect::<Vec<_>>();
+        let mut accs2 = addrs2
+            .iter()
+            .map(|&addr| store2.get(addr))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            agg.final_batch_merge(&mut accs1)?.to_data(),
+            agg.final_batch_merge(&mut accs2)?.to_data(),
+        );
+
+        // hashes must be aligned with values
+        assert!(agg
+            .partial_batch_update_with_hashes(&mut row_accs2, &[values.clone()], &hashes[1..])
+            .is_err());
+
+        // hashes must be computed with the same hash seed
+        let other_agg = AggCollectSet::try_new_with_hash_seed(
+            Arc::new(Column::new("a", 0)),
+            DataType::new_list(DataType::Utf8, true),
+            DataType::Utf8,
+            derive_agg_dyn_set_hash_seed(42),
+        )?
+        .with_insertion_order();
+        let other_hashes = other_agg.hash_values(&values)?;
+        assert!(agg
+            .partial_batch_update_with_hashes(&mut row_accs2, &[values.clone()], &other_hashes)
+            .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_normalized_floats() -> Result<()> {
+        let mut agg = AggCollectSet::try_new(
+            Arc::new(Column::new("a", 0)),
+            DataType::new_list(DataType::Float64, true),
+            DataType::Float64,
+        )?
+        .with_insertion_order();
+        let (initial_acc, addrs) = create_acc_from_initial_value(agg.accums_initial())?;
+        agg.set_accum_state_val_addrs(&addrs);
+
+        let values: ArrayRef = Arc::new(Float64Array::from(vec![
+            f64::NAN,
+            0.0,
+            -f64::NAN,
+            f64::from_bits(0x7ff8_0000_0000_0001),
+            -0.0,
+            1.5,
+            f64::from_bits(0xfff0_0000_0000_0002),
+        ]));
+        let dict_values: ArrayRef = Arc::new(DictionaryArray::<Int32Type>::try_new(
+            Int32Array::from(vec![0, 1]),
+            Arc::new(Float64Array::from(vec![
+                -0.0,
+                f64::from_bits(0x7ff0_0000_0000_0003),
+            ])),
+        )?);
+
+        // row by row, in batch, and merged with a dictionary input
+        let mut accs = vec![initial_acc.clone(); 2];
+        let mut merging_acc = initial_acc.clone();
+        for row_idx in 0..values.len() {
+            agg.partial_update(&mut accs[0].as_mut(), &[values.clone()], row_idx)?;
+        }
+        agg.partial_update_all(&mut accs[1].as_mut(), &[values.clone()])?;
+        agg.partial_update_all(&mut merging_acc.as_mut(), &[dict_values])?;
+        agg.partial_merge(&mut accs[1].as_mut(), &mut merging_acc.as_mut())?;
+
+        let mut ref_accs = accs.iter_mut().map(|acc| acc.as_mut()).collect::<Vec<_>>();
+        let results = agg.final_batch_merge(&mut ref_accs)?;
+        let results = results.as_list::<i32>();
+        for i in 0..2 {
+            let bits = results
+                .value(i)
+                .as_primitive::<Float64Type>()
+                .values()
+                .iter()
+                .map(|v| v.to_bits())
+                .collect::<Vec<_>>();
+            assert_eq!(bits, [f64::NAN.to_bits(), 0, 1.5f64.to_bits()]);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_nested_list_arg() -> Result<()> {
+        let arg_type = DataType::new_list(DataType::Int32, true);
+        let mut agg = AggCollectSet::try_new(
+            Arc::new(Column::new("a", 0)),
+            DataType::new_list(arg_type.clone(), true),
+            arg_type,
+        )?
+        .with_insertion_order();
+        let (initial_acc, addrs) = create_acc_from_initial_value(agg.accums_initial())?;
+        agg.set_accum_state_val_addrs(&addrs);
+
+        // equal inner lists are deduplicated, no matter whether they come from
+        // arrays with or without null buffers
+        let values1: ArrayRef = Arc::new(ListArray::from_iter_primitive::<Int32Type, _, _>(vec![
+            Some(vec![Some(1), Some(2)]),
+            None,
+            Some(vec![]),
+            Some(vec![Some(1), Some(2)]),
+        ]));
+        let values2: ArrayRef = Arc::new(ListArray::from_iter_primitive::<Int32Type, _, _>(vec![
+            Some(vec![]),
+            Some(vec![Some(1), Some(2)]),
+            Some(vec![Some(2), None]),
+        ]));
+        let mut acc = initial_acc.clone();
+        let mut merging_acc = initial_acc.clone();
+        agg.partial_update_all(&mut acc.as_mut(), &[values1])?;
+        for row_idx in 0..values2.len() {
+            agg.partial_update(&mut merging_acc.as_mut(), &[values2.clone()], row_idx)?;
+        }
+        agg.partial_merge(&mut acc.as_mut(), &mut merging_acc.as_mut())?;
+
+        let results = agg.final_batch_merge(&mut [acc.as_mut()])?;
+        assert_eq!(
+            results.as_list::<i32>().value(0).to_data(),
+            ListArray::from_iter_primitive::<Int32Type, _, _>(vec![
+                Some(vec![Some(1), Some(2)]),
+                Some(vec![]),
+                Some(vec![Some(2), None]),
+            ])
+            .to_data(),
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_num_set_promotions() -> Result<()> {
+        let mut agg = AggCollectSet::try_new(
+            Arc::new(Column::new("a", 0)),
+            DataType::new_list(DataType::Int32, true),
+            DataType::Int32,
+        )?;
+        let (initial_acc, addrs) = create_acc_from_initial_value(agg.accums_initial())?;
+        agg.set_accum_state_val_addrs(&addrs);
+
+        let values: ArrayRef = Arc::new(Int32Array::from_iter_values(0..20));
+        let mut accs = vec![initial_acc.clone(); 4];
+        let mut merging_accs = vec![initial_acc.clone(); 4];
+
+        // group 0: promoted by updating row by row
+        for row_idx in 0..values.len() {
+            agg.partial_update(&mut accs[0].as_mut(), &[values.clone()], row_idx)?;
+        }
+        // group 1: promoted by updating in batch
+        agg.partial_update_all(&mut accs[1].as_mut(), &[values.clone()])?;
+        // group 2: not promoted
+        agg.partial_update_all(&mut accs[2].as_mut(), &[values.slice(0, 2)])?;
+        assert_eq!(agg.num_set_promotions().value(), 2);
+
+        // group 0: merging a small set into a huge set is not a promotion
+        // group 2: promoted by merging two small sets
+        // group 3: a huge set is taken by an empty group
+        agg.partial_update_all(&mut merging_accs[0].as_mut(), &[values.slice(0, 2)])?;
+        agg.partial_update_all(&mut merging_accs[2].as_mut(), &[values.slice(2, 3)])?;
+        agg.partial_update_all(&mut merging_accs[3].as_mut(), &[values.clone()])?;
+        assert_eq!(agg.num_set_promotions().value(), 3);
+
+        let mut ref_accs = accs.iter_mut().map(|acc| acc.as_mut()).collect::<Vec<_>>();
+        let mut ref_merging_accs = merging_accs
+            .iter_mut()
+            .map(|acc| acc.as_mut())
+            .collect::<Vec<_>>();
+        agg.partial_batch_merge(&mut ref_accs, &mut ref_merging_accs)?;
+        assert_eq!(agg.num_set_promotions().value(), 4);
+        Ok(())
+    }
+
+    #[test]
+    fn test_max_slots() -> Result<()> {
+        let mut agg = AggCollectSet::try_new(
+            Arc::new(Column::new("a", 0)),
+            DataType::new_list(DataType::Int32, true),
+            DataType::Int32,
+        )?
+        .with_max_slots(64);
+        let (initial_acc, addrs) = create_acc_from_initial_value(agg.accums_initial())?;
+        agg.set_accum_state_val_addrs(&addrs);
+
+        // 56 values fit in a full table of 64 slots
+        let values: ArrayRef = Arc::new(Int32Array::from_iter_values(0..100));
+        let mut acc = initial_acc.clone();
+        agg.partial_update_all(&mut acc.as_mut(), &[values.slice(0, 56)])?;
+        agg.partial_update_all(&mut acc.as_mut(), &[values.slice(0, 56)])?;
+
+        // growing beyond the limit fails
+        let err = agg
+            .partial_update_all(&mut acc.as_mut(), &[values.clone()])
+            .unwrap_err();
+        assert!(matches!(err, DataFusionError::ResourcesExhausted(_)));
+
+        // merging two groups within the limit also fails if the union is beyond
+        let mut acc = initial_acc.clone();
+        let mut merging_acc = initial_acc.clone();
+        agg.partial_update_all(&mut acc.as_mut(), &[values.slice(0, 50)])?;
+        agg.partial_update_all(&mut merging_acc.as_mut(), &[values.slice(50, 50)])?;
+        let err = agg
+            .partial_merge(&mut acc.as_mut(), &mut merging_acc.as_mut())
+            .unwrap_err();
+        assert!(matches!(err, DataFusionError::ResourcesExhausted(_)));
+        Ok(())
+    }
+
+    #[test]
+    fn test_normalization() -> Result<()> {
+        let data_type = DataType::new_list(DataType::Utf8, true);
+        let mut agg = AggCollectSet::try_new(
+            Arc::new(Column::new("a", 0)),
+            data_type.clone(),
+            DataType::Utf8,
+        )?
+        .with_insertion_order()
+        .with_normalization(SetNormalization::CaseInsensitive)?;
+        let (initial_acc, addrs) = create_acc_from_initial_value(agg.accums_initial())?;
+        agg.set_accum_state_val_addrs(&addrs);
+
+        let values: ArrayRef = Arc::new(StringArray::from(vec![
+            Some("A"),
+            Some("a"),
+            None,
+            Some("B"),
+            Some("b"),
+            Some("c"),
+        ]));
+
+        // group 0: updated all at once
+        // group 1: merged from partials, originals of the first partial are kept
+        let mut accs = vec![initial_acc.clone(); 2];
+        agg.partial_update_all(&mut accs[0].as_mut(), &[values.slice(0, 4)])?;
+        let mut merging_acc = initial_acc.clone();
+        agg.partial_update_all(&mut accs[1].as_mut(), &[values.slice(1, 3)])?;
+        agg.partial_update_all(&mut merging_acc.as_mut(), &[values.slice(4, 2)])?;
+        agg.partial_merge(&mut accs[1].as_mut(), &mut merging_acc.as_mut())?;
+
+        let mut ref_accs = accs.iter_mut().map(|acc| acc.as_mut()).collect::<Vec<_>>();
+        let output = agg.final_batch_merge(&mut ref_accs)?;
+        let output = output.as_list::<i32>();
+        assert_eq!(output.data_type(), &data_type);
+        assert_eq!(
+            output.value(0).as_string::<i32>(),
+            &StringArray::from(vec!["A", "B"]),
+        );
+        assert_eq!(
+            output.value(1).as_string::<i32>(),
+            &StringArray::from(vec!["a", "B", "c"]),
+        );
+
+        // trimmed values
+        let mut agg = AggCollectSet::try_new(
+            Arc::new(Column::new("a", 0)),
+            data_type.clone(),
+            DataType::Utf8,
+        )?
+        .with_insertion_order()
+        .with_normalization(SetNormalization::Trim)?;
+        let (initial_acc, addrs) = create_acc_from_initial_value(agg.accums_initial())?;
+        agg.set_accum_state_val_addrs(&addrs);
+        let values: ArrayRef = Arc::new(StringArray::from(vec![" x", "x ", "X", "y"]));
+        let mut acc = initial_acc.clone();
+        for row_idx in 0..values.len() {
+            agg.partial_update(&mut acc.as_mut(), &[values.clone()], row_idx)?;
+        }
+        let output = agg.final_batch_merge(&mut [acc.as_mut()])?;
+        assert_eq!(
+            output.as_list::<i32>().value(0).as_string::<i32>(),
+            &StringArray::from(vec![" x", "X", "y"]),
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_duration_and_interval_args() -> Result<()> {
+        let inputs: Vec<ArrayRef> = vec![
+            Arc::new(DurationMillisecondArray::from(vec![
+                Some(-5),
+                Some(3),
+                None,
+                Some(-5),
+                Some(0),
+            ])),
+            Arc::new(IntervalMonthDayNanoArray::from(vec![
+                Some(IntervalMonthDayNanoType::make_value(1, -2, 3)),
+                Some(IntervalMonthDayNanoType::make_value(-1, 2, -3)),
+                None,
+                Some(IntervalMonthDayNanoType::make_value(1, -2, 3)),
+                Some(IntervalMonthDayNanoType::make_value(0, 0, 0)),
+            ])),
+        ];
+        for values in inputs {
+            let arg_type = values.data_type().clone();
+            let mut agg = AggCollectSet::try_new(
+                Arc::new(Column::new("a", 0)),
+                DataType::new_list(arg_type.clone(), true),
+                arg_type.clone(),
+            )?
+            .with_insertion_order();
+            let (initial_acc, addrs) = create_acc_from_initial_value(agg.accums_initial())?;
+            agg.set_accum_state_val_addrs(&addrs);
+
+            let mut acc = initial_acc.clone();
+            let mut merging_acc = initial_acc.clone();
+            agg.partial_update_all(&mut acc.as_mut(), &[values.slice(0, 2)])?;
+            agg.partial_update_all(&mut merging_acc.as_mut(), &[values.slice(2, 3)])?;
+            agg.partial_merge(&mut acc.as_mut(), &mut merging_acc.as_mut())?;
+
+            // duplicated values are deduplicated, first-seen order is kept
+            let output = agg.final_batch_merge(&mut [acc.as_mut()])?;
+            let output = output.as_list::<i32>();
+            assert_eq!(output.value(0).data_type(), &arg_type);
+            let expected = arrow::compute::take(&values, &UInt32Array::from(vec![0, 1, 4]), None)?;
+            assert_eq!(&output.value(0), &expected);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_rehydrate_from_lists() -> Result<()> {
+        let mut agg = AggCollectSet::try_new(
+            Arc::new(Column::new("a", 0)),
+            DataType::new_list(DataType::Int32, true),
+            DataType::Int32,
+        )?
+        .with_insertion_order();
+        let (initial_acc, addrs) = create_acc_from_initial_value(agg.accums_initial())?;
+        agg.set_accum_state_val_addrs(&addrs);
+
+        // persisted outputs of 3 groups, group 1 is null
+        let persisted = ListArray::from_iter_primitive::<Int32Type, _, _>(vec![
+            Some((0..100).map(Some).collect::<Vec<_>>()),
+            None,
+            Some(vec![Some(1), Some(2)]),
+        ]);
+        let new_values: ArrayRef = Arc::new(Int32Array::from_iter_values(50..150));
+
+        let mut accs = vec![initial_acc.clone(); 3];
+        let mut ref_accs = accs.iter_mut().map(|acc| acc.as_mut()).collect::<Vec<_>>();
+        agg.rehydrate_from_lists(&mut ref_accs, &persisted)?;
+        for acc in &mut ref_accs {
+            agg.partial_update_all(acc, &[new_values.clone()])?;
+        }
+
+        // memory tracking is consistent with the rehydrated sets
+        let tracked = agg.mem_used();
+        agg.reset_mem_used();
+        for acc in &mut ref_accs {
+            agg.increase_acc_mem_used(acc);
+        }
+        assert_eq!(agg.mem_used(), tracked);
+        let output = agg.final_batch_merge(&mut ref_accs)?;
+
+        // fresh aggregation of all original and new values
+        let mut fresh_accs = vec![initial_acc.clone(); 3];
+        for (i, acc) in fresh_accs.iter_mut().enumerate() {
+            if persisted.is_valid(i) {
+                agg.partial_update_all(&mut acc.as_mut(), &[persisted.value(i)])?;
+            }
+            agg.partial_update_all(&mut acc.as_mut(), &[new_values.clone()])?;
+        }
+        let mut ref_fresh_accs = fresh_accs
+            .iter_mut()
+            .map(|acc| acc.as_mut())
+            .collect::<Vec<_>>();
+        let expected = agg.final_batch_merge(&mut ref_fresh_accs)?;
+        assert_eq!(output.to_data(), expected.to_data());
+        assert_eq!(output.as_list::<i32>().value(0).len(), 150);
+
+        // element type must match
+        let mismatched =
+            ListArray::from_iter_primitive::<Int64Type, _, _>(vec![Some(vec![Some(1)]); 3]);
+        assert!(agg
+            .rehydrate_from_lists(&mut ref_accs, &mismatched)
+            .is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_final_batch_merge_exploded() -> Result<()> {
+        let mut agg = AggCollectSet::try_new(
+            Arc::new(Column::new("a", 0)),
+            DataType::new_list(DataType::Int32, true),
+            DataType::Int32,
+        )?
+        .with_insertion_order()
+        .with_sorted_output(false);
+        let (initial_acc, addrs) = create_acc_from_initial_value(agg.accums_initial())?;
+        agg.set_accum_state_val_addrs(&addrs);
+
+        // group 1 has no values
+        let values: ArrayRef = Arc::new(Int32Array::from(vec![
+            Some(3),
+            Some(1),
+            None,
+            Some(3),
+            Some(2),
+            Some(5),
+            Some(4),
+            Some(5),
+        ]));
+        let mut accs = vec![initial_acc.clone(); 3];
+        agg.partial_update_all(&mut accs[0].as_mut(), &[values.slice(0, 5)])?;
+        agg.partial_update_all(&mut accs[2].as_mut(), &[values.slice(5, 3)])?;
+        let mut expected_accs = accs.clone();
+
+        let mut ref_accs = accs.iter_mut().map(|acc| acc.as_mut()).collect::<Vec<_>>();
+        let (group_indices, exploded) = agg.final_batch_merge_exploded(&mut ref_accs)?;
+        assert_eq!(group_indices, UInt32Array::from(vec![0, 0, 0, 2, 2]));
+        assert_eq!(
+            exploded.as_primitive::<Int32Type>(),
+            &Int32Array::from(vec![1, 2, 3, 4, 5]),
+        );
+
+        // same as exploding the list output
+        let mut ref_accs = expected_accs
+            .iter_mut()
+            .map(|acc| acc.as_mut())
+            .collect::<Vec<_>>();
+        let lists = agg.final_batch_merge(&mut ref_accs)?;
+        assert_eq!(&exploded, lists.as_list::<i32>().values());
+        Ok(())
+    }
+
+    #[test]
+    fn test_partial_merge_many() -> Result<()> {
+        // 16 overlapping partials, with an empty one
+        let values: ArrayRef = Arc::new(Int32Array::from_iter_values((0..3000).map(|i| i % 997)));
+        let partials = (0..16)
+            .map(|i| match i {
+                5 => values.slice(0, 0),
+                _ => values.slice(i * 180, 300),
+            })
+            .collect::<Vec<_>>();
+
+        for insertion_order in [false, true] {
+            let mut agg = AggCollectSet::try_new(
+                Arc::new(Column::new("a", 0)),
+                DataType::new_list(DataType::Int32, true),
+                DataType::Int32,
+            )?;
+            if insertion_order {
+                agg = agg.with_insertion_order();
+            }
+            let (initial_acc, addrs) = create_acc_from_initial_value(agg.accums_initial())?;
+            agg.set_accum_state_val_addrs(&addrs);
+            let new_merging_accs = || -> Result<Vec<OwnedAccumStateRow>> {
+                let mut merging_accs = vec![initial_acc.clone(); partials.len()];
+                for (merging_acc, partial) in merging_accs.iter_mut().zip(&partials) {
+                    if !partial.is_empty() {
+                        agg.partial_update_all(&mut merging_acc.as_mut(), &[partial.clone()])?;
+                    }
+                }
+                Ok(merging_accs)
+            };
+
+            // group 0/1: merged pairwise/at once into an empty group
+            // group 2/3: merged pairwise/at once into a non-empty group
+            let mut accs = vec![initial_acc.clone(); 4];
+            for acc in &mut accs[2..4] {
+                agg.partial_update_all(&mut acc.as_mut(), &[values.slice(2900, 100)])?;
+            }
+            for (i, acc) in accs.iter_mut().enumerate() {
+                let mut merging_accs = new_merging_accs()?;
+                let mut ref_merging_accs = merging_accs
+                    .iter_mut()
+                    .map(|acc| acc.as_mut())
+                    .collect::<Vec<_>>();
+                if i % 2 == 0 {
+                    for merging_acc in &mut ref_merging_accs {
+                        agg.partial_merge(&mut acc.as_mut(), merging_acc)?;
+                    }
+                } else {
+                    agg.partial_merge_many(&mut acc.as_mut(), &mut ref_merging_accs)?;
+                }
+            }
+            let mut ref_accs = accs.iter_mut().map(|acc| acc.as_mut()).collect::<Vec<_>>();
+            let output = agg.final_batch_merge(&mut ref_accs)?;
+            let output = output.as_list::<i32>();
+            assert_eq!(agg.mem_used(), 0);
+
+            for (pairwise, many) in [(0, 1), (2, 3)] {
+                let mut pairwise = output
+                    .value(pairwise)
+                    .as_primitive::<Int32Type>()
+                    .values()
+                    .to_vec();
+                let mut many = output
+                    .value(many)
+                    .as_primitive::<Int32Type>()
+                    .values()
+                    .to_vec();
+                assert_eq!(pairwise.len(), 997);
+                if !insertion_order {
+                    pairwise.sort_unstable();
+                    many.sort_unstable();
+                }
+                assert_eq!(pairwise, many);
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_presize_sets() -> Result<()> {
+        let mut agg = AggCollectSet::try_new(
+            Arc::new(Column::new("a", 0)),
+            DataType::new_list(DataType::Int32, true),
+            DataType::Int32,
+        )?
+        .with_insertion_order();
+        let (initial_acc, addrs) = create_acc_from_initial_value(agg.accums_initial())?;
+        agg.set_accum_state_val_addrs(&addrs);
+
+        // group 0: presized large group
+        // group 1: large group without hint
+        // group 2: presized with a small hint
+        // group 3: presized with a large hint, but few values
+        let values: ArrayRef = Arc::new(Int32Array::from_iter_values((0..5000).rev()));
+        let mut accs = vec![initial_acc.clone(); 4];
+        let mut ref_accs = accs.iter_mut().map(|acc| acc.as_mut()).collect::<Vec<_>>();
+        agg.presize_sets(&mut ref_accs, &[5000, 0, 2, 5000])?;
+        for acc in &mut ref_accs[0..3] {
+            agg.partial_update_all(acc, &[values.clone()])?;
+        }
+        agg.partial_update_all(&mut ref_accs[3], &[values.slice(0, 2)])?;
+
+        // only the groups without large hints are promoted
+        assert_eq!(agg.num_set_promotions().value(), 2);
+        let output = agg.final_batch_merge(&mut ref_accs)?;
+        let output = output.as_list::<i32>();
+        assert_eq!(&output.value(0), &values);
+        assert_eq!(&output.value(1), &values);
+        assert_eq!(&output.value(2), &values);
+        assert_eq!(&output.value(3), &values.slice(0, 2));
+        Ok(())
     }
 }
@@ -0,0 +1,358 @@
+// Copyright 2022 The Blaze Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::{
+    any::Any,
+    fmt::{Debug, Formatter},
+    sync::{atomic::AtomicUsize, Arc},
+};
+
+use arrow::{array::*, datatypes::*};
+use datafusion::{
+    common::{Result, ScalarValue},
+    physical_expr::PhysicalExpr,
+};
+use datafusion_ext_commons::{df_execution_err, downcast_any, io::write_scalar};
+
+use crate::agg::{
+    acc::{
+        AccumInitialValue, AccumStateRow, AccumStateValAddr, AggDynBinary, AggDynValue,
+        RefAccumStateRow,
+    },
+    agg_table::gx_hash_with_seed,
+    Agg, WithAggBufAddrs, WithMemTracking,
+};
+
+const CMS_HASH_SEED: i64 = 0x434D_5348;
+
+/// count-min sketch of values, output is the binary of `depth` rows of `width`
+/// u32 counters in little-endian, estimated frequencies of values can be
+/// retrieved with `estimate()`
+pub struct AggCountMinSketch {
+    child: Arc<dyn PhysicalExpr>,
+    data_type: DataType,
+    width: usize,
+    depth: usize,
+    accum_initial: [AccumInitialValue; 1],
+    accum_state_val_addr: AccumStateValAddr,
+    mem_used_tracker: AtomicUsize,
+}
+
+impl WithAggBufAddrs for AggCountMinSketch {
+    fn set_accum_state_val_addrs(&mut self, accum_state_val_addrs: &[AccumStateValAddr]) {
+        self.accum_state_val_addr = accum_state_val_addrs[0];
+    }
+}
+
+impl WithMemTracking for AggCountMinSketch {
+    fn mem_used_tracker(&self) -> &AtomicUsize {
+        &self.mem_used_tracker
+    }
+}
+
+impl AggCountMinSketch {
+    pub fn try_new(child: Arc<dyn PhysicalExpr>, width: usize, depth: usize) -> Result<Self> {
+        if width == 0 || depth == 0 {
+            return df_execution_err!(
+                "count_min_sketch expect positive width and depth, got: {width}x{depth}"
+            );
+        }
+        Ok(Self {
+            child,
+            data_type: DataType::Binary,
+            width,
+            depth,
+            accum_initial: [AccumInitialValue::Scalar(ScalarValue::Binary(None))],
+            accum_state_val_addr: AccumStateValAddr::default(),
+            mem_used_tracker: AtomicUsize::new(0),
+        })
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    /// estimates frequency of the value from a sketch output by this
+    /// aggregate, the estimation is never less than the actual frequency
+    pub fn estimate(&self, sketch: &[u8], value: &ScalarValue) -> Result<u32> {
+        if sketch.len() != self.sketch_len() {
+            return df_execution_err!(
+                "count_min_sketch expect {} bytes, got: {}",
+                self.sketch_len(),
+                sketch.len()
+            );
+        }
+        let mut raw = vec![];
+        write_scalar(value, false, &mut raw)?;
+        Ok(self
+            .counter_indices(&raw)
+            .map(|idx| get_counter(sketch, idx))
+            .min()
+            .unwrap_or(0))
+    }
+
+    fn sketch_len(&self) -> usize {
+        self.width * self.depth * 4
+    }
+
+    /// returns index of the counter in each row, derived from two halves of
+    /// a single hash value (Kirsch-Mitzenmacher)
+    fn counter_indices(&self, raw: &[u8]) -> impl Iterator<Item = usize> + '_ {
+        let hash = gx_hash_with_seed(CMS_HASH_SEED, raw);
+        let h1 = hash as u32;
+        let h2 = (hash >> 32) as u32;
+        (0..self.depth).map(move |i| {
+            let h = h1.wrapping_add((i as u32).wrapping_mul(h2));
+            i * self.width + h as usize % self.width
+        })
+    }
+
+    fn sketch_mut<'a>(&self, acc: &'a mut RefAccumStateRow) -> Result<&'a mut AggDynBinary> {
+        let sketch = match acc.dyn_value_mut(self.accum_state_val_addr) {
+            Some(sketch) => sketch,
+            w => {
+                let new_sketch = AggDynBinary::new(vec![0; self.sketch_len()].into());
+                self.add_mem_used(new_sketch.mem_size());
+                *w = Some(Box::new(new_sketch));
+                w.as_mut().unwrap()
+            }
+        };
+        downcast_any!(sketch, mut AggDynBinary)
+    }
+
+    fn add_value(&self, sketch: &mut [u8], values: &ArrayRef, row_idx: usize) -> Result<()> {
+        let mut raw = vec![];
+        write_scalar(
+            &ScalarValue::try_from_array(values, row_idx)?,
+            false,
+            &mut raw,
+        )?;
+        for idx in self.counter_indices(&raw) {
+            set_counter(sketch, idx, get_counter(sketch, idx).saturating_add(1));
+        }
+        Ok(())
+    }
+
+    fn take_sketch(&self, acc: &mut RefAccumStateRow) -> Result<Vec<u8>> {
+        match std::mem::take(acc.dyn_value_mut(self.accum_state_val_addr)) {
+            Some(w) => {
+                self.sub_mem_used(w.mem_size());
+                let sketch = w
+                    .as_any_boxed()
+                    .downcast::<AggDynBinary>()
+                    .or_else(|_| df_execution_err!("error downcasting to AggDynBinary"))?;
+                Ok(sketch.into_value().into_vec())
+            }
+            None => Ok(vec![0; self.sketch_len()]),
+        }
+    }
+}
+
+fn get_counter(sketch: &[u8], idx: usize) -> u32 {
+    u32::from_le_bytes(sketch[idx * 4..][..4].try_into().unwrap())
+}
+
+fn set_counter(sketch: &mut [u8], idx: usize, counter: u32) {
+    sketch[idx * 4..][..4].copy_from_slice(&counter.to_le_bytes());
+}
+
+impl Debug for AggCountMinSketch {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "CountMinSketch({:?}, {}x{})",
+            self.child, self.width, self.depth
+        )
+    }
+}
+
+impl Agg for AggCountMinSketch {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn exprs(&self) -> Vec<Arc<dyn PhysicalExpr>> {
+        vec![self.child.clone()]
+    }
+
+    fn with_new_exprs(&self, exprs: Vec<Arc<dyn PhysicalExpr>>) -> Result<Arc<dyn Agg>> {
+        Ok(Arc::new(Self::try_new(
+            exprs[0].clone(),
+            self.width,
+            self.depth,
+        )?))
+    }
+
+    fn data_type(&self) -> &DataType {
+        &self.data_type
+    }
+
+    fn nullable(&self) -> bool {
+        false
+    }
+
+    fn accums_initial(&self) -> &[AccumInitialValue] {
+        &self.accum_initial
+    }
+
+    fn increase_acc_mem_used(&self, acc: &mut RefAccumStateRow) {
+        if let Some(v) = acc.dyn_value(self.accum_state_val_addr) {
+            self.add_mem_used(v.mem_size());
+        }
+    }
+
+    fn decrease_acc_mem_used(&self, acc: &mut RefAccumStateRow) {
+        if let Some(v) = acc.dyn_value(self.accum_state_val_addr) {
+            self.sub_mem_used(v.mem_size());
+        }
+    }
+
+    fn partial_update(
+        &self,
+        acc: &mut RefAccumStateRow,
+        values: &[ArrayRef],
+        row_idx: usize,
+    ) -> Result<()> {
+        if values[0].is_valid(row_idx) {
+            let sketch = self.sketch_mut(acc)?;
+            self.add_value(&mut sketch.value, &values[0], row_idx)?;
+        }
+        Ok(())
+    }
+
+    fn partial_update_all(&self, acc: &mut RefAccumStateRow, values: &[ArrayRef]) -> Result<()> {
+        let sketch = self.sketch_mut(acc)?;
+        for row_idx in 0..values[0].len() {
+            if values[0].is_valid(row_idx) {
+                self.add_value(&mut sketch.value, &values[0], row_idx)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn partial_merge(
+        &self,
+        acc: &mut RefAccumStateRow,
+        merging_acc: &mut RefAccumStateRow,
+    ) -> Result<()> {
+        match (
+            acc.dyn_value_mut(self.accum_state_val_addr),
+            merging_acc.dyn_value_mut(self.accum_state_val_addr),
+        ) {
+            (Some(w), Some(v)) => {
+                let w = downcast_any!(w, mut AggDynBinary)?;
+                let v = downcast_any!(v, mut AggDynBinary)?;
+                self.sub_mem_used(v.mem_size());
+                for idx in 0..self.width * self.depth {
+                    let counter = get_counter(&w.value, idx);
+                    set_counter(
+                        &mut w.value,
+                        idx,
+                        counter.saturating_add(get_counter(&v.value, idx)),
+                    );
+                }
+            }
+            (w_none, v @ Some(_)) => *w_none = std::mem::take(v),
+            (None, _) => {}
+            (_, None) => {}
+        }
+        Ok(())
+    }
+
+    fn final_merge(&self, acc: &mut RefAccumStateRow) -> Result<ScalarValue> {
+        Ok(ScalarValue::Binary(Some(self.take_sketch(acc)?)))
+    }
+
+    fn final_batch_merge(&self, accs: &mut [RefAccumStateRow]) -> Result<ArrayRef> {
+        let sketches = accs
+            .iter_mut()
+            .map(|acc| self.take_sketch(acc))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Arc::new(BinaryArray::from_iter_values(sketches)))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use arrow::array::*;
+    use datafusion::{
+        common::{Result, ScalarValue},
+        physical_expr::expressions::Column,
+    };
+
+    use crate::agg::{
+        acc::create_acc_from_initial_value, count_min_sketch::AggCountMinSketch, Agg,
+        WithAggBufAddrs,
+    };
+
+    #[test]
+    fn test_count_min_sketch() -> Result<()> {
+        // eps = e / width, delta = exp(-depth)
+        let width = 272;
+        let depth = 5;
+        let eps = std::f64::consts::E / width as f64;
+        let mut agg = AggCountMinSketch::try_new(Arc::new(Column::new("a", 0)), width, depth)?;
+        let (initial_acc, addrs) = create_acc_from_initial_value(agg.accums_initial())?;
+        agg.set_accum_state_val_addrs(&addrs);
+
+        // skewed distribution: value i occurs 2000/i times
+        let num_distinct = 2000;
+        let mut values = vec![];
+        for i in 1..=num_distinct {
+            values.extend(std::iter::repeat(i as i64).take(num_distinct / i));
+        }
+        let total = values.len() as f64;
+        let values: ArrayRef = Arc::new(Int64Array::from(values));
+
+        // update two halves and merge
+        let mut acc = initial_acc.clone();
+        let mut merging_acc = initial_acc.clone();
+        let half = values.len() / 2;
+        agg.partial_update_all(&mut acc.as_mut(), &[values.slice(0, half)])?;
+        for row_idx in half..values.len() {
+            agg.partial_update(&mut merging_acc.as_mut(), &[values.clone()], row_idx)?;
+        }
+        agg.partial_merge(&mut acc.as_mut(), &mut merging_acc.as_mut())?;
+
+        let sketch = match agg.final_merge(&mut acc.as_mut())? {
+            ScalarValue::Binary(Some(sketch)) => sketch,
+            other => panic!("unexpected sketch: {other:?}"),
+        };
+        assert_eq!(sketch.len(), width * depth * 4);
+
+        let mut num_exceeded = 0;
+        for i in 1..=num_distinct {
+            let actual = (num_distinct / i) as u32;
+            let estimated = agg.estimate(&sketch, &ScalarValue::Int64(Some(i as i64)))?;
+            assert!(estimated >= actual, "{i}: {estimated} < {actual}");
+            if (estimated - actual) as f64 > eps * total {
+                num_exceeded += 1;
+            }
+        }
+        // error exceeds eps*N with probability of at most delta
+        let delta = (-(depth as f64)).exp();
+        assert!(num_exceeded as f64 <= 2.0 * delta * num_distinct as f64);
+
+        // absent value
+        let estimated = agg.estimate(&sketch, &ScalarValue::Int64(Some(-1)))?;
+        assert!(estimated as f64 <= 2.0 * eps * total);
+        Ok(())
+    }
+}
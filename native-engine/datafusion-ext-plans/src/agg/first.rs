@@ -133,6 +133,17 @@ impl Agg for AggFirst {
         }
     }
 
+    fn decrease_acc_mem_used(&self, acc: &mut RefAccumStateRow) {
+        if self.data_type.is_primitive()
+            || matches!(self.data_type, DataType::Null | DataType::Boolean)
+        {
+            return;
+        }
+        if let Some(v) = acc.dyn_value(self.accum_state_val_addr_value) {
+            self.sub_mem_used(v.mem_size());
+        }
+    }
+
     fn partial_update(
         &self,
         acc: &mut RefAccumStateRow,
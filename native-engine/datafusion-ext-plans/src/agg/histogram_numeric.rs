@@ -0,0 +1,490 @@
+// Copyright 2022 The Blaze Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::{
+    any::Any,
+    fmt::{Debug, Formatter},
+    sync::{atomic::AtomicUsize, Arc},
+};
+
+use arrow::{
+    array::*,
+    buffer::{NullBuffer, OffsetBuffer},
+    compute::cast,
+    datatypes::*,
+};
+use datafusion::{
+    common::{Result, ScalarValue},
+    physical_expr::PhysicalExpr,
+};
+use datafusion_ext_commons::{df_execution_err, downcast_any};
+
+use crate::agg::{
+    acc::{
+        AccumInitialValue, AccumStateRow, AccumStateValAddr, AggDynHistogram, AggDynValue,
+        RefAccumStateRow,
+    },
+    Agg, WithAggBufAddrs, WithMemTracking,
+};
+
+/// histogram_numeric(): approximate histogram of numeric values with at most
+/// `num_bins` bins, output as array<struct<x, y>> where x is the center and y
+/// is the height of each bin
+pub struct AggHistogramNumeric {
+    child: Arc<dyn PhysicalExpr>,
+    data_type: DataType,
+    item_field: FieldRef,
+    bin_fields: Fields,
+    num_bins: usize,
+    accum_initial: [AccumInitialValue; 1],
+    accum_state_val_addr: AccumStateValAddr,
+    mem_used_tracker: AtomicUsize,
+}
+
+impl WithAggBufAddrs for AggHistogramNumeric {
+    fn set_accum_state_val_addrs(&mut self, accum_state_val_addrs: &[AccumStateValAddr]) {
+        self.accum_state_val_addr = accum_state_val_addrs[0];
+    }
+}
+
+impl WithMemTracking for AggHistogramNumeric {
+    fn mem_used_tracker(&self) -> &AtomicUsize {
+        &self.mem_used_tracker
+    }
+}
+
+impl AggHistogramNumeric {
+    /// `data_type` must be a list of struct with two fields (x and y)
+    pub fn try_new(
+        child: Arc<dyn PhysicalExpr>,
+        data_type: DataType,
+        num_bins: usize,
+    ) -> Result<Self> {
+        if num_bins < 2 {
+            return df_execution_err!("histogram_numeric expect at least 2 bins, got: {num_bins}");
+        }
+        let (item_field, bin_fields) = match &data_type {
+            DataType::List(item_field) => match item_field.data_type() {
+                DataType::Struct(fields) if fields.len() == 2 => {
+                    (item_field.clone(), fields.clone())
+                }
+                _ => return df_execution_err!("histogram_numeric expect list of struct type"),
+            },
+            _ => return df_execution_err!("histogram_numeric expect list of struct type"),
+        };
+        Ok(Self {
+            child,
+            data_type,
+            item_field,
+            bin_fields,
+            num_bins,
+            accum_initial: [AccumInitialValue::DynHistogram],
+            accum_state_val_addr: AccumStateValAddr::default(),
+            mem_used_tracker: AtomicUsize::new(0),
+        })
+    }
+
+    pub fn num_bins(&self) -> usize {
+        self.num_bins
+    }
+
+    fn histogram_mut<'a>(&self, acc: &'a mut RefAccumStateRow) -> Result<&'a mut AggDynHistogram> {
+        let histogram = match acc.dyn_value_mut(self.accum_state_val_addr) {
+            Some(histogram) => histogram,
+            w => {
+                let new_histogram = AggDynHistogram::default();
+                self.add_mem_used(new_histogram.mem_size());
+                *w = Some(Box::new(new_histogram));
+                w.as_mut().unwrap()
+            }
+        };
+        downcast_any!(histogram, mut AggDynHistogram)
+    }
+
+    fn take_bins(&self, acc: &mut RefAccumStateRow) -> Result<Option<Vec<(f64, f64)>>> {
+        match std::mem::take(acc.dyn_value_mut(self.accum_state_val_addr)) {
+            Some(w) => {
+                self.sub_mem_used(w.mem_size());
+                let histogram = w
+                    .as_any_boxed()
+                    .downcast::<AggDynHistogram>()
+                    .or_else(|_| df_execution_err!("error downcasting to AggDynHistogram"))?;
+                let bins = histogram.into_bins();
+                Ok(Some(bins).filter(|bins| !bins.is_empty()))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// builds output array, groups without any bins are null
+    fn build_bins_array(&self, groups: Vec<Option<Vec<(f64, f64)>>>) -> Result<ArrayRef> {
+        let mut offsets = Vec::with_capacity(groups.len() + 1);
+        let mut valids = Vec::with_capacity(groups.len());
+        let mut xs = vec![];
+        let mut ys = vec![];
+
+        offsets.push(0i32);
+        for group in groups {
+            valids.push(group.is_some());
+            for (x, y) in group.unwrap_or_default() {
+                xs.push(x);
+                ys.push(y);
+            }
+            offsets.push(xs.len() as i32);
+        }
+
+        let xs: ArrayRef = Arc::new(Float64Array::from(xs));
+        let ys: ArrayRef = Arc::new(Float64Array::from(ys));
+        let columns = [xs, ys]
+            .iter()
+            .zip(self.bin_fields.iter())
+            .map(|(column, field)| cast(column, field.data_type()))
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+        let bins = StructArray::try_new(self.bin_fields.clone(), columns, None)?;
+        Ok(Arc::new(ListArray::try_new(
+            self.item_field.clone(),
+            OffsetBuffer::new(offsets.into()),
+            Arc::new(bins),
+            Some(NullBuffer::from(valids)),
+        )?))
+    }
+}
+
+impl Debug for AggHistogramNumeric {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "HistogramNumeric({:?}, {})", self.child, self.num_bins)
+    }
+}
+
+impl Agg for AggHistogramNumeric {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn exprs(&self) -> Vec<Arc<dyn PhysicalExpr>> {
+        vec![self.child.clone()]
+    }
+
+    fn with_new_exprs(&self, exprs: Vec<Arc<dyn PhysicalExpr>>) -> Result<Arc<dyn Agg>> {
+        Ok(Arc::new(Self::try_new(
+            exprs[0].clone(),
+            self.data_type.clone(),
+            self.num_bins,
+        )?))
+    }
+
+    fn data_type(&self) -> &DataType {
+        &self.data_type
+    }
+
+    fn nullable(&self) -> bool {
+        true
+    }
+
+    fn accums_initial(&self) -> &[AccumInitialValue] {
+        &self.accum_initial
+    }
+
+    fn increase_acc_mem_used(&self, acc: &mut RefAccumStateRow) {
+        if let Some(v) = acc.dyn_value(self.accum_state_val_addr) {
+            self.add_mem_used(v.mem_size());
+        }
+    }
+
+    fn decrease_acc_mem_used(&self, acc: &mut RefAccumStateRow) {
+        if let Some(v) = acc.dyn_value(self.accum_state_val_addr) {
+            self.sub_mem_used(v.mem_size());
+        }
+    }
+
+    fn partial_update(
+        &self,
+        acc: &mut RefAccumStateRow,
+        values: &[ArrayRef],
+        row_idx: usize,
+    ) -> Result<()> {
+        if values[0].is_valid(row_idx) {
+            let value = ScalarValue::try_from_array(&values[0], row_idx)?;
+            if let ScalarValue::Float64(Some(x)) = value.cast_to(&DataType::Float64)? {
+                let histogram = self.histogram_mut(acc)?;
+                self.sub_mem_used(histogram.mem_size());
+                histogram.add(x, self.num_bins);
+                self.add_mem_used(histogram.mem_size());
+            }
+        }
+        Ok(())
+    }
+
+    fn partial_batch_update(
+        &self,
+        accs: &mut [RefAccumStateRow],
+        values: &[ArrayRef],
+    ) -> Result<()> {
+        let values = cast(&values[0], &DataType::Float64)?;
+        for (acc, x) in accs.iter_mut().zip(values.as_primitive::<Float64Type>()) {
+            if let Some(x) = x {
+                let histogram = self.histogram_mut(acc)?;
+                self.sub_mem_used(histogram.mem_size());
+                histogram.add(x, self.num_bins);
+                self.add_mem_used(histogram.mem_size());
+            }
+        }
+        Ok(())
+    }
+
+    fn partial_update_all(&self, acc: &mut RefAccumStateRow, values: &[ArrayRef]) -> Result<()> {
+        let values = cast(&values[0], &DataType::Float64)?;
+        let histogram = self.histogram_mut(acc)?;
+        self.sub_mem_used(histogram.mem_size());
+        for x in values.as_primitive::<Float64Type>().iter().flatten() {
+            histogram.add(x, self.num_bins);
+        }
+        self.add_mem_used(histogram.mem_size());
+        Ok(())
+    }
+
+    fn partial_merge(
+        &self,
+        acc: &mut RefAccumStateRow,
+        merging_acc: &mut RefAccumStateRow,
+    ) -> Result<()> {
+        match (
+            acc.dyn_value_mut(self.accum_state_val_addr),
+            merging_acc.dyn_value_mut(self.accum_state_val_addr),
+        ) {
+            (Some(w), Some(v)) => {
+                let w = downcast_any!(w, mut AggDynHistogram)?;
+                let v = downcast_any!(v, mut AggDynHistogram)?;
+                self.sub_mem_used(w.mem_size());
+                self.sub_mem_used(v.mem_size());
+                w.merge(v, self.num_bins);
+                self.add_mem_used(w.mem_size());
+            }
+            (w_none, v @ Some(_)) => *w_none = std::mem::take(v),
+            (None, _) => {}
+            (_, None) => {}
+        }
+        Ok(())
+    }
+
+    fn shrink_acc_to_fit(&self, acc: &mut RefAccumStateRow) {
+        if let Some(v) = acc.dyn_value_mut(self.accum_state_val_addr) {
+            self.sub_mem_used(v.mem_size());
+            v.shrink_to_fit();
+            self.add_mem_used(v.mem_size());
+        }
+    }
+
+    fn final_merge(&self, acc: &mut RefAccumStateRow) -> Result<ScalarValue> {
+        let bins_array = self.build_bins_array(vec![self.take_bins(acc)?])?;
+        ScalarValue::try_from_array(&bins_array, 0)
+    }
+
+    fn final_batch_merge(&self, accs: &mut [RefAccumStateRow]) -> Result<ArrayRef> {
+        let groups = accs
+            .iter_mut()
+            .map(|acc| self.take_bins(acc))
+            .collect::<Result<Vec<_>>>()?;
+        self.build_bins_array(groups)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{io::Cursor, sync::Arc};
+
+    use arrow::{array::*, datatypes::*};
+    use datafusion::{common::Result, physical_expr::expressions::Column};
+
+    use crate::agg::{
+        acc::{
+            create_acc_from_initial_value, create_dyn_loaders_from_initial_value,
+            create_dyn_savers_from_initial_value, AccumStateRow, LoadReader, SaveWriter,
+        },
+        histogram_numeric::AggHistogramNumeric,
+        Agg, WithAggBufAddrs,
+    };
+
+    fn create_agg(num_bins: usize) -> Result<AggHistogramNumeric> {
+        let bin_fields = Fields::from(vec![
+            Field::new("x", DataType::Float64, true),
+            Field::new("y", DataType::Float64, true),
+        ]);
+        AggHistogramNumeric::try_new(
+            Arc::new(Column::new("a", 0)),
+            DataType::new_list(DataType::Struct(bin_fields), true),
+            num_bins,
+        )
+    }
+
+    fn collect_bins(array: &ArrayRef, idx: usize) -> Option<Vec<(f64, f64)>> {
+        let list = array.as_list::<i32>();
+        if list.is_null(idx) {
+            return None;
+        }
+        let bins = list.value(idx);
+        let bins = bins.as_struct();
+        let xs = bins.column(0).as_primitive::<Float64Type>();
+        let ys = bins.column(1).as_primitive::<Float64Type>();
+        Some(
+            xs.values()
+                .iter()
+                .copied()
+                .zip(ys.values().iter().copied())
+                .collect(),
+        )
+    }
+
+    /// straightforward implementation of the streaming histogram algorithm
+    struct ReferenceHistogram {
+        bins: Vec<(f64, f64)>,
+        num_bins: usize,
+    }
+
+    impl ReferenceHistogram {
+        fn add(&mut self, x: f64) {
+            match self.bins.iter_mut().find(|bin| bin.0 == x) {
+                Some(bin) => bin.1 += 1.0,
+                None => {
+                    self.bins.push((x, 1.0));
+                    self.trim();
+                }
+            }
+        }
+
+        fn merge(&mut self, other: ReferenceHistogram) {
+            self.bins.extend(other.bins);
+            self.trim();
+        }
+
+        fn trim(&mut self) {
+            self.bins.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+            while self.bins.len() > self.num_bins {
+                let mut closest = 0;
+                for i in 1..self.bins.len() - 1 {
+                    let d = self.bins[i + 1].0 - self.bins[i].0;
+                    if d < self.bins[closest + 1].0 - self.bins[closest].0 {
+                        closest = i;
+                    }
+                }
+                let (x1, y1) = self.bins[closest];
+                let (x2, y2) = self.bins.remove(closest + 1);
+                self.bins[closest] = ((x1 * y1 + x2 * y2) / (y1 + y2), y1 + y2);
+            }
+        }
+    }
+
+    #[test]
+    fn test_histogram_numeric_exact() -> Result<()> {
+        let mut agg = create_agg(5)?;
+        let (initial_acc, addrs) = create_acc_from_initial_value(agg.accums_initial())?;
+        agg.set_accum_state_val_addrs(&addrs);
+
+        let values: ArrayRef = Arc::new(Int32Array::from(vec![
+            Some(3),
+            None,
+            Some(1),
+            Some(3),
+            Some(2),
+            None,
+            Some(3),
+            Some(1),
+        ]));
+        let mut accs = vec![initial_acc.clone(); 2];
+        agg.partial_update_all(&mut accs[0].as_mut(), &[values.clone()])?;
+        agg.partial_update_all(&mut accs[1].as_mut(), &[values.slice(1, 1)])?;
+
+        let mut ref_accs = accs.iter_mut().map(|acc| acc.as_mut()).collect::<Vec<_>>();
+        let output = agg.final_batch_merge(&mut ref_accs)?;
+        assert_eq!(
+            collect_bins(&output, 0),
+            Some(vec![(1.0, 2.0), (2.0, 1.0), (3.0, 3.0)]),
+        );
+        assert_eq!(collect_bins(&output, 1), None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_histogram_numeric_with_reference() -> Result<()> {
+        let num_bins = 10;
+        let mut agg = create_agg(num_bins)?;
+        let (initial_acc, addrs) = create_acc_from_initial_value(agg.accums_initial())?;
+        agg.set_accum_state_val_addrs(&addrs);
+
+        // xorshift random generator
+        let mut seed = 0x2545F4914F6CDD1Du64;
+        let mut rand = move || {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            seed
+        };
+        let values = (0..4000)
+            .map(|i| (rand() % 1000) as f64 / 10.0 + (i % 7) as f64 * 30.0)
+            .collect::<Vec<_>>();
+
+        // update partitions of values and merge them
+        let mut expected = ReferenceHistogram {
+            bins: vec![],
+            num_bins,
+        };
+        let mut acc = initial_acc.clone();
+        for chunk in values.chunks(1000) {
+            let mut reference = ReferenceHistogram {
+                bins: vec![],
+                num_bins,
+            };
+            chunk.iter().for_each(|&x| reference.add(x));
+            expected.merge(reference);
+
+            let mut partial_acc = initial_acc.clone();
+            let chunk: ArrayRef = Arc::new(Float64Array::from(chunk.to_vec()));
+            for row_idx in 0..chunk.len() {
+                agg.partial_update(&mut partial_acc.as_mut(), &[chunk.clone()], row_idx)?;
+            }
+            agg.partial_merge(&mut acc.as_mut(), &mut partial_acc.as_mut())?;
+        }
+
+        // round-trip through spill
+        let mut buf = vec![];
+        let savers = create_dyn_savers_from_initial_value(agg.accums_initial())?;
+        let loaders = create_dyn_loaders_from_initial_value(agg.accums_initial())?;
+        let mut save_writer = SaveWriter(Box::new(Cursor::new(&mut buf)));
+        savers[0](
+            &mut save_writer,
+            std::mem::take(acc.as_mut().dyn_value_mut(addrs[0])),
+        )?;
+        drop(save_writer);
+        let mut load_reader = LoadReader(Box::new(Cursor::new(&buf)));
+        let mut loaded_acc = initial_acc.clone();
+        *loaded_acc.as_mut().dyn_value_mut(addrs[0]) = loaders[0](&mut load_reader)?;
+
+        let mut ref_accs = vec![loaded_acc.as_mut()];
+        let output = agg.final_batch_merge(&mut ref_accs)?;
+        let bins = collect_bins(&output, 0).expect("non-empty histogram");
+        assert_eq!(bins.len(), num_bins);
+        assert_eq!(bins.len(), expected.bins.len());
+        for (&(x, y), &(expected_x, expected_y)) in bins.iter().zip(&expected.bins) {
+            assert!((x - expected_x).abs() < 1e-9, "{x} vs {expected_x}");
+            assert!((y - expected_y).abs() < 1e-9, "{y} vs {expected_y}");
+        }
+
+        // total count and weighted sum are kept while coalescing bins
+        let count: f64 = bins.iter().map(|&(_, y)| y).sum();
+        let sum: f64 = bins.iter().map(|&(x, y)| x * y).sum();
+        assert_eq!(count, values.len() as f64);
+        assert!((sum - values.iter().sum::<f64>()).abs() < 1e-6);
+        Ok(())
+    }
+}
@@ -0,0 +1,275 @@
+// Copyright 2022 The Blaze Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::{
+    any::Any,
+    fmt::{Debug, Formatter},
+    sync::{atomic::AtomicUsize, Arc},
+};
+
+use arrow::{array::*, datatypes::*};
+use datafusion::{
+    common::{Result, ScalarValue},
+    physical_expr::PhysicalExpr,
+};
+
+use crate::agg::{
+    acc::{AccumInitialValue, AccumStateRow, AccumStateValAddr, RefAccumStateRow},
+    Agg, WithAggBufAddrs, WithMemTracking,
+};
+
+/// sum of float64 values with Neumaier's compensated summation, low-order bits
+/// lost in adding values of different magnitudes are accumulated separately.
+/// the compensation is kept in accumulators so partial results are merged
+/// without losing precision
+pub struct AggKahanSum {
+    child: Arc<dyn PhysicalExpr>,
+    data_type: DataType,
+    accums_initial: [AccumInitialValue; 2],
+    accum_state_val_addrs: [AccumStateValAddr; 2],
+    mem_used_tracker: AtomicUsize,
+}
+
+impl WithAggBufAddrs for AggKahanSum {
+    fn set_accum_state_val_addrs(&mut self, accum_state_val_addrs: &[AccumStateValAddr]) {
+        self.accum_state_val_addrs
+            .copy_from_slice(&accum_state_val_addrs[..2]);
+    }
+}
+
+impl WithMemTracking for AggKahanSum {
+    fn mem_used_tracker(&self) -> &AtomicUsize {
+        &self.mem_used_tracker
+    }
+}
+
+impl AggKahanSum {
+    pub fn try_new(child: Arc<dyn PhysicalExpr>) -> Result<Self> {
+        Ok(Self {
+            child,
+            data_type: DataType::Float64,
+            accums_initial: [
+                AccumInitialValue::Scalar(ScalarValue::Float64(None)),
+                AccumInitialValue::Scalar(ScalarValue::Float64(Some(0.0))),
+            ],
+            accum_state_val_addrs: [AccumStateValAddr::default(); 2],
+            mem_used_tracker: AtomicUsize::new(0),
+        })
+    }
+
+    fn compensated_sum(&self, acc: &RefAccumStateRow) -> Option<CompensatedSum> {
+        let [sum_addr, compensation_addr] = self.accum_state_val_addrs;
+        acc.is_fixed_valid(sum_addr).then(|| CompensatedSum {
+            sum: acc.fixed_value::<f64>(sum_addr),
+            compensation: acc.fixed_value::<f64>(compensation_addr),
+        })
+    }
+
+    fn set_compensated_sum(&self, acc: &mut RefAccumStateRow, sum: CompensatedSum) {
+        let [sum_addr, compensation_addr] = self.accum_state_val_addrs;
+        acc.set_fixed_value::<f64>(sum_addr, sum.sum);
+        acc.set_fixed_valid(sum_addr, true);
+        acc.set_fixed_value::<f64>(compensation_addr, sum.compensation);
+    }
+}
+
+#[derive(Clone, Copy, Default)]
+struct CompensatedSum {
+    sum: f64,
+    compensation: f64,
+}
+
+impl CompensatedSum {
+    fn add(&mut self, value: f64) {
+        let t = self.sum + value;
+        if self.sum.abs() >= value.abs() {
+            self.compensation += (self.sum - t) + value;
+        } else {
+            self.compensation += (value - t) + self.sum;
+        }
+        self.sum = t;
+    }
+
+    fn merge(&mut self, other: CompensatedSum) {
+        self.add(other.sum);
+        self.compensation += other.compensation;
+    }
+
+    fn result(&self) -> f64 {
+        self.sum + self.compensation
+    }
+}
+
+impl Debug for AggKahanSum {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "KahanSum({:?})", self.child)
+    }
+}
+
+impl Agg for AggKahanSum {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn exprs(&self) -> Vec<Arc<dyn PhysicalExpr>> {
+        vec![self.child.clone()]
+    }
+
+    fn with_new_exprs(&self, exprs: Vec<Arc<dyn PhysicalExpr>>) -> Result<Arc<dyn Agg>> {
+        Ok(Arc::new(Self::try_new(exprs[0].clone())?))
+    }
+
+    fn data_type(&self) -> &DataType {
+        &self.data_type
+    }
+
+    fn nullable(&self) -> bool {
+        true
+    }
+
+    fn accums_initial(&self) -> &[AccumInitialValue] {
+        &self.accums_initial
+    }
+
+    fn prepare_partial_args(&self, partial_inputs: &[ArrayRef]) -> Result<Vec<ArrayRef>> {
+        // cast arg1 to float64
+        Ok(vec![datafusion_ext_commons::cast::cast(
+            &partial_inputs[0],
+            &DataType::Float64,
+        )?])
+    }
+
+    fn increase_acc_mem_used(&self, _acc: &mut RefAccumStateRow) {
+        // do nothing
+    }
+
+    fn decrease_acc_mem_used(&self, _acc: &mut RefAccumStateRow) {
+        // do nothing
+    }
+
+    fn partial_update(
+        &self,
+        acc: &mut RefAccumStateRow,
+        values: &[ArrayRef],
+        row_idx: usize,
+    ) -> Result<()> {
+        let values = values[0].as_primitive::<Float64Type>();
+        if values.is_valid(row_idx) {
+            let mut sum = self.compensated_sum(acc).unwrap_or_default();
+            sum.add(values.value(row_idx));
+            self.set_compensated_sum(acc, sum);
+        }
+        Ok(())
+    }
+
+    fn partial_update_all(&self, acc: &mut RefAccumStateRow, values: &[ArrayRef]) -> Result<()> {
+        let values = values[0].as_primitive::<Float64Type>();
+        if values.null_count() < values.len() {
+            let mut sum = self.compensated_sum(acc).unwrap_or_default();
+            for value in values.iter().flatten() {
+                sum.add(value);
+            }
+            self.set_compensated_sum(acc, sum);
+        }
+        Ok(())
+    }
+
+    fn partial_merge(
+        &self,
+        acc: &mut RefAccumStateRow,
+        merging_acc: &mut RefAccumStateRow,
+    ) -> Result<()> {
+        if let Some(merging_sum) = self.compensated_sum(merging_acc) {
+            let mut sum = self.compensated_sum(acc).unwrap_or_default();
+            sum.merge(merging_sum);
+            self.set_compensated_sum(acc, sum);
+        }
+        Ok(())
+    }
+
+    fn final_merge(&self, acc: &mut RefAccumStateRow) -> Result<ScalarValue> {
+        Ok(ScalarValue::Float64(
+            self.compensated_sum(acc).map(|sum| sum.result()),
+        ))
+    }
+
+    fn final_batch_merge(&self, accs: &mut [RefAccumStateRow]) -> Result<ArrayRef> {
+        Ok(Arc::new(
+            accs.iter()
+                .map(|acc| self.compensated_sum(acc).map(|sum| sum.result()))
+                .collect::<Float64Array>(),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use arrow::{array::*, datatypes::*};
+    use datafusion::{common::Result, physical_expr::expressions::Column};
+
+    use crate::agg::{
+        acc::create_acc_from_initial_value, kahan_sum::AggKahanSum, Agg, WithAggBufAddrs,
+    };
+
+    #[test]
+    fn test_kahan_sum() -> Result<()> {
+        let mut agg = AggKahanSum::try_new(Arc::new(Column::new("a", 0)))?;
+        let (initial_acc, addrs) = create_acc_from_initial_value(agg.accums_initial())?;
+        agg.set_accum_state_val_addrs(&addrs);
+
+        // a large value followed by many tiny values, each tiny value is lost
+        // in naive summation. the exact sum is 10000
+        let mut values = vec![Some(1e16)];
+        values.extend(std::iter::repeat(Some(1.0)).take(10000));
+        values.push(None);
+        values.push(Some(-1e16));
+        let values: ArrayRef = Arc::new(Float64Array::from(values));
+        let naive_sum = values
+            .as_primitive::<Float64Type>()
+            .iter()
+            .flatten()
+            .fold(0.0, |sum, v| sum + v);
+        assert_eq!(naive_sum, 0.0);
+
+        // group 0: updated in three partials and merged
+        // group 1: updated row by row, the exact sum is 2
+        // group 2: all nulls
+        let mut accs = vec![initial_acc.clone(); 3];
+        let mut merging_acc1 = initial_acc.clone();
+        let mut merging_acc2 = initial_acc.clone();
+        agg.partial_update_all(&mut accs[0].as_mut(), &[values.slice(0, 3000)])?;
+        agg.partial_update_all(&mut merging_acc1.as_mut(), &[values.slice(3000, 5000)])?;
+        agg.partial_update_all(&mut merging_acc2.as_mut(), &[values.slice(8000, 2003)])?;
+        agg.partial_merge(&mut merging_acc1.as_mut(), &mut merging_acc2.as_mut())?;
+        agg.partial_merge(&mut accs[0].as_mut(), &mut merging_acc1.as_mut())?;
+
+        let values2: ArrayRef = Arc::new(Float64Array::from(vec![1.0, 1e100, 1.0, -1e100]));
+        for row_idx in 0..values2.len() {
+            agg.partial_update(&mut accs[1].as_mut(), &[values2.clone()], row_idx)?;
+        }
+
+        let values3: ArrayRef = Arc::new(Float64Array::from(vec![None, None]));
+        agg.partial_update_all(&mut accs[2].as_mut(), &[values3])?;
+
+        let mut ref_accs = accs.iter_mut().map(|acc| acc.as_mut()).collect::<Vec<_>>();
+        assert_eq!(
+            agg.final_batch_merge(&mut ref_accs)?
+                .as_primitive::<Float64Type>(),
+            &Float64Array::from(vec![Some(10000.0), Some(2.0), None]),
+        );
+        Ok(())
+    }
+}
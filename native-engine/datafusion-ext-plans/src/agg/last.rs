@@ -0,0 +1,448 @@
+// Copyright 2022 The Blaze Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::{
+    any::Any,
+    fmt::{Debug, Formatter},
+    sync::{atomic::AtomicUsize, Arc},
+};
+
+use arrow::{array::*, datatypes::*};
+use datafusion::{
+    common::{Result, ScalarValue},
+    physical_expr::PhysicalExpr,
+};
+use datafusion_ext_commons::downcast_any;
+use paste::paste;
+
+use crate::agg::{
+    acc::{
+        AccumInitialValue, AccumStateRow, AccumStateValAddr, AggDynBinary, AggDynScalar, AggDynStr,
+        AggDynValue, RefAccumStateRow,
+    },
+    default_final_batch_merge_with_addr, default_final_merge_with_addr, Agg, WithAggBufAddrs,
+    WithMemTracking,
+};
+
+/// last(x) and last(x, ignoreNulls), merging accumulators must be in arrival
+/// order, the later one wins
+pub struct AggLast {
+    child: Arc<dyn PhysicalExpr>,
+    data_type: DataType,
+    ignores_null: bool,
+    accums_initial: Vec<AccumInitialValue>,
+    accum_state_val_addr_value: AccumStateValAddr,
+    accum_state_val_addr_valid: AccumStateValAddr,
+    partial_updater: fn(&Self, &mut RefAccumStateRow, &ArrayRef, usize) -> Result<()>,
+    partial_buf_merger: fn(&Self, &mut RefAccumStateRow, &mut RefAccumStateRow),
+    mem_used_tracker: AtomicUsize,
+}
+
+impl WithAggBufAddrs for AggLast {
+    fn set_accum_state_val_addrs(&mut self, accum_state_val_addrs: &[AccumStateValAddr]) {
+        self.accum_state_val_addr_value = accum_state_val_addrs[0];
+        self.accum_state_val_addr_valid = accum_state_val_addrs[1];
+    }
+}
+
+impl WithMemTracking for AggLast {
+    fn mem_used_tracker(&self) -> &AtomicUsize {
+        &self.mem_used_tracker
+    }
+}
+
+impl AggLast {
+    pub fn try_new(
+        child: Arc<dyn PhysicalExpr>,
+        data_type: DataType,
+        ignores_null: bool,
+    ) -> Result<Self> {
+        let accums_initial = vec![
+            AccumInitialValue::Scalar(ScalarValue::try_from(&data_type)?),
+            AccumInitialValue::Scalar(ScalarValue::Null), // touched
+        ];
+        let partial_updater = get_partial_updater(&data_type)?;
+        let partial_buf_merger = get_partial_buf_merger(&data_type)?;
+        Ok(Self {
+            child,
+            data_type,
+            ignores_null,
+            accums_initial,
+            accum_state_val_addr_value: AccumStateValAddr::default(),
+            accum_state_val_addr_valid: AccumStateValAddr::default(),
+            partial_updater,
+            partial_buf_merger,
+            mem_used_tracker: AtomicUsize::new(0),
+        })
+    }
+
+    pub fn ignores_null(&self) -> bool {
+        self.ignores_null
+    }
+
+    fn is_touched(&self, acc: &RefAccumStateRow) -> bool {
+        acc.is_fixed_valid(self.accum_state_val_addr_valid)
+    }
+
+    fn set_touched(&self, acc: &mut RefAccumStateRow) {
+        acc.set_fixed_valid(self.accum_state_val_addr_valid, true)
+    }
+}
+
+impl Debug for AggLast {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        if self.ignores_null {
+            write!(f, "LastIgnoresNull({:?})", self.child)
+        } else {
+            write!(f, "Last({:?})", self.child)
+        }
+    }
+}
+
+impl Agg for AggLast {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn exprs(&self) -> Vec<Arc<dyn PhysicalExpr>> {
+        vec![self.child.clone()]
+    }
+
+    fn with_new_exprs(&self, exprs: Vec<Arc<dyn PhysicalExpr>>) -> Result<Arc<dyn Agg>> {
+        Ok(Arc::new(Self::try_new(
+            exprs[0].clone(),
+            self.data_type.clone(),
+            self.ignores_null,
+        )?))
+    }
+
+    fn data_type(&self) -> &DataType {
+        &self.data_type
+    }
+
+    fn nullable(&self) -> bool {
+        true
+    }
+
+    fn accums_initial(&self) -> &[AccumInitialValue] {
+        &self.accums_initial
+    }
+
+    fn increase_acc_mem_used(&self, acc: &mut RefAccumStateRow) {
+        if self.data_type.is_primitive()
+            || matches!(self.data_type, DataType::Null | DataType::Boolean)
+        {
+            return;
+        }
+        if let Some(v) = acc.dyn_value(self.accum_state_val_addr_value) {
+            self.add_mem_used(v.mem_size());
+        }
+    }
+
+    fn decrease_acc_mem_used(&self, acc: &mut RefAccumStateRow) {
+        if self.data_type.is_primitive()
+            || matches!(self.data_type, DataType::Null | DataType::Boolean)
+        {
+            return;
+        }
+        if let Some(v) = acc.dyn_value(self.accum_state_val_addr_value) {
+            self.sub_mem_used(v.mem_size());
+        }
+    }
+
+    fn partial_update(
+        &self,
+        acc: &mut RefAccumStateRow,
+        values: &[ArrayRef],
+        row_idx: usize,
+    ) -> Result<()> {
+        if !self.ignores_null || values[0].is_valid(row_idx) {
+            let partial_updater = self.partial_updater;
+            partial_updater(self, acc, &values[0], row_idx)?;
+        }
+        Ok(())
+    }
+
+    fn partial_update_all(&self, acc: &mut RefAccumStateRow, values: &[ArrayRef]) -> Result<()> {
+        let value = &values[0];
+        let last_idx = if self.ignores_null {
+            (0..value.len()).rev().find(|&i| value.is_valid(i))
+        } else {
+            value.len().checked_sub(1)
+        };
+        if let Some(i) = last_idx {
+            let partial_updater = self.partial_updater;
+            partial_updater(self, acc, value, i)?;
+        }
+        Ok(())
+    }
+
+    fn partial_merge(
+        &self,
+        acc1: &mut RefAccumStateRow,
+        acc2: &mut RefAccumStateRow,
+    ) -> Result<()> {
+        let partial_buf_merger = self.partial_buf_merger;
+        partial_buf_merger(self, acc1, acc2);
+        Ok(())
+    }
+
+    fn final_merge(&self, acc: &mut RefAccumStateRow) -> Result<ScalarValue> {
+        default_final_merge_with_addr(self, acc, self.accum_state_val_addr_value)
+    }
+
+    fn final_batch_merge(&self, accs: &mut [RefAccumStateRow]) -> Result<ArrayRef> {
+        default_final_batch_merge_with_addr(self, accs, self.accum_state_val_addr_value)
+    }
+}
+
+fn get_partial_updater(
+    dt: &DataType,
+) -> Result<fn(&AggLast, &mut RefAccumStateRow, &ArrayRef, usize) -> Result<()>> {
+    // overwrites the current value, including null
+
+    macro_rules! fn_fixed {
+        ($ty:ident) => {{
+            Ok(|this, acc, v, i| {
+                type TArray = paste! {[<$ty Array>]};
+                if v.is_valid(i) {
+                    let value = v.as_any().downcast_ref::<TArray>().unwrap();
+                    acc.set_fixed_value(this.accum_state_val_addr_value, value.value(i));
+                    acc.set_fixed_valid(this.accum_state_val_addr_value, true);
+                } else {
+                    acc.set_fixed_valid(this.accum_state_val_addr_value, false);
+                }
+                this.set_touched(acc);
+                Ok(())
+            })
+        }};
+    }
+    macro_rules! fn_dyn {
+        ($new:expr) => {{
+            Ok(
+                |this: &AggLast, acc: &mut RefAccumStateRow, v: &ArrayRef, i: usize| {
+                    let w = acc.dyn_value_mut(this.accum_state_val_addr_value);
+                    if let Some(old) = w {
+                        this.sub_mem_used(old.mem_size());
+                    }
+                    *w = if v.is_valid(i) {
+                        let new = $new(v, i)?;
+                        this.add_mem_used(new.mem_size());
+                        Some(Box::new(new))
+                    } else {
+                        None
+                    };
+                    this.set_touched(acc);
+                    Ok(())
+                },
+            )
+        }};
+    }
+    match dt {
+        DataType::Null => Ok(|this, acc, _, _| {
+            this.set_touched(acc);
+            Ok(())
+        }),
+        DataType::Boolean => fn_fixed!(Boolean),
+        DataType::Float32 => fn_fixed!(Float32),
+        DataType::Float64 => fn_fixed!(Float64),
+        DataType::Int8 => fn_fixed!(Int8),
+        DataType::Int16 => fn_fixed!(Int16),
+        DataType::Int32 => fn_fixed!(Int32),
+        DataType::Int64 => fn_fixed!(Int64),
+        DataType::UInt8 => fn_fixed!(UInt8),
+        DataType::UInt16 => fn_fixed!(UInt16),
+        DataType::UInt32 => fn_fixed!(UInt32),
+        DataType::UInt64 => fn_fixed!(UInt64),
+        DataType::Date32 => fn_fixed!(Date32),
+        DataType::Date64 => fn_fixed!(Date64),
+        DataType::Timestamp(TimeUnit::Second, _) => fn_fixed!(TimestampSecond),
+        DataType::Timestamp(TimeUnit::Millisecond, _) => fn_fixed!(TimestampMillisecond),
+        DataType::Timestamp(TimeUnit::Microsecond, _) => fn_fixed!(TimestampMicrosecond),
+        DataType::Timestamp(TimeUnit::Nanosecond, _) => fn_fixed!(TimestampNanosecond),
+        DataType::Decimal128(..) => fn_fixed!(Decimal128),
+        DataType::Utf8 => fn_dyn!(|v: &ArrayRef, i| -> Result<_> {
+            Ok(AggDynStr::from_str(downcast_any!(v, StringArray)?.value(i)))
+        }),
+        DataType::Binary => fn_dyn!(|v: &ArrayRef, i| -> Result<_> {
+            Ok(AggDynBinary::from_slice(
+                downcast_any!(v, BinaryArray)?.value(i),
+            ))
+        }),
+        _other => fn_dyn!(|v: &ArrayRef, i| -> Result<_> {
+            Ok(AggDynScalar::new(ScalarValue::try_from_array(v, i)?))
+        }),
+    }
+}
+
+fn get_partial_buf_merger(
+    dt: &DataType,
+) -> Result<fn(&AggLast, &mut RefAccumStateRow, &mut RefAccumStateRow)> {
+    // acc2 arrives later, so it overwrites acc1 if touched
+
+    macro_rules! fn_fixed {
+        ($ty:ty) => {{
+            Ok(|this, acc1, acc2| {
+                if this.is_touched(acc2) {
+                    let addr = this.accum_state_val_addr_value;
+                    if acc2.is_fixed_valid(addr) {
+                        acc1.set_fixed_value(addr, acc2.fixed_value::<$ty>(addr));
+                        acc1.set_fixed_valid(addr, true);
+                    } else {
+                        acc1.set_fixed_valid(addr, false);
+                    }
+                    this.set_touched(acc1);
+                }
+            })
+        }};
+    }
+    match dt {
+        DataType::Null => Ok(|this, acc1, acc2| {
+            if this.is_touched(acc2) {
+                this.set_touched(acc1);
+            }
+        }),
+        DataType::Boolean => fn_fixed!(bool),
+        DataType::Float32 => fn_fixed!(f32),
+        DataType::Float64 => fn_fixed!(f64),
+        DataType::Int8 => fn_fixed!(i8),
+        DataType::Int16 => fn_fixed!(i16),
+        DataType::Int32 => fn_fixed!(i32),
+        DataType::Int64 => fn_fixed!(i64),
+        DataType::UInt8 => fn_fixed!(u8),
+        DataType::UInt16 => fn_fixed!(u16),
+        DataType::UInt32 => fn_fixed!(u32),
+        DataType::UInt64 => fn_fixed!(u64),
+        DataType::Date32 => fn_fixed!(i32),
+        DataType::Date64 => fn_fixed!(i64),
+        DataType::Timestamp(..) => fn_fixed!(i64),
+        DataType::Decimal128(..) => fn_fixed!(i128),
+        _other => Ok(|this, acc1, acc2| {
+            if this.is_touched(acc2) {
+                let addr = this.accum_state_val_addr_value;
+                let w = acc1.dyn_value_mut(addr);
+                if let Some(old) = w {
+                    this.sub_mem_used(old.mem_size()); // old will be dropped
+                }
+                *w = std::mem::take(acc2.dyn_value_mut(addr));
+                this.set_touched(acc1);
+            }
+        }),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use arrow::{array::*, datatypes::DataType};
+    use datafusion::{
+        common::{Result, ScalarValue},
+        physical_expr::expressions::Column,
+    };
+
+    use crate::agg::{acc::create_acc_from_initial_value, last::AggLast, Agg, WithAggBufAddrs};
+
+    #[test]
+    fn test_last() -> Result<()> {
+        let values: ArrayRef = Arc::new(StringArray::from(vec![
+            Some("Panda"),
+            Some("Tiger"),
+            None,
+            Some("Lion"),
+            None,
+        ]));
+        for (ignores_null, expected) in [(false, None), (true, Some("Lion"))] {
+            let mut agg =
+                AggLast::try_new(Arc::new(Column::new("a", 0)), DataType::Utf8, ignores_null)?;
+            let (initial_acc, addrs) = create_acc_from_initial_value(agg.accums_initial())?;
+            agg.set_accum_state_val_addrs(&addrs);
+
+            // update row by row
+            let mut acc = initial_acc.clone();
+            for i in 0..values.len() {
+                agg.partial_update(&mut acc.as_mut(), &[values.clone()], i)?;
+            }
+            assert_eq!(
+                agg.final_merge(&mut acc.as_mut())?,
+                ScalarValue::from(expected)
+            );
+
+            // update all
+            let mut acc = initial_acc.clone();
+            agg.partial_update_all(&mut acc.as_mut(), &[values.clone()])?;
+            assert_eq!(
+                agg.final_merge(&mut acc.as_mut())?,
+                ScalarValue::from(expected)
+            );
+
+            // merge, the later one wins if touched
+            let mut acc1 = initial_acc.clone();
+            let mut acc2 = initial_acc.clone();
+            let mut acc3 = initial_acc.clone();
+            agg.partial_update_all(&mut acc1.as_mut(), &[values.slice(0, 4)])?;
+            agg.partial_update_all(&mut acc2.as_mut(), &[values.slice(4, 1)])?;
+            agg.partial_merge(&mut acc1.as_mut(), &mut acc3.as_mut())?;
+            agg.partial_merge(&mut acc1.as_mut(), &mut acc2.as_mut())?;
+            assert_eq!(
+                agg.final_merge(&mut acc1.as_mut())?,
+                ScalarValue::from(expected)
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_last_primitive() -> Result<()> {
+        let values: ArrayRef = Arc::new(Int32Array::from(vec![Some(1), None, Some(3), None]));
+        for (ignores_null, expected) in [(false, None), (true, Some(3))] {
+            let mut agg =
+                AggLast::try_new(Arc::new(Column::new("a", 0)), DataType::Int32, ignores_null)?;
+            let (initial_acc, addrs) = create_acc_from_initial_value(agg.accums_initial())?;
+            agg.set_accum_state_val_addrs(&addrs);
+
+            // update row by row, a trailing null overwrites the earlier value
+            let mut acc = initial_acc.clone();
+            for i in 0..values.len() {
+                agg.partial_update(&mut acc.as_mut(), &[values.clone()], i)?;
+            }
+            assert_eq!(
+                agg.final_merge(&mut acc.as_mut())?,
+                ScalarValue::Int32(expected)
+            );
+
+            // update all
+            let mut acc = initial_acc.clone();
+            agg.partial_update_all(&mut acc.as_mut(), &[values.clone()])?;
+            assert_eq!(
+                agg.final_merge(&mut acc.as_mut())?,
+                ScalarValue::Int32(expected)
+            );
+
+            // merge, a later null overwrites the earlier value if not ignored
+            let mut acc1 = initial_acc.clone();
+            let mut acc2 = initial_acc.clone();
+            let mut acc3 = initial_acc.clone();
+            agg.partial_update_all(&mut acc1.as_mut(), &[values.slice(0, 3)])?;
+            agg.partial_update_all(&mut acc2.as_mut(), &[values.slice(3, 1)])?;
+            agg.partial_merge(&mut acc1.as_mut(), &mut acc3.as_mut())?;
+            agg.partial_merge(&mut acc1.as_mut(), &mut acc2.as_mut())?;
+            assert_eq!(
+                agg.final_merge(&mut acc1.as_mut())?,
+                ScalarValue::Int32(expected)
+            );
+        }
+        Ok(())
+    }
+}
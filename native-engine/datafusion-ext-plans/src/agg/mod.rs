@@ -15,18 +15,31 @@
 pub mod acc;
 pub mod agg_context;
 pub mod agg_table;
+pub mod array_intersect;
 pub mod avg;
+pub mod bounded_collect_list;
+pub mod bounded_collect_set;
 pub mod brickhouse;
 pub mod collect_list;
 pub mod collect_set;
 pub mod count;
+pub mod count_min_sketch;
 pub mod first;
 pub mod first_ignores_null;
+pub mod histogram_numeric;
+pub mod kahan_sum;
+pub mod last;
 pub mod maxmin;
+pub mod percentile_approx;
 pub mod sum;
+pub mod sum_distinct;
+pub mod top_distinct;
+pub mod value_counts;
+pub mod variance;
 
 use std::{
     any::Any,
+    collections::HashSet,
     fmt::Debug,
     sync::{
         atomic::{AtomicUsize, Ordering::SeqCst},
@@ -38,9 +51,9 @@ use arrow::{array::*, datatypes::*};
 use datafusion::{
     common::{Result, ScalarValue},
     logical_expr::aggregate_function,
-    physical_expr::PhysicalExpr,
+    physical_expr::{expressions::Literal, PhysicalExpr},
 };
-use datafusion_ext_commons::df_execution_err;
+use datafusion_ext_commons::{df_execution_err, downcast_any};
 use datafusion_ext_exprs::cast::TryCastExpr;
 use slimmer_box::SlimmerBox;
 
@@ -81,17 +94,42 @@ impl AggMode {
 pub enum AggFunction {
     Count,
     Sum,
+    SumDistinct,
+    KahanSum,
     Avg,
     Max,
     Min,
     First,
     FirstIgnoresNull,
+    Last,
+    LastIgnoresNull,
     CollectList,
     CollectSet,
+    BoundedCollectList,
+    BoundedCollectSet,
+    TopDistinct,
+    ValueCounts,
+    HistogramNumeric,
+    PercentileApprox,
+    CountMinSketch,
+    VarSamp,
+    VarPop,
+    StddevSamp,
+    StddevPop,
+    ArrayAgg,
+    ArrayUnion,
+    ArrayIntersect,
     BrickhouseCollect,
     BrickhouseCombineUnique,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CollectKind {
+    Set,
+    SortedSet { descending: bool },
+    List,
+}
+
 #[derive(Debug, Clone)]
 pub struct GroupingExpr {
     pub field_name: String,
@@ -117,7 +155,9 @@ pub trait WithMemTracking {
     }
 
     fn add_mem_used(&self, mem_used: usize) {
-        self.mem_used_tracker().fetch_add(mem_used, SeqCst);
+        let _ = self
+            .mem_used_tracker()
+            .fetch_update(SeqCst, SeqCst, |v| Some(v.saturating_add(mem_used)));
     }
 
     fn sub_mem_used(&self, mem_used: usize) {
@@ -146,6 +186,11 @@ pub trait Agg: WithAggBufAddrs + WithMemTracking + Send + Sync + Debug {
 
     fn increase_acc_mem_used(&self, acc: &mut RefAccumStateRow);
 
+    /// stops tracking memory of dyn values in the accumulator, which are moved
+    /// out of the table (like spilled). the inverse of
+    /// `increase_acc_mem_used()`
+    fn decrease_acc_mem_used(&self, acc: &mut RefAccumStateRow);
+
     fn partial_update(
         &self,
         acc: &mut RefAccumStateRow,
@@ -185,10 +230,67 @@ pub trait Agg: WithAggBufAddrs + WithMemTracking + Send + Sync + Debug {
         Ok(())
     }
 
+    /// merges many partials of the same group, like calling `partial_merge()`
+    /// for each of them in order. aggregates with dyn values can override it
+    /// to merge all partials in one pass
+    fn partial_merge_many(
+        &self,
+        acc: &mut RefAccumStateRow,
+        merging_accs: &mut [RefAccumStateRow],
+    ) -> Result<()> {
+        for merging_acc in merging_accs {
+            self.partial_merge(acc, merging_acc)?;
+        }
+        Ok(())
+    }
+
+    /// releases over-allocated memory of dyn values in the accumulator
+    fn shrink_acc_to_fit(&self, _acc: &mut RefAccumStateRow) {}
+
     fn final_merge(&self, acc: &mut RefAccumStateRow) -> Result<ScalarValue>;
     fn final_batch_merge(&self, accs: &mut [RefAccumStateRow]) -> Result<ArrayRef>;
 }
 
+/// merges like `agg.partial_batch_merge()`, but `merging_accs` may alias rows
+/// of `accs` at any index, or earlier merging rows, e.g. rows got from
+/// `AccStore::get()` with the same address. aliased merging rows are only read
+/// to clone them before merging, so that implementations never access the
+/// same dyn values through two rows, and a row merged with itself gets the
+/// result of merging with a copy of itself
+pub fn partial_batch_merge_aliasing_safe(
+    agg: &dyn Agg,
+    accs: &mut [RefAccumStateRow],
+    merging_accs: &mut [RefAccumStateRow],
+) -> Result<()> {
+    let mut seen_ptrs = accs.iter().map(|acc| acc.as_ptrs()).collect::<HashSet<_>>();
+    let mut copies = merging_accs
+        .iter()
+        .map(|merging_acc| {
+            let is_aliased = !seen_ptrs.insert(merging_acc.as_ptrs());
+            is_aliased.then(|| merging_acc.to_owned_row())
+        })
+        .collect::<Vec<_>>();
+    if copies.iter().all(|copy| copy.is_none()) {
+        return agg.partial_batch_merge(accs, merging_accs);
+    }
+
+    let mut merging_accs = merging_accs
+        .iter_mut()
+        .zip(&mut copies)
+        .map(|(merging_acc, copy)| match copy {
+            Some(copy) => {
+                // copied dyn values are tracked, since merging releases the memory
+                // of merging rows
+                let mut copy = copy.as_mut();
+                agg.increase_acc_mem_used(&mut copy);
+                copy
+            }
+            None => merging_acc.reborrow(),
+        })
+        .collect::<Vec<_>>();
+    agg.partial_batch_merge(accs, &mut merging_accs)
+}
+
 pub fn create_agg(
     agg_function: AggFunction,
     children: &[Arc<dyn PhysicalExpr>],
@@ -210,6 +312,19 @@ pub fn create_agg(
                 return_type,
             )?)
         }
+        AggFunction::SumDistinct => {
+            let arg_type = children[0].data_type(input_schema)?;
+            let return_type = aggregate_function::AggregateFunction::return_type(
+                &aggregate_function::AggregateFunction::Sum,
+                &[arg_type.clone()],
+            )?;
+            Arc::new(sum_distinct::AggSumDistinct::try_new(
+                children[0].clone(),
+                return_type,
+                arg_type,
+            )?)
+        }
+        AggFunction::KahanSum => Arc::new(kahan_sum::AggKahanSum::try_new(children[0].clone())?),
         AggFunction::Avg => {
             let arg_type = children[0].data_type(input_schema)?;
             let return_type = aggregate_function::AggregateFunction::return_type(
@@ -240,24 +355,203 @@ pub fn create_agg(
                 dt,
             )?)
         }
+        AggFunction::Last => {
+            let dt = children[0].data_type(input_schema)?;
+            Arc::new(last::AggLast::try_new(children[0].clone(), dt, false)?)
+        }
+        AggFunction::LastIgnoresNull => {
+            let dt = children[0].data_type(input_schema)?;
+            Arc::new(last::AggLast::try_new(children[0].clone(), dt, true)?)
+        }
+        AggFunction::CollectList if children.len() > 1 => {
+            // collect_list(a, b, ...) is collected like collect_list(struct(a, b, ...))
+            let fields = children
+                .iter()
+                .enumerate()
+                .map(|(i, child)| {
+                    Ok(Field::new(
+                        format!("col{}", i + 1),
+                        child.data_type(input_schema)?,
+                        child.nullable(input_schema)?,
+                    ))
+                })
+                .collect::<Result<Vec<_>>>()?;
+            let arg_type = DataType::Struct(Fields::from(fields));
+            let return_type = DataType::new_list(arg_type.clone(), true);
+            Arc::new(collect_list::AggCollectList::try_new_with_multiple_args(
+                children.to_vec(),
+                return_type,
+                arg_type,
+            )?)
+        }
         AggFunction::CollectList => {
             let arg_type = children[0].data_type(input_schema)?;
             let return_type = DataType::new_list(arg_type.clone(), true);
-            Arc::new(collect_list::AggCollectList::try_new(
+            create_collect_agg(
+                CollectKind::List,
+                children[0].clone(),
+                arg_type,
+                return_type,
+            )?
+        }
+        AggFunction::CollectSet => {
+            let arg_type = children[0].data_type(input_schema)?;
+            let return_type = DataType::new_list(arg_type.clone(), true);
+            create_collect_agg(CollectKind::Set, children[0].clone(), arg_type, return_type)?
+        }
+        AggFunction::BoundedCollectList => {
+            let arg_type = children[0].data_type(input_schema)?;
+            let return_type = DataType::new_list(arg_type.clone(), true);
+            let limit = literal_usize_param(&children[1], "bounded_collect_list")?;
+            Arc::new(bounded_collect_list::AggBoundedCollectList::try_new(
                 children[0].clone(),
                 return_type,
                 arg_type,
+                limit,
+                false,
             )?)
         }
-        AggFunction::CollectSet => {
+        AggFunction::BoundedCollectSet => {
+            let arg_type = children[0].data_type(input_schema)?;
+            let list_type = DataType::new_list(arg_type.clone(), true);
+            let max_set_mem_size = literal_usize_param(&children[1], "bounded_collect_set")?;
+            Arc::new(bounded_collect_set::AggBoundedCollectSet::try_new(
+                children[0].clone(),
+                list_type,
+                arg_type,
+                max_set_mem_size,
+            )?)
+        }
+        AggFunction::TopDistinct => {
+            // top_distinct(x, n, descending)
             let arg_type = children[0].data_type(input_schema)?;
             let return_type = DataType::new_list(arg_type.clone(), true);
-            Arc::new(collect_set::AggCollectSet::try_new(
+            let limit = literal_usize_param(&children[1], "top_distinct")?;
+            let descending = match downcast_any!(children[2], Literal)?.value() {
+                ScalarValue::Boolean(Some(descending)) => *descending,
+                other => {
+                    return df_execution_err!("top_distinct expect boolean param, got: {other}")
+                }
+            };
+            Arc::new(top_distinct::AggTopDistinct::try_new(
                 children[0].clone(),
                 return_type,
                 arg_type,
+                limit,
+                descending,
+            )?)
+        }
+        AggFunction::ValueCounts => {
+            let arg_type = children[0].data_type(input_schema)?;
+            Arc::new(value_counts::AggValueCounts::try_new(
+                children[0].clone(),
+                arg_type,
             )?)
         }
+        AggFunction::HistogramNumeric => {
+            let num_bins = literal_usize_param(&children[1], "histogram_numeric")?;
+            let return_type = DataType::new_list(
+                DataType::Struct(Fields::from(vec![
+                    Field::new("x", DataType::Float64, true),
+                    Field::new("y", DataType::Float64, true),
+                ])),
+                true,
+            );
+            Arc::new(histogram_numeric::AggHistogramNumeric::try_new(
+                children[0].clone(),
+                return_type,
+                num_bins,
+            )?)
+        }
+        AggFunction::PercentileApprox => {
+            // percentile_approx(x, quantile(s), accuracy), accuracy is used as
+            // compression of the t-digest
+            let arg_type = children[0].data_type(input_schema)?;
+            let (quantiles, return_type) = match downcast_any!(children[1], Literal)?.value() {
+                ScalarValue::Float64(Some(q)) => (vec![*q], arg_type),
+                ScalarValue::List(qs) if !qs.is_null(0) => {
+                    let qs = qs.value(0);
+                    let qs = downcast_any!(qs, Float64Array)?;
+                    (
+                        qs.iter().flatten().collect(),
+                        DataType::new_list(arg_type, true),
+                    )
+                }
+                other => {
+                    return df_execution_err!(
+                        "percentile_approx expect double or array<double> param, got: {other}"
+                    )
+                }
+            };
+            let accuracy = literal_usize_param(&children[2], "percentile_approx")?;
+            Arc::new(percentile_approx::AggPercentileApprox::try_new(
+                children[0].clone(),
+                return_type,
+                quantiles,
+                accuracy as f64,
+            )?)
+        }
+        AggFunction::CountMinSketch => {
+            let width = literal_usize_param(&children[1], "count_min_sketch")?;
+            let depth = literal_usize_param(&children[2], "count_min_sketch")?;
+            Arc::new(count_min_sketch::AggCountMinSketch::try_new(
+                children[0].clone(),
+                width,
+                depth,
+            )?)
+        }
+        AggFunction::VarSamp => Arc::new(variance::AggVariance::try_new(
+            children[0].clone(),
+            variance::VarianceMode::VarSamp,
+        )?),
+        AggFunction::VarPop => Arc::new(variance::AggVariance::try_new(
+            children[0].clone(),
+            variance::VarianceMode::VarPop,
+        )?),
+        AggFunction::StddevSamp => Arc::new(variance::AggVariance::try_new(
+            children[0].clone(),
+            variance::VarianceMode::StddevSamp,
+        )?),
+        AggFunction::StddevPop => Arc::new(variance::AggVariance::try_new(
+            children[0].clone(),
+            variance::VarianceMode::StddevPop,
+        )?),
+        AggFunction::ArrayAgg => {
+            let arg_type = children[0].data_type(input_schema)?;
+            let return_type = DataType::new_list(arg_type.clone(), true);
+            Arc::new(
+                collect_list::AggCollectList::try_new(children[0].clone(), return_type, arg_type)?
+                    .with_include_nulls(),
+            )
+        }
+        AggFunction::ArrayUnion | AggFunction::ArrayIntersect => {
+            let arg_type = children[0].data_type(input_schema)?;
+            let arg_list_inner_type = match &arg_type {
+                DataType::List(field) => field.data_type().clone(),
+                _ => {
+                    return df_execution_err!("{agg_function:?} expect list type, got: {arg_type}")
+                }
+            };
+            let return_type = DataType::new_list(arg_list_inner_type.clone(), true);
+            if agg_function == AggFunction::ArrayUnion {
+                // union of arrays is collect_set() of all elements
+                Arc::new(
+                    collect_set::AggCollectSet::try_new(
+                        children[0].clone(),
+                        return_type,
+                        arg_list_inner_type,
+                    )?
+                    .with_insertion_order()
+                    .with_merge_lists(),
+                )
+            } else {
+                Arc::new(array_intersect::AggArrayIntersect::try_new(
+                    children[0].clone(),
+                    return_type,
+                    arg_list_inner_type,
+                )?)
+            }
+        }
         AggFunction::BrickhouseCollect => {
             let arg_type = children[0].data_type(input_schema)?;
             let arg_list_inner_type = match arg_type {
@@ -283,6 +577,44 @@ pub fn create_agg(
     })
 }
 
+/// creates a collect_set()/collect_list() aggregate with default options,
+/// `data_type` is the list type of `arg_type`
+pub fn create_collect_agg(
+    kind: CollectKind,
+    child: Arc<dyn PhysicalExpr>,
+    arg_type: DataType,
+    data_type: DataType,
+) -> Result<Arc<dyn Agg>> {
+    match &data_type {
+        DataType::List(field) if field.data_type() == &arg_type => {}
+        _ => {
+            return df_execution_err!(
+                "{kind:?} collect agg expect list of {arg_type} return type, got: {data_type}"
+            );
+        }
+    }
+    Ok(match kind {
+        CollectKind::Set => Arc::new(collect_set::AggCollectSet::try_new(
+            child, data_type, arg_type,
+        )?),
+        CollectKind::SortedSet { descending } => Arc::new(
+            collect_set::AggCollectSet::try_new(child, data_type, arg_type)?
+                .with_sorted_output(descending),
+        ),
+        CollectKind::List => Arc::new(collect_list::AggCollectList::try_new(
+            child, data_type, arg_type,
+        )?),
+    })
+}
+
+fn literal_usize_param(child: &Arc<dyn PhysicalExpr>, agg_name: &str) -> Result<usize> {
+    match downcast_any!(child, Literal)?.value() {
+        ScalarValue::Int32(Some(v)) if *v >= 0 => Ok(*v as usize),
+        ScalarValue::Int64(Some(v)) if *v >= 0 => Ok(*v as usize),
+        other => df_execution_err!("{agg_name} expect non-negative integer param, got: {other}"),
+    }
+}
+
 fn default_final_merge_with_addr(
     agg: &impl Agg,
     acc: &mut RefAccumStateRow,
@@ -499,3 +831,201 @@ fn default_final_batch_merge_with_addr(
         }
     })
 }
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use arrow::{array::*, datatypes::*};
+    use datafusion::{common::Result, physical_expr::expressions::Column};
+
+    use crate::agg::{
+        acc::{
+            create_acc_from_initial_value, AccStore, AccumStateRow, OwnedAccumStateRow,
+            RefAccumStateRow,
+        },
+        bounded_collect_list::AggBoundedCollectList,
+        bounded_collect_set::AggBoundedCollectSet,
+        collect_list::AggCollectList,
+        collect_set::AggCollectSet,
+        count_min_sketch::AggCountMinSketch,
+        partial_batch_merge_aliasing_safe,
+        percentile_approx::AggPercentileApprox,
+        top_distinct::AggTopDistinct,
+        value_counts::AggValueCounts,
+        Agg, WithAggBufAddrs,
+    };
+
+    #[test]
+    fn test_partial_batch_merge_aliasing_safe() -> Result<()> {
+        let mut agg = AggCollectList::try_new(
+            Arc::new(Column::new("a", 0)),
+            DataType::new_list(DataType::Int32, true),
+            DataType::Int32,
+        )?;
+        let (initial_acc, addrs) = create_acc_from_initial_value(agg.accums_initial())?;
+        agg.set_accum_state_val_addrs(&addrs);
+
+        let mut store = AccStore::new(initial_acc);
+        let acc_addrs = (0..3).map(|_| store.new_acc()).collect::<Vec<_>>();
+        let values: ArrayRef = Arc::new(Int32Array::from(vec![1, 2, 3, 4]));
+        agg.partial_update_all(&mut store.get(acc_addrs[0]), &[values.slice(0, 2)])?;
+        agg.partial_update_all(&mut store.get(acc_addrs[1]), &[values.slice(2, 1)])?;
+        agg.partial_update_all(&mut store.get(acc_addrs[2]), &[values.slice(3, 1)])?;
+
+        // aliased rows are created from the raw pointers of the same accumulator
+        // instead of borrowing it twice from the store
+        let ptrs = acc_addrs
+            .iter()
+            .map(|&acc_addr| {
+                let mut acc = store.get(acc_addr);
+                let fixed_len = acc.fixed().len();
+                let dyns_len = acc.dyns().len();
+                (
+                    acc.fixed_mut().as_mut_ptr(),
+                    fixed_len,
+                    acc.dyns_mut().as_mut_ptr(),
+                    dyns_len,
+                )
+            })
+            .collect::<Vec<_>>();
+        let row = |i: usize| unsafe {
+            // safety: aliased merging rows are only read before merging
+            let (fixed, fixed_len, dyns, dyns_len) = ptrs[i];
+            RefAccumStateRow::from_raw_parts(fixed, fixed_len, dyns, dyns_len)
+        };
+
+        // rows 0 and 1 are merged with each other, row 2 is merged with itself
+        let mut accs = vec![row(0), row(1), row(2)];
+        let mut merging_accs = vec![row(1), row(0), row(2)];
+        assert!(accs[0].ptr_eq(&merging_accs[1]));
+        assert!(accs[2].ptr_eq(&merging_accs[2]));
+        partial_batch_merge_aliasing_safe(&agg, &mut accs, &mut merging_accs)?;
+        drop(merging_accs);
+
+        assert_eq!(
+            agg.final_batch_merge(&mut accs)?.to_data(),
+            ListArray::from_iter_primitive::<Int32Type, _, _>(vec![
+                Some(vec![Some(1), Some(2), Some(3)]),
+                Some(vec![Some(3), Some(1), Some(2)]),
+                Some(vec![Some(4), Some(4)]),
+            ])
+            .to_data(),
+        );
+        Ok(())
+    }
+
+    /// asserts that the tracked memory equals the memory recomputed from
+    /// scratch with the accumulators
+    fn assert_mem_used_consistent<'a>(
+        agg: &dyn Agg,
+        accs: impl IntoIterator<Item = &'a mut OwnedAccumStateRow>,
+    ) {
+        let tracked = agg.mem_used();
+        agg.reset_mem_used();
+        for acc in accs {
+            agg.increase_acc_mem_used(&mut acc.as_mut());
+        }
+        assert_eq!(agg.mem_used(), tracked, "{agg:?}");
+    }
+
+    #[test]
+    fn test_mem_used_consistency() -> Result<()> {
+        let list_type = DataType::new_list(DataType::Int32, true);
+        let child = || Arc::new(Column::new("a", 0));
+        let aggs: Vec<Box<dyn Agg>> = vec![
+            Box::new(AggCollectList::try_new(
+                child(),
+                list_type.clone(),
+                DataType::Int32,
+            )?),
+            Box::new(
+                AggCollectList::try_new(child(), list_type.clone(), DataType::Int32)?
+                    .with_include_nulls()
+                    .with_run_length_encoding(),
+            ),
+            Box::new(
+                AggCollectSet::try_new(child(), list_type.clone(), DataType::Int32)?
+                    .with_insertion_order(),
+            ),
+            Box::new(AggBoundedCollectList::try_new(
+                child(),
+                list_type.clone(),
+                DataType::Int32,
+                50,
+                false,
+            )?),
+            Box::new(AggBoundedCollectSet::try_new(
+                child(),
+                list_type.clone(),
+                DataType::Int32,
+                256,
+            )?),
+            Box::new(AggCountMinSketch::try_new(child(), 16, 2)?),
+            Box::new(AggValueCounts::try_new(child(), DataType::Int32)?),
+            Box::new(AggPercentileApprox::try_new(
+                child(),
+                DataType::Int32,
+                vec![0.5],
+                20.0,
+            )?),
+            Box::new(AggTopDistinct::try_new(
+                child(),
+                list_type.clone(),
+                DataType::Int32,
+                5,
+                true,
+            )?),
+        ];
+        let values: ArrayRef = Arc::new(Int32Array::from_iter(
+            (0..200).map(|i| Some(i % 70).filter(|v| v % 7 != 0)),
+        ));
+
+        for mut agg in aggs {
+            let (initial_acc, addrs) = create_acc_from_initial_value(agg.accums_initial())?;
+            agg.set_accum_state_val_addrs(&addrs);
+            let mut accs = vec![initial_acc.clone(); 3];
+            let mut merging_accs = vec![initial_acc.clone(); 3];
+            for acc in accs.iter_mut().chain(&mut merging_accs) {
+                agg.increase_acc_mem_used(&mut acc.as_mut());
+            }
+
+            // updated groups
+            agg.partial_update_all(&mut accs[0].as_mut(), &[values.clone()])?;
+            for row_idx in 0..values.len() {
+                agg.partial_update(&mut accs[1].as_mut(), &[values.clone()], row_idx)?;
+            }
+            agg.partial_update_all(&mut merging_accs[0].as_mut(), &[values.slice(0, 100)])?;
+            agg.partial_update_all(&mut merging_accs[2].as_mut(), &[values.slice(100, 100)])?;
+            assert_mem_used_consistent(agg.as_ref(), accs.iter_mut().chain(&mut merging_accs));
+
+            // merged groups, merging groups are discarded after merging
+            agg.partial_merge(&mut accs[0].as_mut(), &mut merging_accs[0].as_mut())?;
+            let mut ref_accs = accs[1..]
+                .iter_mut()
+                .map(|acc| acc.as_mut())
+                .collect::<Vec<_>>();
+            let mut ref_merging_accs = merging_accs[1..]
+                .iter_mut()
+                .map(|acc| acc.as_mut())
+                .collect::<Vec<_>>();
+            agg.partial_batch_merge(&mut ref_accs, &mut ref_merging_accs)?;
+            for acc in &mut accs {
+                agg.shrink_acc_to_fit(&mut acc.as_mut());
+            }
+            assert_mem_used_consistent(agg.as_ref(), &mut accs);
+
+            // groups moved out of the table are no longer tracked
+            agg.decrease_acc_mem_used(&mut accs[0].as_mut());
+            assert_mem_used_consistent(agg.as_ref(), &mut accs[1..]);
+            agg.increase_acc_mem_used(&mut accs[0].as_mut());
+
+            // all memory is released after outputting
+            let mut ref_accs = accs.iter_mut().map(|acc| acc.as_mut()).collect::<Vec<_>>();
+            agg.final_batch_merge(&mut ref_accs)?;
+            assert_eq!(agg.mem_used(), 0, "{agg:?}");
+            assert_mem_used_consistent(agg.as_ref(), &mut accs);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,435 @@
+// Copyright 2022 The Blaze Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::{
+    any::Any,
+    fmt::{Debug, Formatter},
+    sync::{atomic::AtomicUsize, Arc},
+};
+
+use arrow::{
+    array::*,
+    buffer::{NullBuffer, OffsetBuffer},
+    compute::cast,
+    datatypes::*,
+};
+use datafusion::{
+    common::{Result, ScalarValue},
+    physical_expr::PhysicalExpr,
+};
+use datafusion_ext_commons::{df_execution_err, downcast_any};
+
+use crate::agg::{
+    acc::{
+        AccumInitialValue, AccumStateRow, AccumStateValAddr, AggDynTDigest, AggDynValue,
+        RefAccumStateRow,
+    },
+    Agg, WithAggBufAddrs, WithMemTracking,
+};
+
+/// percentile_approx(): approximate quantiles of numeric values estimated with
+/// a t-digest of the given compression. output is a single value if
+/// `data_type` is not a list, otherwise a list of values for each quantile
+pub struct AggPercentileApprox {
+    child: Arc<dyn PhysicalExpr>,
+    data_type: DataType,
+    quantiles: Vec<f64>,
+    compression: f64,
+    accum_initial: [AccumInitialValue; 1],
+    accum_state_val_addr: AccumStateValAddr,
+    mem_used_tracker: AtomicUsize,
+}
+
+impl WithAggBufAddrs for AggPercentileApprox {
+    fn set_accum_state_val_addrs(&mut self, accum_state_val_addrs: &[AccumStateValAddr]) {
+        self.accum_state_val_addr = accum_state_val_addrs[0];
+    }
+}
+
+impl WithMemTracking for AggPercentileApprox {
+    fn mem_used_tracker(&self) -> &AtomicUsize {
+        &self.mem_used_tracker
+    }
+}
+
+impl AggPercentileApprox {
+    pub fn try_new(
+        child: Arc<dyn PhysicalExpr>,
+        data_type: DataType,
+        quantiles: Vec<f64>,
+        compression: f64,
+    ) -> Result<Self> {
+        if quantiles.is_empty() || quantiles.iter().any(|q| !(0.0..=1.0).contains(q)) {
+            return df_execution_err!(
+                "percentile_approx expect quantiles between 0.0 and 1.0, got: {quantiles:?}"
+            );
+        }
+        if !matches!(&data_type, DataType::List(_)) && quantiles.len() > 1 {
+            return df_execution_err!(
+                "percentile_approx expect list type for multiple quantiles, got: {data_type}"
+            );
+        }
+        if !(compression >= 1.0 && compression.is_finite()) {
+            return df_execution_err!(
+                "percentile_approx expect compression of at least 1.0, got: {compression}"
+            );
+        }
+        Ok(Self {
+            child,
+            data_type,
+            quantiles,
+            compression,
+            accum_initial: [AccumInitialValue::DynTDigest],
+            accum_state_val_addr: AccumStateValAddr::default(),
+            mem_used_tracker: AtomicUsize::new(0),
+        })
+    }
+
+    pub fn quantiles(&self) -> &[f64] {
+        &self.quantiles
+    }
+
+    pub fn compression(&self) -> f64 {
+        self.compression
+    }
+
+    fn tdigest_mut<'a>(&self, acc: &'a mut RefAccumStateRow) -> Result<&'a mut AggDynTDigest> {
+        let tdigest = match acc.dyn_value_mut(self.accum_state_val_addr) {
+            Some(tdigest) => tdigest,
+            w => {
+                let new_tdigest = AggDynTDigest::default();
+                self.add_mem_used(new_tdigest.mem_size());
+                *w = Some(Box::new(new_tdigest));
+                w.as_mut().unwrap()
+            }
+        };
+        downcast_any!(tdigest, mut AggDynTDigest)
+    }
+
+    /// takes estimated quantiles of the group, None if no values are added
+    fn take_quantiles(&self, acc: &mut RefAccumStateRow) -> Result<Option<Vec<f64>>> {
+        match std::mem::take(acc.dyn_value_mut(self.accum_state_val_addr)) {
+            Some(w) => {
+                self.sub_mem_used(w.mem_size());
+                let mut tdigest = w
+                    .as_any_boxed()
+                    .downcast::<AggDynTDigest>()
+                    .or_else(|_| df_execution_err!("error downcasting to AggDynTDigest"))?;
+                Ok(self
+                    .quantiles
+                    .iter()
+                    .map(|&q| tdigest.quantile(q, self.compression))
+                    .collect())
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// builds output array, groups without any values are null
+    fn build_output_array(&self, groups: Vec<Option<Vec<f64>>>) -> Result<ArrayRef> {
+        let item_field = match &self.data_type {
+            DataType::List(item_field) => item_field.clone(),
+            _ => {
+                let values =
+                    Float64Array::from_iter(groups.into_iter().map(|group| group.map(|qs| qs[0])));
+                return Ok(cast(&values, &self.data_type)?);
+            }
+        };
+
+        let mut offsets = Vec::with_capacity(groups.len() + 1);
+        let mut valids = Vec::with_capacity(groups.len());
+        let mut values = vec![];
+        offsets.push(0i32);
+        for group in groups {
+            valids.push(group.is_some());
+            values.extend(group.unwrap_or_default());
+            offsets.push(values.len() as i32);
+        }
+        let values = cast(&Float64Array::from(values), item_field.data_type())?;
+        Ok(Arc::new(ListArray::try_new(
+            item_field,
+            OffsetBuffer::new(offsets.into()),
+            values,
+            Some(NullBuffer::from(valids)),
+        )?))
+    }
+}
+
+impl Debug for AggPercentileApprox {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "PercentileApprox({:?}, {:?}, {})",
+            self.child, self.quantiles, self.compression
+        )
+    }
+}
+
+impl Agg for AggPercentileApprox {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn exprs(&self) -> Vec<Arc<dyn PhysicalExpr>> {
+        vec![self.child.clone()]
+    }
+
+    fn with_new_exprs(&self, exprs: Vec<Arc<dyn PhysicalExpr>>) -> Result<Arc<dyn Agg>> {
+        Ok(Arc::new(Self::try_new(
+            exprs[0].clone(),
+            self.data_type.clone(),
+            self.quantiles.clone(),
+            self.compression,
+        )?))
+    }
+
+    fn data_type(&self) -> &DataType {
+        &self.data_type
+    }
+
+    fn nullable(&self) -> bool {
+        true
+    }
+
+    fn accums_initial(&self) -> &[AccumInitialValue] {
+        &self.accum_initial
+    }
+
+    fn increase_acc_mem_used(&self, acc: &mut RefAccumStateRow) {
+        if let Some(v) = acc.dyn_value(self.accum_state_val_addr) {
+            self.add_mem_used(v.mem_size());
+        }
+    }
+
+    fn decrease_acc_mem_used(&self, acc: &mut RefAccumStateRow) {
+        if let Some(v) = acc.dyn_value(self.accum_state_val_addr) {
+            self.sub_mem_used(v.mem_size());
+        }
+    }
+
+    fn partial_update(
+        &self,
+        acc: &mut RefAccumStateRow,
+        values: &[ArrayRef],
+        row_idx: usize,
+    ) -> Result<()> {
+        if values[0].is_valid(row_idx) {
+            let value = ScalarValue::try_from_array(&values[0], row_idx)?;
+            if let ScalarValue::Float64(Some(x)) = value.cast_to(&DataType::Float64)? {
+                let tdigest = self.tdigest_mut(acc)?;
+                self.sub_mem_used(tdigest.mem_size());
+                tdigest.add(x, self.compression);
+                self.add_mem_used(tdigest.mem_size());
+            }
+        }
+        Ok(())
+    }
+
+    fn partial_batch_update(
+        &self,
+        accs: &mut [RefAccumStateRow],
+        values: &[ArrayRef],
+    ) -> Result<()> {
+        let values = cast(&values[0], &DataType::Float64)?;
+        for (acc, x) in accs.iter_mut().zip(values.as_primitive::<Float64Type>()) {
+            if let Some(x) = x {
+                let tdigest = self.tdigest_mut(acc)?;
+                self.sub_mem_used(tdigest.mem_size());
+                tdigest.add(x, self.compression);
+                self.add_mem_used(tdigest.mem_size());
+            }
+        }
+        Ok(())
+    }
+
+    fn partial_update_all(&self, acc: &mut RefAccumStateRow, values: &[ArrayRef]) -> Result<()> {
+        let values = cast(&values[0], &DataType::Float64)?;
+        let tdigest = self.tdigest_mut(acc)?;
+        self.sub_mem_used(tdigest.mem_size());
+        for x in values.as_primitive::<Float64Type>().iter().flatten() {
+            tdigest.add(x, self.compression);
+        }
+        self.add_mem_used(tdigest.mem_size());
+        Ok(())
+    }
+
+    fn partial_merge(
+        &self,
+        acc: &mut RefAccumStateRow,
+        merging_acc: &mut RefAccumStateRow,
+    ) -> Result<()> {
+        match (
+            acc.dyn_value_mut(self.accum_state_val_addr),
+            merging_acc.dyn_value_mut(self.accum_state_val_addr),
+        ) {
+            (Some(w), Some(v)) => {
+                let w = downcast_any!(w, mut AggDynTDigest)?;
+                let v = downcast_any!(v, mut AggDynTDigest)?;
+                self.sub_mem_used(w.mem_size());
+                self.sub_mem_used(v.mem_size());
+                w.merge(v, self.compression);
+                self.add_mem_used(w.mem_size());
+            }
+            (w_none, v @ Some(_)) => *w_none = std::mem::take(v),
+            (None, _) => {}
+            (_, None) => {}
+        }
+        Ok(())
+    }
+
+    fn shrink_acc_to_fit(&self, acc: &mut RefAccumStateRow) {
+        if let Some(v) = acc.dyn_value_mut(self.accum_state_val_addr) {
+            self.sub_mem_used(v.mem_size());
+            v.shrink_to_fit();
+            self.add_mem_used(v.mem_size());
+        }
+    }
+
+    fn final_merge(&self, acc: &mut RefAccumStateRow) -> Result<ScalarValue> {
+        let output_array = self.build_output_array(vec![self.take_quantiles(acc)?])?;
+        ScalarValue::try_from_array(&output_array, 0)
+    }
+
+    fn final_batch_merge(&self, accs: &mut [RefAccumStateRow]) -> Result<ArrayRef> {
+        let groups = accs
+            .iter_mut()
+            .map(|acc| self.take_quantiles(acc))
+            .collect::<Result<Vec<_>>>()?;
+        self.build_output_array(groups)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{io::Cursor, sync::Arc};
+
+    use arrow::{array::*, datatypes::*};
+    use datafusion::{common::Result, physical_expr::expressions::Column};
+
+    use crate::agg::{
+        acc::{
+            create_acc_from_initial_value, create_dyn_loaders_from_initial_value,
+            create_dyn_savers_from_initial_value, AccumStateRow, LoadReader, SaveWriter,
+        },
+        percentile_approx::AggPercentileApprox,
+        Agg, WithAggBufAddrs,
+    };
+
+    #[test]
+    fn test_percentile_approx_exact() -> Result<()> {
+        let mut agg = AggPercentileApprox::try_new(
+            Arc::new(Column::new("a", 0)),
+            DataType::Float64,
+            vec![0.5],
+            100.0,
+        )?;
+        let (initial_acc, addrs) = create_acc_from_initial_value(agg.accums_initial())?;
+        agg.set_accum_state_val_addrs(&addrs);
+
+        let values: ArrayRef = Arc::new(Int32Array::from(vec![
+            Some(5),
+            None,
+            Some(1),
+            Some(4),
+            Some(2),
+            Some(3),
+        ]));
+        let mut accs = vec![initial_acc.clone(); 2];
+        agg.partial_update_all(&mut accs[0].as_mut(), &[values.clone()])?;
+        agg.partial_update_all(&mut accs[1].as_mut(), &[values.slice(1, 1)])?;
+
+        let mut ref_accs = accs.iter_mut().map(|acc| acc.as_mut()).collect::<Vec<_>>();
+        let output = agg.final_batch_merge(&mut ref_accs)?;
+        assert_eq!(
+            output.as_primitive::<Float64Type>(),
+            &Float64Array::from(vec![Some(3.0), None]),
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_percentile_approx_with_exact_quantiles() -> Result<()> {
+        let compression = 100.0;
+        let quantiles = vec![0.0, 0.001, 0.01, 0.1, 0.5, 0.9, 0.99, 0.999, 1.0];
+        let mut agg = AggPercentileApprox::try_new(
+            Arc::new(Column::new("a", 0)),
+            DataType::new_list(DataType::Float64, true),
+            quantiles.clone(),
+            compression,
+        )?;
+        let (initial_acc, addrs) = create_acc_from_initial_value(agg.accums_initial())?;
+        agg.set_accum_state_val_addrs(&addrs);
+
+        // xorshift random generator, values are skewed to the right
+        let mut seed = 0x2545F4914F6CDD1Du64;
+        let mut rand = move || {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            (seed >> 11) as f64 / (1u64 << 53) as f64
+        };
+        let values = (0..20000)
+            .map(|_| -rand().max(1e-12).ln() * 100.0)
+            .collect::<Vec<_>>();
+
+        // update partitions of values and merge them
+        let mut acc = initial_acc.clone();
+        for chunk in values.chunks(3000) {
+            let mut partial_acc = initial_acc.clone();
+            let chunk: ArrayRef = Arc::new(Float64Array::from(chunk.to_vec()));
+            for row_idx in 0..chunk.len() {
+                agg.partial_update(&mut partial_acc.as_mut(), &[chunk.clone()], row_idx)?;
+            }
+            agg.partial_merge(&mut acc.as_mut(), &mut partial_acc.as_mut())?;
+        }
+
+        // round-trip through spill
+        let mut buf = vec![];
+        let savers = create_dyn_savers_from_initial_value(agg.accums_initial())?;
+        let loaders = create_dyn_loaders_from_initial_value(agg.accums_initial())?;
+        let mut save_writer = SaveWriter(Box::new(Cursor::new(&mut buf)));
+        savers[0](
+            &mut save_writer,
+            std::mem::take(acc.as_mut().dyn_value_mut(addrs[0])),
+        )?;
+        drop(save_writer);
+        let mut load_reader = LoadReader(Box::new(Cursor::new(&buf)));
+        let mut loaded_acc = initial_acc.clone();
+        *loaded_acc.as_mut().dyn_value_mut(addrs[0]) = loaders[0](&mut load_reader)?;
+
+        let mut ref_accs = vec![loaded_acc.as_mut()];
+        let output = agg.final_batch_merge(&mut ref_accs)?;
+        let estimated = output.as_list::<i32>().value(0);
+        let estimated = estimated.as_primitive::<Float64Type>().values();
+        assert_eq!(estimated.len(), quantiles.len());
+
+        // with k1 scale function, a centroid at quantile q covers about
+        // 2pi * sqrt(q(1-q)) / compression of ranks, the rank error of
+        // interpolated estimations is bounded by twice of it
+        let mut sorted = values.clone();
+        sorted.sort_unstable_by(f64::total_cmp);
+        let n = sorted.len() as f64;
+        for (&q, &x) in quantiles.iter().zip(estimated) {
+            let rank = sorted.partition_point(|&v| v < x) as f64 / n;
+            let bound = 4.0 * std::f64::consts::PI * (q * (1.0 - q)).sqrt() / compression;
+            assert!(
+                (rank - q).abs() <= bound + 1.0 / n,
+                "q={q}: estimated={x}, rank={rank}, bound={bound}"
+            );
+        }
+        assert_eq!(estimated[0], sorted[0]);
+        assert_eq!(estimated[quantiles.len() - 1], sorted[sorted.len() - 1]);
+        Ok(())
+    }
+}
@@ -0,0 +1,300 @@
+// Copyright 2022 The Blaze Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::{
+    any::Any,
+    fmt::{Debug, Formatter},
+    sync::{atomic::AtomicUsize, Arc},
+};
+
+use arrow::{array::*, compute::cast, datatypes::*};
+use datafusion::{
+    common::{Result, ScalarValue},
+    physical_expr::PhysicalExpr,
+};
+use datafusion_ext_commons::{df_execution_err, df_unimplemented_err};
+
+use crate::agg::{
+    acc::{AccumInitialValue, AccumStateValAddr, RefAccumStateRow},
+    collect_set::AggCollectSet,
+    Agg, WithAggBufAddrs, WithMemTracking,
+};
+
+/// sum(distinct x), values are deduplicated with the internal set of
+/// collect_set() in partial aggregation, since merging partial results
+/// requires the distinct values. the set is only collapsed to a sum in final
+/// merging
+pub struct AggSumDistinct {
+    inner_collect_set: AggCollectSet,
+    data_type: DataType,
+}
+
+impl WithAggBufAddrs for AggSumDistinct {
+    fn set_accum_state_val_addrs(&mut self, accum_state_val_addrs: &[AccumStateValAddr]) {
+        self.inner_collect_set
+            .set_accum_state_val_addrs(accum_state_val_addrs);
+    }
+}
+
+impl WithMemTracking for AggSumDistinct {
+    fn mem_used_tracker(&self) -> &AtomicUsize {
+        self.inner_collect_set.mem_used_tracker()
+    }
+}
+
+impl AggSumDistinct {
+    /// `data_type` is the promoted sum type of `arg_type`, which is Int64,
+    /// UInt64, Float64 or Decimal128 with extended precision
+    pub fn try_new(
+        child: Arc<dyn PhysicalExpr>,
+        data_type: DataType,
+        arg_type: DataType,
+    ) -> Result<Self> {
+        if !matches!(
+            data_type,
+            DataType::Int64 | DataType::UInt64 | DataType::Float64 | DataType::Decimal128(..)
+        ) {
+            return df_unimplemented_err!("sum(distinct) does not support return type {data_type}");
+        }
+        Ok(Self {
+            inner_collect_set: AggCollectSet::try_new(
+                child,
+                DataType::new_list(arg_type.clone(), true),
+                arg_type,
+            )?,
+            data_type,
+        })
+    }
+
+    pub fn arg_type(&self) -> &DataType {
+        self.inner_collect_set.arg_type()
+    }
+
+    /// sums up distinct values of a group, overflowed decimal sum is null and
+    /// overflowed integral sum wraps around, following spark's non-ansi mode
+    fn sum_values(&self, values: ArrayRef) -> Result<ScalarValue> {
+        if values.is_empty() {
+            return ScalarValue::try_from(&self.data_type);
+        }
+        let values = cast(&values, &self.data_type)?;
+        Ok(match &self.data_type {
+            DataType::Int64 => {
+                ScalarValue::Int64(arrow::compute::sum(values.as_primitive::<Int64Type>()))
+            }
+            DataType::UInt64 => {
+                ScalarValue::UInt64(arrow::compute::sum(values.as_primitive::<UInt64Type>()))
+            }
+            DataType::Float64 => {
+                ScalarValue::Float64(arrow::compute::sum(values.as_primitive::<Float64Type>()))
+            }
+            &DataType::Decimal128(precision, scale) => {
+                let sum = arrow::compute::sum_checked(values.as_primitive::<Decimal128Type>())
+                    .ok()
+                    .flatten()
+                    .filter(|&sum| {
+                        Decimal128Type::validate_decimal_precision(sum, precision).is_ok()
+                    });
+                ScalarValue::Decimal128(sum, precision, scale)
+            }
+            other => return df_execution_err!("sum(distinct) got unexpected type: {other}"),
+        })
+    }
+}
+
+impl Debug for AggSumDistinct {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "SumDistinct({:?})", self.inner_collect_set.exprs()[0])
+    }
+}
+
+impl Agg for AggSumDistinct {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn exprs(&self) -> Vec<Arc<dyn PhysicalExpr>> {
+        self.inner_collect_set.exprs()
+    }
+
+    fn with_new_exprs(&self, exprs: Vec<Arc<dyn PhysicalExpr>>) -> Result<Arc<dyn Agg>> {
+        Ok(Arc::new(Self::try_new(
+            exprs[0].clone(),
+            self.data_type.clone(),
+            self.arg_type().clone(),
+        )?))
+    }
+
+    fn data_type(&self) -> &DataType {
+        &self.data_type
+    }
+
+    fn nullable(&self) -> bool {
+        true
+    }
+
+    fn accums_initial(&self) -> &[AccumInitialValue] {
+        self.inner_collect_set.accums_initial()
+    }
+
+    fn increase_acc_mem_used(&self, acc: &mut RefAccumStateRow) {
+        self.inner_collect_set.increase_acc_mem_used(acc);
+    }
+
+    fn decrease_acc_mem_used(&self, acc: &mut RefAccumStateRow) {
+        self.inner_collect_set.decrease_acc_mem_used(acc);
+    }
+
+    fn partial_update(
+        &self,
+        acc: &mut RefAccumStateRow,
+        values: &[ArrayRef],
+        row_idx: usize,
+    ) -> Result<()> {
+        self.inner_collect_set.partial_update(acc, values, row_idx)
+    }
+
+    fn partial_batch_update(
+        &self,
+        accs: &mut [RefAccumStateRow],
+        values: &[ArrayRef],
+    ) -> Result<()> {
+        self.inner_collect_set.partial_batch_update(accs, values)
+    }
+
+    fn partial_update_all(&self, acc: &mut RefAccumStateRow, values: &[ArrayRef]) -> Result<()> {
+        self.inner_collect_set.partial_update_all(acc, values)
+    }
+
+    fn partial_merge(
+        &self,
+        acc: &mut RefAccumStateRow,
+        merging_acc: &mut RefAccumStateRow,
+    ) -> Result<()> {
+        self.inner_collect_set.partial_merge(acc, merging_acc)
+    }
+
+    fn partial_batch_merge(
+        &self,
+        accs: &mut [RefAccumStateRow],
+        merging_accs: &mut [RefAccumStateRow],
+    ) -> Result<()> {
+        self.inner_collect_set
+            .partial_batch_merge(accs, merging_accs)
+    }
+
+    fn shrink_acc_to_fit(&self, acc: &mut RefAccumStateRow) {
+        self.inner_collect_set.shrink_acc_to_fit(acc)
+    }
+
+    fn final_merge(&self, acc: &mut RefAccumStateRow) -> Result<ScalarValue> {
+        match self.inner_collect_set.final_merge(acc)? {
+            ScalarValue::List(list) if list.is_valid(0) => self.sum_values(list.value(0)),
+            _ => ScalarValue::try_from(&self.data_type),
+        }
+    }
+
+    fn final_batch_merge(&self, accs: &mut [RefAccumStateRow]) -> Result<ArrayRef> {
+        let lists = self.inner_collect_set.final_batch_merge(accs)?;
+        let sums = lists
+            .as_list::<i32>()
+            .iter()
+            .map(|values| match values {
+                Some(values) => self.sum_values(values),
+                None => ScalarValue::try_from(&self.data_type),
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        if sums.is_empty() {
+            return Ok(new_empty_array(&self.data_type));
+        }
+        Ok(ScalarValue::iter_to_array(sums)?)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use arrow::{array::*, datatypes::*};
+    use datafusion::{
+        common::{Result, ScalarValue},
+        physical_expr::expressions::Column,
+    };
+
+    use crate::agg::{
+        acc::create_acc_from_initial_value, sum_distinct::AggSumDistinct, Agg, WithAggBufAddrs,
+    };
+
+    #[test]
+    fn test_sum_distinct() -> Result<()> {
+        let mut agg = AggSumDistinct::try_new(
+            Arc::new(Column::new("a", 0)),
+            DataType::Int64,
+            DataType::Int32,
+        )?;
+        let (initial_acc, addrs) = create_acc_from_initial_value(agg.accums_initial())?;
+        agg.set_accum_state_val_addrs(&addrs);
+
+        // duplicated values split across partitions
+        let values1: ArrayRef = Arc::new(Int32Array::from(vec![
+            Some(1),
+            Some(2),
+            None,
+            Some(2),
+            Some(3),
+        ]));
+        let values2: ArrayRef = Arc::new(Int32Array::from(vec![Some(3), Some(4), Some(1), None]));
+        let mut accs = vec![initial_acc.clone(); 3];
+        agg.partial_update_all(&mut accs[0].as_mut(), &[values1])?;
+        let mut merging_acc = initial_acc.clone();
+        agg.partial_update_all(&mut merging_acc.as_mut(), &[values2])?;
+        agg.partial_merge(&mut accs[0].as_mut(), &mut merging_acc.as_mut())?;
+
+        // all nulls
+        let values3: ArrayRef = Arc::new(Int32Array::from(vec![None, None]));
+        agg.partial_update_all(&mut accs[1].as_mut(), &[values3])?;
+
+        let mut ref_accs = accs.iter_mut().map(|acc| acc.as_mut()).collect::<Vec<_>>();
+        assert_eq!(
+            agg.final_batch_merge(&mut ref_accs)?
+                .as_primitive::<Int64Type>(),
+            &Int64Array::from(vec![Some(10), None, None]),
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_sum_distinct_decimal() -> Result<()> {
+        let mut agg = AggSumDistinct::try_new(
+            Arc::new(Column::new("a", 0)),
+            DataType::Decimal128(15, 2),
+            DataType::Decimal128(5, 2),
+        )?;
+        let (initial_acc, addrs) = create_acc_from_initial_value(agg.accums_initial())?;
+        agg.set_accum_state_val_addrs(&addrs);
+
+        let values: ArrayRef = Arc::new(
+            Decimal128Array::from(vec![12345, 100, 12345, -1]).with_precision_and_scale(5, 2)?,
+        );
+        let mut acc = initial_acc.clone();
+        for row_idx in 0..values.len() {
+            agg.partial_update(&mut acc.as_mut(), &[values.clone()], row_idx)?;
+        }
+        assert_eq!(
+            agg.final_merge(&mut acc.as_mut())?,
+            ScalarValue::Decimal128(Some(12444), 15, 2),
+        );
+        Ok(())
+    }
+}
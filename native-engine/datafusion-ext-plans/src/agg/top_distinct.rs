@@ -0,0 +1,521 @@
+// Copyright 2022 The Blaze Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::{
+    any::Any,
+    fmt::{Debug, Formatter},
+    sync::{atomic::AtomicUsize, Arc},
+};
+
+use arrow::{
+    array::*,
+    compute::SortOptions,
+    datatypes::*,
+    row::{RowConverter, SortField},
+};
+use datafusion::{
+    common::{Result, ScalarValue},
+    physical_expr::PhysicalExpr,
+};
+use datafusion_ext_commons::{df_execution_err, downcast_any};
+use itertools::Itertools;
+use parking_lot::Mutex;
+
+use crate::agg::{
+    acc::{
+        AccumInitialValue, AccumStateRow, AccumStateValAddr, AggDynBinary, AggDynValue,
+        RefAccumStateRow,
+    },
+    collect_list::build_list,
+    Agg, WithAggBufAddrs, WithMemTracking,
+};
+
+/// top-N distinct values of a group, same as
+/// `slice(sort_array(collect_set(x), asc), 1, N)` but computed in one pass.
+/// each group keeps at most N distinct values as sorted rows encoded in arrow
+/// row format, so values ranked out of the top-N are dropped on arrival
+pub struct AggTopDistinct {
+    child: Arc<dyn PhysicalExpr>,
+    data_type: DataType,
+    arg_type: DataType,
+    limit: usize,
+    descending: bool,
+    row_converter: Arc<Mutex<RowConverter>>,
+    accum_initial: [AccumInitialValue; 1],
+    accum_state_val_addr: AccumStateValAddr,
+    mem_used_tracker: AtomicUsize,
+}
+
+impl WithAggBufAddrs for AggTopDistinct {
+    fn set_accum_state_val_addrs(&mut self, accum_state_val_addrs: &[AccumStateValAddr]) {
+        self.accum_state_val_addr = accum_state_val_addrs[0];
+    }
+}
+
+impl WithMemTracking for AggTopDistinct {
+    fn mem_used_tracker(&self) -> &AtomicUsize {
+        &self.mem_used_tracker
+    }
+}
+
+impl AggTopDistinct {
+    pub fn try_new(
+        child: Arc<dyn PhysicalExpr>,
+        data_type: DataType,
+        arg_type: DataType,
+        limit: usize,
+        descending: bool,
+    ) -> Result<Self> {
+        match &data_type {
+            DataType::List(field) if field.data_type() == &arg_type => {}
+            _ => {
+                return df_execution_err!(
+                    "top_distinct expect list of {arg_type} return type, got: {data_type}"
+                );
+            }
+        }
+        if limit == 0 {
+            return df_execution_err!("top_distinct expect positive limit, got: {limit}");
+        }
+        let row_converter = RowConverter::new(vec![SortField::new_with_options(
+            arg_type.clone(),
+            SortOptions {
+                descending,
+                nulls_first: !descending,
+            },
+        )])?;
+        Ok(Self {
+            child,
+            data_type,
+            arg_type,
+            limit,
+            descending,
+            row_converter: Arc::new(Mutex::new(row_converter)),
+            accum_initial: [AccumInitialValue::Scalar(ScalarValue::Binary(None))],
+            accum_state_val_addr: AccumStateValAddr::default(),
+            mem_used_tracker: AtomicUsize::new(0),
+        })
+    }
+
+    pub fn arg_type(&self) -> &DataType {
+        &self.arg_type
+    }
+
+    pub fn limit(&self) -> usize {
+        self.limit
+    }
+
+    pub fn descending(&self) -> bool {
+        self.descending
+    }
+
+    fn top_mut<'a>(&self, acc: &'a mut RefAccumStateRow) -> Result<&'a mut AggDynBinary> {
+        let top = match acc.dyn_value_mut(self.accum_state_val_addr) {
+            Some(top) => top,
+            w => {
+                let new_top = AggDynBinary::new(vec![].into());
+                self.add_mem_used(new_top.mem_size());
+                *w = Some(Box::new(new_top));
+                w.as_mut().unwrap()
+            }
+        };
+        downcast_any!(top, mut AggDynBinary)
+    }
+
+    fn take_top(&self, acc: &mut RefAccumStateRow) -> Result<Option<Vec<u8>>> {
+        match std::mem::take(acc.dyn_value_mut(self.accum_state_val_addr)) {
+            Some(w) => {
+                self.sub_mem_used(w.mem_size());
+                let top = w
+                    .as_any_boxed()
+                    .downcast::<AggDynBinary>()
+                    .or_else(|_| df_execution_err!("error downcasting to AggDynBinary"))?;
+                Ok(Some(top.into_value().into_vec()))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// merges sorted distinct rows into the top-N of the group
+    fn merge_rows<'a>(
+        &self,
+        acc: &mut RefAccumStateRow,
+        rows: impl IntoIterator<Item = &'a [u8]>,
+    ) -> Result<()> {
+        let top = self.top_mut(acc)?;
+        let merged = encode_rows(
+            decode_rows(top.value())
+                .merge(rows)
+                .dedup()
+                .take(self.limit),
+        );
+        self.sub_mem_used(top.mem_size());
+        top.value = merged.into();
+        self.add_mem_used(top.mem_size());
+        Ok(())
+    }
+
+    /// inserts a row into the top-N of the group, the state is unchanged if
+    /// the row is duplicated or ranked out of the top-N
+    fn insert_row(&self, acc: &mut RefAccumStateRow, row: &[u8]) -> Result<()> {
+        let top = self.top_mut(acc)?;
+        let rows = decode_rows(top.value()).collect::<Vec<_>>();
+        let pos = match rows.binary_search(&row) {
+            Err(pos) if pos < self.limit => pos,
+            _ => return Ok(()),
+        };
+        let inserted = encode_rows(
+            rows[..pos]
+                .iter()
+                .copied()
+                .chain([row])
+                .chain(rows[pos..].iter().copied())
+                .take(self.limit),
+        );
+        self.sub_mem_used(top.mem_size());
+        top.value = inserted.into();
+        self.add_mem_used(top.mem_size());
+        Ok(())
+    }
+
+    /// builds output array from top-N rows of each group, a missing group is
+    /// output as null
+    fn build_output_array(&self, groups: Vec<Option<Vec<u8>>>) -> Result<ArrayRef> {
+        let row_converter = self.row_converter.lock();
+        let row_parser = row_converter.parser();
+        let values = row_converter.convert_rows(
+            groups
+                .iter()
+                .flatten()
+                .flat_map(|top| decode_rows(&top[..]))
+                .map(|row| row_parser.parse(row)),
+        )?;
+
+        let mut offset = 0;
+        let groups = groups
+            .iter()
+            .map(|group| {
+                group.as_ref().map(|top| {
+                    let len = decode_rows(top).count();
+                    offset += len;
+                    values[0].slice(offset - len, len)
+                })
+            })
+            .collect();
+        build_list(&self.data_type, groups)
+    }
+}
+
+/// iterates rows from the encoded top-N, rows are encoded as
+/// [len: u32][row bytes]
+fn decode_rows(encoded: &[u8]) -> impl Iterator<Item = &[u8]> {
+    let mut pos = 0;
+    std::iter::from_fn(move || {
+        if pos >= encoded.len() {
+            return None;
+        }
+        let len = u32::from_le_bytes(encoded[pos..][..4].try_into().unwrap()) as usize;
+        let row = &encoded[pos + 4..][..len];
+        pos += 4 + len;
+        Some(row)
+    })
+}
+
+fn encode_rows<'a>(rows: impl IntoIterator<Item = &'a [u8]>) -> Vec<u8> {
+    let mut encoded = vec![];
+    for row in rows {
+        encoded.extend_from_slice(&(row.len() as u32).to_le_bytes());
+        encoded.extend_from_slice(row);
+    }
+    encoded
+}
+
+impl Debug for AggTopDistinct {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "TopDistinct({:?}, {}, {})",
+            self.child,
+            self.limit,
+            if self.descending { "desc" } else { "asc" },
+        )
+    }
+}
+
+impl Agg for AggTopDistinct {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn exprs(&self) -> Vec<Arc<dyn PhysicalExpr>> {
+        vec![self.child.clone()]
+    }
+
+    fn with_new_exprs(&self, exprs: Vec<Arc<dyn PhysicalExpr>>) -> Result<Arc<dyn Agg>> {
+        Ok(Arc::new(Self::try_new(
+            exprs[0].clone(),
+            self.data_type.clone(),
+            self.arg_type.clone(),
+            self.limit,
+            self.descending,
+        )?))
+    }
+
+    fn data_type(&self) -> &DataType {
+        &self.data_type
+    }
+
+    fn nullable(&self) -> bool {
+        true
+    }
+
+    fn accums_initial(&self) -> &[AccumInitialValue] {
+        &self.accum_initial
+    }
+
+    fn increase_acc_mem_used(&self, acc: &mut RefAccumStateRow) {
+        if let Some(v) = acc.dyn_value(self.accum_state_val_addr) {
+            self.add_mem_used(v.mem_size());
+        }
+    }
+
+    fn decrease_acc_mem_used(&self, acc: &mut RefAccumStateRow) {
+        if let Some(v) = acc.dyn_value(self.accum_state_val_addr) {
+            self.sub_mem_used(v.mem_size());
+        }
+    }
+
+    fn partial_update(
+        &self,
+        acc: &mut RefAccumStateRow,
+        values: &[ArrayRef],
+        row_idx: usize,
+    ) -> Result<()> {
+        if values[0].is_valid(row_idx) {
+            let rows = self
+                .row_converter
+                .lock()
+                .convert_columns(&[values[0].slice(row_idx, 1)])?;
+            self.insert_row(acc, rows.row(0).as_ref())?;
+        }
+        Ok(())
+    }
+
+    fn partial_batch_update(
+        &self,
+        accs: &mut [RefAccumStateRow],
+        values: &[ArrayRef],
+    ) -> Result<()> {
+        let rows = self
+            .row_converter
+            .lock()
+            .convert_columns(&[values[0].clone()])?;
+        for (row_idx, acc) in accs.iter_mut().enumerate() {
+            if values[0].is_valid(row_idx) {
+                self.insert_row(acc, rows.row(row_idx).as_ref())?;
+            }
+        }
+        Ok(())
+    }
+
+    fn partial_update_all(&self, acc: &mut RefAccumStateRow, values: &[ArrayRef]) -> Result<()> {
+        // sorts the whole batch and merges only its own top-N into the group
+        let rows = self
+            .row_converter
+            .lock()
+            .convert_columns(&[values[0].clone()])?;
+        let batch_top = (0..values[0].len())
+            .filter(|&row_idx| values[0].is_valid(row_idx))
+            .map(|row_idx| rows.row(row_idx))
+            .sorted_unstable()
+            .dedup()
+            .take(self.limit)
+            .collect::<Vec<_>>();
+        self.merge_rows(acc, batch_top.iter().map(|row| row.as_ref()))
+    }
+
+    fn partial_merge(
+        &self,
+        acc: &mut RefAccumStateRow,
+        merging_acc: &mut RefAccumStateRow,
+    ) -> Result<()> {
+        if let Some(merging_top) = self.take_top(merging_acc)? {
+            self.merge_rows(acc, decode_rows(&merging_top))?;
+        }
+        Ok(())
+    }
+
+    fn final_merge(&self, acc: &mut RefAccumStateRow) -> Result<ScalarValue> {
+        let output_array = self.build_output_array(vec![self.take_top(acc)?])?;
+        ScalarValue::try_from_array(&output_array, 0)
+    }
+
+    fn final_batch_merge(&self, accs: &mut [RefAccumStateRow]) -> Result<ArrayRef> {
+        let groups = accs
+            .iter_mut()
+            .map(|acc| self.take_top(acc))
+            .collect::<Result<Vec<_>>>()?;
+        self.build_output_array(groups)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use arrow::{array::*, datatypes::*};
+    use datafusion::{common::Result, physical_expr::expressions::Column};
+
+    use crate::agg::{
+        acc::{create_acc_from_initial_value, AccumStateRow},
+        collect_set::AggCollectSet,
+        top_distinct::AggTopDistinct,
+        Agg, WithAggBufAddrs,
+    };
+
+    /// runs the top_distinct and the naive composition of collect_set and
+    /// sort_array on the same groups, and compares the results
+    fn check_with_naive(
+        values: ArrayRef,
+        group_ids: &[usize],
+        limit: usize,
+        descending: bool,
+    ) -> Result<()> {
+        let arg_type = values.data_type().clone();
+        let list_type = DataType::new_list(arg_type.clone(), true);
+        let num_groups = group_ids.iter().max().map(|&i| i + 1).unwrap_or(0);
+        let group_values = (0..num_groups)
+            .map(|group_id| {
+                let indices = UInt32Array::from_iter_values(
+                    (0..group_ids.len())
+                        .filter(|&i| group_ids[i] == group_id)
+                        .map(|i| i as u32),
+                );
+                Ok(arrow::compute::take(&values, &indices, None)?)
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let mut naive_agg = AggCollectSet::try_new(
+            Arc::new(Column::new("a", 0)),
+            list_type.clone(),
+            arg_type.clone(),
+        )?
+        .with_sorted_output(descending);
+        let (initial_acc, addrs) = create_acc_from_initial_value(naive_agg.accums_initial())?;
+        naive_agg.set_accum_state_val_addrs(&addrs);
+        let mut naive_accs = vec![initial_acc.clone(); num_groups];
+        for (acc, values) in naive_accs.iter_mut().zip(&group_values) {
+            naive_agg.partial_update_all(&mut acc.as_mut(), &[values.clone()])?;
+        }
+        let mut ref_naive_accs = naive_accs
+            .iter_mut()
+            .map(|acc| acc.as_mut())
+            .collect::<Vec<_>>();
+        let naive_output = naive_agg.final_batch_merge(&mut ref_naive_accs)?;
+
+        let mut agg = AggTopDistinct::try_new(
+            Arc::new(Column::new("a", 0)),
+            list_type,
+            arg_type,
+            limit,
+            descending,
+        )?;
+        let (initial_acc, addrs) = create_acc_from_initial_value(agg.accums_initial())?;
+        agg.set_accum_state_val_addrs(&addrs);
+
+        // first half: batch update, then merged into groups
+        // second half: per-group updates into partials, then merged
+        let half = values.len() / 2;
+        let mut accs = vec![initial_acc.clone(); num_groups];
+        let mut row_accs = vec![initial_acc.clone(); half];
+        let mut ref_row_accs = row_accs
+            .iter_mut()
+            .map(|acc| acc.as_mut())
+            .collect::<Vec<_>>();
+        agg.partial_batch_update(&mut ref_row_accs, &[values.slice(0, half)])?;
+        for (row_acc, &group_id) in ref_row_accs.iter_mut().zip(&group_ids[..half]) {
+            agg.partial_merge(&mut accs[group_id].as_mut(), row_acc)?;
+        }
+
+        for group_id in 0..num_groups {
+            let indices = UInt32Array::from_iter_values(
+                (half..group_ids.len())
+                    .filter(|&i| group_ids[i] == group_id)
+                    .map(|i| i as u32),
+            );
+            let group_values = arrow::compute::take(&values, &indices, None)?;
+            let mut partial_acc = initial_acc.clone();
+            if group_id % 2 == 0 {
+                agg.partial_update_all(&mut partial_acc.as_mut(), &[group_values])?;
+            } else {
+                for row_idx in 0..group_values.len() {
+                    agg.partial_update(
+                        &mut partial_acc.as_mut(),
+                        &[group_values.clone()],
+                        row_idx,
+                    )?;
+                }
+            }
+            agg.partial_merge(&mut accs[group_id].as_mut(), &mut partial_acc.as_mut())?;
+        }
+        let mut ref_accs = accs.iter_mut().map(|acc| acc.as_mut()).collect::<Vec<_>>();
+        let output = agg.final_batch_merge(&mut ref_accs)?;
+
+        let output = output.as_list::<i32>();
+        let naive_output = naive_output.as_list::<i32>();
+        for group_id in 0..num_groups {
+            let expected = naive_output.value(group_id);
+            let expected = expected.slice(0, expected.len().min(limit));
+            assert_eq!(
+                output.value(group_id).to_data(),
+                expected.to_data(),
+                "group {group_id}, limit {limit}, descending {descending}"
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_top_distinct_with_naive() -> Result<()> {
+        // xorshift random generator
+        let mut seed = 0x2545F4914F6CDD1Du64;
+        let mut rand = move || {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            seed
+        };
+        let num_rows = 3000;
+        let group_ids = (0..num_rows)
+            .map(|_| (rand() % 7) as usize)
+            .collect::<Vec<_>>();
+        let ints: ArrayRef = Arc::new(Int64Array::from_iter((0..num_rows).map(|_| {
+            let v = rand() % 500;
+            Some(v as i64 - 250).filter(|_| v % 11 != 0)
+        })));
+        let strs: ArrayRef = Arc::new(StringArray::from_iter((0..num_rows).map(|_| {
+            let v = rand() % 300;
+            Some(format!("s{v}")).filter(|_| v % 13 != 0)
+        })));
+
+        for values in [ints, strs] {
+            for limit in [1, 5, 1000] {
+                for descending in [false, true] {
+                    check_with_naive(values.clone(), &group_ids, limit, descending)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
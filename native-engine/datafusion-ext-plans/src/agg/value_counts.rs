@@ -0,0 +1,373 @@
+// Copyright 2022 The Blaze Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::{
+    any::Any,
+    fmt::{Debug, Formatter},
+    sync::{atomic::AtomicUsize, Arc},
+};
+
+use arrow::{array::*, buffer::OffsetBuffer, datatypes::*};
+use datafusion::{
+    common::{Result, ScalarValue},
+    physical_expr::PhysicalExpr,
+};
+use datafusion_ext_commons::{
+    df_execution_err, df_unimplemented_err, downcast_any, io::is_scalar_serde_supported,
+};
+
+use crate::agg::{
+    acc::{
+        AccumInitialValue, AccumStateRow, AccumStateValAddr, AggDynValue, AggDynValueCounts,
+        RefAccumStateRow, AGG_DYN_SET_HASH_SEED,
+    },
+    Agg, WithAggBufAddrs, WithMemTracking,
+};
+
+/// counts occurrences of each distinct value in a group, like collect_set()
+/// with frequencies. output is a map of value -> count, null values are
+/// ignored
+pub struct AggValueCounts {
+    child: Arc<dyn PhysicalExpr>,
+    data_type: DataType,
+    arg_type: DataType,
+    accum_initial: [AccumInitialValue; 1],
+    accum_state_val_addr: AccumStateValAddr,
+    mem_used_tracker: AtomicUsize,
+}
+
+impl WithAggBufAddrs for AggValueCounts {
+    fn set_accum_state_val_addrs(&mut self, accum_state_val_addrs: &[AccumStateValAddr]) {
+        self.accum_state_val_addr = accum_state_val_addrs[0];
+    }
+}
+
+impl WithMemTracking for AggValueCounts {
+    fn mem_used_tracker(&self) -> &AtomicUsize {
+        &self.mem_used_tracker
+    }
+}
+
+impl AggValueCounts {
+    pub fn try_new(child: Arc<dyn PhysicalExpr>, arg_type: DataType) -> Result<Self> {
+        if !is_scalar_serde_supported(&arg_type) {
+            return df_unimplemented_err!(
+                "value_counts does not support argument type: {arg_type}"
+            );
+        }
+        Ok(Self {
+            child,
+            data_type: Self::return_type(&arg_type),
+            accum_initial: [AccumInitialValue::DynValueCounts(
+                arg_type.clone(),
+                AGG_DYN_SET_HASH_SEED,
+            )],
+            arg_type,
+            accum_state_val_addr: AccumStateValAddr::default(),
+            mem_used_tracker: AtomicUsize::new(0),
+        })
+    }
+
+    /// returns Map<arg_type, Int64>
+    pub fn return_type(arg_type: &DataType) -> DataType {
+        DataType::Map(Arc::new(Self::entries_field(arg_type)), false)
+    }
+
+    pub fn arg_type(&self) -> &DataType {
+        &self.arg_type
+    }
+
+    fn entries_field(arg_type: &DataType) -> Field {
+        Field::new(
+            "entries",
+            DataType::Struct(Fields::from(vec![
+                Field::new("key", arg_type.clone(), false),
+                Field::new("value", DataType::Int64, false),
+            ])),
+            false,
+        )
+    }
+
+    fn value_counts_mut<'a>(
+        &self,
+        acc: &'a mut RefAccumStateRow,
+    ) -> Result<&'a mut AggDynValueCounts> {
+        let w = acc.dyn_value_mut(self.accum_state_val_addr);
+        if w.is_none() {
+            let new_value_counts = AggDynValueCounts::with_hash_seed(AGG_DYN_SET_HASH_SEED);
+            self.add_mem_used(new_value_counts.mem_size());
+            *w = Some(Box::new(new_value_counts));
+        }
+        downcast_any!(w.as_mut().unwrap(), mut AggDynValueCounts)
+    }
+
+    fn take_value_counts(&self, acc: &mut RefAccumStateRow) -> Result<Option<AggDynValueCounts>> {
+        match std::mem::take(acc.dyn_value_mut(self.accum_state_val_addr)) {
+            Some(w) => {
+                self.sub_mem_used(w.mem_size());
+                let value_counts = w
+                    .as_any_boxed()
+                    .downcast::<AggDynValueCounts>()
+                    .or_else(|_| df_execution_err!("error downcasting to AggDynValueCounts"))?;
+                Ok(Some(*value_counts))
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn add_values(
+        &self,
+        acc: &mut RefAccumStateRow,
+        values: &ArrayRef,
+        row_indices: impl Iterator<Item = usize>,
+    ) -> Result<()> {
+        let value_counts = self.value_counts_mut(acc)?;
+        self.sub_mem_used(value_counts.mem_size());
+        for i in row_indices {
+            if values.is_valid(i) {
+                value_counts.add(&ScalarValue::try_from_array(values, i)?, false, 1)?;
+            }
+        }
+        self.add_mem_used(value_counts.mem_size());
+        Ok(())
+    }
+}
+
+impl Debug for AggValueCounts {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "ValueCounts({:?})", self.child)
+    }
+}
+
+impl Agg for AggValueCounts {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn exprs(&self) -> Vec<Arc<dyn PhysicalExpr>> {
+        vec![self.child.clone()]
+    }
+
+    fn with_new_exprs(&self, exprs: Vec<Arc<dyn PhysicalExpr>>) -> Result<Arc<dyn Agg>> {
+        Ok(Arc::new(Self::try_new(
+            exprs[0].clone(),
+            self.arg_type.clone(),
+        )?))
+    }
+
+    fn data_type(&self) -> &DataType {
+        &self.data_type
+    }
+
+    fn nullable(&self) -> bool {
+        false
+    }
+
+    fn accums_initial(&self) -> &[AccumInitialValue] {
+        &self.accum_initial
+    }
+
+    fn increase_acc_mem_used(&self, acc: &mut RefAccumStateRow) {
+        if let Some(v) = acc.dyn_value(self.accum_state_val_addr) {
+            self.add_mem_used(v.mem_size());
+        }
+    }
+
+    fn decrease_acc_mem_used(&self, acc: &mut RefAccumStateRow) {
+        if let Some(v) = acc.dyn_value(self.accum_state_val_addr) {
+            self.sub_mem_used(v.mem_size());
+        }
+    }
+
+    fn partial_update(
+        &self,
+        acc: &mut RefAccumStateRow,
+        values: &[ArrayRef],
+        row_idx: usize,
+    ) -> Result<()> {
+        if values[0].is_valid(row_idx) {
+            self.add_values(acc, &values[0], std::iter::once(row_idx))?;
+        }
+        Ok(())
+    }
+
+    fn partial_update_all(&self, acc: &mut RefAccumStateRow, values: &[ArrayRef]) -> Result<()> {
+        self.add_values(acc, &values[0], 0..values[0].len())
+    }
+
+    fn partial_merge(
+        &self,
+        acc: &mut RefAccumStateRow,
+        merging_acc: &mut RefAccumStateRow,
+    ) -> Result<()> {
+        let Some(mut merging_value_counts) = self.take_value_counts(merging_acc)? else {
+            return Ok(());
+        };
+        let value_counts = self.value_counts_mut(acc)?;
+        self.sub_mem_used(value_counts.mem_size());
+        value_counts.merge(&mut merging_value_counts);
+        self.add_mem_used(value_counts.mem_size());
+        Ok(())
+    }
+
+    fn shrink_acc_to_fit(&self, acc: &mut RefAccumStateRow) {
+        if let Some(v) = acc.dyn_value_mut(self.accum_state_val_addr) {
+            self.sub_mem_used(v.mem_size());
+            v.shrink_to_fit();
+            self.add_mem_used(v.mem_size());
+        }
+    }
+
+    fn final_merge(&self, acc: &mut RefAccumStateRow) -> Result<ScalarValue> {
+        let array = self.final_batch_merge(std::slice::from_mut(acc))?;
+        ScalarValue::try_from_array(&array, 0)
+    }
+
+    fn final_batch_merge(&self, accs: &mut [RefAccumStateRow]) -> Result<ArrayRef> {
+        if accs.is_empty() {
+            return Ok(new_empty_array(&self.data_type));
+        }
+        let mut lens = Vec::with_capacity(accs.len());
+        let mut keys = Vec::with_capacity(accs.len());
+        let mut counts = Vec::with_capacity(accs.len());
+
+        for acc in accs {
+            let value_counts = self
+                .take_value_counts(acc)?
+                .unwrap_or_else(|| AggDynValueCounts::with_hash_seed(AGG_DYN_SET_HASH_SEED));
+            let (group_keys, group_counts) = value_counts.into_arrays(&self.arg_type, false)?;
+            lens.push(group_keys.len());
+            keys.push(group_keys);
+            counts.push(Arc::new(group_counts) as ArrayRef);
+        }
+
+        let entries_field = Self::entries_field(&self.arg_type);
+        let entries_fields = match entries_field.data_type() {
+            DataType::Struct(fields) => fields.clone(),
+            _ => unreachable!(),
+        };
+        let entries = StructArray::try_new(
+            entries_fields,
+            vec![
+                arrow::compute::concat(&keys.iter().map(|k| k.as_ref()).collect::<Vec<_>>())?,
+                arrow::compute::concat(&counts.iter().map(|c| c.as_ref()).collect::<Vec<_>>())?,
+            ],
+            None,
+        )?;
+        Ok(Arc::new(MapArray::try_new(
+            Arc::new(entries_field),
+            OffsetBuffer::from_lengths(lens),
+            entries,
+            None,
+            false,
+        )?))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{collections::HashMap, io::Cursor, sync::Arc};
+
+    use arrow::{array::*, datatypes::*};
+    use datafusion::{common::Result, physical_expr::expressions::Column};
+
+    use crate::agg::{
+        acc::{
+            create_acc_from_initial_value, create_dyn_loaders_from_initial_value,
+            create_dyn_savers_from_initial_value, AccumStateRow, LoadReader, SaveWriter,
+        },
+        value_counts::AggValueCounts,
+        Agg, WithAggBufAddrs,
+    };
+
+    fn map_to_hashmap(map: &MapArray, i: usize) -> HashMap<String, i64> {
+        let entries = map.value(i);
+        let keys = entries.column(0).as_string::<i32>();
+        let counts = entries.column(1).as_primitive::<Int64Type>();
+        (0..entries.len())
+            .map(|j| (keys.value(j).to_string(), counts.value(j)))
+            .collect()
+    }
+
+    #[test]
+    fn test_value_counts() -> Result<()> {
+        let mut agg = AggValueCounts::try_new(Arc::new(Column::new("a", 0)), DataType::Utf8)?;
+        let (initial_acc, addrs) = create_acc_from_initial_value(agg.accums_initial())?;
+        agg.set_accum_state_val_addrs(&addrs);
+
+        let values1: ArrayRef = Arc::new(StringArray::from(vec![
+            Some("a"),
+            Some("b"),
+            None,
+            Some("a"),
+            Some("c"),
+        ]));
+        let values2: ArrayRef = Arc::new(StringArray::from(vec![
+            Some("b"),
+            Some("d"),
+            Some("a"),
+            Some("b"),
+        ]));
+
+        let mut acc1 = initial_acc.clone();
+        for row_idx in 0..values1.len() {
+            agg.partial_update(&mut acc1.as_mut(), &[values1.clone()], row_idx)?;
+        }
+        let mut acc2 = initial_acc.clone();
+        agg.partial_update_all(&mut acc2.as_mut(), &[values2.clone()])?;
+
+        // round-trip the second partial through spill
+        let mut buf = vec![];
+        let savers = create_dyn_savers_from_initial_value(agg.accums_initial())?;
+        let loaders = create_dyn_loaders_from_initial_value(agg.accums_initial())?;
+        let mut save_writer = SaveWriter(Box::new(Cursor::new(&mut buf)));
+        savers[0](
+            &mut save_writer,
+            std::mem::take(acc2.as_mut().dyn_value_mut(addrs[0])),
+        )?;
+        drop(save_writer);
+        let mut load_reader = LoadReader(Box::new(Cursor::new(&buf)));
+        let mut loaded_acc = initial_acc.clone();
+        *loaded_acc.as_mut().dyn_value_mut(addrs[0]) = loaders[0](&mut load_reader)?;
+        agg.partial_merge(&mut acc1.as_mut(), &mut loaded_acc.as_mut())?;
+
+        let mut empty_acc = initial_acc.clone();
+        let output = agg.final_batch_merge(&mut [acc1.as_mut(), empty_acc.as_mut()])?;
+        let output = output.as_map();
+        assert_eq!(output.len(), 2);
+
+        // keys are the distinct values
+        let mut keys = output
+            .value(0)
+            .column(0)
+            .as_string::<i32>()
+            .iter()
+            .map(|k| k.unwrap().to_string())
+            .collect::<Vec<_>>();
+        keys.sort();
+        assert_eq!(keys, vec!["a", "b", "c", "d"]);
+
+        assert_eq!(
+            map_to_hashmap(output, 0),
+            HashMap::from([
+                ("a".to_string(), 3),
+                ("b".to_string(), 3),
+                ("c".to_string(), 1),
+                ("d".to_string(), 1),
+            ]),
+        );
+        assert!(map_to_hashmap(output, 1).is_empty());
+        Ok(())
+    }
+}
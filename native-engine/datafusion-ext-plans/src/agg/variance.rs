@@ -0,0 +1,333 @@
+// Copyright 2022 The Blaze Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::{
+    any::Any,
+    fmt::{Debug, Formatter},
+    sync::{atomic::AtomicUsize, Arc},
+};
+
+use arrow::{array::*, datatypes::*};
+use datafusion::{
+    common::{Result, ScalarValue},
+    physical_expr::PhysicalExpr,
+};
+
+use crate::agg::{
+    acc::{AccumInitialValue, AccumStateRow, AccumStateValAddr, RefAccumStateRow},
+    Agg, WithAggBufAddrs, WithMemTracking,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VarianceMode {
+    VarSamp,
+    VarPop,
+    StddevSamp,
+    StddevPop,
+}
+
+/// variance and standard deviation, accumulated as (count, mean, m2) with
+/// Welford's algorithm for numerical stability. all states are fixed values,
+/// so accumulators can be spilled like other fixed-sized aggregates
+pub struct AggVariance {
+    child: Arc<dyn PhysicalExpr>,
+    mode: VarianceMode,
+    data_type: DataType,
+    accums_initial: [AccumInitialValue; 3],
+    accum_state_val_addrs: [AccumStateValAddr; 3],
+    mem_used_tracker: AtomicUsize,
+}
+
+impl WithAggBufAddrs for AggVariance {
+    fn set_accum_state_val_addrs(&mut self, accum_state_val_addrs: &[AccumStateValAddr]) {
+        self.accum_state_val_addrs
+            .copy_from_slice(&accum_state_val_addrs[..3]);
+    }
+}
+
+impl WithMemTracking for AggVariance {
+    fn mem_used_tracker(&self) -> &AtomicUsize {
+        &self.mem_used_tracker
+    }
+}
+
+impl AggVariance {
+    pub fn try_new(child: Arc<dyn PhysicalExpr>, mode: VarianceMode) -> Result<Self> {
+        Ok(Self {
+            child,
+            mode,
+            data_type: DataType::Float64,
+            accums_initial: [
+                AccumInitialValue::Scalar(ScalarValue::Int64(Some(0))),
+                AccumInitialValue::Scalar(ScalarValue::Float64(Some(0.0))),
+                AccumInitialValue::Scalar(ScalarValue::Float64(Some(0.0))),
+            ],
+            accum_state_val_addrs: [AccumStateValAddr::default(); 3],
+            mem_used_tracker: AtomicUsize::new(0),
+        })
+    }
+
+    pub fn mode(&self) -> VarianceMode {
+        self.mode
+    }
+
+    fn moments(&self, acc: &RefAccumStateRow) -> Moments {
+        let [count_addr, mean_addr, m2_addr] = self.accum_state_val_addrs;
+        Moments {
+            count: acc.fixed_value::<i64>(count_addr),
+            mean: acc.fixed_value::<f64>(mean_addr),
+            m2: acc.fixed_value::<f64>(m2_addr),
+        }
+    }
+
+    fn set_moments(&self, acc: &mut RefAccumStateRow, moments: Moments) {
+        let [count_addr, mean_addr, m2_addr] = self.accum_state_val_addrs;
+        acc.set_fixed_value::<i64>(count_addr, moments.count);
+        acc.set_fixed_value::<f64>(mean_addr, moments.mean);
+        acc.set_fixed_value::<f64>(m2_addr, moments.m2);
+    }
+
+    fn evaluate(&self, moments: Moments) -> Option<f64> {
+        let variance = match self.mode {
+            VarianceMode::VarSamp | VarianceMode::StddevSamp if moments.count > 1 => {
+                moments.m2 / (moments.count - 1) as f64
+            }
+            VarianceMode::VarPop | VarianceMode::StddevPop if moments.count > 0 => {
+                moments.m2 / moments.count as f64
+            }
+            _ => return None,
+        };
+        Some(match self.mode {
+            VarianceMode::StddevSamp | VarianceMode::StddevPop => variance.sqrt(),
+            _ => variance,
+        })
+    }
+}
+
+#[derive(Clone, Copy, Default)]
+struct Moments {
+    count: i64,
+    mean: f64,
+    m2: f64,
+}
+
+impl Moments {
+    fn update(&mut self, value: f64) {
+        self.count += 1;
+        let delta = value - self.mean;
+        self.mean += delta / self.count as f64;
+        self.m2 += delta * (value - self.mean);
+    }
+
+    /// combines moments of two disjoint sets (Chan et al.)
+    fn merge(&mut self, other: Moments) {
+        if other.count == 0 {
+            return;
+        }
+        if self.count == 0 {
+            *self = other;
+            return;
+        }
+        let count = self.count + other.count;
+        let delta = other.mean - self.mean;
+        self.mean += delta * other.count as f64 / count as f64;
+        self.m2 += other.m2 + delta * delta * self.count as f64 * other.count as f64 / count as f64;
+        self.count = count;
+    }
+}
+
+impl Debug for AggVariance {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}({:?})", self.mode, self.child)
+    }
+}
+
+impl Agg for AggVariance {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn exprs(&self) -> Vec<Arc<dyn PhysicalExpr>> {
+        vec![self.child.clone()]
+    }
+
+    fn with_new_exprs(&self, exprs: Vec<Arc<dyn PhysicalExpr>>) -> Result<Arc<dyn Agg>> {
+        Ok(Arc::new(Self::try_new(exprs[0].clone(), self.mode)?))
+    }
+
+    fn data_type(&self) -> &DataType {
+        &self.data_type
+    }
+
+    fn nullable(&self) -> bool {
+        true
+    }
+
+    fn accums_initial(&self) -> &[AccumInitialValue] {
+        &self.accums_initial
+    }
+
+    fn prepare_partial_args(&self, partial_inputs: &[ArrayRef]) -> Result<Vec<ArrayRef>> {
+        // cast arg1 to float64
+        Ok(vec![datafusion_ext_commons::cast::cast(
+            &partial_inputs[0],
+            &DataType::Float64,
+        )?])
+    }
+
+    fn increase_acc_mem_used(&self, _acc: &mut RefAccumStateRow) {
+        // do nothing
+    }
+
+    fn decrease_acc_mem_used(&self, _acc: &mut RefAccumStateRow) {
+        // do nothing
+    }
+
+    fn partial_update(
+        &self,
+        acc: &mut RefAccumStateRow,
+        values: &[ArrayRef],
+        row_idx: usize,
+    ) -> Result<()> {
+        let values = values[0].as_primitive::<Float64Type>();
+        if values.is_valid(row_idx) {
+            let mut moments = self.moments(acc);
+            moments.update(values.value(row_idx));
+            self.set_moments(acc, moments);
+        }
+        Ok(())
+    }
+
+    fn partial_update_all(&self, acc: &mut RefAccumStateRow, values: &[ArrayRef]) -> Result<()> {
+        let mut batch_moments = Moments::default();
+        for value in values[0].as_primitive::<Float64Type>().iter().flatten() {
+            batch_moments.update(value);
+        }
+        let mut moments = self.moments(acc);
+        moments.merge(batch_moments);
+        self.set_moments(acc, moments);
+        Ok(())
+    }
+
+    fn partial_merge(
+        &self,
+        acc: &mut RefAccumStateRow,
+        merging_acc: &mut RefAccumStateRow,
+    ) -> Result<()> {
+        let mut moments = self.moments(acc);
+        moments.merge(self.moments(merging_acc));
+        self.set_moments(acc, moments);
+        Ok(())
+    }
+
+    fn final_merge(&self, acc: &mut RefAccumStateRow) -> Result<ScalarValue> {
+        Ok(ScalarValue::Float64(self.evaluate(self.moments(acc))))
+    }
+
+    fn final_batch_merge(&self, accs: &mut [RefAccumStateRow]) -> Result<ArrayRef> {
+        Ok(Arc::new(
+            accs.iter()
+                .map(|acc| self.evaluate(self.moments(acc)))
+                .collect::<Float64Array>(),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use arrow::{array::*, datatypes::*};
+    use datafusion::{common::Result, physical_expr::expressions::Column};
+
+    use crate::agg::{
+        acc::create_acc_from_initial_value,
+        variance::{AggVariance, VarianceMode},
+        Agg, WithAggBufAddrs,
+    };
+
+    #[test]
+    fn test_variance() -> Result<()> {
+        // large values with small variance, sensitive to cancellation
+        let values1: ArrayRef = Arc::new(Float64Array::from(vec![
+            1e9 + 4.0,
+            1e9 + 7.0,
+            1e9 + 13.0,
+            1e9 + 16.0,
+        ]));
+        let values2: ArrayRef = Arc::new(Float64Array::from(vec![
+            Some(2.5),
+            Some(-3.25),
+            Some(7.0),
+            None,
+            Some(0.125),
+        ]));
+        let values3: ArrayRef = Arc::new(Float64Array::from(vec![Some(42.0), None]));
+
+        // reference values of numpy.var/numpy.std with ddof=0/1
+        let expected = [
+            (
+                VarianceMode::VarPop,
+                [Some(22.5), Some(13.9169921875), Some(0.0)],
+            ),
+            (
+                VarianceMode::VarSamp,
+                [Some(30.0), Some(18.555989583333332), None],
+            ),
+            (
+                VarianceMode::StddevPop,
+                [Some(4.743416490252569), Some(3.7305485102729867), Some(0.0)],
+            ),
+            (
+                VarianceMode::StddevSamp,
+                [Some(5.477225575051661), Some(4.3076663732621325), None],
+            ),
+        ];
+
+        for (mode, expected) in expected {
+            let mut agg = AggVariance::try_new(Arc::new(Column::new("a", 0)), mode)?;
+            let (initial_acc, addrs) = create_acc_from_initial_value(agg.accums_initial())?;
+            agg.set_accum_state_val_addrs(&addrs);
+
+            // group 0: updated in two partials and merged
+            // group 1: updated row by row, including null
+            // group 2: single value
+            // group 3: no values
+            let mut accs = vec![initial_acc.clone(); 4];
+            let mut merging_acc = initial_acc.clone();
+            agg.partial_update_all(&mut accs[0].as_mut(), &[values1.slice(0, 3)])?;
+            agg.partial_update(&mut merging_acc.as_mut(), &[values1.clone()], 3)?;
+            agg.partial_merge(&mut accs[0].as_mut(), &mut merging_acc.as_mut())?;
+            for row_idx in 0..values2.len() {
+                agg.partial_update(&mut accs[1].as_mut(), &[values2.clone()], row_idx)?;
+            }
+            agg.partial_update_all(&mut accs[2].as_mut(), &[values3.clone()])?;
+
+            let mut ref_accs = accs.iter_mut().map(|acc| acc.as_mut()).collect::<Vec<_>>();
+            let results = agg.final_batch_merge(&mut ref_accs)?;
+            let results = results.as_primitive::<Float64Type>();
+            for (i, expected) in expected.into_iter().chain([None]).enumerate() {
+                let result = results.is_valid(i).then(|| results.value(i));
+                match (result, expected) {
+                    (Some(result), Some(expected)) => assert!(
+                        (result - expected).abs() <= expected.abs() * 1e-9,
+                        "{mode:?} group {i}: {result} != {expected}"
+                    ),
+                    (result, expected) => assert_eq!(result, expected, "{mode:?} group {i}"),
+                }
+            }
+        }
+        Ok(())
+    }
+}
@@ -28,7 +28,9 @@ use datafusion::{
     execution::context::TaskContext,
     physical_expr::PhysicalSortExpr,
     physical_plan::{
-        metrics::{BaselineMetrics, ExecutionPlanMetricsSet, MetricsSet},
+        metrics::{
+            BaselineMetrics, Count, ExecutionPlanMetricsSet, Gauge, Metric, MetricValue, MetricsSet,
+        },
         stream::RecordBatchStreamAdapter,
         DisplayAs, DisplayFormatType, ExecutionPlan, Partitioning, SendableRecordBatchStream,
     },
@@ -43,6 +45,8 @@ use crate::{
         acc::OwnedAccumStateRow,
         agg_context::AggContext,
         agg_table::{AggTable, InMemMode},
+        collect_list::AggCollectList,
+        collect_set::AggCollectSet,
         AggExecMode, AggExpr, GroupingExpr,
     },
     common::{
@@ -141,7 +145,63 @@ impl ExecutionPlan for AggExec {
     }
 
     fn metrics(&self) -> Option<MetricsSet> {
-        Some(self.metrics.clone_inner())
+        let mut metrics = self.metrics.clone_inner();
+
+        // counters of collect_set() are kept in the aggregates, which are shared
+        // by all partitions
+        let collect_sets = self
+            .agg_ctx
+            .aggs
+            .iter()
+            .filter_map(|agg| agg.agg.as_any().downcast_ref::<AggCollectSet>())
+            .collect::<Vec<_>>();
+        if !collect_sets.is_empty() {
+            let num_set_promotions = Count::new();
+            for collect_set in collect_sets {
+                num_set_promotions.add(collect_set.num_set_promotions().value());
+            }
+            metrics.push(Arc::new(Metric::new(
+                MetricValue::Count {
+                    name: "set_promotions".into(),
+                    count: num_set_promotions,
+                },
+                None,
+            )));
+        }
+
+        // serialized bytes of collect_list(), telling a skewed group from broad
+        // growth when memory blows up
+        let collect_lists = self
+            .agg_ctx
+            .aggs
+            .iter()
+            .filter_map(|agg| agg.agg.as_any().downcast_ref::<AggCollectList>())
+            .collect::<Vec<_>>();
+        if !collect_lists.is_empty() {
+            let total_raw_bytes = Gauge::new();
+            let max_group_raw_bytes = Gauge::new();
+            for collect_list in collect_lists {
+                total_raw_bytes.add(collect_list.total_raw_bytes());
+                max_group_raw_bytes.set(
+                    max_group_raw_bytes
+                        .value()
+                        .max(collect_list.max_group_raw_bytes()),
+                );
+            }
+            for (name, gauge) in [
+                ("list_raw_bytes", total_raw_bytes),
+                ("list_max_group_raw_bytes", max_group_raw_bytes),
+            ] {
+                metrics.push(Arc::new(Metric::new(
+                    MetricValue::Gauge {
+                        name: name.into(),
+                        gauge,
+                    },
+                    None,
+                )));
+            }
+        }
+        Some(metrics)
     }
 
     fn statistics(&self) -> Result<Statistics> {
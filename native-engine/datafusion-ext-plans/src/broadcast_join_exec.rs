@@ -60,6 +60,8 @@ pub struct BroadcastJoinExec {
     join_type: JoinType,
     /// Optional filter before outputting
     join_filter: Option<JoinFilter>,
+    /// Whether null keys are matched as equal, like null-safe equality
+    null_equals_null: bool,
     /// The schema once the join is applied
     schema: SchemaRef,
     /// Execution metrics
@@ -73,6 +75,7 @@ impl BroadcastJoinExec {
         on: JoinOn,
         join_type: JoinType,
         join_filter: Option<JoinFilter>,
+        null_equals_null: bool,
     ) -> Result<Self> {
         if matches!(
             join_type,
@@ -95,6 +98,7 @@ impl BroadcastJoinExec {
             on,
             join_type,
             join_filter,
+            null_equals_null,
             schema,
             metrics: ExecutionPlanMetricsSet::new(),
         })
@@ -132,6 +136,7 @@ impl ExecutionPlan for BroadcastJoinExec {
             self.on.iter().cloned().collect(),
             self.join_type,
             self.join_filter.clone(),
+            self.null_equals_null,
         )?))
     }
 
@@ -148,6 +153,7 @@ impl ExecutionPlan for BroadcastJoinExec {
             self.on.clone(),
             self.join_type,
             self.join_filter.clone(),
+            self.null_equals_null,
             BaselineMetrics::new(&self.metrics, partition),
         );
 
@@ -180,6 +186,7 @@ async fn execute_broadcast_join(
     on: JoinOn,
     join_type: JoinType,
     join_filter: Option<JoinFilter>,
+    null_equals_null: bool,
     metrics: BaselineMetrics,
 ) -> Result<SendableRecordBatchStream> {
     let enabled_fallback_to_smj = conf::BHJ_FALLBACKS_TO_SMJ_ENABLE.value()?;
@@ -245,7 +252,7 @@ async fn execute_broadcast_join(
                 join_filter,
                 &join_type,
                 PartitionMode::CollectLeft,
-                false,
+                null_equals_null,
             )?);
             log::info!("BroadcastJoin is using hash join mode: {:?}", &join);
 
@@ -298,6 +305,7 @@ async fn execute_broadcast_join(
                 join_type,
                 join_filter,
                 sort_exprs.into_iter().map(|se| se.options).collect(),
+                null_equals_null,
             )?);
             log::info!("BroadcastJoin is using sort-merge join mode: {:?}", &join);
 
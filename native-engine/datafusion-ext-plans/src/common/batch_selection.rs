@@ -61,11 +61,31 @@ fn take_batch_internal(batch: RecordBatch, indices: UInt32Array) -> Result<Recor
     Ok(taken)
 }
 
+/// interleaves rows of batches by (batch_idx, row_idx) indices. if all indices
+/// are consecutive rows of a single batch, which is common in semi joins when
+/// all rows are matched, the batch is sliced without copying
 pub fn interleave_batches(
     schema: SchemaRef,
     batches: &[RecordBatch],
     indices: &[(usize, usize)],
 ) -> Result<RecordBatch> {
+    if let Some(&(batch_idx, row_idx)) = indices.first() {
+        let is_contiguous = indices
+            .iter()
+            .enumerate()
+            .all(|(i, &idx)| idx == (batch_idx, row_idx + i));
+        if is_contiguous {
+            return Ok(RecordBatch::try_new_with_options(
+                schema,
+                batches[batch_idx]
+                    .slice(row_idx, indices.len())
+                    .columns()
+                    .to_vec(),
+                &RecordBatchOptions::new().with_row_count(Some(indices.len())),
+            )?);
+        }
+    }
+
     let mut batches_arrays: Vec<Vec<ArrayRef>> = schema
         .fields()
         .iter()
@@ -94,3 +114,60 @@ pub fn interleave_batches(
         &RecordBatchOptions::new().with_row_count(Some(indices.len())),
     )?)
 }
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use arrow::{
+        array::*,
+        datatypes::{DataType, Field, Int32Type, Schema},
+        record_batch::RecordBatch,
+    };
+    use datafusion::common::Result;
+
+    use crate::common::batch_selection::interleave_batches;
+
+    #[test]
+    fn test_interleave_batches() -> Result<()> {
+        let schema = Arc::new(Schema::new(vec![Field::new("a", DataType::Int32, true)]));
+        let batches = [
+            RecordBatch::try_new(
+                schema.clone(),
+                vec![Arc::new(Int32Array::from(vec![Some(0), None, Some(2)]))],
+            )?,
+            RecordBatch::try_new(
+                schema.clone(),
+                vec![Arc::new(Int32Array::from(vec![10, 11, 12, 13]))],
+            )?,
+        ];
+
+        // (indices, expected values), contiguous rows of a single batch are
+        // sliced, others are interleaved
+        let cases: Vec<(Vec<(usize, usize)>, Vec<Option<i32>>)> = vec![
+            (
+                vec![(1, 0), (1, 1), (1, 2), (1, 3)],
+                vec![Some(10), Some(11), Some(12), Some(13)],
+            ),
+            (vec![(0, 1), (0, 2)], vec![None, Some(2)]),
+            (vec![(1, 2)], vec![Some(12)]),
+            (
+                vec![(0, 2), (1, 0), (1, 1)],
+                vec![Some(2), Some(10), Some(11)],
+            ),
+            (vec![(1, 3), (1, 2)], vec![Some(13), Some(12)]),
+            (vec![(1, 0), (1, 2)], vec![Some(10), Some(12)]),
+            (vec![], vec![]),
+        ];
+        for (indices, expected) in cases {
+            let batch = interleave_batches(schema.clone(), &batches, &indices)?;
+            assert_eq!(batch.schema(), schema);
+            assert_eq!(batch.num_rows(), expected.len());
+            assert_eq!(
+                batch.column(0).as_primitive::<Int32Type>(),
+                &Int32Array::from(expected),
+            );
+        }
+        Ok(())
+    }
+}
@@ -190,7 +190,7 @@ impl TaskOutputter for Arc<TaskContext> {
                         mem_consumer.name(),
                     );
                     let mut spill = try_new_spill(&spill_metrics)?;
-                    let mut spill_writer = spill.get_compressed_writer();
+                    let mut spill_writer = spill.get_compressed_writer()?;
 
                     // write all batches to spill, releasing all holding memory
                     while let Some(batch) = stream.next().await.transpose()? {
@@ -200,10 +200,10 @@ impl TaskOutputter for Arc<TaskContext> {
                         spill_writer.write_all(&buf)?;
                     }
                     let mut timer = baseline_metrics.elapsed_compute().timer();
-                    drop(spill_writer);
+                    spill_writer.finish()?;
 
                     // read all batches from spill and output
-                    let mut spill_reader = spill.get_compressed_reader();
+                    let mut spill_reader = spill.get_compressed_reader()?;
                     while let Some(batch) = read_one_batch(&mut spill_reader, &schema)? {
                         sender.send(Ok(batch), Some(&mut timer)).await;
                     }
@@ -15,37 +15,592 @@
 use std::{
     any::Any,
     fs::{File, OpenOptions},
-    io::{BufReader, BufWriter, Cursor, Read, Seek, Write},
+    io::{BufRead, BufReader, BufWriter, Cursor, Read, Seek, Write},
     sync::Arc,
     time::Duration,
 };
 
 use blaze_jni_bridge::{
+    conf,
+    conf::{BooleanConf, StringConf},
     is_jni_bridge_inited,
     jni_bridge::{JObject, LocalRef},
     jni_call, jni_call_static, jni_get_string, jni_new_direct_byte_buffer, jni_new_global_ref,
 };
 use datafusion::{common::Result, parquet::file::reader::Length, physical_plan::metrics::Time};
+use datafusion_ext_commons::df_execution_err;
 use jni::{objects::GlobalRef, sys::jlong};
 
 use crate::memmgr::metrics::SpillMetrics;
 
-pub type SpillCompressedReader<'a> =
-    lz4_flex::frame::FrameDecoder<BufReader<Box<dyn Read + Send + 'a>>>;
-pub type SpillCompressedWriter<'a> =
-    lz4_flex::frame::AutoFinishEncoder<BufWriter<Box<dyn Write + Send + 'a>>>;
+/// compression codec of spill data, the codec id is written in the header of
+/// a spill stream so that readers can select the matching decoder
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SpillCodec {
+    None,
+    Lz4,
+    Zstd(i32),
+}
+
+impl Default for SpillCodec {
+    fn default() -> Self {
+        Self::Lz4
+    }
+}
+
+impl SpillCodec {
+    const DEFAULT_ZSTD_LEVEL: i32 = 1;
+
+    /// returns the codec configured by spark.blaze.spill.compression.codec
+    pub fn try_from_conf() -> Result<Self> {
+        if !is_jni_bridge_inited() {
+            return Ok(Self::default());
+        }
+        Self::try_from_name(&conf::SPILL_COMPRESSION_CODEC.value()?)
+    }
+
+    /// parses codec from name: none, lz4, zstd, zstd-{level}
+    pub fn try_from_name(name: &str) -> Result<Self> {
+        let name = name.trim().to_ascii_lowercase();
+        Ok(match name.as_str() {
+            "none" => Self::None,
+            "lz4" => Self::Lz4,
+            "zstd" => Self::Zstd(Self::DEFAULT_ZSTD_LEVEL),
+            _ => match name.strip_prefix("zstd-").map(|level| level.parse::<i32>()) {
+                Some(Ok(level)) => Self::Zstd(level),
+                _ => return df_execution_err!("unsupported spill compression codec: {name}"),
+            },
+        })
+    }
+
+    fn id(&self) -> u8 {
+        match self {
+            Self::None => 0,
+            Self::Lz4 => 1,
+            Self::Zstd(_) => 2,
+        }
+    }
+}
+
+/// size of uncompressed data covered by one checksum
+const SPILL_CHECKSUM_BLOCK_SIZE: usize = 65536;
+const SPILL_CHECKSUM_SEED: i64 = 0x5350_494C;
+
+/// returns whether block checksums are enabled by
+/// spark.blaze.spill.checksum.enable
+pub fn spill_checksum_enabled() -> Result<bool> {
+    if !is_jni_bridge_inited() {
+        return Ok(false);
+    }
+    conf::SPILL_CHECKSUM_ENABLE.value()
+}
+
+/// returns the size of independently compressed blocks configured by
+/// spark.blaze.spill.compression.blockSize, 0 for a continuous stream
+pub fn spill_compression_block_size() -> Result<usize> {
+    if !is_jni_bridge_inited() {
+        return Ok(0);
+    }
+    Ok(conf::SPILL_COMPRESSION_BLOCK_SIZE.value()?.max(0) as usize)
+}
+
+/// flags in the second byte of the stream header
+const SPILL_FLAG_CHECKSUM: u8 = 0x01;
+const SPILL_FLAG_BLOCKED: u8 = 0x02;
+
+// first byte of the lz4 frame magic number (0x184D2204 in little endian).
+// spills written before the stream header was introduced are plain lz4
+// frames, they are detected by this byte which never collides with a codec id
+const LEGACY_LZ4_FRAME_MAGIC_FIRST_BYTE: u8 = 0x04;
+
+fn spill_checksum(block: &[u8]) -> u64 {
+    gxhash::gxhash64(block, SPILL_CHECKSUM_SEED)
+}
+
+enum SpillDecoder<'a> {
+    None(BufReader<Box<dyn Read + Send + 'a>>),
+    Lz4(lz4_flex::frame::FrameDecoder<BufReader<Box<dyn Read + Send + 'a>>>),
+    Zstd(zstd::Decoder<'static, BufReader<Box<dyn Read + Send + 'a>>>),
+    Blocked(SpillBlockDecoder<'a>),
+}
+
+impl Read for SpillDecoder<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match self {
+            Self::None(r) => r.read(buf),
+            Self::Lz4(r) => r.read(buf),
+            Self::Zstd(r) => r.read(buf),
+            Self::Blocked(r) => r.read(buf),
+        }
+    }
+}
+
+/// reads the u32 length prefix of a block, returns None on eof before the
+/// first byte
+fn read_block_len<R: Read>(input: &mut R, block_idx: usize) -> std::io::Result<Option<usize>> {
+    let mut len_buf = [0u8; 4];
+    let mut len_buf_filled = 0;
+    while len_buf_filled < len_buf.len() {
+        match input.read(&mut len_buf[len_buf_filled..])? {
+            0 if len_buf_filled == 0 => return Ok(None),
+            0 => {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::UnexpectedEof,
+                    format!("spill block #{block_idx} is truncated"),
+                ))
+            }
+            n => len_buf_filled += n,
+        }
+    }
+    Ok(Some(u32::from_le_bytes(len_buf) as usize))
+}
+
+fn verify_spill_checksum(
+    block: &[u8],
+    expected_checksum: u64,
+    block_idx: usize,
+) -> std::io::Result<()> {
+    let checksum = spill_checksum(block);
+    if checksum != expected_checksum {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!(
+                "spill block #{} is corrupted: checksum mismatch, expected {:016x}, got {:016x}",
+                block_idx, expected_checksum, checksum,
+            ),
+        ));
+    }
+    Ok(())
+}
+
+/// decoder of blocked spill streams, each block is written as
+/// (compressed_len: u32, len: u32, [checksum: u64], compressed data) and
+/// decompressed independently
+struct SpillBlockDecoder<'a> {
+    input: BufReader<Box<dyn Read + Send + 'a>>,
+    codec_id: u8,
+    checksum_enabled: bool,
+    compressed: Vec<u8>,
+    block: Vec<u8>,
+    pos: usize,
+    block_idx: usize,
+}
+
+impl SpillBlockDecoder<'_> {
+    /// reads and decompresses the next block, returns false on eof
+    fn next_block(&mut self) -> std::io::Result<bool> {
+        let Some(compressed_len) = read_block_len(&mut self.input, self.block_idx)? else {
+            return Ok(false);
+        };
+        let mut buf = [0u8; 8];
+        self.input.read_exact(&mut buf[..4])?;
+        let len = u32::from_le_bytes(buf[..4].try_into().unwrap()) as usize;
+        let expected_checksum = if self.checksum_enabled {
+            self.input.read_exact(&mut buf)?;
+            Some(u64::from_le_bytes(buf))
+        } else {
+            None
+        };
+        self.compressed.resize(compressed_len, 0);
+        self.input.read_exact(&mut self.compressed)?;
+
+        let invalid_data = |err: String| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("spill block #{} is corrupted: {err}", self.block_idx),
+            )
+        };
+        self.block = match self.codec_id {
+            1 => lz4_flex::block::decompress(&self.compressed, len)
+                .map_err(|err| invalid_data(err.to_string()))?,
+            2 => zstd::bulk::decompress(&self.compressed, len)
+                .map_err(|err| invalid_data(err.to_string()))?,
+            _ => std::mem::take(&mut self.compressed),
+        };
+        if self.block.len() != len {
+            return Err(invalid_data(format!(
+                "expected {len} bytes, got {}",
+                self.block.len()
+            )));
+        }
+        if let Some(expected_checksum) = expected_checksum {
+            verify_spill_checksum(&self.block, expected_checksum, self.block_idx)?;
+        }
+        self.pos = 0;
+        self.block_idx += 1;
+        Ok(true)
+    }
+}
+
+impl Read for SpillBlockDecoder<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        while self.pos == self.block.len() {
+            if !self.next_block()? {
+                return Ok(0);
+            }
+        }
+        let len = buf.len().min(self.block.len() - self.pos);
+        buf[..len].copy_from_slice(&self.block[self.pos..][..len]);
+        self.pos += len;
+        Ok(len)
+    }
+}
+
+/// reader of spill streams written by `SpillCompressedWriter`, decoder and
+/// checksum verification are selected by the stream header
+pub struct SpillCompressedReader<'a> {
+    decoder: SpillDecoder<'a>,
+    checksum_block: Option<ChecksumBlock>,
+    legacy: bool,
+}
+
+#[derive(Default)]
+struct ChecksumBlock {
+    data: Vec<u8>,
+    pos: usize,
+    block_idx: usize,
+}
+
+impl<'a> SpillCompressedReader<'a> {
+    /// reads codec id and flags from the stream header and creates the
+    /// matching decoder. headerless lz4 streams written by older binaries are
+    /// read as lz4 without flags
+    pub fn try_new(mut input: BufReader<Box<dyn Read + Send + 'a>>) -> Result<Self> {
+        if input.fill_buf()?.first() == Some(&LEGACY_LZ4_FRAME_MAGIC_FIRST_BYTE) {
+            return Ok(Self {
+                decoder: SpillDecoder::Lz4(lz4_flex::frame::FrameDecoder::new(input)),
+                checksum_block: None,
+                legacy: true,
+            });
+        }
+
+        let mut header = [0u8; 2];
+        input.read_exact(&mut header)?;
+        let checksum_enabled = header[1] & SPILL_FLAG_CHECKSUM != 0;
+        let blocked = header[1] & SPILL_FLAG_BLOCKED != 0;
+        let decoder = match header[0] {
+            id @ 0..=2 if blocked => SpillDecoder::Blocked(SpillBlockDecoder {
+                input,
+                codec_id: id,
+                checksum_enabled,
+                compressed: vec![],
+                block: vec![],
+                pos: 0,
+                block_idx: 0,
+            }),
+            0 => SpillDecoder::None(input),
+            1 => SpillDecoder::Lz4(lz4_flex::frame::FrameDecoder::new(input)),
+            2 => SpillDecoder::Zstd(zstd::Decoder::with_buffer(input)?),
+            id => return df_execution_err!("unknown spill compression codec id: {id}"),
+        };
+
+        // blocked streams are checksummed by the block decoder
+        let checksum_block = (checksum_enabled && !blocked).then(ChecksumBlock::default);
+        Ok(Self {
+            decoder,
+            checksum_block,
+            legacy: false,
+        })
+    }
+
+    /// returns whether the stream is a headerless lz4 stream written by older
+    /// binaries
+    pub fn is_legacy(&self) -> bool {
+        self.legacy
+    }
+
+    /// reads and verifies the next checksum block, returns false on eof
+    fn next_checksum_block(&mut self) -> std::io::Result<bool> {
+        let block = self.checksum_block.as_mut().unwrap();
+        let Some(len) = read_block_len(&mut self.decoder, block.block_idx)? else {
+            return Ok(false);
+        };
+        let mut checksum_buf = [0u8; 8];
+        self.decoder.read_exact(&mut checksum_buf)?;
+
+        let expected_checksum = u64::from_le_bytes(checksum_buf);
+        block.data.resize(len, 0);
+        block.pos = 0;
+        self.decoder.read_exact(&mut block.data)?;
+        verify_spill_checksum(&block.data, expected_checksum, block.block_idx)?;
+        block.block_idx += 1;
+        Ok(true)
+    }
+}
+
+impl Read for SpillCompressedReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let Some(block) = &self.checksum_block else {
+            return self.decoder.read(buf);
+        };
+        if block.pos == block.data.len() && !self.next_checksum_block()? {
+            return Ok(0);
+        }
+        let block = self.checksum_block.as_mut().unwrap();
+        let len = buf.len().min(block.data.len() - block.pos);
+        buf[..len].copy_from_slice(&block.data[block.pos..][..len]);
+        block.pos += len;
+        Ok(len)
+    }
+}
+
+enum SpillEncoder<'a> {
+    None(BufWriter<Box<dyn Write + Send + 'a>>),
+    Lz4(lz4_flex::frame::FrameEncoder<BufWriter<Box<dyn Write + Send + 'a>>>),
+    Zstd(zstd::Encoder<'static, BufWriter<Box<dyn Write + Send + 'a>>>),
+    Blocked(SpillBlockEncoder<'a>),
+}
+
+impl SpillEncoder<'_> {
+    /// finishes the compressed stream and flushes all data to the output
+    fn finish(self) -> std::io::Result<()> {
+        match self {
+            Self::None(mut w) => w.flush(),
+            Self::Lz4(w) => w.finish().map_err(std::io::Error::from)?.flush(),
+            Self::Zstd(w) => w.finish()?.flush(),
+            Self::Blocked(mut w) => w.flush(),
+        }
+    }
+}
+
+impl Write for SpillEncoder<'_> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            Self::None(w) => w.write(buf),
+            Self::Lz4(w) => w.write(buf),
+            Self::Zstd(w) => w.write(buf),
+            Self::Blocked(w) => w.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            Self::None(w) => w.flush(),
+            Self::Lz4(w) => w.flush(),
+            Self::Zstd(w) => w.flush(),
+            Self::Blocked(w) => w.flush(),
+        }
+    }
+}
+
+/// encoder of blocked spill streams, uncompressed data is divided into
+/// blocks of `block_size` bytes, each block is compressed independently so
+/// that it can be decompressed without the previous blocks
+struct SpillBlockEncoder<'a> {
+    output: BufWriter<Box<dyn Write + Send + 'a>>,
+    codec: SpillCodec,
+    checksum_enabled: bool,
+    block_size: usize,
+    block: Vec<u8>,
+}
+
+impl SpillBlockEncoder<'_> {
+    fn flush_block(&mut self) -> std::io::Result<()> {
+        if self.block.is_empty() {
+            return Ok(());
+        }
+        let compressed = match self.codec {
+            SpillCodec::None => None,
+            SpillCodec::Lz4 => Some(lz4_flex::block::compress(&self.block)),
+            SpillCodec::Zstd(level) => Some(zstd::bulk::compress(&self.block, level)?),
+        };
+        let compressed = compressed.as_deref().unwrap_or(&self.block);
+
+        self.output
+            .write_all(&(compressed.len() as u32).to_le_bytes())?;
+        self.output
+            .write_all(&(self.block.len() as u32).to_le_bytes())?;
+        if self.checksum_enabled {
+            self.output
+                .write_all(&spill_checksum(&self.block).to_le_bytes())?;
+        }
+        self.output.write_all(compressed)?;
+        self.block.clear();
+        Ok(())
+    }
+}
+
+impl Write for SpillBlockEncoder<'_> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let len = buf.len().min(self.block_size - self.block.len());
+        self.block.extend_from_slice(&buf[..len]);
+        if self.block.len() == self.block_size {
+            self.flush_block()?;
+        }
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.flush_block()?;
+        self.output.flush()
+    }
+}
+
+/// writer of spill streams, the stream starts with a header of codec id and
+/// flags. when checksum is enabled, uncompressed data is divided into blocks
+/// of (len: u32, checksum: u64, data) to detect corrupted spills. when
+/// `block_size` is non-zero, data is compressed in independent blocks (see
+/// `SpillBlockEncoder`) instead of a continuous stream.
+/// `finish()` must be called after all data is written, otherwise errors of
+/// writing the remaining data are lost
+pub struct SpillCompressedWriter<'a> {
+    encoder: Option<SpillEncoder<'a>>,
+    checksum_block: Option<Vec<u8>>,
+}
+
+impl<'a> SpillCompressedWriter<'a> {
+    /// writes the stream header and creates the matching encoder
+    pub fn try_new(
+        mut output: BufWriter<Box<dyn Write + Send + 'a>>,
+        codec: SpillCodec,
+        checksum_enabled: bool,
+        block_size: usize,
+    ) -> Result<Self> {
+        let blocked = block_size > 0;
+        let mut flags = 0;
+        if checksum_enabled {
+            flags |= SPILL_FLAG_CHECKSUM;
+        }
+        if blocked {
+            flags |= SPILL_FLAG_BLOCKED;
+        }
+        output.write_all(&[codec.id(), flags])?;
+
+        if blocked {
+            return Ok(Self {
+                encoder: Some(SpillEncoder::Blocked(SpillBlockEncoder {
+                    output,
+                    codec,
+                    checksum_enabled,
+                    block_size,
+                    block: Vec::with_capacity(block_size),
+                })),
+                checksum_block: None,
+            });
+        }
+        let encoder = match codec {
+            SpillCodec::None => SpillEncoder::None(output),
+            SpillCodec::Lz4 => SpillEncoder::Lz4(lz4_flex::frame::FrameEncoder::new(output)),
+            SpillCodec::Zstd(level) => SpillEncoder::Zstd(zstd::Encoder::new(output, level)?),
+        };
+        let checksum_block =
+            checksum_enabled.then(|| Vec::with_capacity(SPILL_CHECKSUM_BLOCK_SIZE));
+        Ok(Self {
+            encoder: Some(encoder),
+            checksum_block,
+        })
+    }
+
+    /// writes the remaining data and finishes the stream
+    pub fn finish(mut self) -> Result<()> {
+        Ok(self.finish_encoder()?)
+    }
+
+    fn finish_encoder(&mut self) -> std::io::Result<()> {
+        let Some(mut encoder) = self.encoder.take() else {
+            return Ok(());
+        };
+        if let Some(block) = &mut self.checksum_block {
+            write_checksum_block(&mut encoder, block)?;
+        }
+        encoder.finish()
+    }
+
+    fn encoder(&mut self) -> &mut SpillEncoder<'a> {
+        self.encoder
+            .as_mut()
+            .expect("SpillCompressedWriter is already finished")
+    }
+
+    fn flush_checksum_block(&mut self) -> std::io::Result<()> {
+        let encoder = self
+            .encoder
+            .as_mut()
+            .expect("SpillCompressedWriter is already finished");
+        if let Some(block) = &mut self.checksum_block {
+            write_checksum_block(encoder, block)?;
+        }
+        Ok(())
+    }
+}
+
+/// writes a non-empty checksum block as (len: u32, checksum: u64, data) and
+/// clears it
+fn write_checksum_block<W: Write>(output: &mut W, block: &mut Vec<u8>) -> std::io::Result<()> {
+    if !block.is_empty() {
+        output.write_all(&(block.len() as u32).to_le_bytes())?;
+        output.write_all(&spill_checksum(block).to_le_bytes())?;
+        output.write_all(block)?;
+        block.clear();
+    }
+    Ok(())
+}
+
+impl Write for SpillCompressedWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let Some(block) = &mut self.checksum_block else {
+            return self.encoder().write(buf);
+        };
+        let len = buf.len().min(SPILL_CHECKSUM_BLOCK_SIZE - block.len());
+        block.extend_from_slice(&buf[..len]);
+        if block.len() == SPILL_CHECKSUM_BLOCK_SIZE {
+            self.flush_checksum_block()?;
+        }
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.flush_checksum_block()?;
+        self.encoder().flush()
+    }
+}
+
+impl Drop for SpillCompressedWriter<'_> {
+    fn drop(&mut self) {
+        // fallback of unfinished writers, errors can only be reported by
+        // finish() so dropping without it is a bug unless already panicking
+        debug_assert!(
+            self.encoder.is_none() || std::thread::panicking(),
+            "SpillCompressedWriter is dropped without finish()"
+        );
+        let _ = self.finish_encoder();
+    }
+}
 
 pub trait Spill: Send + Sync {
     fn as_any(&self) -> &dyn Any;
     fn get_buf_reader<'a>(&'a self) -> BufReader<Box<dyn Read + Send + 'a>>;
     fn get_buf_writer<'a>(&'a mut self) -> BufWriter<Box<dyn Write + Send + 'a>>;
 
-    fn get_compressed_reader(&self) -> SpillCompressedReader<'_> {
-        lz4_flex::frame::FrameDecoder::new(self.get_buf_reader())
+    fn get_compressed_reader(&self) -> Result<SpillCompressedReader<'_>> {
+        SpillCompressedReader::try_new(self.get_buf_reader())
     }
 
-    fn get_compressed_writer(&mut self) -> SpillCompressedWriter<'_> {
-        lz4_flex::frame::FrameEncoder::new(self.get_buf_writer()).auto_finish()
+    fn get_compressed_writer(&mut self) -> Result<SpillCompressedWriter<'_>> {
+        let codec = SpillCodec::try_from_conf()?;
+        let checksum_enabled = spill_checksum_enabled()?;
+        let block_size = spill_compression_block_size()?;
+        self.get_blocked_compressed_writer(codec, checksum_enabled, block_size)
+    }
+
+    fn get_compressed_writer_with_options(
+        &mut self,
+        codec: SpillCodec,
+        checksum_enabled: bool,
+    ) -> Result<SpillCompressedWriter<'_>> {
+        self.get_blocked_compressed_writer(codec, checksum_enabled, 0)
+    }
+
+    /// like `get_compressed_writer_with_options()`, data is compressed in
+    /// independent blocks of `block_size` bytes, or as a continuous stream if
+    /// `block_size` is 0
+    fn get_blocked_compressed_writer(
+        &mut self,
+        codec: SpillCodec,
+        checksum_enabled: bool,
+        block_size: usize,
+    ) -> Result<SpillCompressedWriter<'_>> {
+        SpillCompressedWriter::try_new(self.get_buf_writer(), codec, checksum_enabled, block_size)
     }
 }
 
@@ -259,3 +814,158 @@ impl<W: Write> Write for IoTimeWriteWrapper<W> {
         self.0.flush()
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::io::{Read, Write};
+
+    use datafusion::common::Result;
+
+    use crate::memmgr::spill::{Spill, SpillCodec, SPILL_CHECKSUM_BLOCK_SIZE};
+
+    fn test_data() -> Vec<u8> {
+        (0..100000u32)
+            .flat_map(|i| (i % 1000).to_le_bytes())
+            .collect::<Vec<u8>>()
+    }
+
+    #[test]
+    fn test_spill_codecs() -> Result<()> {
+        let data = test_data();
+        for codec in [SpillCodec::None, SpillCodec::Lz4, SpillCodec::Zstd(3)] {
+            for checksum_enabled in [false, true] {
+                let mut spill: Box<dyn Spill> = Box::new(vec![]);
+                let mut writer =
+                    spill.get_compressed_writer_with_options(codec, checksum_enabled)?;
+                writer.write_all(&data)?;
+                writer.finish()?;
+
+                let mut read_data = vec![];
+                spill.get_compressed_reader()?.read_to_end(&mut read_data)?;
+                assert_eq!(
+                    read_data, data,
+                    "codec: {codec:?}, checksum: {checksum_enabled}"
+                );
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_legacy_lz4_spill() -> Result<()> {
+        let data = test_data();
+        let mut spill: Vec<u8> = vec![];
+        let mut encoder = lz4_flex::frame::FrameEncoder::new(&mut spill);
+        encoder.write_all(&data)?;
+        encoder.finish().unwrap();
+
+        let mut reader = spill.get_compressed_reader()?;
+        assert!(reader.is_legacy());
+        let mut read_data = vec![];
+        reader.read_to_end(&mut read_data)?;
+        assert_eq!(read_data, data);
+
+        let mut spill: Vec<u8> = vec![];
+        let mut writer = spill.get_compressed_writer_with_options(SpillCodec::Lz4, false)?;
+        writer.write_all(&data)?;
+        writer.finish()?;
+        assert!(!spill.get_compressed_reader()?.is_legacy());
+        Ok(())
+    }
+
+    #[test]
+    fn test_spill_checksum_mismatch() -> Result<()> {
+        let data = test_data();
+        let mut spill: Vec<u8> = vec![];
+        let mut writer = spill.get_compressed_writer_with_options(SpillCodec::None, true)?;
+        writer.write_all(&data)?;
+        writer.finish()?;
+
+        // flip a byte in the second block, the first block is still readable
+        let block_header_len = 12;
+        let flipped_pos =
+            2 + (block_header_len + SPILL_CHECKSUM_BLOCK_SIZE) + block_header_len + 100;
+        spill[flipped_pos] ^= 0xff;
+
+        let mut reader = spill.get_compressed_reader()?;
+        let mut first_block = vec![0; SPILL_CHECKSUM_BLOCK_SIZE];
+        reader.read_exact(&mut first_block)?;
+        assert_eq!(first_block, data[..SPILL_CHECKSUM_BLOCK_SIZE]);
+
+        let err = reader.read_to_end(&mut vec![]).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+        assert!(err.to_string().contains("spill block #1 is corrupted"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_spill_blocked_codecs() -> Result<()> {
+        let data = test_data();
+        for codec in [SpillCodec::None, SpillCodec::Lz4, SpillCodec::Zstd(3)] {
+            for checksum_enabled in [false, true] {
+                for block_size in [7, 1000, 65536, 1 << 20] {
+                    let mut spill: Box<dyn Spill> = Box::new(vec![]);
+                    let mut writer =
+                        spill.get_blocked_compressed_writer(codec, checksum_enabled, block_size)?;
+                    writer.write_all(&data)?;
+                    writer.finish()?;
+
+                    let mut read_data = vec![];
+                    spill.get_compressed_reader()?.read_to_end(&mut read_data)?;
+                    assert_eq!(
+                        read_data, data,
+                        "codec: {codec:?}, checksum: {checksum_enabled}, block size: {block_size}"
+                    );
+                }
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_spill_blocks_independently_decompressible() -> Result<()> {
+        let data = test_data();
+        let block_size = 10000;
+        let mut spill: Vec<u8> = vec![];
+        let mut writer = spill.get_blocked_compressed_writer(SpillCodec::Lz4, true, block_size)?;
+        writer.write_all(&data)?;
+        writer.finish()?;
+
+        // walk through block headers and decompress each block separately
+        let mut pos = 2;
+        let mut num_blocks = 0;
+        while pos < spill.len() {
+            let compressed_len = u32::from_le_bytes(spill[pos..][..4].try_into().unwrap());
+            let len = u32::from_le_bytes(spill[pos + 4..][..4].try_into().unwrap());
+            pos += 16;
+            let block =
+                lz4_flex::block::decompress(&spill[pos..][..compressed_len as usize], len as usize)
+                    .unwrap();
+            let start = num_blocks * block_size;
+            assert_eq!(block, data[start..][..block.len()]);
+            pos += compressed_len as usize;
+            num_blocks += 1;
+        }
+        assert_eq!(num_blocks, (data.len() + block_size - 1) / block_size);
+
+        // corrupted block is detected
+        spill[2 + 16 + 10] ^= 0xff;
+        let err = spill
+            .get_compressed_reader()?
+            .read_to_end(&mut vec![])
+            .unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+        assert!(err.to_string().contains("spill block #0 is corrupted"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_spill_codec_from_name() -> Result<()> {
+        assert_eq!(SpillCodec::try_from_name("none")?, SpillCodec::None);
+        assert_eq!(SpillCodec::try_from_name("LZ4")?, SpillCodec::Lz4);
+        assert_eq!(SpillCodec::try_from_name("zstd")?, SpillCodec::Zstd(1));
+        assert_eq!(SpillCodec::try_from_name("zstd-9")?, SpillCodec::Zstd(9));
+        assert!(SpillCodec::try_from_name("snappy").is_err());
+        Ok(())
+    }
+}
@@ -258,7 +258,7 @@ impl BufferedData {
         spill: &mut Box<dyn Spill>,
         sub_batch_size: usize,
     ) -> Result<()> {
-        let mut writer = spill.get_compressed_writer();
+        let mut writer = spill.get_compressed_writer()?;
         for (key_collector, batch) in
             self.into_sorted_batches::<SqueezeKeyCollector>(sub_batch_size, sorter)?
         {
@@ -267,6 +267,7 @@ impl BufferedData {
             writer.write_all(&buf)?;
             writer.write_all(&key_collector.store)?;
         }
+        writer.finish()?;
         Ok(())
     }
 
@@ -718,7 +719,7 @@ impl<'a> SpillCursor<'a> {
         let mut iter = SpillCursor {
             id,
             pruned_schema,
-            input: spill.get_compressed_reader(),
+            input: spill.get_compressed_reader()?,
             cur_batch_num_rows: 0,
             cur_loaded_num_rows: 0,
             cur_batches: vec![],
@@ -950,7 +951,7 @@ fn merge_spills(
     }
 
     let mut output_spill = try_new_spill(spill_metrics)?;
-    let mut output_writer = output_spill.get_compressed_writer();
+    let mut output_writer = output_spill.get_compressed_writer()?;
     let mut merger = ExternalMerger::<SqueezeKeyCollector>::try_new(
         &mut spills,
         pruned_schema,
@@ -964,7 +965,7 @@ fn merge_spills(
         output_writer.write_all(&buf)?;
         output_writer.write_all(&key_collector.store)?;
     }
-    drop(output_writer);
+    output_writer.finish()?;
     Ok(output_spill)
 }
 
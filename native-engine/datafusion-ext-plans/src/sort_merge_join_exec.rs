@@ -68,6 +68,8 @@ pub struct SortMergeJoinExec {
     /// Sort options of join columns used in sorting left and right execution
     /// plans
     sort_options: Vec<SortOptions>,
+    /// Whether null keys are matched as equal, like null-safe equality
+    null_equals_null: bool,
 }
 
 impl SortMergeJoinExec {
@@ -78,6 +80,7 @@ impl SortMergeJoinExec {
         join_type: JoinType,
         join_filter: Option<JoinFilter>,
         sort_options: Vec<SortOptions>,
+        null_equals_null: bool,
     ) -> Result<Self> {
         let left_schema = left.schema();
         let right_schema = right.schema();
@@ -107,6 +110,7 @@ impl SortMergeJoinExec {
             schema,
             metrics: ExecutionPlanMetricsSet::new(),
             sort_options,
+            null_equals_null,
         })
     }
 
@@ -137,6 +141,7 @@ impl SortMergeJoinExec {
             on_data_types,
             join_filter: self.join_filter.clone(),
             sort_options: self.sort_options.clone(),
+            null_equals_null: self.null_equals_null,
             batch_size: sub_batch_size,
             left_output_projection: (0..self.left.schema().fields().len()).collect(),
             right_output_projection: (0..self.right.schema().fields().len()).collect(),
@@ -191,6 +196,7 @@ impl ExecutionPlan for SortMergeJoinExec {
             self.join_type,
             self.join_filter.clone(),
             self.sort_options.clone(),
+            self.null_equals_null,
         )?))
     }
 
@@ -246,6 +252,7 @@ struct JoinParams {
     on_right: Vec<usize>,
     on_data_types: Vec<DataType>,
     sort_options: Vec<SortOptions>,
+    null_equals_null: bool,
     join_filter: Option<JoinFilter>,
     left_output_projection: Vec<usize>,
     right_output_projection: Vec<usize>,
@@ -341,6 +348,7 @@ impl JoinParams {
             on_right: on_right_projected,
             on_data_types: self.on_data_types.clone(),
             sort_options: self.sort_options.clone(),
+            null_equals_null: self.null_equals_null,
             join_filter: join_filter_projected,
             batch_size: self.batch_size,
             left_output_projection: (0..num_left_output_columns).collect(),
@@ -437,7 +445,13 @@ async fn execute_join(
 
     // process records until one side is exhausted
     while !lcur.finished && !rcur.finished {
-        let r = compare_cursor(&lcur, lcur.cur_idx, &rcur, rcur.cur_idx);
+        let r = compare_cursor(
+            &lcur,
+            lcur.cur_idx,
+            &rcur,
+            rcur.cur_idx,
+            join_params.null_equals_null,
+        );
         match r {
             Ordering::Less => {
                 if matches!(join_type, Left | LeftAnti | Full) {
@@ -885,11 +899,14 @@ impl Joiner {
     }
 }
 
+/// compares keys of two cursors, keys containing nulls never equal unless
+/// `null_equals_null` is true
 fn compare_cursor(
     lcur: &StreamCursor,
     lidx: (usize, usize),
     rcur: &StreamCursor,
     ridx: (usize, usize),
+    null_equals_null: bool,
 ) -> Ordering {
     match (&lcur.on_rows.get(lidx.0), &rcur.on_rows.get(ridx.0)) {
         (None, _) => Ordering::Greater,
@@ -901,6 +918,9 @@ fn compare_cursor(
                 Ordering::Greater => Ordering::Greater,
                 Ordering::Less => Ordering::Less,
                 _ => {
+                    if null_equals_null {
+                        return Ordering::Equal;
+                    }
                     if let Some(nb) = &lcur.on_row_null_buffers[lidx.0] {
                         if nb.is_null(lidx.1) {
                             return Ordering::Less;
@@ -1056,7 +1076,7 @@ mod tests {
         join_type: JoinType,
         sort_options: Vec<SortOptions>,
     ) -> Result<SortMergeJoinExec> {
-        SortMergeJoinExec::try_new(left, right, on, join_type, None, sort_options)
+        SortMergeJoinExec::try_new(left, right, on, join_type, None, sort_options, false)
     }
 
     async fn join_collect(
@@ -1235,6 +1255,67 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn join_with_null_equals_null() -> Result<()> {
+        let left = build_table_i32_nullable(
+            ("a1", &vec![Some(1), Some(1), Some(2), Some(2), Some(3)]),
+            ("b2", &vec![None, Some(1), Some(2), Some(2), None]), // null in key field
+            ("c1", &vec![Some(1), None, Some(8), Some(9), Some(5)]),
+        );
+        let right = build_table_i32_nullable(
+            ("a1", &vec![Some(1), Some(1), Some(2), Some(3)]),
+            ("b2", &vec![None, Some(1), Some(2), Some(2)]),
+            ("c2", &vec![Some(10), Some(70), Some(80), Some(90)]),
+        );
+        let on: JoinOn = vec![
+            (
+                Arc::new(Column::new_with_schema("a1", &left.schema())?),
+                Arc::new(Column::new_with_schema("a1", &right.schema())?),
+            ),
+            (
+                Arc::new(Column::new_with_schema("b2", &left.schema())?),
+                Arc::new(Column::new_with_schema("b2", &right.schema())?),
+            ),
+        ];
+
+        let session_ctx = SessionContext::new();
+        let sort_options = vec![SortOptions::default(); on.len()];
+        let join = SortMergeJoinExec::try_new(
+            left.clone(),
+            right.clone(),
+            on.clone(),
+            Inner,
+            None,
+            sort_options.clone(),
+            true,
+        )?;
+        let batches = common::collect(join.execute(0, session_ctx.task_ctx())?).await?;
+        let expected = vec![
+            "+----+----+----+----+----+----+",
+            "| a1 | b2 | c1 | a1 | b2 | c2 |",
+            "+----+----+----+----+----+----+",
+            "| 1  |    | 1  | 1  |    | 10 |",
+            "| 1  | 1  |    | 1  | 1  | 70 |",
+            "| 2  | 2  | 8  | 2  | 2  | 80 |",
+            "| 2  | 2  | 9  | 2  | 2  | 80 |",
+            "+----+----+----+----+----+----+",
+        ];
+        assert_batches_sorted_eq!(expected, &batches);
+
+        // (3, null) on the left does not match (3, 2) on the right
+        let join = SortMergeJoinExec::try_new(left, right, on, LeftAnti, None, sort_options, true)?;
+        let batches = common::collect(join.execute(0, session_ctx.task_ctx())?).await?;
+        let expected = vec![
+            "+----+----+----+",
+            "| a1 | b2 | c1 |",
+            "+----+----+----+",
+            "| 3  |    | 5  |",
+            "+----+----+----+",
+        ];
+        assert_batches_sorted_eq!(expected, &batches);
+        Ok(())
+    }
+
     #[tokio::test]
     async fn join_inner_with_nulls_with_options() -> Result<()> {
         let left = build_table_i32_nullable(
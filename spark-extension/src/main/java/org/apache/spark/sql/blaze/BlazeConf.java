@@ -64,6 +64,30 @@ public enum BlazeConf {
 
     /// mininum number of rows to trigger partial aggregate skipping
     PARTIAL_AGG_SKIPPING_MIN_ROWS("spark.blaze.partialAggSkipping.minRows", BATCH_SIZE.intConf() * 2),
+
+    /// spills aggregate tables when the memory budget is reached. if disabled, the task fails
+    /// instead of spilling, for latency-sensitive queries preferring to fail fast.
+    AGG_SPILL_ENABLE("spark.blaze.agg.spill.enable", true),
+
+    /// number of threads merging collect_set() groups of a large batch
+    COLLECT_SET_MERGE_NUM_THREADS("spark.blaze.collectSetMergeNumThreads", 1),
+
+    /// max number of slots of the hash table of each collect_set() group, 0 for unlimited.
+    /// a group exceeding the limit fails the task instead of reallocating a huge table.
+    /// the limit is not routed into spilling since spilled groups are merged back on output
+    COLLECT_SET_MAX_SLOTS("spark.blaze.collectSetMaxSlots", 0),
+
+    /// compression codec of spills, supported values: none, lz4, zstd, zstd-{level}.
+    /// use none/lz4 on cpu-bound nodes and zstd on io-bound nodes.
+    SPILL_COMPRESSION_CODEC("spark.blaze.spill.compression.codec", "lz4"),
+
+    /// writes block checksums into spills and verifies them on reading, detecting corrupted
+    /// spill files at the cost of an extra copy and hashing of all spilled data.
+    SPILL_CHECKSUM_ENABLE("spark.blaze.spill.checksum.enable", false),
+
+    /// compresses spills in independent blocks of the given size in bytes, which can be
+    /// decompressed separately. use 0 to compress spills as a continuous stream.
+    SPILL_COMPRESSION_BLOCK_SIZE("spark.blaze.spill.compression.blockSize", 0),
     ;
 
     private String key;
@@ -90,6 +114,10 @@ public enum BlazeConf {
         return conf().getDouble(key, (double) defaultValue);
     }
 
+    public String stringConf() {
+        return conf().get(key, (String) defaultValue);
+    }
+
     public static boolean booleanConf(String confName) {
         return BlazeConf.valueOf(confName).booleanConf();
     }
@@ -106,6 +134,10 @@ public enum BlazeConf {
         return BlazeConf.valueOf(confName).doubleConf();
     }
 
+    public static String stringConf(String confName) {
+        return BlazeConf.valueOf(confName).stringConf();
+    }
+
     private static SparkConf conf() {
         return SparkEnv$.MODULE$.get().conf();
     }